@@ -0,0 +1,238 @@
+// The AMD Am29F040, a 512K flash memory in 8 sectors of 64K, two of which
+// hold the ROML and ROMH banks of an EasyFlash cartridge. It reads like a
+// ROM until a command is written: every command starts with the unlock
+// cycles $AA to $555 and $55 to $2AA, of which the chip only decodes the
+// address lines A0-A10. Programming can only clear bits, erasing sets all
+// bits of a sector or of the chip.
+//
+// Programming and erasing finish at once, so status polling through DQ7
+// and DQ6 sees the operation completed on the first read.
+
+pub const SIZE: usize = 0x80000;
+const SECTOR_SIZE: usize = 0x10000;
+const COMMAND_ADDRESS_MASK: usize = 0x7FF;
+const UNLOCK_ADDRESS_1: usize = 0x555;
+const UNLOCK_ADDRESS_2: usize = 0x2AA;
+
+const MANUFACTURER_ID: u8 = 0x01;
+const DEVICE_ID: u8 = 0xA4;
+
+// Command cycles, written to the unlock addresses
+const UNLOCK_1: u8 = 0xAA;
+const UNLOCK_2: u8 = 0x55;
+const PROGRAM: u8 = 0xA0;
+const AUTOSELECT: u8 = 0x90;
+const ERASE: u8 = 0x80;
+const CHIP_ERASE: u8 = 0x10;
+// Written to an address in the sector instead
+const SECTOR_ERASE: u8 = 0x30;
+// Written anywhere
+const RESET: u8 = 0xF0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Read,
+    Unlocked1,
+    Unlocked2,
+    Program,
+    Erase,
+    EraseUnlocked1,
+    EraseUnlocked2,
+    Autoselect
+}
+
+pub struct Am29f040 {
+    data: Vec<u8>,
+    state: State,
+    modified: bool
+}
+
+impl Am29f040 {
+    /// A chip with every bit erased.
+    pub fn new() -> Am29f040 {
+        Am29f040 {
+            data: vec![0xFF; SIZE],
+            state: State::Read,
+            modified: false
+        }
+    }
+
+    /// Fills the chip from an image, which doesn't count as a modification.
+    pub fn load(self: &mut Am29f040, offset: usize, data: &[u8]) {
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    pub fn data(self: &Am29f040) -> &[u8] {
+        &self.data
+    }
+
+    pub fn modified(self: &Am29f040) -> bool {
+        self.modified
+    }
+
+    pub fn set_saved(self: &mut Am29f040) {
+        self.modified = false;
+    }
+
+    pub fn reset(self: &mut Am29f040) {
+        self.state = State::Read;
+    }
+
+    pub fn read(self: &Am29f040, offset: usize) -> u8 {
+        if self.state == State::Autoselect {
+            match offset & 0x03 {
+                0 => MANUFACTURER_ID,
+                1 => DEVICE_ID,
+                // No sector is protected
+                _ => 0x00
+            }
+        } else {
+            self.data[offset]
+        }
+    }
+
+    pub fn write(self: &mut Am29f040, offset: usize, value: u8) {
+        let command_address = offset & COMMAND_ADDRESS_MASK;
+        self.state = match (self.state, value) {
+            (State::Program, _) => {
+                self.data[offset] &= value;
+                self.modified = true;
+                State::Read
+            }
+            (_, RESET) => State::Read,
+            (State::Read, UNLOCK_1) | (State::Autoselect, UNLOCK_1) if command_address == UNLOCK_ADDRESS_1 => State::Unlocked1,
+            (State::Unlocked1, UNLOCK_2) if command_address == UNLOCK_ADDRESS_2 => State::Unlocked2,
+            (State::Unlocked2, PROGRAM) if command_address == UNLOCK_ADDRESS_1 => State::Program,
+            (State::Unlocked2, AUTOSELECT) if command_address == UNLOCK_ADDRESS_1 => State::Autoselect,
+            (State::Unlocked2, ERASE) if command_address == UNLOCK_ADDRESS_1 => State::Erase,
+            (State::Erase, UNLOCK_1) if command_address == UNLOCK_ADDRESS_1 => State::EraseUnlocked1,
+            (State::EraseUnlocked1, UNLOCK_2) if command_address == UNLOCK_ADDRESS_2 => State::EraseUnlocked2,
+            (State::EraseUnlocked2, CHIP_ERASE) if command_address == UNLOCK_ADDRESS_1 => {
+                for byte in self.data.iter_mut() {
+                    *byte = 0xFF;
+                }
+                self.modified = true;
+                State::Read
+            }
+            (State::EraseUnlocked2, SECTOR_ERASE) => {
+                let sector = offset & !(SECTOR_SIZE - 1);
+                for byte in self.data[sector..sector + SECTOR_SIZE].iter_mut() {
+                    *byte = 0xFF;
+                }
+                self.modified = true;
+                State::Read
+            }
+            (State::Autoselect, _) => State::Autoselect,
+            // Anything out of sequence aborts the command
+            _ => State::Read
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The unlock cycles and a command, at addresses with the bits above
+    // A10 set, which the chip ignores
+    fn command(chip: &mut Am29f040, command: u8) {
+        chip.write(0x7F800 | UNLOCK_ADDRESS_1, UNLOCK_1);
+        chip.write(0x7F800 | UNLOCK_ADDRESS_2, UNLOCK_2);
+        chip.write(0x7F800 | UNLOCK_ADDRESS_1, command);
+    }
+
+    fn programmed() -> Am29f040 {
+        let mut chip = Am29f040::new();
+        chip.load(0, &vec![0x00; SIZE]);
+        chip
+    }
+
+    #[test]
+    fn reads_like_a_rom_until_unlocked() {
+        let mut chip = programmed();
+        assert!(!chip.modified());
+        chip.write(0x1234, 0xFF);
+        chip.write(UNLOCK_ADDRESS_1, PROGRAM);
+        chip.write(0x1234, 0x55);
+        assert_eq!(chip.read(0x1234), 0x00);
+        assert_eq!(chip.state, State::Read);
+        assert!(!chip.modified());
+    }
+
+    #[test]
+    fn programs_a_byte_by_clearing_bits() {
+        let mut chip = Am29f040::new();
+        command(&mut chip, PROGRAM);
+        chip.write(0x12345, 0xF0);
+        assert_eq!(chip.read(0x12345), 0xF0);
+        assert!(chip.modified());
+        // Back to reading after one byte
+        chip.write(0x12346, 0x00);
+        assert_eq!(chip.read(0x12346), 0xFF);
+        command(&mut chip, PROGRAM);
+        chip.write(0x12345, 0x3C);
+        assert_eq!(chip.read(0x12345), 0x30);
+        chip.set_saved();
+        assert!(!chip.modified());
+    }
+
+    #[test]
+    fn aborts_commands_out_of_sequence() {
+        let mut chip = Am29f040::new();
+        // Second unlock cycle at the wrong address
+        chip.write(UNLOCK_ADDRESS_1, UNLOCK_1);
+        chip.write(UNLOCK_ADDRESS_1, UNLOCK_2);
+        chip.write(UNLOCK_ADDRESS_1, PROGRAM);
+        chip.write(0, 0x00);
+        assert_eq!(chip.read(0), 0xFF);
+        // Unknown command
+        command(&mut chip, 0x12);
+        assert_eq!(chip.state, State::Read);
+        // Reset in the middle of the sequence
+        chip.write(UNLOCK_ADDRESS_1, UNLOCK_1);
+        chip.write(UNLOCK_ADDRESS_2, RESET);
+        assert_eq!(chip.state, State::Read);
+    }
+
+    #[test]
+    fn erases_a_sector() {
+        let mut chip = programmed();
+        command(&mut chip, ERASE);
+        chip.write(UNLOCK_ADDRESS_1, UNLOCK_1);
+        chip.write(UNLOCK_ADDRESS_2, UNLOCK_2);
+        chip.write(0x2ABCD, SECTOR_ERASE);
+        assert!(chip.data()[0x20000..0x30000].iter().all(|&byte| byte == 0xFF));
+        assert_eq!((chip.read(0x1FFFF), chip.read(0x30000)), (0x00, 0x00));
+        assert!(chip.modified());
+        assert_eq!(chip.state, State::Read);
+    }
+
+    #[test]
+    fn erases_the_chip() {
+        let mut chip = programmed();
+        command(&mut chip, ERASE);
+        chip.write(UNLOCK_ADDRESS_1, UNLOCK_1);
+        chip.write(UNLOCK_ADDRESS_2, UNLOCK_2);
+        // Chip erase needs the command address
+        chip.write(0x123, CHIP_ERASE);
+        assert_eq!(chip.read(0), 0x00);
+        command(&mut chip, ERASE);
+        command(&mut chip, CHIP_ERASE);
+        assert!(chip.data().iter().all(|&byte| byte == 0xFF));
+        assert!(chip.modified());
+    }
+
+    #[test]
+    fn identifies_itself_until_reset() {
+        let mut chip = programmed();
+        command(&mut chip, AUTOSELECT);
+        assert_eq!((chip.read(0x10000), chip.read(0x10001), chip.read(0x10002)), (MANUFACTURER_ID, DEVICE_ID, 0x00));
+        chip.write(0, 0x00);
+        assert_eq!(chip.read(0), MANUFACTURER_ID);
+        chip.write(0, RESET);
+        assert_eq!(chip.read(1), 0x00);
+        command(&mut chip, AUTOSELECT);
+        chip.reset();
+        assert_eq!(chip.read(1), 0x00);
+    }
+}
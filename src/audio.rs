@@ -0,0 +1,317 @@
+extern crate sdl2;
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufWriter, SeekFrom};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use audio::sdl2::audio::{AudioQueue, AudioSpecDesired};
+
+// Fraction of the output Nyquist frequency kept intact by the resampler. The
+// stop band starts where aliases would fold back into the pass band.
+const PASSBAND_FRACTION: f64 = 0.9;
+const STOPBAND_ATTENUATION: f64 = 80.0;
+// Number of fractional sample positions the filter is tabulated for
+const FIR_PHASES: usize = 64;
+
+// Zeroth order modified Bessel function of the first kind, for the Kaiser window
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)) * (x / (2.0 * k));
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+/// Converts the stereo output of the SIDs, one sample pair per cycle, to a
+/// lower sample rate with a Kaiser windowed sinc filter. Output samples are
+/// due on fixed cycles, so the result only depends on the input.
+pub struct Resampler {
+    clock_frequency: u32,
+    sample_rate: u32,
+    // Filter coefficients, FIR_PHASES + 1 rows of `taps` values. Row p is
+    // for an output instant p / FIR_PHASES input samples before the newest.
+    fir: Vec<f32>,
+    taps: usize,
+    // The last `taps` input samples of each channel twice over, so that they
+    // can always be read as one slice starting at `index`
+    left: Vec<f32>,
+    right: Vec<f32>,
+    index: usize,
+    // Number of newest input samples equal on both channels. While it covers
+    // the whole filter the right channel needs no filtering of its own.
+    identical: usize,
+    // Fraction of the next output sample elapsed, in units of 1 / clock_frequency
+    phase: u32
+}
+
+// Dot product with independent partial sums, which lets the compiler
+// vectorize it
+fn convolve(fir: &[f32], samples: &[f32]) -> f32 {
+    let mut sums = [0.0f32; 8];
+    for (f, x) in fir.chunks(8).zip(samples.chunks(8)) {
+        for i in 0..f.len() {
+            sums[i] += f[i] * x[i];
+        }
+    }
+    sums.iter().sum()
+}
+
+impl Resampler {
+    pub fn new(clock_frequency: u32, sample_rate: u32) -> Resampler {
+        let input_rate = clock_frequency as f64;
+        let passband = PASSBAND_FRACTION * sample_rate as f64 / 2.0;
+        let transition = sample_rate as f64 - 2.0 * passband;
+        let cutoff = sample_rate as f64 / 2.0 / input_rate;
+        let beta = 0.1102 * (STOPBAND_ATTENUATION - 8.7);
+        let taps = ((STOPBAND_ATTENUATION - 7.95) / (2.285 * 2.0 * PI * transition / input_rate)).ceil() as usize | 1;
+        let center = (taps - 1) as f64 / 2.0;
+
+        let mut fir = Vec::with_capacity((FIR_PHASES + 1) * taps);
+        for phase in 0..=FIR_PHASES {
+            let fraction = phase as f64 / FIR_PHASES as f64;
+            for j in 0..taps {
+                // Distance of input sample j from the filter center
+                let t = (taps - 1 - j) as f64 - fraction - center;
+                let x = 2.0 * cutoff * t;
+                let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                let w = t / (center + 1.0);
+                let window = bessel_i0(beta * (1.0 - w * w).sqrt()) / bessel_i0(beta);
+                fir.push((2.0 * cutoff * sinc * window) as f32);
+            }
+        }
+
+        Resampler {
+            clock_frequency,
+            sample_rate,
+            fir,
+            taps,
+            left: vec![0.0; 2 * taps],
+            right: vec![0.0; 2 * taps],
+            index: 0,
+            identical: 0,
+            phase: 0
+        }
+    }
+
+    pub fn sample_rate(self: &Resampler) -> u32 {
+        self.sample_rate
+    }
+
+    /// Feeds in the output of one cycle and returns an output sample pair
+    /// when one is due.
+    pub fn push(self: &mut Resampler, left: i16, right: i16) -> Option<(i16, i16)> {
+        let taps = self.taps;
+        self.left[self.index] = left as f32;
+        self.left[self.index + taps] = left as f32;
+        self.right[self.index] = right as f32;
+        self.right[self.index + taps] = right as f32;
+        self.index = (self.index + 1) % taps;
+        self.identical = if left == right { (self.identical + 1).min(taps) } else { 0 };
+
+        self.phase += self.sample_rate;
+        if self.phase < self.clock_frequency {
+            return None;
+        }
+        self.phase -= self.clock_frequency;
+        // The output instant lies this far before the newest input sample
+        let fraction = self.phase as f64 / self.sample_rate as f64;
+        let row = (fraction * FIR_PHASES as f64).round() as usize;
+        let fir = &self.fir[row * taps..(row + 1) * taps];
+        let left = convolve(fir, &self.left[self.index..self.index + taps]);
+        let right = if self.identical == taps {
+            left
+        } else {
+            convolve(fir, &self.right[self.index..self.index + taps])
+        };
+        Some((left.clamp(-32768.0, 32767.0) as i16, right.clamp(-32768.0, 32767.0) as i16))
+    }
+}
+
+// 16-bit PCM WAV file. The sizes in the header are filled in when finished.
+struct WavWriter {
+    writer: BufWriter<File>,
+    data_size: u32
+}
+
+impl WavWriter {
+    fn create(filename: &str, sample_rate: u32, channels: u16) -> io::Result<WavWriter> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        let block_align = channels * 2;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&[0; 4])?;
+        writer.write_all(b"WAVE")?;
+        writer.write_all(b"fmt ")?;
+        write_u32(&mut writer, 16)?;
+        write_u16(&mut writer, 1)?; // PCM
+        write_u16(&mut writer, channels)?;
+        write_u32(&mut writer, sample_rate)?;
+        write_u32(&mut writer, sample_rate * block_align as u32)?;
+        write_u16(&mut writer, block_align)?;
+        write_u16(&mut writer, 16)?;
+        writer.write_all(b"data")?;
+        writer.write_all(&[0; 4])?;
+        Ok(WavWriter {
+            writer,
+            data_size: 0
+        })
+    }
+
+    fn write_sample(self: &mut WavWriter, value: i16) -> io::Result<()> {
+        write_u16(&mut self.writer, value as u16)?;
+        self.data_size += 2;
+        Ok(())
+    }
+
+    fn finish(mut self: WavWriter) -> io::Result<()> {
+        let data_size = self.data_size;
+        self.writer.seek(SeekFrom::Start(4))?;
+        write_u32(&mut self.writer, 36 + data_size)?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        write_u32(&mut self.writer, data_size)?;
+        self.writer.flush()
+    }
+}
+
+fn write_u16<W: Write>(writer: &mut W, value: u16) -> io::Result<()> {
+    writer.write_all(&[value as u8, (value >> 8) as u8])
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+/// Records the SID output to a stereo WAV file. The output is fed in once
+/// per cycle and resampled, so recordings are reproducible.
+pub struct AudioRecorder {
+    wav: WavWriter,
+    filename: String,
+    resampler: Resampler
+}
+
+impl AudioRecorder {
+    pub fn create(filename: &str, clock_frequency: u32, sample_rate: u32) -> io::Result<AudioRecorder> {
+        Ok(AudioRecorder {
+            wav: WavWriter::create(filename, sample_rate, 2)?,
+            filename: filename.to_string(),
+            resampler: Resampler::new(clock_frequency, sample_rate)
+        })
+    }
+
+    pub fn filename(self: &AudioRecorder) -> &str {
+        &self.filename
+    }
+
+    pub fn push(self: &mut AudioRecorder, left: i16, right: i16) -> io::Result<()> {
+        match self.resampler.push(left, right) {
+            Some((left, right)) => {
+                self.wav.write_sample(left)?;
+                self.wav.write_sample(right)
+            }
+            None => Ok(())
+        }
+    }
+
+    pub fn finish(self: AudioRecorder) -> io::Result<()> {
+        self.wav.finish()
+    }
+}
+
+// Latency bounds of the audio output in milliseconds. The latency grows when
+// the device runs out of samples and shrinks back slowly when it doesn't.
+const MIN_LATENCY: u32 = 40;
+const MAX_LATENCY: u32 = 400;
+const LATENCY_STEP: u32 = 20;
+const FRAMES_BEFORE_SHRINK: u32 = 500;
+// A longer gap between frames means the emulation was paused, e.g. in the
+// debugger, and the device running dry is not an underrun
+const MAX_FRAME_GAP: u64 = 500;
+
+/// Plays the SID output through an SDL audio device. The emulation waits at
+/// the end of each frame while the device has enough samples queued, which
+/// locks the emulation speed to the audio clock.
+pub struct AudioOutput {
+    queue: AudioQueue<i16>,
+    resampler: Resampler,
+    samples: Vec<i16>,
+    // Target amount of queued audio in milliseconds
+    latency: u32,
+    frames_without_underrun: u32,
+    last_frame: Option<Instant>
+}
+
+impl AudioOutput {
+    pub fn new(sdl_context: &sdl2::Sdl, clock_frequency: u32) -> Result<AudioOutput, String> {
+        let audio_subsystem = sdl_context.audio()?;
+        let desired = AudioSpecDesired {
+            freq: Some(48000),
+            channels: Some(2),
+            samples: Some(512)
+        };
+        let queue = audio_subsystem.open_queue::<i16, _>(None, &desired)?;
+        let sample_rate = queue.spec().freq as u32;
+        queue.resume();
+        Ok(AudioOutput {
+            queue,
+            resampler: Resampler::new(clock_frequency, sample_rate),
+            samples: Vec::new(),
+            latency: MIN_LATENCY,
+            frames_without_underrun: 0,
+            last_frame: None
+        })
+    }
+
+    pub fn push(self: &mut AudioOutput, left: i16, right: i16) {
+        if let Some((left, right)) = self.resampler.push(left, right) {
+            self.samples.push(left);
+            self.samples.push(right);
+        }
+    }
+
+    fn queued_milliseconds(self: &AudioOutput) -> u32 {
+        // Four bytes per sample pair
+        let queued_samples = self.queue.size() / 4;
+        queued_samples * 1000 / self.resampler.sample_rate()
+    }
+
+    /// Queues the samples of the finished frame and waits until the device
+    /// has played enough of the queued audio.
+    pub fn end_frame(self: &mut AudioOutput) {
+        let paused = match self.last_frame {
+            Some(last_frame) => last_frame.elapsed() > Duration::from_millis(MAX_FRAME_GAP),
+            None => true
+        };
+        if !paused && self.queue.size() == 0 {
+            self.latency = (self.latency + LATENCY_STEP).min(MAX_LATENCY);
+            self.frames_without_underrun = 0;
+        } else {
+            self.frames_without_underrun += 1;
+            if self.frames_without_underrun >= FRAMES_BEFORE_SHRINK {
+                self.latency = (self.latency - LATENCY_STEP).max(MIN_LATENCY);
+                self.frames_without_underrun = 0;
+            }
+        }
+        self.queue.queue(&self.samples);
+        self.samples.clear();
+
+        while self.queued_milliseconds() > self.latency {
+            thread::sleep(Duration::from_millis(1));
+        }
+        self.last_frame = Some(Instant::now());
+    }
+
+    pub fn sample_rate(self: &AudioOutput) -> u32 {
+        self.resampler.sample_rate()
+    }
+
+    pub fn latency(self: &AudioOutput) -> u32 {
+        self.latency
+    }
+}
@@ -0,0 +1,518 @@
+// Cartridges in CRT images. The header gives the hardware type and the
+// levels of the EXROM and GAME lines at power on, the ROM contents follow
+// in CHIP packets, each with its bank number and load address. Banks are
+// 8K: ROML is seen at $8000-$9FFF, ROMH at $A000-$BFFF in 16K mode and at
+// $E000-$FFFF in Ultimax mode. Bank switched cartridges are controlled
+// through the IO1 and IO2 areas at $DE00 and $DF00.
+//
+// The EasyFlash has 64 banks in two flash chips, one for ROML and one for
+// ROMH, which are programmed by writes in Ultimax mode, and 256 bytes of
+// RAM at $DF00. A jumper pulls GAME low at power on, to boot from ROMH.
+// What is flashed can be written back to the image.
+
+use std::fs::File;
+use std::io::prelude::*;
+
+use am29f040;
+use am29f040::Am29f040;
+
+const SIGNATURE: &[u8] = b"C64 CARTRIDGE   ";
+const HEADER_LENGTH: usize = 0x10;
+const HARDWARE_TYPE: usize = 0x16;
+const EXROM_LINE: usize = 0x18;
+const GAME_LINE: usize = 0x19;
+const NAME: usize = 0x20;
+const NAME_LENGTH: usize = 32;
+const MIN_HEADER_SIZE: usize = 0x40;
+
+const CHIP_SIGNATURE: &[u8] = b"CHIP";
+const CHIP_HEADER_SIZE: usize = 0x10;
+// Offsets in a CHIP packet
+const PACKET_LENGTH: usize = 0x04;
+const BANK: usize = 0x0A;
+const LOAD_ADDRESS: usize = 0x0C;
+const IMAGE_SIZE: usize = 0x0E;
+const FLASH_CHIP: u16 = 2;
+
+pub const BANK_SIZE: usize = 0x2000;
+pub const IO2_START: u16 = 0xDF00;
+const FLASH_BANKS: usize = am29f040::SIZE / BANK_SIZE;
+
+// EasyFlash control register bits
+const EASYFLASH_MODE: u8 = 0x04;
+const EASYFLASH_EXROM: u8 = 0x02;
+const EASYFLASH_GAME: u8 = 0x01;
+const EASYFLASH_RAM_SIZE: usize = 256;
+
+// What an access reads where no chip answers
+pub const OPEN_BUS: u8 = 0xFF;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Hardware {
+    Normal,
+    Ocean,
+    FunPlay,
+    System3,
+    Dinamic,
+    MagicDesk,
+    EasyFlash
+}
+
+impl Hardware {
+    fn from_id(id: u16) -> Option<Hardware> {
+        match id {
+            0 => Some(Hardware::Normal),
+            5 => Some(Hardware::Ocean),
+            7 => Some(Hardware::FunPlay),
+            15 => Some(Hardware::System3),
+            17 => Some(Hardware::Dinamic),
+            19 => Some(Hardware::MagicDesk),
+            32 => Some(Hardware::EasyFlash),
+            _ => None
+        }
+    }
+}
+
+// The ROM of a bank for each window, 8K each, either may be missing
+struct Bank {
+    roml: Option<Vec<u8>>,
+    romh: Option<Vec<u8>>
+}
+
+enum Memory {
+    Rom(Vec<Bank>),
+    // The chips of ROML and ROMH, each holding all banks
+    Flash(Am29f040, Am29f040)
+}
+
+pub struct Cartridge {
+    filename: String,
+    name: String,
+    hardware: Hardware,
+    // Everything up to the first CHIP packet, kept for writing the image
+    header: Vec<u8>,
+    memory: Memory,
+    ram: Vec<u8>,
+    // Levels of the lines at power on, low is active
+    initial_exrom: bool,
+    initial_game: bool,
+    exrom: bool,
+    game: bool,
+    bank: usize
+}
+
+// Numbers in CRT images are big endian
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    (bytes[offset] as u16) << 8 | bytes[offset + 1] as u16
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    (read_u16(bytes, offset) as u32) << 16 | read_u16(bytes, offset + 2) as u32
+}
+
+// Chips smaller than a bank are mirrored, as the address lines they lack
+// are not decoded
+fn fill_bank(data: &[u8]) -> Vec<u8> {
+    (0..BANK_SIZE).map(|offset| data[offset % data.len()]).collect()
+}
+
+pub fn mode_name(exrom: bool, game: bool) -> &'static str {
+    match (exrom, game) {
+        (true, true) => "off",
+        (false, true) => "8K",
+        (false, false) => "16K",
+        (true, false) => "Ultimax"
+    }
+}
+
+impl Cartridge {
+    pub fn load(filename: &str) -> Result<Cartridge, String> {
+        let mut bytes = Vec::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|err| format!("Failed to read {}: {}", filename, err))?;
+        Cartridge::parse(filename, &bytes)
+    }
+
+    pub fn parse(filename: &str, bytes: &[u8]) -> Result<Cartridge, String> {
+        if bytes.len() < MIN_HEADER_SIZE || !bytes.starts_with(SIGNATURE) {
+            return Err(format!("Not a CRT image: {}", filename));
+        }
+        let id = read_u16(bytes, HARDWARE_TYPE);
+        let hardware = Hardware::from_id(id).ok_or(format!("Unsupported cartridge type {}: {}", id, filename))?;
+        let name_bytes = &bytes[NAME..NAME + NAME_LENGTH];
+        let name = String::from_utf8_lossy(&name_bytes[..name_bytes.iter().position(|&c| c == 0).unwrap_or(NAME_LENGTH)]).trim().to_string();
+        // Some images have a header length of 0x20, which is too short
+        let header_length = (read_u32(bytes, HEADER_LENGTH) as usize).max(MIN_HEADER_SIZE);
+        let mut offset = header_length;
+        let mut banks: Vec<Bank> = Vec::new();
+        while offset + CHIP_HEADER_SIZE <= bytes.len() {
+            let packet = &bytes[offset..];
+            if !packet.starts_with(CHIP_SIGNATURE) {
+                return Err(format!("Invalid CHIP packet at offset {} of {}", offset, filename));
+            }
+            let bank = read_u16(packet, BANK) as usize;
+            let load_address = read_u16(packet, LOAD_ADDRESS);
+            let size = read_u16(packet, IMAGE_SIZE) as usize;
+            if size == 0 || CHIP_HEADER_SIZE + size > packet.len() {
+                return Err(format!("CHIP packet at offset {} of {} is truncated", offset, filename));
+            }
+            let data = &packet[CHIP_HEADER_SIZE..CHIP_HEADER_SIZE + size];
+            while banks.len() <= bank {
+                banks.push(Bank { roml: None, romh: None });
+            }
+            match load_address {
+                0x8000 if size > BANK_SIZE => {
+                    // A 16K chip covers both windows
+                    banks[bank].roml = Some(fill_bank(&data[..BANK_SIZE]));
+                    banks[bank].romh = Some(fill_bank(&data[BANK_SIZE..]));
+                }
+                0x8000 | 0x9000 => banks[bank].roml = Some(fill_bank(data)),
+                0xA000 | 0xB000 | 0xE000 | 0xF000 if size <= BANK_SIZE => banks[bank].romh = Some(fill_bank(data)),
+                _ => return Err(format!("Unsupported CHIP packet of {} bytes at 0x{:04X} in {}", size, load_address, filename))
+            }
+            offset += (read_u32(packet, PACKET_LENGTH) as usize).max(CHIP_HEADER_SIZE + size);
+        }
+        let memory = match hardware {
+            Hardware::EasyFlash => {
+                if banks.len() > FLASH_BANKS {
+                    return Err(format!("{} has {} banks, an EasyFlash has {}", filename, banks.len(), FLASH_BANKS));
+                }
+                let mut roml = Am29f040::new();
+                let mut romh = Am29f040::new();
+                for (i, bank) in banks.iter().enumerate() {
+                    if let Some(ref data) = bank.roml {
+                        roml.load(i * BANK_SIZE, data);
+                    }
+                    if let Some(ref data) = bank.romh {
+                        romh.load(i * BANK_SIZE, data);
+                    }
+                }
+                Memory::Flash(roml, romh)
+            }
+            _ if banks.is_empty() => return Err(format!("No CHIP packets in {}", filename)),
+            _ => Memory::Rom(banks)
+        };
+        let exrom = bytes[EXROM_LINE] != 0;
+        let game = bytes[GAME_LINE] != 0;
+        Ok(Cartridge {
+            filename: filename.to_string(),
+            name,
+            hardware,
+            header: bytes[..header_length.min(bytes.len())].to_vec(),
+            memory,
+            ram: if hardware == Hardware::EasyFlash { vec![0; EASYFLASH_RAM_SIZE] } else { Vec::new() },
+            initial_exrom: exrom,
+            initial_game: game,
+            exrom,
+            game,
+            bank: 0
+        })
+    }
+
+    pub fn filename(self: &Cartridge) -> &str {
+        &self.filename
+    }
+
+    /// Switches to bank 0 and sets the lines as at power on.
+    pub fn reset(self: &mut Cartridge) {
+        self.exrom = self.initial_exrom;
+        self.game = self.initial_game;
+        self.bank = 0;
+        if let Memory::Flash(ref mut roml, ref mut romh) = self.memory {
+            roml.reset();
+            romh.reset();
+        }
+    }
+
+    /// Whether the flash has been programmed or erased since the image was
+    /// loaded or saved.
+    pub fn modified(self: &Cartridge) -> bool {
+        match self.memory {
+            Memory::Flash(ref roml, ref romh) => roml.modified() || romh.modified(),
+            Memory::Rom(_) => false
+        }
+    }
+
+    /// Writes the flash back to the image. Erased banks are left out, as
+    /// the tools that make EasyFlash images do.
+    pub fn save(self: &mut Cartridge) -> Result<(), String> {
+        let mut bytes = self.header.clone();
+        match self.memory {
+            Memory::Flash(ref roml, ref romh) => {
+                for bank in 0..FLASH_BANKS {
+                    for &(chip, load_address) in [(roml, 0x8000), (romh, 0xA000)].iter() {
+                        let data = &chip.data()[bank * BANK_SIZE..(bank + 1) * BANK_SIZE];
+                        if data.iter().all(|&byte| byte == 0xFF) {
+                            continue;
+                        }
+                        let length = (CHIP_HEADER_SIZE + BANK_SIZE) as u32;
+                        bytes.extend_from_slice(CHIP_SIGNATURE);
+                        bytes.extend_from_slice(&[(length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8, length as u8]);
+                        for &word in [FLASH_CHIP, bank as u16, load_address, BANK_SIZE as u16].iter() {
+                            bytes.extend_from_slice(&[(word >> 8) as u8, word as u8]);
+                        }
+                        bytes.extend_from_slice(data);
+                    }
+                }
+            }
+            Memory::Rom(_) => return Err(format!("{} has no flash to write back", self.filename))
+        }
+        File::create(&self.filename)
+            .and_then(|mut f| f.write_all(&bytes))
+            .map_err(|err| format!("Failed to write {}: {}", self.filename, err))?;
+        if let Memory::Flash(ref mut roml, ref mut romh) = self.memory {
+            roml.set_saved();
+            romh.set_saved();
+        }
+        Ok(())
+    }
+
+    /// The level of the EXROM line, low selects the cartridge ROM.
+    pub fn exrom(self: &Cartridge) -> bool {
+        self.exrom
+    }
+
+    pub fn game(self: &Cartridge) -> bool {
+        self.game
+    }
+
+    // A bank switched cartridge shows one 8K bank in both windows, an Ocean
+    // cartridge in 16K mode for example. Where the bank has a chip for
+    // each window they are read separately.
+    fn read(self: &Cartridge, addr: u16, low: bool) -> u8 {
+        let offset = addr as usize & (BANK_SIZE - 1);
+        let bank = match self.memory {
+            Memory::Flash(ref roml, ref romh) => return (if low { roml } else { romh }).read(self.bank * BANK_SIZE + offset),
+            Memory::Rom(ref banks) => match banks.get(self.bank) {
+                Some(bank) => bank,
+                None => return OPEN_BUS
+            }
+        };
+        let (own, other) = if low { (&bank.roml, &bank.romh) } else { (&bank.romh, &bank.roml) };
+        match own.as_ref().or(other.as_ref()) {
+            Some(rom) => rom[offset],
+            None => OPEN_BUS
+        }
+    }
+
+    pub fn read_roml(self: &Cartridge, addr: u16) -> u8 {
+        self.read(addr, true)
+    }
+
+    pub fn read_romh(self: &Cartridge, addr: u16) -> u8 {
+        self.read(addr, false)
+    }
+
+    // Only the Ultimax mode lets the writes through to the cartridge
+    fn write(self: &mut Cartridge, addr: u16, value: u8, low: bool) {
+        if let Memory::Flash(ref mut roml, ref mut romh) = self.memory {
+            (if low { roml } else { romh }).write(self.bank * BANK_SIZE + (addr as usize & (BANK_SIZE - 1)), value);
+        }
+    }
+
+    pub fn write_roml(self: &mut Cartridge, addr: u16, value: u8) {
+        self.write(addr, value, true);
+    }
+
+    pub fn write_romh(self: &mut Cartridge, addr: u16, value: u8) {
+        self.write(addr, value, false);
+    }
+
+    /// Reads from IO1 or IO2, None if the cartridge doesn't answer.
+    pub fn read_io(self: &mut Cartridge, addr: u16) -> Option<u8> {
+        match self.hardware {
+            // Reading IO1 selects the bank given by the low bits of the address
+            Hardware::Dinamic if addr < IO2_START => {
+                self.bank = (addr & 0x0F) as usize;
+                None
+            }
+            Hardware::EasyFlash if addr >= IO2_START => Some(self.ram[(addr - IO2_START) as usize]),
+            _ => None
+        }
+    }
+
+    /// Writes to IO1 or IO2, returns false if the cartridge doesn't decode
+    /// the address.
+    pub fn write_io(self: &mut Cartridge, addr: u16, value: u8) -> bool {
+        if addr >= IO2_START {
+            if self.hardware != Hardware::EasyFlash {
+                return false;
+            }
+            self.ram[(addr - IO2_START) as usize] = value;
+            return true;
+        }
+        match self.hardware {
+            Hardware::Normal | Hardware::Dinamic => return false,
+            Hardware::Ocean => self.bank = (value & 0x3F) as usize,
+            Hardware::FunPlay => {
+                // The bank number is scrambled over the data lines
+                self.bank = ((value >> 3) & 0x07 | (value & 0x01) << 3) as usize;
+                match value & 0xC6 {
+                    0x00 => {
+                        self.exrom = false;
+                        self.game = true;
+                    }
+                    0x86 => {
+                        self.exrom = true;
+                        self.game = true;
+                    }
+                    _ => {}
+                }
+            }
+            // The address selects the bank, the value is ignored
+            Hardware::System3 => self.bank = (addr & 0x3F) as usize,
+            Hardware::MagicDesk => {
+                // Bit 7 switches the cartridge off
+                self.bank = (value & 0x7F) as usize;
+                self.exrom = value & 0x80 != 0;
+            }
+            // The bank register and the control register, mirrored across IO1
+            Hardware::EasyFlash if addr & 0x02 == 0 => self.bank = (value & 0x3F) as usize,
+            Hardware::EasyFlash => {
+                self.exrom = value & EASYFLASH_EXROM == 0;
+                // Without the mode bit the boot jumper keeps GAME low
+                self.game = value & EASYFLASH_MODE != 0 && value & EASYFLASH_GAME == 0;
+            }
+        }
+        true
+    }
+
+    pub fn print_status(self: &Cartridge) {
+        let banks = match self.memory {
+            Memory::Rom(ref banks) => banks.len(),
+            Memory::Flash(_, _) => FLASH_BANKS
+        };
+        println!("{}: \"{}\", {:?} with {} banks{}", self.filename, self.name, self.hardware, banks, if self.modified() { ", modified" } else { "" });
+        println!("Bank {}, EXROM {}, GAME {}, {} mode", self.bank, self.exrom as u8, self.game as u8, mode_name(self.exrom, self.game));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A CRT image of the given type and lines with CHIP packets of bank,
+    // load address and contents
+    fn crt(id: u16, exrom: u8, game: u8, chips: &[(u16, u16, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 0x40, 1, 0, (id >> 8) as u8, id as u8, exrom, game, 0, 0, 0, 0, 0, 0]);
+        let mut name = b"TEST CART".to_vec();
+        name.resize(NAME_LENGTH, 0);
+        bytes.extend_from_slice(&name);
+        for &(bank, load_address, ref data) in chips {
+            let length = (CHIP_HEADER_SIZE + data.len()) as u32;
+            bytes.extend_from_slice(CHIP_SIGNATURE);
+            bytes.extend_from_slice(&[(length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8, length as u8]);
+            for &word in [0, bank, load_address, data.len() as u16].iter() {
+                bytes.extend_from_slice(&[(word >> 8) as u8, word as u8]);
+            }
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    // 8K banks at $8000 filled with their bank number
+    fn banked(id: u16, exrom: u8, game: u8, banks: u16) -> Cartridge {
+        let chips: Vec<(u16, u16, Vec<u8>)> = (0..banks).map(|bank| (bank, 0x8000, vec![bank as u8; BANK_SIZE])).collect();
+        Cartridge::parse("test.crt", &crt(id, exrom, game, &chips)).unwrap()
+    }
+
+    #[test]
+    fn parses_the_header() {
+        let cartridge = banked(0, 0, 1, 1);
+        assert_eq!(cartridge.name, "TEST CART");
+        assert_eq!(cartridge.hardware, Hardware::Normal);
+        assert_eq!((cartridge.exrom(), cartridge.game()), (false, true));
+        assert_eq!(cartridge.header.len(), MIN_HEADER_SIZE);
+
+        let mut short_header = crt(0, 0, 0, &[(0, 0x8000, vec![1; BANK_SIZE])]);
+        short_header[HEADER_LENGTH + 3] = 0x20;
+        assert!(Cartridge::parse("test.crt", &short_header).is_ok());
+
+        let mut not_crt = short_header.clone();
+        not_crt[0] = b'X';
+        assert!(Cartridge::parse("test.crt", &not_crt).is_err());
+        assert!(Cartridge::parse("test.crt", &crt(99, 0, 0, &[(0, 0x8000, vec![0; BANK_SIZE])])).err().unwrap().contains("type 99"));
+        assert!(Cartridge::parse("test.crt", &crt(0, 0, 0, &[])).err().unwrap().contains("No CHIP packets"));
+    }
+
+    #[test]
+    fn parses_the_chip_packets() {
+        let mut rom_16k = vec![0x11; BANK_SIZE];
+        rom_16k.extend(vec![0x22; BANK_SIZE]);
+        let chips = [
+            (0, 0x8000, rom_16k),
+            // A 4K chip shows twice in its bank
+            (1, 0x8000, (0..0x1000).map(|i| i as u8).collect()),
+            (1, 0xE000, vec![0x33; BANK_SIZE])
+        ];
+        let mut cartridge = Cartridge::parse("test.crt", &crt(5, 0, 0, &chips)).unwrap();
+        assert_eq!((cartridge.read_roml(0x8000), cartridge.read_romh(0xA000)), (0x11, 0x22));
+        cartridge.write_io(0xDE00, 1);
+        assert_eq!((cartridge.read_roml(0x8001), cartridge.read_roml(0x9001), cartridge.read_romh(0xE000)), (0x01, 0x01, 0x33));
+
+        let mut truncated = crt(0, 0, 0, &chips[..1]);
+        truncated.truncate(truncated.len() - 1);
+        assert!(Cartridge::parse("test.crt", &truncated).err().unwrap().contains("truncated"));
+        let mut bad_packet = crt(0, 0, 0, &chips[..1]);
+        bad_packet[MIN_HEADER_SIZE] = b'X';
+        assert!(Cartridge::parse("test.crt", &bad_packet).err().unwrap().contains("Invalid CHIP packet"));
+        assert!(Cartridge::parse("test.crt", &crt(0, 0, 0, &[(0, 0xC000, vec![0; BANK_SIZE])])).is_err());
+    }
+
+    #[test]
+    fn switches_ocean_banks() {
+        let mut cartridge = banked(5, 0, 0, 16);
+        cartridge.write_io(0xDE00, 0x80 | 9);
+        // A bank shows in both windows in 16K mode
+        assert_eq!((cartridge.read_roml(0x8000), cartridge.read_romh(0xA000)), (9, 9));
+        cartridge.write_io(0xDE00, 20);
+        assert_eq!(cartridge.read_roml(0x8000), OPEN_BUS);
+        assert!(!cartridge.write_io(0xDF00, 0));
+    }
+
+    #[test]
+    fn switches_fun_play_banks_and_lines() {
+        let mut cartridge = banked(7, 0, 1, 16);
+        cartridge.write_io(0xDE00, 0x08);
+        assert_eq!(cartridge.read_roml(0x8000), 1);
+        cartridge.write_io(0xDE00, 0x01);
+        assert_eq!(cartridge.read_roml(0x8000), 8);
+        cartridge.write_io(0xDE00, 0x86);
+        assert_eq!((cartridge.exrom(), cartridge.game()), (true, true));
+        cartridge.write_io(0xDE00, 0x00);
+        assert_eq!((cartridge.exrom(), cartridge.game(), cartridge.read_roml(0x8000)), (false, true, 0));
+    }
+
+    #[test]
+    fn switches_system_3_banks_by_address() {
+        let mut cartridge = banked(15, 0, 1, 64);
+        cartridge.write_io(0xDE05, 0xFF);
+        assert_eq!(cartridge.read_roml(0x8000), 5);
+        cartridge.write_io(0xDE3F, 0);
+        assert_eq!(cartridge.read_roml(0x8000), 63);
+    }
+
+    #[test]
+    fn switches_dinamic_banks_by_reading() {
+        let mut cartridge = banked(17, 0, 1, 16);
+        assert!(!cartridge.write_io(0xDE03, 3));
+        assert_eq!(cartridge.read_roml(0x8000), 0);
+        assert_eq!(cartridge.read_io(0xDE03), None);
+        assert_eq!(cartridge.read_roml(0x8000), 3);
+        cartridge.read_io(0xDF0F);
+        assert_eq!(cartridge.read_roml(0x8000), 3);
+    }
+
+    #[test]
+    fn switches_magic_desk_banks_and_off() {
+        let mut cartridge = banked(19, 0, 1, 64);
+        cartridge.write_io(0xDE00, 42);
+        assert_eq!((cartridge.exrom(), cartridge.read_roml(0x8000)), (false, 42));
+        cartridge.write_io(0xDE00, 0x80);
+        assert!(cartridge.exrom());
+        cartridge.reset();
+        assert_eq!((cartridge.exrom(), cartridge.game(), cartridge.read_roml(0x8000)), (false, true, 0));
+    }
+}
@@ -0,0 +1,888 @@
+// Implementation shared by CIA1 and CIA2. Registers are addressed by the low
+// nibble of the address, so the chips are mirrored throughout their pages.
+
+use snapshot::{Reader, Writer};
+
+bitflags! {
+    struct ICS: u8 {
+        const TIMER_A_UNDERFLOW_INTERRUPT = 0b0000_0001;
+        const TIMER_B_UNDERFLOW_INTERRUPT = 0b0000_0010;
+        const TOD_ALARM_INTERRUPT         = 0b0000_0100;
+        const SSR_RECV_SENT_INTERRUPT     = 0b0000_1000;
+        const FLAG_PIN_POS_EDGE_INTERRUPT = 0b0001_0000;
+    }
+}
+
+bitflags! {
+    struct TACR: u8 {
+        const START_TIMER                    = 0b0000_0001;
+        const INDICATE_UNDERFLOW_ON_B6       = 0b0000_0010;
+        const GEN_POS_EDGE_ON_B6_ON_UNDEFLOW = 0b0000_0100;
+        const STOP_ON_UNDERFLOW              = 0b0000_1000;
+        const LOAD_START_VALUE               = 0b0001_0000;
+        const COUNT_CNT_POS_EDGES            = 0b0010_0000;
+        const SSR_OUT                        = 0b0100_0000;
+        const TOD_SPEED                      = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    struct TBCR: u8 {
+        const START_TIMER                    = 0b0000_0001;
+        const INDICATE_UNDERFLOW_ON_B7       = 0b0000_0010;
+        const GEN_POS_EDGE_ON_B7_ON_UNDEFLOW = 0b0000_0100;
+        const STOP_ON_UNDERFLOW              = 0b0000_1000;
+        const LOAD_START_VALUE               = 0b0001_0000;
+        const COUNT_CNT_POS_EDGES            = 0b0010_0000;
+        const COUNT_TIMER_A_UNDERFLOWS       = 0b0100_0000;
+        const WRITE_TOD_ALARM                = 0b1000_0000;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CiaModel {
+    /// The original 6526. Its interrupt output goes active one cycle after
+    /// the interrupt condition, reading the ICR in the cycle timer B
+    /// underflows loses the timer B interrupt, and the TOD alarm only
+    /// triggers when the clock advances.
+    Mos6526,
+    /// The 6526A and the 8521 of later boards. The interrupt output follows
+    /// the interrupt condition immediately and the TOD alarm also triggers
+    /// when writing the clock or the alarm makes them equal.
+    Mos6526A
+}
+
+struct Timer {
+    counter: u16,
+    latch: u16,
+    // State of the PB6/PB7 output in toggle mode
+    toggle: bool,
+    // Set for the cycle following an underflow, for the PB6/PB7 output in pulse mode
+    pulse: bool
+}
+
+impl Timer {
+    fn new() -> Timer {
+        Timer {
+            counter: 0xFFFF,
+            latch: 0xFFFF,
+            toggle: false,
+            pulse: false
+        }
+    }
+
+    // Returns true on underflow
+    fn count(self: &mut Timer) -> bool {
+        match self.counter.checked_sub(1) {
+            Some(result) => {
+                self.counter = result;
+                false
+            }
+            None => {
+                self.counter = self.latch;
+                self.toggle = !self.toggle;
+                self.pulse = true;
+                true
+            }
+        }
+    }
+
+    fn write_latch_lo(self: &mut Timer, value: u8) {
+        self.latch = (self.latch & 0xFF00) | value as u16;
+    }
+
+    fn write_latch_hi(self: &mut Timer, value: u8, running: bool) {
+        self.latch = (self.latch & 0x00FF) | ((value as u16) << 8);
+        if !running {
+            self.counter = self.latch;
+        }
+    }
+
+    fn port_bit(self: &Timer, toggle_mode: bool) -> bool {
+        if toggle_mode { self.toggle } else { self.pulse }
+    }
+
+    fn save_state(self: &Timer, writer: &mut Writer) {
+        writer.write_u16(self.counter);
+        writer.write_u16(self.latch);
+        writer.write_bool(self.toggle);
+        writer.write_bool(self.pulse);
+    }
+
+    fn load_state(self: &mut Timer, reader: &mut Reader) -> Result<(), String> {
+        self.counter = reader.read_u16()?;
+        self.latch = reader.read_u16()?;
+        self.toggle = reader.read_bool()?;
+        self.pulse = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Time {
+    tenths: u8,
+    seconds: u8,
+    minutes: u8,
+    // BCD 1-12, bit 7 is the PM flag
+    hours: u8
+}
+
+fn bcd_increment(value: u8) -> u8 {
+    if value & 0x0F == 0x09 {
+        (value & 0xF0) + 0x10
+    } else {
+        value + 1
+    }
+}
+
+impl Time {
+    fn new() -> Time {
+        Time {
+            tenths: 0,
+            seconds: 0,
+            minutes: 0,
+            hours: 0x01
+        }
+    }
+
+    fn save_state(self: &Time, writer: &mut Writer) {
+        writer.write_bytes(&[self.tenths, self.seconds, self.minutes, self.hours]);
+    }
+
+    fn load_state(self: &mut Time, reader: &mut Reader) -> Result<(), String> {
+        self.tenths = reader.read_u8()?;
+        self.seconds = reader.read_u8()?;
+        self.minutes = reader.read_u8()?;
+        self.hours = reader.read_u8()?;
+        Ok(())
+    }
+
+    fn advance(self: &mut Time) {
+        if self.tenths < 9 {
+            self.tenths += 1;
+            return;
+        }
+        self.tenths = 0;
+        self.seconds = bcd_increment(self.seconds);
+        if self.seconds == 0x60 {
+            self.seconds = 0;
+        } else {
+            return;
+        }
+        self.minutes = bcd_increment(self.minutes);
+        if self.minutes == 0x60 {
+            self.minutes = 0;
+        } else {
+            return;
+        }
+        let pm = self.hours & 0x80;
+        let hours = self.hours & 0x1F;
+        self.hours = match hours {
+            0x11 => 0x12 | (pm ^ 0x80),
+            0x12 => 0x01 | pm,
+            _ => bcd_increment(hours) | pm
+        };
+    }
+}
+
+struct Tod {
+    time: Time,
+    alarm: Time,
+    // Snapshot returned while reading the clock, taken when reading hours
+    latched: Option<Time>,
+    // Writing hours stops the clock until tenths are written
+    running: bool,
+    // CPU cycles between two pulses of the mains frequency
+    pulse_cycles: u32,
+    cycles: u32,
+    pulses: u8
+}
+
+impl Tod {
+    fn new(pulse_cycles: u32) -> Tod {
+        Tod {
+            time: Time::new(),
+            alarm: Time::new(),
+            latched: None,
+            running: true,
+            pulse_cycles,
+            cycles: 0,
+            pulses: 0
+        }
+    }
+
+    fn save_state(self: &Tod, writer: &mut Writer) {
+        self.time.save_state(writer);
+        self.alarm.save_state(writer);
+        writer.write_bool(self.latched.is_some());
+        self.latched.unwrap_or(self.time).save_state(writer);
+        writer.write_bool(self.running);
+        writer.write_u32(self.cycles);
+        writer.write_u8(self.pulses);
+    }
+
+    fn load_state(self: &mut Tod, reader: &mut Reader) -> Result<(), String> {
+        self.time.load_state(reader)?;
+        self.alarm.load_state(reader)?;
+        let latched = reader.read_bool()?;
+        let mut time = Time::new();
+        time.load_state(reader)?;
+        self.latched = if latched { Some(time) } else { None };
+        self.running = reader.read_bool()?;
+        self.cycles = reader.read_u32()?;
+        self.pulses = reader.read_u8()?;
+        Ok(())
+    }
+
+    // Returns true when the alarm time is reached
+    fn tick(self: &mut Tod, fifty_hz: bool) -> bool {
+        self.cycles += 1;
+        if self.cycles < self.pulse_cycles {
+            return false;
+        }
+        self.cycles = 0;
+        self.pulses += 1;
+        if self.pulses < if fifty_hz { 5 } else { 6 } {
+            return false;
+        }
+        self.pulses = 0;
+        if self.running {
+            self.time.advance();
+            self.time == self.alarm
+        } else {
+            false
+        }
+    }
+
+    fn read(self: &mut Tod, reg: u16) -> u8 {
+        let time = self.latched.unwrap_or(self.time);
+        match reg {
+            0x08 => {
+                self.latched = None;
+                time.tenths
+            }
+            0x09 => time.seconds,
+            0x0A => time.minutes,
+            _ => {
+                self.latched = Some(time);
+                time.hours
+            }
+        }
+    }
+
+    // Returns true if the write makes the time equal to the alarm time
+    fn write(self: &mut Tod, reg: u16, value: u8, alarm: bool) -> bool {
+        let target = if alarm { &mut self.alarm } else { &mut self.time };
+        match reg {
+            0x08 => {
+                target.tenths = value & 0x0F;
+                if !alarm {
+                    self.running = true;
+                }
+            }
+            0x09 => {
+                target.seconds = value & 0x7F;
+            }
+            0x0A => {
+                target.minutes = value & 0x7F;
+            }
+            _ => {
+                // Writing 12 flips the AM/PM flag
+                target.hours = if value & 0x1F == 0x12 { (value & 0x9F) ^ 0x80 } else { value & 0x9F };
+                if !alarm {
+                    self.running = false;
+                }
+            }
+        }
+        self.time == self.alarm
+    }
+}
+
+struct SerialPort {
+    shift_register: u8,
+    // Bits shifted in or out of the shift register so far
+    bits: u8,
+    // Output mode: the SDR holds a byte waiting to be shifted out
+    loaded: bool,
+    transmitting: bool,
+    // Pin levels, driven by the CIA in output mode and from outside in input mode
+    cnt: bool,
+    sp: bool,
+    // A positive CNT edge seen since the last tick, for the timers
+    cnt_edge: bool
+}
+
+impl SerialPort {
+    fn new() -> SerialPort {
+        SerialPort {
+            shift_register: 0,
+            bits: 0,
+            loaded: false,
+            transmitting: false,
+            cnt: true,
+            sp: true,
+            cnt_edge: false
+        }
+    }
+
+    fn save_state(self: &SerialPort, writer: &mut Writer) {
+        writer.write_u8(self.shift_register);
+        writer.write_u8(self.bits);
+        writer.write_bool(self.loaded);
+        writer.write_bool(self.transmitting);
+        writer.write_bool(self.cnt);
+        writer.write_bool(self.sp);
+        writer.write_bool(self.cnt_edge);
+    }
+
+    fn load_state(self: &mut SerialPort, reader: &mut Reader) -> Result<(), String> {
+        self.shift_register = reader.read_u8()?;
+        self.bits = reader.read_u8()?;
+        self.loaded = reader.read_bool()?;
+        self.transmitting = reader.read_bool()?;
+        self.cnt = reader.read_bool()?;
+        self.sp = reader.read_bool()?;
+        self.cnt_edge = reader.read_bool()?;
+        Ok(())
+    }
+
+    // Output mode, called on timer A underflow. Every underflow toggles CNT,
+    // so shifting out a byte takes 16 underflows. Returns true when the last
+    // bit of a byte has been sent.
+    fn timer_a_underflow(self: &mut SerialPort, sdr: u8) -> bool {
+        if !self.transmitting {
+            if !self.loaded {
+                return false;
+            }
+            self.shift_register = sdr;
+            self.loaded = false;
+            self.transmitting = true;
+            self.bits = 0;
+        }
+        self.cnt = !self.cnt;
+        if !self.cnt {
+            self.sp = self.shift_register & 0x80 > 0;
+            self.shift_register <<= 1;
+            false
+        } else {
+            self.bits += 1;
+            if self.bits < 8 {
+                return false;
+            }
+            if self.loaded {
+                self.shift_register = sdr;
+                self.loaded = false;
+                self.bits = 0;
+            } else {
+                self.transmitting = false;
+            }
+            true
+        }
+    }
+
+    // Input mode, called on a positive CNT edge. Returns the received byte
+    // once 8 bits have been shifted in.
+    fn shift_in(self: &mut SerialPort) -> Option<u8> {
+        self.shift_register = (self.shift_register << 1) | self.sp as u8;
+        self.bits += 1;
+        if self.bits == 8 {
+            self.bits = 0;
+            Some(self.shift_register)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Cia {
+    model: CiaModel,
+    port_a: u8,
+    port_b: u8,
+    port_a_ddr: u8,
+    port_b_ddr: u8,
+    // Levels driven onto the port pins by external devices
+    port_a_input: u8,
+    port_b_input: u8,
+    timer_a: Timer,
+    timer_b: Timer,
+    tod: Tod,
+    sdr: u8,
+    serial_port: SerialPort,
+    // Level of the FLAG input
+    flag: bool,
+    interrupt_mask: ICS,
+    interrupt_data: ICS,
+    // Level of the IRQ/NMI output
+    interrupt_line: bool,
+    // Interrupt condition seen in the previous cycle, for the delayed output of the 6526
+    interrupt_pending: bool,
+    timer_b_underflow: bool,
+    tacr: TACR,
+    tbcr: TBCR
+}
+
+impl Cia {
+    /// A chip whose TOD input pulses every given number of cycles.
+    pub fn new(model: CiaModel, tod_pulse_cycles: u32) -> Cia {
+        Cia {
+            model,
+            port_a: 0,
+            port_b: 0,
+            port_a_ddr: 0,
+            port_b_ddr: 0,
+            port_a_input: 0xFF,
+            port_b_input: 0xFF,
+            timer_a: Timer::new(),
+            timer_b: Timer::new(),
+            tod: Tod::new(tod_pulse_cycles),
+            sdr: 0,
+            serial_port: SerialPort::new(),
+            flag: true,
+            interrupt_mask: ICS { bits: 0 },
+            interrupt_data: ICS { bits: 0 },
+            interrupt_line: false,
+            interrupt_pending: false,
+            timer_b_underflow: false,
+            tacr: TACR { bits: 0 },
+            tbcr: TBCR { bits: 0 }
+        }
+    }
+
+    pub fn model(self: &Cia) -> CiaModel {
+        self.model
+    }
+
+    pub fn set_model(self: &mut Cia, model: CiaModel) {
+        self.model = model;
+    }
+
+    /// Saves the model, the ports, the timers, the clock, the serial port
+    /// and the interrupt state.
+    pub fn save_state(self: &Cia, writer: &mut Writer) {
+        writer.write_u8(match self.model {
+            CiaModel::Mos6526 => 0,
+            CiaModel::Mos6526A => 1
+        });
+        writer.write_bytes(&[self.port_a, self.port_b, self.port_a_ddr, self.port_b_ddr, self.port_a_input, self.port_b_input]);
+        self.timer_a.save_state(writer);
+        self.timer_b.save_state(writer);
+        self.tod.save_state(writer);
+        writer.write_u8(self.sdr);
+        self.serial_port.save_state(writer);
+        writer.write_bool(self.flag);
+        writer.write_u8(self.interrupt_mask.bits);
+        writer.write_u8(self.interrupt_data.bits);
+        writer.write_bool(self.interrupt_line);
+        writer.write_bool(self.interrupt_pending);
+        writer.write_bool(self.timer_b_underflow);
+        writer.write_u8(self.tacr.bits);
+        writer.write_u8(self.tbcr.bits);
+    }
+
+    /// Leaves the chip as it was if the state can't be read.
+    pub fn load_state(self: &mut Cia, reader: &mut Reader) -> Result<(), String> {
+        let model = match reader.read_u8()? {
+            0 => CiaModel::Mos6526,
+            _ => CiaModel::Mos6526A
+        };
+        let mut cia = Cia::new(model, self.tod.pulse_cycles);
+        cia.port_a = reader.read_u8()?;
+        cia.port_b = reader.read_u8()?;
+        cia.port_a_ddr = reader.read_u8()?;
+        cia.port_b_ddr = reader.read_u8()?;
+        cia.port_a_input = reader.read_u8()?;
+        cia.port_b_input = reader.read_u8()?;
+        cia.timer_a.load_state(reader)?;
+        cia.timer_b.load_state(reader)?;
+        cia.tod.load_state(reader)?;
+        cia.sdr = reader.read_u8()?;
+        cia.serial_port.load_state(reader)?;
+        cia.flag = reader.read_bool()?;
+        cia.interrupt_mask = ICS::from_bits_truncate(reader.read_u8()?);
+        cia.interrupt_data = ICS::from_bits_truncate(reader.read_u8()?);
+        cia.interrupt_line = reader.read_bool()?;
+        cia.interrupt_pending = reader.read_bool()?;
+        cia.timer_b_underflow = reader.read_bool()?;
+        cia.tacr = TACR::from_bits_truncate(reader.read_u8()?);
+        cia.tbcr = TBCR::from_bits_truncate(reader.read_u8()?);
+        *self = cia;
+        Ok(())
+    }
+
+    /// Loads a CIA module of a VICE snapshot: the ports and their data
+    /// directions, the timer counters, the clock, the SDR, the interrupt
+    /// mask, the control registers, the timer latches, the interrupt flags,
+    /// the PB6/PB7 toggles, the bits left to shift and the alarm. The model
+    /// stays as it is, VICE keeps it in its settings.
+    pub fn load_vice_state(self: &mut Cia, reader: &mut Reader) -> Result<(), String> {
+        let mut cia = Cia::new(self.model, self.tod.pulse_cycles);
+        cia.port_a = reader.read_u8()?;
+        cia.port_b = reader.read_u8()?;
+        cia.port_a_ddr = reader.read_u8()?;
+        cia.port_b_ddr = reader.read_u8()?;
+        cia.timer_a.counter = reader.read_u16()?;
+        cia.timer_b.counter = reader.read_u16()?;
+        cia.tod.time.load_state(reader)?;
+        cia.sdr = reader.read_u8()?;
+        cia.interrupt_mask = ICS::from_bits_truncate(reader.read_u8()?);
+        cia.tacr = TACR::from_bits_truncate(reader.read_u8()? & !TACR::LOAD_START_VALUE.bits);
+        cia.tbcr = TBCR::from_bits_truncate(reader.read_u8()? & !TBCR::LOAD_START_VALUE.bits);
+        cia.timer_a.latch = reader.read_u16()?;
+        cia.timer_b.latch = reader.read_u16()?;
+        let interrupt_data = reader.read_u8()?;
+        cia.interrupt_data = ICS::from_bits_truncate(interrupt_data);
+        cia.interrupt_line = interrupt_data & 0x80 > 0;
+        let toggles = reader.read_u8()?;
+        cia.timer_a.toggle = toggles & 0x40 > 0;
+        cia.timer_b.toggle = toggles & 0x80 > 0;
+        reader.skip(1)?;
+        cia.tod.alarm.load_state(reader)?;
+        *self = cia;
+        Ok(())
+    }
+
+    pub fn set_port_a_input(self: &mut Cia, value: u8) {
+        self.port_a_input = value;
+    }
+
+    pub fn set_port_b_input(self: &mut Cia, value: u8) {
+        self.port_b_input = value;
+    }
+
+    /// Levels of the port A pins. Input pins float high, outputs and
+    /// external devices can pull them low.
+    pub fn port_a_pins(self: &Cia) -> u8 {
+        (self.port_a | !self.port_a_ddr) & self.port_a_input
+    }
+
+    /// Levels of the port B pins, with PB6 and PB7 replaced by the timer
+    /// outputs when enabled.
+    pub fn port_b_pins(self: &Cia) -> u8 {
+        let mut value = (self.port_b | !self.port_b_ddr) & self.port_b_input;
+        if self.tacr.contains(TACR::INDICATE_UNDERFLOW_ON_B6) {
+            let bit = self.timer_a.port_bit(self.tacr.contains(TACR::GEN_POS_EDGE_ON_B6_ON_UNDEFLOW));
+            value = (value & 0b1011_1111) | if bit { 0b0100_0000 } else { 0 };
+        }
+        if self.tbcr.contains(TBCR::INDICATE_UNDERFLOW_ON_B7) {
+            let bit = self.timer_b.port_bit(self.tbcr.contains(TBCR::GEN_POS_EDGE_ON_B7_ON_UNDEFLOW));
+            value = (value & 0b0111_1111) | if bit { 0b1000_0000 } else { 0 };
+        }
+        value
+    }
+
+    /// Level of the CNT pin.
+    pub fn cnt(self: &Cia) -> bool {
+        self.serial_port.cnt
+    }
+
+    /// Level of the SP pin.
+    pub fn sp(self: &Cia) -> bool {
+        self.serial_port.sp
+    }
+
+    /// Drives the CNT pin from outside. Ignored while the serial port is in
+    /// output mode, as the CIA drives CNT itself.
+    pub fn set_cnt(self: &mut Cia, level: bool) {
+        if self.tacr.contains(TACR::SSR_OUT) {
+            return;
+        }
+        let positive_edge = level && !self.serial_port.cnt;
+        self.serial_port.cnt = level;
+        if positive_edge {
+            self.serial_port.cnt_edge = true;
+            if let Some(value) = self.serial_port.shift_in() {
+                self.sdr = value;
+                self.interrupt_data.insert(ICS::SSR_RECV_SENT_INTERRUPT);
+            }
+        }
+    }
+
+    /// Drives the SP pin from outside. Ignored in output mode.
+    pub fn set_sp(self: &mut Cia, level: bool) {
+        if !self.tacr.contains(TACR::SSR_OUT) {
+            self.serial_port.sp = level;
+        }
+    }
+
+    /// Drives the FLAG pin from outside. A falling edge raises the FLAG
+    /// interrupt.
+    pub fn set_flag(self: &mut Cia, level: bool) {
+        if !level && self.flag {
+            self.interrupt_data.insert(ICS::FLAG_PIN_POS_EDGE_INTERRUPT);
+        }
+        self.flag = level;
+    }
+
+    fn update_interrupt_line(self: &mut Cia) {
+        let condition = self.interrupt_data.intersects(self.interrupt_mask);
+        match self.model {
+            CiaModel::Mos6526 => {
+                if self.interrupt_pending {
+                    self.interrupt_line = true;
+                }
+                self.interrupt_pending = condition && !self.interrupt_line;
+            }
+            CiaModel::Mos6526A => {
+                if condition {
+                    self.interrupt_line = true;
+                }
+            }
+        }
+    }
+
+    pub fn write(self: &mut Cia, addr: u16, value: u8) {
+        let reg = addr & 0x0F;
+        match reg {
+            0x00 => {
+                self.port_a = value;
+            }
+            0x01 => {
+                self.port_b = value;
+            }
+            0x02 => {
+                self.port_a_ddr = value;
+            }
+            0x03 => {
+                self.port_b_ddr = value;
+            }
+            0x04 => {
+                self.timer_a.write_latch_lo(value);
+            }
+            0x05 => {
+                let running = self.tacr.contains(TACR::START_TIMER);
+                self.timer_a.write_latch_hi(value, running);
+            }
+            0x06 => {
+                self.timer_b.write_latch_lo(value);
+            }
+            0x07 => {
+                let running = self.tbcr.contains(TBCR::START_TIMER);
+                self.timer_b.write_latch_hi(value, running);
+            }
+            0x08 | 0x09 | 0x0A | 0x0B => {
+                let alarm = self.tbcr.contains(TBCR::WRITE_TOD_ALARM);
+                if self.tod.write(reg, value, alarm) && self.model == CiaModel::Mos6526A {
+                    self.interrupt_data.insert(ICS::TOD_ALARM_INTERRUPT);
+                }
+            }
+            0x0C => {
+                self.sdr = value;
+                if self.tacr.contains(TACR::SSR_OUT) {
+                    self.serial_port.loaded = true;
+                }
+            }
+            0x0D => {
+                let bits = ICS::from_bits_truncate(value);
+                if value & 0x80 > 0 {
+                    self.interrupt_mask.insert(bits);
+                } else {
+                    self.interrupt_mask.remove(bits);
+                }
+            }
+            0x0E => {
+                if value & TACR::START_TIMER.bits > 0 && !self.tacr.contains(TACR::START_TIMER) {
+                    self.timer_a.toggle = true;
+                }
+                if (value & TACR::SSR_OUT.bits > 0) != self.tacr.contains(TACR::SSR_OUT) {
+                    // Switching direction aborts a transfer and releases the pins
+                    self.serial_port.bits = 0;
+                    self.serial_port.loaded = false;
+                    self.serial_port.transmitting = false;
+                    self.serial_port.cnt = true;
+                    self.serial_port.sp = true;
+                }
+                self.tacr.bits = value & !TACR::LOAD_START_VALUE.bits;
+                if value & TACR::LOAD_START_VALUE.bits > 0 {
+                    self.timer_a.counter = self.timer_a.latch;
+                }
+            }
+            _ => {
+                if value & TBCR::START_TIMER.bits > 0 && !self.tbcr.contains(TBCR::START_TIMER) {
+                    self.timer_b.toggle = true;
+                }
+                self.tbcr.bits = value & !TBCR::LOAD_START_VALUE.bits;
+                if value & TBCR::LOAD_START_VALUE.bits > 0 {
+                    self.timer_b.counter = self.timer_b.latch;
+                }
+            }
+        }
+    }
+
+    pub fn read(self: &mut Cia, addr: u16) -> u8 {
+        let reg = addr & 0x0F;
+        match reg {
+            0x00 => {
+                self.port_a_pins()
+            }
+            0x01 => {
+                self.port_b_pins()
+            }
+            0x02 => {
+                self.port_a_ddr
+            }
+            0x03 => {
+                self.port_b_ddr
+            }
+            0x04 => {
+                (self.timer_a.counter & 0x00FF) as u8
+            }
+            0x05 => {
+                ((self.timer_a.counter & 0xFF00) >> 8) as u8
+            }
+            0x06 => {
+                (self.timer_b.counter & 0x00FF) as u8
+            }
+            0x07 => {
+                ((self.timer_b.counter & 0xFF00) >> 8) as u8
+            }
+            0x08 | 0x09 | 0x0A | 0x0B => {
+                self.tod.read(reg)
+            }
+            0x0C => {
+                self.sdr
+            }
+            0x0D => {
+                if self.model == CiaModel::Mos6526 && self.timer_b_underflow {
+                    self.interrupt_data.remove(ICS::TIMER_B_UNDERFLOW_INTERRUPT);
+                }
+                let result = self.interrupt_data.bits | if self.interrupt_line { 0x80 } else { 0 };
+                self.interrupt_data = ICS { bits: 0 };
+                self.interrupt_line = false;
+                self.interrupt_pending = false;
+                result
+            }
+            0x0E => {
+                self.tacr.bits
+            }
+            _ => {
+                self.tbcr.bits
+            }
+        }
+    }
+
+    /// Advances the chip by one cycle. Returns whether the interrupt output
+    /// is asserted.
+    pub fn tick(self: &mut Cia) -> bool {
+        self.timer_a.pulse = false;
+        self.timer_b.pulse = false;
+        self.timer_b_underflow = false;
+
+        let cnt_edge = self.serial_port.cnt_edge;
+        self.serial_port.cnt_edge = false;
+
+        let timer_a_input = !self.tacr.contains(TACR::COUNT_CNT_POS_EDGES) || cnt_edge;
+        let timer_a_underflow = self.tacr.contains(TACR::START_TIMER) && timer_a_input && self.timer_a.count();
+        if timer_a_underflow {
+            if self.tacr.contains(TACR::STOP_ON_UNDERFLOW) {
+                self.tacr.remove(TACR::START_TIMER);
+            }
+            self.interrupt_data.insert(ICS::TIMER_A_UNDERFLOW_INTERRUPT);
+            if self.tacr.contains(TACR::SSR_OUT) && self.serial_port.timer_a_underflow(self.sdr) {
+                self.interrupt_data.insert(ICS::SSR_RECV_SENT_INTERRUPT);
+            }
+        }
+
+        let timer_b_input = match (self.tbcr.contains(TBCR::COUNT_TIMER_A_UNDERFLOWS), self.tbcr.contains(TBCR::COUNT_CNT_POS_EDGES)) {
+            (false, false) => true,
+            (false, true) => cnt_edge,
+            (true, false) => timer_a_underflow,
+            (true, true) => timer_a_underflow && self.serial_port.cnt
+        };
+        if self.tbcr.contains(TBCR::START_TIMER) && timer_b_input && self.timer_b.count() {
+            if self.tbcr.contains(TBCR::STOP_ON_UNDERFLOW) {
+                self.tbcr.remove(TBCR::START_TIMER);
+            }
+            self.interrupt_data.insert(ICS::TIMER_B_UNDERFLOW_INTERRUPT);
+            self.timer_b_underflow = true;
+        }
+
+        if self.tod.tick(self.tacr.contains(TACR::TOD_SPEED)) {
+            self.interrupt_data.insert(ICS::TOD_ALARM_INTERRUPT);
+        }
+
+        self.update_interrupt_line();
+        self.interrupt_line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vic_ii::{NTSC, PAL};
+
+    // Cycles from starting timer A until the interrupt output goes active
+    fn timer_a_irq_cycles(model: CiaModel) -> u32 {
+        let mut cia = Cia::new(model, PAL.tod_pulse_cycles());
+        cia.write(0x04, 0x02);
+        cia.write(0x05, 0x00);
+        cia.write(0x0D, 0x81);
+        cia.write(0x0E, 0x11);
+        let mut cycles = 1;
+        while !cia.tick() {
+            cycles += 1;
+            assert!(cycles < 10, "no interrupt");
+        }
+        cycles
+    }
+
+    #[test]
+    fn timer_interrupt_comes_a_cycle_later_on_6526() {
+        assert_eq!(timer_a_irq_cycles(CiaModel::Mos6526A), 3);
+        assert_eq!(timer_a_irq_cycles(CiaModel::Mos6526), 4);
+    }
+
+    // The interrupt flags read in the cycle timer B underflows
+    fn icr_at_timer_b_underflow(model: CiaModel) -> u8 {
+        let mut cia = Cia::new(model, PAL.tod_pulse_cycles());
+        cia.write(0x06, 0x03);
+        cia.write(0x07, 0x00);
+        cia.write(0x0F, 0x01);
+        while !cia.timer_b_underflow {
+            cia.tick();
+        }
+        cia.read(0x0D)
+    }
+
+    #[test]
+    fn reading_icr_at_timer_b_underflow_loses_the_interrupt_on_6526() {
+        assert_eq!(icr_at_timer_b_underflow(CiaModel::Mos6526) & 0x02, 0);
+        assert_eq!(icr_at_timer_b_underflow(CiaModel::Mos6526A) & 0x02, 0x02);
+    }
+
+    // Writes an alarm of 1 AM, the time the clock starts at
+    fn icr_after_alarm_write(model: CiaModel) -> u8 {
+        let mut cia = Cia::new(model, PAL.tod_pulse_cycles());
+        cia.write(0x0F, 0x80);
+        cia.write(0x0B, 0x02);
+        assert_eq!(cia.read(0x0D), 0);
+        cia.write(0x0B, 0x01);
+        cia.read(0x0D)
+    }
+
+    #[test]
+    fn alarm_write_matching_the_time_interrupts_on_6526a() {
+        assert_eq!(icr_after_alarm_write(CiaModel::Mos6526A), 0x04);
+        assert_eq!(icr_after_alarm_write(CiaModel::Mos6526), 0);
+    }
+
+    // Cycles until the TOD clock shows the first tenth of a second, with
+    // the 50 Hz bit of control register A as given
+    fn cycles_to_first_tenth(tod_pulse_cycles: u32, fifty_hz: bool) -> u32 {
+        let mut cia = Cia::new(CiaModel::Mos6526A, tod_pulse_cycles);
+        cia.write(0x0E, if fifty_hz { 0x80 } else { 0x00 });
+        let mut cycles = 0;
+        while cia.read(0x08) == 0 {
+            cia.tick();
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn tod_counts_the_mains_frequency_of_the_machine() {
+        assert_eq!(PAL.tod_pulse_cycles(), 19705);
+        assert_eq!(NTSC.tod_pulse_cycles(), 17045);
+        assert_eq!(cycles_to_first_tenth(PAL.tod_pulse_cycles(), true), 5 * 19705);
+        assert_eq!(cycles_to_first_tenth(NTSC.tod_pulse_cycles(), false), 6 * 17045);
+    }
+}
@@ -1,31 +1,8 @@
-bitflags! {
-    struct ICS: u8 {
-        const TIMER_A_UNDERFLOW_INTERRUPT = 0b0000_0001;
-        const TIMER_B_UNDERFLOW_INTERRUPT = 0b0000_0010;
-        const TOD_ALARM_INTERRUPT         = 0b0000_0100;
-        const SSR_RECV_SENT_INTERRUPT     = 0b0000_1000;
-        const FLAG_PIN_POS_EDGE_INTERRUPT = 0b0001_0000;
-    }
-}
-
-bitflags! {
-    struct TACR: u8 {
-        const START_TIMER                    = 0b0000_0001;
-        const INDICATE_UNDERFLOW_ON_B6       = 0b0000_0010;
-        const GEN_POS_EDGE_ON_B6_ON_UNDEFLOW = 0b0000_0100;
-        const STOP_ON_UNDERFLOW              = 0b0000_1000;
-        const LOAD_START_VALUE               = 0b0001_0000;
-        const COUNT_CNT_POS_EDGES            = 0b0010_0000;
-        const SSR_OUT                        = 0b0100_0000;
-        const TOD_SPEED                      = 0b1000_0000;
-    }
-}
+use cia::{Cia, CiaModel};
+use snapshot::{Reader, Writer};
 
 pub struct Cia1 {
-    timer_a: u16,
-    timer_a_start: u16,
-    ics: ICS,
-    tacr: TACR
+    cia: Cia
 }
 
 pub enum Effect {
@@ -33,86 +10,75 @@ pub enum Effect {
 }
 
 impl Cia1 {
-    pub fn new() -> Cia1 {
+    pub fn new(model: CiaModel, tod_pulse_cycles: u32) -> Cia1 {
         Cia1 {
-            timer_a: 0,
-            timer_a_start: 0,
-            ics: ICS { bits: 0 },
-            tacr: TACR { bits: 0 }
+            cia: Cia::new(model, tod_pulse_cycles)
         }
     }
 
-    pub fn write(self: &mut Cia1, addr: u16, value: u8) {
-        match addr {
-            0xDC04 => {
-                self.timer_a_start = (self.timer_a_start & 0xFF00) | value as u16;
-            }
-            0xDC05 => {
-                self.timer_a_start = (self.timer_a_start & 0x00FF) | ((value as u16) << 8);
-            }
-            0xDC0D => {
-                self.ics.bits = value;
-            }
-            0xDC0E => {
-                self.tacr.bits = value
-            }
-            _ => {
-                println!("Unhandled write to CIA1: 0x{:02X} -> 0x{:04X}", value, addr);
-            }
-        }
+    pub fn model(self: &Cia1) -> CiaModel {
+        self.cia.model()
     }
 
-    pub fn read(self: &mut Cia1, addr: u16) -> u8 {
-        match addr {
-            0xDC04 => {
-                (self.timer_a & 0x00FF) as u8
-            }
-            0xDC05 => {
-                ((self.timer_a & 0xFF00) >> 8) as u8
-            }
-            0xDC0D => {
-                let result = self.ics.bits;
-                self.ics.bits = 0;
-                result
-            }
-            0xDC0E => {
-                self.tacr.bits
-            }
-            _ => {
-                println!("Unhandled read from CIA1: 0x{:04X}", addr);
-                0
-            }
-        }
+    pub fn set_model(self: &mut Cia1, model: CiaModel) {
+        self.cia.set_model(model);
     }
 
-    fn timer_a_underflow(self: &mut Cia1) -> Option<Effect> {
-        if self.tacr.contains(TACR::STOP_ON_UNDERFLOW) {
-            self.tacr.set(TACR::START_TIMER, false);
-        } else {
-            self.timer_a = self.timer_a_start; // restart timer
-        }
-        let result = if self.ics.contains(ICS::TIMER_A_UNDERFLOW_INTERRUPT) {
-            Some(Effect::IRQ)
-        } else {
-            None
-        };
-        self.ics.set(ICS::TIMER_A_UNDERFLOW_INTERRUPT, true);
-        result
+    pub fn save_state(self: &Cia1, writer: &mut Writer) {
+        self.cia.save_state(writer);
+    }
+
+    pub fn load_state(self: &mut Cia1, reader: &mut Reader) -> Result<(), String> {
+        self.cia.load_state(reader)
+    }
+
+    pub fn load_vice_state(self: &mut Cia1, reader: &mut Reader) -> Result<(), String> {
+        self.cia.load_vice_state(reader)
+    }
+
+    pub fn set_port_a_input(self: &mut Cia1, value: u8) {
+        self.cia.set_port_a_input(value);
+    }
+
+    pub fn set_port_b_input(self: &mut Cia1, value: u8) {
+        self.cia.set_port_b_input(value);
+    }
+
+    pub fn cnt(self: &Cia1) -> bool {
+        self.cia.cnt()
+    }
+
+    pub fn sp(self: &Cia1) -> bool {
+        self.cia.sp()
+    }
+
+    pub fn set_cnt(self: &mut Cia1, level: bool) {
+        self.cia.set_cnt(level);
+    }
+
+    pub fn set_sp(self: &mut Cia1, level: bool) {
+        self.cia.set_sp(level);
+    }
+
+    /// Level of the FLAG pin, connected to the SRQ line of the serial bus
+    /// and the cassette read line.
+    pub fn set_flag(self: &mut Cia1, level: bool) {
+        self.cia.set_flag(level);
+    }
+
+    pub fn write(self: &mut Cia1, addr: u16, value: u8) {
+        self.cia.write(addr, value);
+    }
+
+    pub fn read(self: &mut Cia1, addr: u16) -> u8 {
+        self.cia.read(addr)
     }
 
     pub fn tick(self: &mut Cia1) -> Option<Effect> {
-        if self.tacr.contains(TACR::START_TIMER) {
-            match self.timer_a.checked_sub(1) {
-                Some(result) => {
-                    self.timer_a = result;
-                    None
-                }
-                None => {
-                    self.timer_a_underflow()
-                }
-            }
+        if self.cia.tick() {
+            Some(Effect::IRQ)
         } else {
             None
         }
     }
-}
\ No newline at end of file
+}
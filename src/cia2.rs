@@ -0,0 +1,117 @@
+use cia::{Cia, CiaModel};
+use snapshot::{Reader, Writer};
+
+// Port A bits. The IEC outputs go through inverters, so writing 1 pulls the
+// line low. The inputs read the line levels directly.
+const IEC_ATN_OUT: u8  = 0b0000_1000;
+const IEC_CLK_OUT: u8  = 0b0001_0000;
+const IEC_DATA_OUT: u8 = 0b0010_0000;
+const IEC_CLK_IN: u8   = 0b0100_0000;
+const IEC_DATA_IN: u8  = 0b1000_0000;
+
+pub struct Cia2 {
+    cia: Cia
+}
+
+pub enum Effect {
+    NMI
+}
+
+impl Cia2 {
+    pub fn new(model: CiaModel, tod_pulse_cycles: u32) -> Cia2 {
+        Cia2 {
+            cia: Cia::new(model, tod_pulse_cycles)
+        }
+    }
+
+    pub fn model(self: &Cia2) -> CiaModel {
+        self.cia.model()
+    }
+
+    pub fn set_model(self: &mut Cia2, model: CiaModel) {
+        self.cia.set_model(model);
+    }
+
+    pub fn save_state(self: &Cia2, writer: &mut Writer) {
+        self.cia.save_state(writer);
+    }
+
+    pub fn load_state(self: &mut Cia2, reader: &mut Reader) -> Result<(), String> {
+        self.cia.load_state(reader)
+    }
+
+    pub fn load_vice_state(self: &mut Cia2, reader: &mut Reader) -> Result<(), String> {
+        self.cia.load_vice_state(reader)
+    }
+
+    /// Start address of the 16K bank the VIC-II sees, selected by the
+    /// inverted port A bits 0-1.
+    pub fn vic_bank_start(self: &Cia2) -> u16 {
+        0x4000 * (0b11 - (self.cia.port_a_pins() as u16 & 0b11))
+    }
+
+    pub fn iec_atn_out(self: &Cia2) -> bool {
+        self.cia.port_a_pins() & IEC_ATN_OUT > 0
+    }
+
+    pub fn iec_clk_out(self: &Cia2) -> bool {
+        self.cia.port_a_pins() & IEC_CLK_OUT > 0
+    }
+
+    pub fn iec_data_out(self: &Cia2) -> bool {
+        self.cia.port_a_pins() & IEC_DATA_OUT > 0
+    }
+
+    /// Sets the levels of the serial bus CLK and DATA lines, true meaning high.
+    pub fn set_iec_inputs(self: &mut Cia2, clk: bool, data: bool) {
+        let mut value = 0xFF;
+        if !clk {
+            value &= !IEC_CLK_IN;
+        }
+        if !data {
+            value &= !IEC_DATA_IN;
+        }
+        self.cia.set_port_a_input(value);
+    }
+
+    /// Levels of the user port data lines PB0-PB7.
+    pub fn user_port(self: &Cia2) -> u8 {
+        self.cia.port_b_pins()
+    }
+
+    pub fn set_user_port_input(self: &mut Cia2, value: u8) {
+        self.cia.set_port_b_input(value);
+    }
+
+    pub fn cnt(self: &Cia2) -> bool {
+        self.cia.cnt()
+    }
+
+    pub fn sp(self: &Cia2) -> bool {
+        self.cia.sp()
+    }
+
+    pub fn set_cnt(self: &mut Cia2, level: bool) {
+        self.cia.set_cnt(level);
+    }
+
+    pub fn set_sp(self: &mut Cia2, level: bool) {
+        self.cia.set_sp(level);
+    }
+
+    pub fn write(self: &mut Cia2, addr: u16, value: u8) {
+        self.cia.write(addr, value);
+    }
+
+    pub fn read(self: &mut Cia2, addr: u16) -> u8 {
+        self.cia.read(addr)
+    }
+
+    pub fn tick(self: &mut Cia2) -> Option<Effect> {
+        if self.cia.tick() {
+            Some(Effect::NMI)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,680 @@
+// D64 disk images: the 256-byte sectors of a 1541 disk in track order, for
+// 35 or 40 tracks, optionally followed by an error code for every sector.
+// Sector 0 of track 18 holds the BAM and the disk name, the directory
+// follows from sector 1 on. Files are chains of sectors that start with the
+// track and sector of the next one.
+
+use std::fs::{self, File};
+use std::io::prelude::*;
+
+const SECTOR_SIZE: usize = 256;
+const SECTORS_35_TRACKS: usize = 683;
+const SECTORS_40_TRACKS: usize = 768;
+
+const DIRECTORY_TRACK: u8 = 18;
+const BAM_SECTOR: u8 = 0;
+const FIRST_DIRECTORY_SECTOR: u8 = 1;
+// Only the first 35 tracks have a BAM entry in the standard format
+const BAM_TRACKS: u8 = 35;
+const DIRECTORY_ENTRY_SIZE: usize = 32;
+const NAME_LENGTH: usize = 16;
+// Names are padded with shifted spaces
+const NAME_PADDING: u8 = 0xA0;
+// Sector offsets of the next data sector, the same as the 1541 DOS
+const DATA_INTERLEAVE: u8 = 10;
+const DIRECTORY_INTERLEAVE: u8 = 3;
+// Data bytes in a sector after the link to the next one
+const DATA_BYTES: usize = SECTOR_SIZE - 2;
+
+// Error codes of the error bytes. Images without them have no errors, and
+// some tools write 0 for sectors that were read without errors.
+pub const NO_ERROR: u8 = 1;
+
+// Load address of directory listings, the start of BASIC on a PET
+const LISTING_ADDRESS: u16 = 0x0401;
+
+/// Whether an image file can only be read, which protects the disk in it.
+pub fn is_read_only(filename: &str) -> bool {
+    fs::metadata(filename).map(|metadata| metadata.permissions().readonly()).unwrap_or(false)
+}
+
+pub fn sectors_per_track(track: u8) -> u8 {
+    match track {
+        1..=17 => 21,
+        18..=24 => 19,
+        25..=30 => 18,
+        _ => 17
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FileType {
+    Del = 0,
+    Seq = 1,
+    Prg = 2,
+    Usr = 3,
+    Rel = 4
+}
+
+impl FileType {
+    fn from_bits(bits: u8) -> Option<FileType> {
+        match bits & 0x07 {
+            0 => Some(FileType::Del),
+            1 => Some(FileType::Seq),
+            2 => Some(FileType::Prg),
+            3 => Some(FileType::Usr),
+            4 => Some(FileType::Rel),
+            _ => None
+        }
+    }
+
+    fn name(self: FileType) -> &'static [u8] {
+        match self {
+            FileType::Del => b"DEL",
+            FileType::Seq => b"SEQ",
+            FileType::Prg => b"PRG",
+            FileType::Usr => b"USR",
+            FileType::Rel => b"REL"
+        }
+    }
+}
+
+pub struct DirectoryEntry {
+    // PETSCII without the padding
+    pub name: Vec<u8>,
+    pub file_type: FileType,
+    // Files that weren't closed properly are listed with a '*'
+    pub closed: bool,
+    pub locked: bool,
+    pub blocks: u16,
+    track: u8,
+    sector: u8,
+    // Location of the entry in the directory
+    entry_track: u8,
+    entry_sector: u8,
+    entry_offset: usize
+}
+
+/// Matches a file name against a pattern in which '?' matches any character
+/// and '*' the rest of the name.
+pub fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    for (i, &c) in pattern.iter().enumerate() {
+        if c == b'*' {
+            return true;
+        }
+        match name.get(i) {
+            Some(&n) if c == b'?' || c == n => (),
+            _ => return false
+        }
+    }
+    pattern.len() == name.len()
+}
+
+pub struct DiskImage {
+    filename: String,
+    // The file can't be written, the disk is write protected
+    read_only: bool,
+    tracks: u8,
+    data: Vec<u8>,
+    errors: Option<Vec<u8>>
+}
+
+impl DiskImage {
+    pub fn load(filename: &str) -> Result<DiskImage, String> {
+        let mut bytes = Vec::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|err| format!("Failed to read {}: {}", filename, err))?;
+        let mut disk = DiskImage::parse(filename, bytes).map_err(|msg| format!("{}: {}", filename, msg))?;
+        disk.read_only = is_read_only(filename);
+        Ok(disk)
+    }
+
+    pub fn parse(filename: &str, mut bytes: Vec<u8>) -> Result<DiskImage, String> {
+        let (tracks, sectors, has_errors) = match bytes.len() {
+            n if n == SECTORS_35_TRACKS * SECTOR_SIZE => (35, SECTORS_35_TRACKS, false),
+            n if n == SECTORS_35_TRACKS * (SECTOR_SIZE + 1) => (35, SECTORS_35_TRACKS, true),
+            n if n == SECTORS_40_TRACKS * SECTOR_SIZE => (40, SECTORS_40_TRACKS, false),
+            n if n == SECTORS_40_TRACKS * (SECTOR_SIZE + 1) => (40, SECTORS_40_TRACKS, true),
+            n => return Err(format!("Not a D64 image, unexpected size {}", n))
+        };
+        let errors = if has_errors { Some(bytes.split_off(sectors * SECTOR_SIZE)) } else { None };
+        Ok(DiskImage {
+            filename: filename.to_string(),
+            read_only: false,
+            tracks,
+            data: bytes,
+            errors
+        })
+    }
+
+    pub fn filename(self: &DiskImage) -> &str {
+        &self.filename
+    }
+
+    /// Writes the image back to the file it was loaded from.
+    pub fn save(self: &DiskImage) -> Result<(), String> {
+        File::create(&self.filename)
+            .and_then(|mut f| {
+                f.write_all(&self.data)?;
+                match self.errors {
+                    Some(ref errors) => f.write_all(errors),
+                    None => Ok(())
+                }
+            })
+            .map_err(|err| format!("Failed to write {}: {}", self.filename, err))
+    }
+
+    /// Whether the image was loaded from a file that can't be written.
+    pub fn read_only(self: &DiskImage) -> bool {
+        self.read_only
+    }
+
+    pub fn tracks(self: &DiskImage) -> u8 {
+        self.tracks
+    }
+
+    fn sector_index(self: &DiskImage, track: u8, sector: u8) -> Option<usize> {
+        if track < 1 || track > self.tracks || sector >= sectors_per_track(track) {
+            return None;
+        }
+        let preceding: usize = (1..track).map(|t| sectors_per_track(t) as usize).sum();
+        Some(preceding + sector as usize)
+    }
+
+    /// Returns the sector, or the error of the drive as a status message.
+    pub fn read_sector(self: &DiskImage, track: u8, sector: u8) -> Result<&[u8], String> {
+        let index = match self.sector_index(track, sector) {
+            Some(index) => index,
+            None => return Err(format!("66,ILLEGAL TRACK OR SECTOR,{:02},{:02}", track, sector))
+        };
+        let error = self.errors.as_ref().map_or(NO_ERROR, |errors| errors[index]);
+        let message = match error {
+            0 | NO_ERROR => None,
+            2 => Some("20,READ ERROR"),
+            3 => Some("21,READ ERROR"),
+            4 => Some("22,READ ERROR"),
+            5 => Some("23,READ ERROR"),
+            8 => Some("26,WRITE PROTECT ON"),
+            9 => Some("27,READ ERROR"),
+            11 => Some("29,DISK ID MISMATCH"),
+            15 => Some("74,DRIVE NOT READY"),
+            _ => Some("20,READ ERROR")
+        };
+        match message {
+            Some(message) => Err(format!("{},{:02},{:02}", message, track, sector)),
+            None => Ok(&self.data[index * SECTOR_SIZE..(index + 1) * SECTOR_SIZE])
+        }
+    }
+
+    /// Contents and error code of a sector whatever the error, for encoding
+    /// the disk surface.
+    pub fn raw_sector(self: &DiskImage, track: u8, sector: u8) -> (&[u8], u8) {
+        let index = self.sector_index(track, sector).unwrap();
+        let error = self.errors.as_ref().map_or(NO_ERROR, |errors| errors[index]);
+        (&self.data[index * SECTOR_SIZE..(index + 1) * SECTOR_SIZE], error)
+    }
+
+    fn write_sector(self: &mut DiskImage, track: u8, sector: u8, contents: &[u8]) {
+        let index = self.sector_index(track, sector).unwrap();
+        self.data[index * SECTOR_SIZE..(index + 1) * SECTOR_SIZE].copy_from_slice(contents);
+        if let Some(ref mut errors) = self.errors {
+            errors[index] = NO_ERROR;
+        }
+    }
+
+    fn bam(self: &DiskImage) -> &[u8] {
+        let index = self.sector_index(DIRECTORY_TRACK, BAM_SECTOR).unwrap();
+        &self.data[index * SECTOR_SIZE..(index + 1) * SECTOR_SIZE]
+    }
+
+    fn bam_mut(self: &mut DiskImage) -> &mut [u8] {
+        let index = self.sector_index(DIRECTORY_TRACK, BAM_SECTOR).unwrap();
+        &mut self.data[index * SECTOR_SIZE..(index + 1) * SECTOR_SIZE]
+    }
+
+    pub fn disk_name(self: &DiskImage) -> &[u8] {
+        &self.bam()[0x90..0x90 + NAME_LENGTH]
+    }
+
+    /// Disk ID, a shifted space and the DOS type, as shown in the listing.
+    pub fn disk_id(self: &DiskImage) -> &[u8] {
+        &self.bam()[0xA2..0xA7]
+    }
+
+    pub fn blocks_free(self: &DiskImage) -> u16 {
+        let bam = self.bam();
+        (1..=BAM_TRACKS)
+            .filter(|&track| track != DIRECTORY_TRACK)
+            .map(|track| bam[4 * track as usize] as u16)
+            .sum()
+    }
+
+    fn is_free(self: &DiskImage, track: u8, sector: u8) -> bool {
+        let bam = self.bam();
+        track <= BAM_TRACKS && bam[4 * track as usize + 1 + sector as usize / 8] & (1 << (sector % 8)) != 0
+    }
+
+    fn set_free(self: &mut DiskImage, track: u8, sector: u8, free: bool) {
+        if track > BAM_TRACKS || self.is_free(track, sector) == free {
+            return;
+        }
+        let bam = self.bam_mut();
+        let entry = 4 * track as usize;
+        bam[entry + 1 + sector as usize / 8] ^= 1 << (sector % 8);
+        // Counted from the bitmap rather than adjusted, the count of a
+        // damaged BAM may not match it
+        bam[entry] = bam[entry + 1..entry + 4].iter().map(|byte| byte.count_ones() as u8).sum();
+    }
+
+    // Next free sector for data, preferring the given track and moving away
+    // from the directory track otherwise, as the DOS does
+    fn find_free_sector(self: &DiskImage, track: u8, sector: u8) -> Option<(u8, u8)> {
+        let mut tracks = vec![track];
+        for distance in 1..BAM_TRACKS {
+            if DIRECTORY_TRACK > distance {
+                tracks.push(DIRECTORY_TRACK - distance);
+            }
+            if DIRECTORY_TRACK + distance <= BAM_TRACKS {
+                tracks.push(DIRECTORY_TRACK + distance);
+            }
+        }
+        for &t in tracks.iter().filter(|&&t| t != DIRECTORY_TRACK) {
+            let count = sectors_per_track(t);
+            let start = if t == track { (sector + DATA_INTERLEAVE) % count } else { 0 };
+            if let Some(s) = (0..count).map(|i| (start + i) % count).find(|&s| self.is_free(t, s)) {
+                return Some((t, s));
+            }
+        }
+        None
+    }
+
+    // Follows a chain of sectors, with the number of used bytes in the last
+    // one. Fails on read errors and on chains that loop.
+    fn chain(self: &DiskImage, track: u8, sector: u8) -> Result<Vec<(u8, u8)>, String> {
+        let mut sectors = Vec::new();
+        let (mut t, mut s) = (track, sector);
+        loop {
+            if sectors.len() > self.data.len() / SECTOR_SIZE {
+                return Err(format!("Sector chain from {}/{} loops", track, sector));
+            }
+            sectors.push((t, s));
+            let contents = self.read_sector(t, s)?;
+            if contents[0] == 0 {
+                return Ok(sectors);
+            }
+            t = contents[0];
+            s = contents[1];
+        }
+    }
+
+    pub fn directory(self: &DiskImage) -> Result<Vec<DirectoryEntry>, String> {
+        let mut entries = Vec::new();
+        for (track, sector) in self.chain(DIRECTORY_TRACK, FIRST_DIRECTORY_SECTOR)? {
+            let contents = self.read_sector(track, sector)?;
+            for offset in (0..SECTOR_SIZE).step_by(DIRECTORY_ENTRY_SIZE) {
+                let entry = &contents[offset..offset + DIRECTORY_ENTRY_SIZE];
+                let file_type = match FileType::from_bits(entry[2]) {
+                    Some(file_type) if entry[2] != 0 => file_type,
+                    _ => continue
+                };
+                let name = &entry[5..5 + NAME_LENGTH];
+                let length = name.iter().position(|&c| c == NAME_PADDING).unwrap_or(NAME_LENGTH);
+                entries.push(DirectoryEntry {
+                    name: name[..length].to_vec(),
+                    file_type,
+                    closed: entry[2] & 0x80 != 0,
+                    locked: entry[2] & 0x40 != 0,
+                    blocks: entry[0x1E] as u16 | (entry[0x1F] as u16) << 8,
+                    track: entry[3],
+                    sector: entry[4],
+                    entry_track: track,
+                    entry_sector: sector,
+                    entry_offset: offset
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    /// The first closed file matching the pattern.
+    pub fn find(self: &DiskImage, pattern: &[u8]) -> Result<Option<DirectoryEntry>, String> {
+        Ok(self.directory()?.into_iter()
+            .find(|entry| entry.closed && entry.file_type != FileType::Del && matches(pattern, &entry.name)))
+    }
+
+    pub fn read_file(self: &DiskImage, entry: &DirectoryEntry) -> Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+        for (track, sector) in self.chain(entry.track, entry.sector)? {
+            let contents = self.read_sector(track, sector)?;
+            // The last sector has the position of its last byte in place of the sector link
+            let end = if contents[0] == 0 { (contents[1] as usize + 1).max(2) } else { SECTOR_SIZE };
+            data.extend_from_slice(&contents[2..end]);
+        }
+        Ok(data)
+    }
+
+    fn delete(self: &mut DiskImage, entry: &DirectoryEntry) -> Result<(), String> {
+        for (track, sector) in self.chain(entry.track, entry.sector)? {
+            self.set_free(track, sector, true);
+        }
+        let mut contents = self.read_sector(entry.entry_track, entry.entry_sector)?.to_vec();
+        contents[entry.entry_offset + 2] = 0;
+        self.write_sector(entry.entry_track, entry.entry_sector, &contents);
+        Ok(())
+    }
+
+    // Track, sector and offset of an unused directory entry, extending the
+    // directory by a sector if needed
+    fn free_directory_entry(self: &mut DiskImage) -> Result<(u8, u8, usize), String> {
+        let chain = self.chain(DIRECTORY_TRACK, FIRST_DIRECTORY_SECTOR)?;
+        for &(track, sector) in chain.iter() {
+            let contents = self.read_sector(track, sector)?;
+            if let Some(offset) = (0..SECTOR_SIZE).step_by(DIRECTORY_ENTRY_SIZE).find(|&offset| contents[offset + 2] == 0) {
+                return Ok((track, sector, offset));
+            }
+        }
+        let &(last_track, last_sector) = chain.last().unwrap();
+        let count = sectors_per_track(DIRECTORY_TRACK);
+        let sector = (0..count)
+            .map(|i| (last_sector + DIRECTORY_INTERLEAVE + i) % count)
+            .find(|&s| self.is_free(DIRECTORY_TRACK, s))
+            .ok_or("72,DISK FULL,00,00")?;
+        self.set_free(DIRECTORY_TRACK, sector, false);
+        let mut contents = vec![0; SECTOR_SIZE];
+        contents[1] = 0xFF;
+        self.write_sector(DIRECTORY_TRACK, sector, &contents);
+        let mut last = self.read_sector(last_track, last_sector)?.to_vec();
+        last[0] = DIRECTORY_TRACK;
+        last[1] = sector;
+        self.write_sector(last_track, last_sector, &last);
+        Ok((DIRECTORY_TRACK, sector, 0))
+    }
+
+    /// Writes a new file, replacing an existing one of the same name if
+    /// asked to. Errors are returned as drive status messages.
+    pub fn write_file(self: &mut DiskImage, name: &[u8], file_type: FileType, data: &[u8], replace: bool) -> Result<(), String> {
+        if name.is_empty() || name.len() > NAME_LENGTH || name.iter().any(|&c| c == b'*' || c == b'?') {
+            return Err("33,SYNTAX ERROR,00,00".to_string());
+        }
+        if self.read_only {
+            return Err("26,WRITE PROTECT ON,00,00".to_string());
+        }
+        if let Some(entry) = self.directory()?.into_iter().find(|entry| entry.name == name) {
+            if !replace {
+                return Err("63,FILE EXISTS,00,00".to_string());
+            }
+            self.delete(&entry)?;
+        }
+        let blocks = data.len().div_ceil(DATA_BYTES).max(1);
+        if (self.blocks_free() as usize) < blocks {
+            return Err("72,DISK FULL,00,00".to_string());
+        }
+        let (entry_track, entry_sector, entry_offset) = self.free_directory_entry()?;
+
+        let mut sectors = Vec::with_capacity(blocks);
+        let (mut track, mut sector) = (DIRECTORY_TRACK - 1, 0);
+        for _ in 0..blocks {
+            let (t, s) = self.find_free_sector(track, sector).ok_or("72,DISK FULL,00,00")?;
+            self.set_free(t, s, false);
+            sectors.push((t, s));
+            track = t;
+            sector = s;
+        }
+        for (i, &(track, sector)) in sectors.iter().enumerate() {
+            let chunk = &data[(i * DATA_BYTES).min(data.len())..((i + 1) * DATA_BYTES).min(data.len())];
+            let mut contents = vec![0; SECTOR_SIZE];
+            match sectors.get(i + 1) {
+                Some(&(next_track, next_sector)) => {
+                    contents[0] = next_track;
+                    contents[1] = next_sector;
+                }
+                None => {
+                    contents[1] = (chunk.len() + 1) as u8;
+                }
+            }
+            contents[2..2 + chunk.len()].copy_from_slice(chunk);
+            self.write_sector(track, sector, &contents);
+        }
+
+        let mut contents = self.read_sector(entry_track, entry_sector)?.to_vec();
+        {
+            let entry = &mut contents[entry_offset..entry_offset + DIRECTORY_ENTRY_SIZE];
+            let link = [entry[0], entry[1]];
+            for byte in entry.iter_mut() {
+                *byte = 0;
+            }
+            entry[0] = link[0];
+            entry[1] = link[1];
+            entry[2] = 0x80 | file_type as u8;
+            entry[3] = sectors[0].0;
+            entry[4] = sectors[0].1;
+            for (i, byte) in entry[5..5 + NAME_LENGTH].iter_mut().enumerate() {
+                *byte = *name.get(i).unwrap_or(&NAME_PADDING);
+            }
+            entry[0x1E] = blocks as u8;
+            entry[0x1F] = (blocks >> 8) as u8;
+        }
+        self.write_sector(entry_track, entry_sector, &contents);
+        Ok(())
+    }
+
+    /// The directory as the BASIC program the drive sends for LOAD"$".
+    pub fn directory_listing(self: &DiskImage) -> Result<Vec<u8>, String> {
+        let files = self.directory()?.iter()
+            .map(|entry| listing_line(&entry.name, entry.file_type, entry.blocks, entry.closed, entry.locked))
+            .collect();
+        Ok(listing_program(self.disk_name(), self.disk_id(), files, self.blocks_free()))
+    }
+}
+
+/// The line of a directory listing for a file, as the line number, which
+/// is the size in blocks, and the text.
+pub fn listing_line(name: &[u8], file_type: FileType, blocks: u16, closed: bool, locked: bool) -> (u16, Vec<u8>) {
+    let mut line = Vec::new();
+    let indent = match blocks {
+        0..=9 => 3,
+        10..=99 => 2,
+        100..=999 => 1,
+        _ => 0
+    };
+    line.extend(vec![b' '; indent]);
+    line.push(b'"');
+    line.extend_from_slice(name);
+    line.push(b'"');
+    line.extend(vec![b' '; NAME_LENGTH.saturating_sub(name.len())]);
+    line.push(if closed { b' ' } else { b'*' });
+    line.extend_from_slice(file_type.name());
+    if locked {
+        line.push(b'<');
+    }
+    (blocks, line)
+}
+
+/// The BASIC program of a directory listing: the header with the disk name
+/// and ID in reverse, the lines of the files and the blocks free.
+pub fn listing_program(disk_name: &[u8], disk_id: &[u8], files: Vec<(u16, Vec<u8>)>, blocks_free: u16) -> Vec<u8> {
+    let mut lines: Vec<(u16, Vec<u8>)> = Vec::new();
+    let mut header = vec![0x12, b'"'];
+    header.extend(disk_name.iter().map(|&c| if c == NAME_PADDING { b' ' } else { c }));
+    header.extend_from_slice(b"\" ");
+    header.extend(disk_id.iter().map(|&c| if c == NAME_PADDING { b' ' } else { c }));
+    lines.push((0, header));
+    lines.extend(files);
+    lines.push((blocks_free, b"BLOCKS FREE.".to_vec()));
+
+    let mut program = vec![LISTING_ADDRESS as u8, (LISTING_ADDRESS >> 8) as u8];
+    let mut address = LISTING_ADDRESS;
+    for (number, text) in lines {
+        // Link to the next line, line number, text and terminating zero
+        address += 2 + 2 + text.len() as u16 + 1;
+        program.extend_from_slice(&[address as u8, (address >> 8) as u8, number as u8, (number >> 8) as u8]);
+        program.extend_from_slice(&text);
+        program.push(0);
+    }
+    program.extend_from_slice(&[0, 0]);
+    program
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A freshly formatted 35-track disk named TEST with ID AB
+    fn blank_disk() -> DiskImage {
+        let mut disk = DiskImage::parse("test.d64", vec![0; SECTORS_35_TRACKS * SECTOR_SIZE]).unwrap();
+        let mut bam = vec![0; SECTOR_SIZE];
+        bam[0] = DIRECTORY_TRACK;
+        bam[1] = FIRST_DIRECTORY_SECTOR;
+        bam[2] = 0x41;
+        for track in 1..=BAM_TRACKS {
+            let count = sectors_per_track(track);
+            let entry = 4 * track as usize;
+            bam[entry] = count;
+            for sector in 0..count {
+                bam[entry + 1 + sector as usize / 8] |= 1 << (sector % 8);
+            }
+        }
+        for byte in bam[0x90..0xAB].iter_mut() {
+            *byte = NAME_PADDING;
+        }
+        bam[0x90..0x94].copy_from_slice(b"TEST");
+        bam[0xA2..0xA4].copy_from_slice(b"AB");
+        bam[0xA5..0xA7].copy_from_slice(b"2A");
+        disk.write_sector(DIRECTORY_TRACK, BAM_SECTOR, &bam);
+        disk.set_free(DIRECTORY_TRACK, BAM_SECTOR, false);
+        let mut directory = vec![0; SECTOR_SIZE];
+        directory[1] = 0xFF;
+        disk.write_sector(DIRECTORY_TRACK, FIRST_DIRECTORY_SECTOR, &directory);
+        disk.set_free(DIRECTORY_TRACK, FIRST_DIRECTORY_SECTOR, false);
+        disk
+    }
+
+    fn read(disk: &DiskImage, name: &[u8]) -> Vec<u8> {
+        let entry = disk.find(name).unwrap().unwrap();
+        disk.read_file(&entry).unwrap()
+    }
+
+    // The lines of a BASIC program as line number and text
+    fn lines(program: &[u8]) -> Vec<(u16, Vec<u8>)> {
+        let mut lines = Vec::new();
+        let mut offset = 2;
+        while program[offset] != 0 || program[offset + 1] != 0 {
+            let number = program[offset + 2] as u16 | (program[offset + 3] as u16) << 8;
+            let end = offset + 4 + program[offset + 4..].iter().position(|&c| c == 0).unwrap();
+            lines.push((number, program[offset + 4..end].to_vec()));
+            let next = program[offset] as usize | (program[offset + 1] as usize) << 8;
+            assert_eq!(next - LISTING_ADDRESS as usize + 2, end + 1);
+            offset = end + 1;
+        }
+        lines
+    }
+
+    #[test]
+    fn parses_the_image_sizes() {
+        for &(sectors, with_errors, tracks) in [(SECTORS_35_TRACKS, false, 35), (SECTORS_35_TRACKS, true, 35), (SECTORS_40_TRACKS, false, 40), (SECTORS_40_TRACKS, true, 40)].iter() {
+            let size = sectors * if with_errors { SECTOR_SIZE + 1 } else { SECTOR_SIZE };
+            let disk = DiskImage::parse("test.d64", vec![NO_ERROR; size]).unwrap();
+            assert_eq!(disk.tracks(), tracks);
+            assert_eq!(disk.errors.is_some(), with_errors);
+            assert_eq!(disk.data.len(), sectors * SECTOR_SIZE);
+        }
+        assert!(DiskImage::parse("test.d64", vec![0; SECTORS_35_TRACKS * SECTOR_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn reports_the_errors_of_the_error_bytes() {
+        let mut bytes = vec![0; SECTORS_35_TRACKS * (SECTOR_SIZE + 1)];
+        bytes[SECTORS_35_TRACKS * SECTOR_SIZE + 22] = 5;
+        let disk = DiskImage::parse("test.d64", bytes).unwrap();
+        assert!(disk.read_sector(2, 0).is_ok());
+        assert_eq!(disk.read_sector(2, 1).err().unwrap(), "23,READ ERROR,02,01");
+        assert_eq!(disk.raw_sector(2, 1).1, 5);
+        assert_eq!(disk.read_sector(36, 0).err().unwrap(), "66,ILLEGAL TRACK OR SECTOR,36,00");
+        assert_eq!(disk.read_sector(1, 21).err().unwrap(), "66,ILLEGAL TRACK OR SECTOR,01,21");
+    }
+
+    #[test]
+    fn writes_files_that_read_back() {
+        let mut disk = blank_disk();
+        assert_eq!(disk.blocks_free(), 664);
+        let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        disk.write_file(b"DATA", FileType::Prg, &data, false).unwrap();
+        disk.write_file(b"EMPTY", FileType::Seq, &[], false).unwrap();
+        assert_eq!(read(&disk, b"DATA"), data);
+        assert_eq!(read(&disk, b"EMPTY"), Vec::<u8>::new());
+        assert_eq!(read(&disk, b"D*"), data);
+        assert_eq!(disk.blocks_free(), 664 - 4 - 1);
+        let entry = disk.find(b"DATA").unwrap().unwrap();
+        assert_eq!((entry.file_type, entry.blocks, entry.track), (FileType::Prg, 4, 17));
+    }
+
+    #[test]
+    fn replaces_files_only_when_asked_to() {
+        let mut disk = blank_disk();
+        disk.write_file(b"FILE", FileType::Prg, &[1; 600], false).unwrap();
+        assert_eq!(disk.write_file(b"FILE", FileType::Prg, &[2; 10], false).err().unwrap(), "63,FILE EXISTS,00,00");
+        assert_eq!(read(&disk, b"FILE"), vec![1; 600]);
+        disk.write_file(b"FILE", FileType::Prg, &[2; 10], true).unwrap();
+        assert_eq!(read(&disk, b"FILE"), vec![2; 10]);
+        assert_eq!(disk.directory().unwrap().len(), 1);
+        assert_eq!(disk.blocks_free(), 663);
+    }
+
+    #[test]
+    fn refuses_files_that_do_not_fit() {
+        let mut disk = blank_disk();
+        assert_eq!(disk.write_file(b"BIG", FileType::Prg, &vec![0; 665 * DATA_BYTES], false).err().unwrap(), "72,DISK FULL,00,00");
+        assert_eq!(disk.blocks_free(), 664);
+        disk.write_file(b"ALL", FileType::Prg, &vec![3; 664 * DATA_BYTES], false).unwrap();
+        assert_eq!(disk.blocks_free(), 0);
+        assert_eq!(read(&disk, b"ALL"), vec![3; 664 * DATA_BYTES]);
+        assert_eq!(disk.write_file(b"MORE", FileType::Prg, &[0], false).err().unwrap(), "72,DISK FULL,00,00");
+        assert_eq!(disk.write_file(b"A*", FileType::Prg, &[0], false).err().unwrap(), "33,SYNTAX ERROR,00,00");
+    }
+
+    #[test]
+    fn refuses_to_write_to_read_only_images() {
+        let mut disk = blank_disk();
+        disk.read_only = true;
+        assert_eq!(disk.write_file(b"FILE", FileType::Prg, &[0], false).err().unwrap(), "26,WRITE PROTECT ON,00,00");
+        assert_eq!(disk.blocks_free(), 664);
+    }
+
+    #[test]
+    fn extends_the_directory_when_it_is_full() {
+        let mut disk = blank_disk();
+        for i in 0..9 {
+            disk.write_file(format!("FILE{}", i).as_bytes(), FileType::Prg, &[i], false).unwrap();
+        }
+        assert_eq!(disk.chain(DIRECTORY_TRACK, FIRST_DIRECTORY_SECTOR).unwrap(), vec![(18, 1), (18, 4)]);
+        assert_eq!(read(&disk, b"FILE8"), vec![8]);
+        assert_eq!(disk.blocks_free(), 664 - 9);
+    }
+
+    #[test]
+    fn counts_the_free_sectors_of_a_damaged_bam_again() {
+        let mut disk = blank_disk();
+        disk.bam_mut()[4 * 17] = 0;
+        disk.bam_mut()[4 * 19] = 255;
+        disk.write_file(b"FILE", FileType::Prg, &[0; 100], false).unwrap();
+        disk.delete(&disk.find(b"FILE").unwrap().unwrap()).unwrap();
+        assert_eq!(disk.bam()[4 * 17], 21);
+        assert_eq!(disk.bam()[4 * 19], 255);
+    }
+
+    #[test]
+    fn lists_the_directory_as_a_basic_program() {
+        let mut disk = blank_disk();
+        disk.write_file(b"GAME", FileType::Prg, &vec![0; 30 * DATA_BYTES], false).unwrap();
+        disk.write_file(b"NOTES", FileType::Seq, &[0], false).unwrap();
+        let program = disk.directory_listing().unwrap();
+        assert_eq!(&program[..2], &[0x01, 0x04]);
+        assert_eq!(lines(&program), vec![
+            (0, b"\x12\"TEST            \" AB 2A".to_vec()),
+            (30, b"  \"GAME\"             PRG".to_vec()),
+            (1, b"   \"NOTES\"            SEQ".to_vec()),
+            (633, b"BLOCKS FREE.".to_vec())
+        ]);
+    }
+}
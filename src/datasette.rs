@@ -0,0 +1,260 @@
+// The datasette, the C64's tape recorder. Its buttons are sensed on bit 4
+// of the processor port, bit 5 switches the motor and bit 3 is the write
+// line. What is read from the tape goes to the FLAG input of CIA1, where
+// the KERNAL measures the time between the falling edges with the timers.
+//
+// TAP images store the tape as the length of each pulse in units of 8
+// cycles, a zero standing for a longer pause: in version 0 it is 256 units
+// long, from version 1 on the exact number of cycles follows in 3 bytes.
+// Version 2 stores half-waves instead of whole pulses, as the C16 needs.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::mem;
+
+const TAP_SIGNATURE: &[u8] = b"C64-TAPE-RAW";
+const TAP_HEADER_SIZE: usize = 20;
+const TAP_VERSION: usize = 12;
+const TAP_DATA_LENGTH: usize = 16;
+// Version written when recording
+const RECORDING_VERSION: u8 = 1;
+
+// Cycles of a unit of the pulse lengths
+const PULSE_UNIT: u32 = 8;
+// Length of a pause in a version 0 image
+const OVERFLOW_PULSE: u32 = 256 * PULSE_UNIT;
+
+pub struct Tape {
+    filename: String,
+    // Lengths in cycles of the pulses, or half-waves from version 2 on
+    pulses: Vec<u32>,
+    half_waves: bool,
+    modified: bool
+}
+
+impl Tape {
+    pub fn load(filename: &str) -> Result<Tape, String> {
+        let mut bytes = Vec::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|err| format!("Failed to read {}: {}", filename, err))?;
+        if bytes.len() < TAP_HEADER_SIZE || !bytes.starts_with(TAP_SIGNATURE) {
+            return Err(format!("Not a TAP image: {}", filename));
+        }
+        let version = bytes[TAP_VERSION];
+        if version > 2 {
+            return Err(format!("Unsupported TAP version {}: {}", version, filename));
+        }
+        let length = bytes[TAP_DATA_LENGTH] as usize | (bytes[TAP_DATA_LENGTH + 1] as usize) << 8
+            | (bytes[TAP_DATA_LENGTH + 2] as usize) << 16 | (bytes[TAP_DATA_LENGTH + 3] as usize) << 24;
+        // Some tools get the length wrong, the data goes to the end of the file
+        let data = &bytes[TAP_HEADER_SIZE..(TAP_HEADER_SIZE + length).min(bytes.len())];
+        let mut pulses = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            if data[i] != 0 {
+                pulses.push(data[i] as u32 * PULSE_UNIT);
+                i += 1;
+            } else if version == 0 {
+                pulses.push(OVERFLOW_PULSE);
+                i += 1;
+            } else {
+                let cycles = data[i + 1..].iter().take(3).enumerate().fold(0, |cycles, (n, &byte)| cycles | (byte as u32) << (8 * n));
+                pulses.push(cycles.max(1));
+                i += 4;
+            }
+        }
+        Ok(Tape {
+            filename: filename.to_string(),
+            pulses,
+            half_waves: version == 2,
+            modified: false
+        })
+    }
+
+    /// An empty tape, written to the file once something is recorded.
+    pub fn blank(filename: &str) -> Tape {
+        Tape {
+            filename: filename.to_string(),
+            pulses: Vec::new(),
+            half_waves: false,
+            modified: false
+        }
+    }
+
+    pub fn filename(self: &Tape) -> &str {
+        &self.filename
+    }
+
+    pub fn modified(self: &Tape) -> bool {
+        self.modified
+    }
+
+    /// Writes the tape back to its file as a version 1 image.
+    pub fn save(self: &mut Tape) -> Result<(), String> {
+        let mut data = Vec::with_capacity(self.pulses.len());
+        for &cycles in self.pulses.iter() {
+            let units = (cycles + PULSE_UNIT / 2) / PULSE_UNIT;
+            if units > 0 && units < 256 {
+                data.push(units as u8);
+            } else {
+                data.extend_from_slice(&[0, cycles as u8, (cycles >> 8) as u8, (cycles >> 16) as u8]);
+            }
+        }
+        let mut bytes = TAP_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[RECORDING_VERSION, 0, 0, 0]);
+        let length = data.len() as u32;
+        bytes.extend_from_slice(&[length as u8, (length >> 8) as u8, (length >> 16) as u8, (length >> 24) as u8]);
+        bytes.extend(data);
+        File::create(&self.filename)
+            .and_then(|mut f| f.write_all(&bytes))
+            .map_err(|err| format!("Failed to write {}: {}", self.filename, err))?;
+        self.modified = false;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Buttons {
+    Stop,
+    Play,
+    Record
+}
+
+pub struct Datasette {
+    tape: Option<Tape>,
+    buttons: Buttons,
+    // Index of the next pulse
+    position: usize,
+    // Cycles left of the pulse under the head and its length
+    pulse_left: u32,
+    pulse_length: u32,
+    read_level: bool,
+    write_level: bool,
+    // Cycles since the last rising edge of the write line
+    write_cycles: u32
+}
+
+impl Datasette {
+    pub fn new() -> Datasette {
+        Datasette {
+            tape: None,
+            buttons: Buttons::Stop,
+            position: 0,
+            pulse_left: 0,
+            pulse_length: 0,
+            read_level: true,
+            write_level: false,
+            write_cycles: 0
+        }
+    }
+
+    /// Inserts a tape, rewound, and returns the one taken out.
+    pub fn insert(self: &mut Datasette, tape: Option<Tape>) -> Option<Tape> {
+        self.buttons = Buttons::Stop;
+        self.position = 0;
+        self.pulse_left = 0;
+        self.read_level = true;
+        mem::replace(&mut self.tape, tape)
+    }
+
+    pub fn tape(self: &Datasette) -> Option<&Tape> {
+        self.tape.as_ref()
+    }
+
+    pub fn tape_mut(self: &mut Datasette) -> Option<&mut Tape> {
+        self.tape.as_mut()
+    }
+
+    pub fn play(self: &mut Datasette) -> Result<(), String> {
+        if self.tape.is_none() {
+            return Err("No tape inserted".to_string());
+        }
+        self.buttons = Buttons::Play;
+        Ok(())
+    }
+
+    /// Presses RECORD and PLAY. What is on the tape after the head is
+    /// recorded over.
+    pub fn record(self: &mut Datasette) -> Result<(), String> {
+        match self.tape {
+            Some(ref tape) if tape.half_waves => return Err("Recording needs a TAP image of version 0 or 1".to_string()),
+            Some(_) => (),
+            None => return Err("No tape inserted".to_string())
+        }
+        self.buttons = Buttons::Record;
+        self.pulse_left = 0;
+        self.write_cycles = 0;
+        Ok(())
+    }
+
+    pub fn stop(self: &mut Datasette) {
+        self.buttons = Buttons::Stop;
+    }
+
+    /// Rewinds the tape to the start, at once, and stops.
+    pub fn rewind(self: &mut Datasette) {
+        self.stop();
+        self.position = 0;
+        self.pulse_left = 0;
+        self.read_level = true;
+    }
+
+    /// Whether a button is pressed, which pulls the sense line low.
+    pub fn sense(self: &Datasette) -> bool {
+        self.buttons != Buttons::Stop
+    }
+
+    /// Runs the tape for a cycle while the motor is on and returns the
+    /// level of the read line.
+    pub fn tick(self: &mut Datasette, motor: bool, write: bool) -> bool {
+        let tape = match self.tape {
+            Some(ref mut tape) if motor => tape,
+            _ => return self.read_level
+        };
+        match self.buttons {
+            Buttons::Stop => (),
+            Buttons::Play => {
+                if self.pulse_left == 0 {
+                    let pulse = match tape.pulses.get(self.position) {
+                        Some(&pulse) => pulse,
+                        None => return self.read_level
+                    };
+                    self.position += 1;
+                    self.pulse_left = pulse.max(1);
+                    self.pulse_length = self.pulse_left;
+                    self.read_level = tape.half_waves && !self.read_level;
+                }
+                self.pulse_left -= 1;
+                // A whole pulse starts with a falling edge and is low for its first half
+                if !tape.half_waves && self.pulse_left == self.pulse_length / 2 {
+                    self.read_level = true;
+                }
+            }
+            Buttons::Record => {
+                self.write_cycles = self.write_cycles.saturating_add(1);
+                if write && !self.write_level {
+                    tape.pulses.truncate(self.position);
+                    tape.pulses.push(self.write_cycles);
+                    tape.modified = true;
+                    self.position += 1;
+                    self.write_cycles = 0;
+                }
+            }
+        }
+        self.write_level = write;
+        self.read_level
+    }
+
+    /// Prints the position on the tape, in seconds at the given clock.
+    pub fn print_status(self: &Datasette, clock_frequency: u32) {
+        match self.tape {
+            Some(ref tape) => {
+                let cycles: u64 = tape.pulses[..self.position.min(tape.pulses.len())].iter().map(|&pulse| pulse as u64).sum();
+                println!("{}: {:?}, pulse {} of {}, {:.1} s into the tape{}", tape.filename, self.buttons, self.position, tape.pulses.len(),
+                    cycles as f64 / clock_frequency as f64, if tape.modified { ", modified" } else { "" });
+            }
+            None => println!("No tape inserted")
+        }
+    }
+}
@@ -0,0 +1,150 @@
+// Self test of sample playback through the SID volume register. A small
+// program writes a 4-bit triangle wave to $D418 every 32 cycles, alternating
+// between a 4-cycle absolute store and a 6-cycle indirect indexed store that
+// are timed to reach the bus exactly 32 cycles apart. The output is checked
+// for a step at every write, at the right cycle and with the right size and
+// direction, or for silence where no samples should be heard.
+
+use sid::SidModel;
+use vic_ii;
+use Machine;
+
+const PROGRAM_ADDR: u16 = 0xC000;
+const TABLE_ADDR: u16 = 0xC100;
+
+const PROGRAM: [u8; 51] = [
+    0x78,             //        SEI
+    0xA9, 0x18,       //        LDA #$18
+    0x85, 0xFB,       //        STA $FB
+    0xA9, 0xD4,       //        LDA #$D4
+    0x85, 0xFC,       //        STA $FC
+    0xA0, 0x00,       //        LDY #0
+    0xA2, 0x00,       //        LDX #0
+    0xBD, 0x00, 0xC1, // loop:  LDA $C100,X     cycles 0-3
+    0x8D, 0x18, 0xD4, //        STA $D418       cycles 4-7, write on 7
+    0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA,
+    0xBD, 0x01, 0xC1, //        LDA $C101,X     cycles 30-33
+    0x91, 0xFB,       //        STA ($FB),Y     cycles 34-39, write on 39
+    0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA,
+    0x24, 0xFE,       //        BIT $FE
+    0xE8,             //        INX
+    0xE8,             //        INX
+    0xD0, 0xDD,       //        BNE loop        64 cycles per loop
+    0x4C, 0x0D, 0xC0  //        JMP loop
+];
+
+pub const WRITE_INTERVAL: usize = 32;
+pub const TRIANGLE_STEPS: usize = 15;
+// The loop runs 128 times before X wraps around, which changes its timing
+const WARMUP_CYCLES: usize = 500;
+const MEASURED_CYCLES: usize = 7000;
+// Smallest change of the output between two cycles counted as a step
+const MIN_STEP: i32 = 20;
+// Allowed deviation of a step from the average step size
+const STEP_TOLERANCE: f32 = 0.1;
+
+/// Volume of the i-th sample of the triangle wave, from 0 up to 15 and back.
+pub fn triangle(i: usize) -> u8 {
+    let t = i % (2 * TRIANGLE_STEPS);
+    if t <= TRIANGLE_STEPS { t as u8 } else { (2 * TRIANGLE_STEPS - t) as u8 }
+}
+
+fn record(model: SidModel, digi_boost: bool) -> Result<Vec<i16>, String> {
+    let mut machine = Machine::new(None, 44100, vic_ii::PAL);
+    machine.sids.built_in().set_model(model);
+    machine.sids.set_digi_boost(digi_boost);
+    machine.reset();
+    for (i, &byte) in PROGRAM.iter().enumerate() {
+        machine.ram[PROGRAM_ADDR as usize + i] = byte;
+    }
+    for i in 0..256 {
+        machine.ram[TABLE_ADDR as usize + i] = triangle(i);
+    }
+    machine.mos6510.set_pc(PROGRAM_ADDR);
+
+    let mut output = Vec::with_capacity(MEASURED_CYCLES);
+    for cycle in 0..WARMUP_CYCLES + MEASURED_CYCLES {
+        machine.tick()?;
+        if cycle >= WARMUP_CYCLES {
+            output.push(machine.sids.output().0);
+        }
+    }
+    Ok(output)
+}
+
+/// Cycles where the output jumps after settling, with the size of the jump.
+/// The external low-pass filter spreads a step over the following cycles.
+pub fn steps(output: &[i16]) -> Vec<(usize, i32)> {
+    let deltas: Vec<i32> = output.windows(2).map(|pair| pair[1] as i32 - pair[0] as i32).collect();
+    deltas.windows(2).enumerate()
+        .filter(|&(_, pair)| pair[1].abs() >= MIN_STEP && pair[0].abs() * 2 < pair[1].abs())
+        .map(|(i, pair)| (i + 2, pair[1]))
+        .collect()
+}
+
+/// Checks that there is a step every WRITE_INTERVAL cycles, that the steps
+/// are of equal size and that their direction turns every TRIANGLE_STEPS.
+pub fn check_samples(output: &[i16]) -> Result<String, String> {
+    let steps = steps(output);
+    let expected = output.len() / WRITE_INTERVAL;
+    if steps.len() + 1 < expected {
+        return Err(format!("{} steps, expected {}", steps.len(), expected));
+    }
+    for pair in steps.windows(2) {
+        if pair[1].0 - pair[0].0 != WRITE_INTERVAL {
+            return Err(format!("steps {} cycles apart at cycle {}, expected {}", pair[1].0 - pair[0].0, pair[0].0, WRITE_INTERVAL));
+        }
+    }
+    let average = steps.iter().map(|&(_, step)| step.abs() as f32).sum::<f32>() / steps.len() as f32;
+    if let Some(&(cycle, step)) = steps.iter().find(|&&(_, step)| (step.abs() as f32 - average).abs() > average * STEP_TOLERANCE) {
+        return Err(format!("step of {} at cycle {}, average {:.1}", step, cycle, average));
+    }
+    // Runs of steps in one direction, the first and last ones cut short
+    let mut runs = vec![1];
+    for pair in steps.windows(2) {
+        if (pair[0].1 > 0) == (pair[1].1 > 0) {
+            *runs.last_mut().unwrap() += 1;
+        } else {
+            runs.push(1);
+        }
+    }
+    if runs.len() < 3 || runs[1..runs.len() - 1].iter().any(|&run| run != TRIANGLE_STEPS) {
+        return Err(format!("step directions change after {:?} steps, expected {}", runs, TRIANGLE_STEPS));
+    }
+    Ok(format!("{} steps {} cycles apart, {:.1} per volume step", steps.len(), WRITE_INTERVAL, average))
+}
+
+pub fn check_silence(output: &[i16]) -> Result<String, String> {
+    match output.iter().map(|&value| (value as i32).abs()).max() {
+        Some(peak) if peak > 1 => Err(format!("output reaches {}, expected silence", peak)),
+        _ => Ok("silent".to_string())
+    }
+}
+
+/// Runs the test on the 6581, on the 8580 and on the 8580 with digi boost.
+/// Returns false if any of them fails.
+pub fn run() -> bool {
+    let cases = [
+        ("6581", SidModel::Mos6581, false),
+        ("8580", SidModel::Mos8580, false),
+        ("8580 with digi boost", SidModel::Mos8580, true)
+    ];
+    let mut passed = true;
+    for &(name, model, digi_boost) in cases.iter() {
+        let result = record(model, digi_boost).and_then(|output| {
+            if model == SidModel::Mos6581 || digi_boost {
+                check_samples(&output)
+            } else {
+                check_silence(&output)
+            }
+        });
+        match result {
+            Ok(msg) => println!("{}: ok, {}", name, msg),
+            Err(msg) => {
+                println!("{}: FAILED, {}", name, msg);
+                passed = false;
+            }
+        }
+    }
+    passed
+}
@@ -0,0 +1,413 @@
+// A 1541 disk drive with its own 6502 running the DOS from ROM, for the
+// programs that talk to the drive directly instead of through the KERNAL.
+// VIA1 connects the drive to the serial bus, VIA2 to the mechanics: the
+// stepper motor moving the head by half-tracks, the spindle motor and the
+// read/write electronics that turn the GCR bit stream into bytes.
+//
+// The drive is clocked at 1 MHz. The bit cells of the disk are counted in
+// 4 MHz ticks, 16 minus the speed zone per bit. Reading goes at the speed
+// the track was written with, writing at the one selected by the DOS.
+
+use std::fs::File;
+use std::io::prelude::*;
+
+use gcr::{GcrDisk, HALF_TRACKS};
+use iec::Lines;
+use memory::{ReadView, WriteView};
+use mos6510::Mos6510;
+use via::Via;
+
+pub const CLOCK_FREQUENCY: u32 = 1_000_000;
+
+const RAM_SIZE: usize = 2048;
+const ROM_SIZE: usize = 16384;
+
+// VIA1 port B, the serial bus. The inputs are inverted, the outputs pull the
+// line low when set.
+const BUS_DATA_IN: u8 = 0b0000_0001;
+const BUS_DATA_OUT: u8 = 0b0000_0010;
+const BUS_CLK_IN: u8 = 0b0000_0100;
+const BUS_CLK_OUT: u8 = 0b0000_1000;
+// Acknowledges ATN, DATA is pulled low while it differs from the ATN input
+const BUS_ATN_ACK: u8 = 0b0001_0000;
+// Device number jumpers, read as the device number minus 8
+const DEVICE_SHIFT: u8 = 5;
+const BUS_ATN_IN: u8 = 0b1000_0000;
+
+// VIA2 port B, the drive mechanics
+const STEPPER_PHASE: u8 = 0b0000_0011;
+const MOTOR_ON: u8 = 0b0000_0100;
+const LED_ON: u8 = 0b0000_1000;
+// Low when the disk is write protected
+const WRITE_ENABLED: u8 = 0b0001_0000;
+const DENSITY: u8 = 0b0110_0000;
+const DENSITY_SHIFT: u8 = 5;
+// Low while the head is over a sync mark
+const NO_SYNC: u8 = 0b1000_0000;
+
+const TICKS_PER_CYCLE: u8 = 4;
+// Ten ones in a row are a sync mark
+const SYNC_BITS: u16 = 0x3FF;
+// The head rests on track 18 at power on
+const INITIAL_HALF_TRACK: usize = 34;
+
+struct DriveMemory<'a> {
+    ram: &'a mut [u8],
+    rom: &'a [u8],
+    via1: &'a mut Via,
+    via2: &'a mut Via
+}
+
+impl<'a> DriveMemory<'a> {
+    fn new(ram: &'a mut [u8], rom: &'a [u8], via1: &'a mut Via, via2: &'a mut Via) -> DriveMemory<'a> {
+        DriveMemory {
+            ram,
+            rom,
+            via1,
+            via2
+        }
+    }
+}
+
+// The ROM is at $C000-$FFFF and mirrored at $8000. Below that only 13
+// address lines are decoded: RAM at $0000-$07FF, VIA1 at $1800 and VIA2 at
+// $1C00, with their mirrors.
+impl<'a> ReadView for DriveMemory<'a> {
+    fn read(self: &mut DriveMemory<'a>, addr: u16) -> u8 {
+        if addr >= 0x8000 {
+            self.rom[addr as usize & (ROM_SIZE - 1)]
+        } else {
+            match addr & 0x1C00 {
+                0x0000 | 0x0400 => self.ram[addr as usize & (RAM_SIZE - 1)],
+                0x1800 => self.via1.read(addr),
+                0x1C00 => self.via2.read(addr),
+                // Nothing drives the data bus, the high byte of the address is left on it
+                _ => (addr >> 8) as u8
+            }
+        }
+    }
+}
+
+impl<'a> WriteView for DriveMemory<'a> {
+    fn write(self: &mut DriveMemory<'a>, addr: u16, value: u8) -> () {
+        if addr < 0x8000 {
+            match addr & 0x1C00 {
+                0x0000 | 0x0400 => self.ram[addr as usize & (RAM_SIZE - 1)] = value,
+                0x1800 => self.via1.write(addr, value),
+                0x1C00 => self.via2.write(addr, value),
+                _ => ()
+            }
+        }
+    }
+}
+
+pub struct Drive1541 {
+    cpu: Mos6510,
+    ram: [u8; RAM_SIZE],
+    rom: Vec<u8>,
+    via1: Via,
+    via2: Via,
+    device: u8,
+    disk: Option<GcrDisk>,
+    half_track: usize,
+    stepper_phase: u8,
+    // Position of the head on the track
+    byte_position: usize,
+    bit_position: u8,
+    // 4 MHz ticks into the current bit cell
+    bit_clock: u8,
+    // Last bits read, for finding sync marks
+    read_register: u16,
+    write_register: u8,
+    bits_in_byte: u8,
+    writing: bool,
+    // The serial bus lines that are low
+    bus: Lines
+}
+
+impl Drive1541 {
+    pub fn new(device: u8) -> Drive1541 {
+        Drive1541 {
+            cpu: Mos6510::new(),
+            ram: [0; RAM_SIZE],
+            rom: vec![0; ROM_SIZE],
+            via1: Via::new(),
+            via2: Via::new(),
+            device,
+            disk: None,
+            half_track: INITIAL_HALF_TRACK,
+            stepper_phase: 0,
+            byte_position: 0,
+            bit_position: 0,
+            bit_clock: 0,
+            read_register: 0,
+            write_register: 0,
+            bits_in_byte: 0,
+            writing: false,
+            bus: Lines::default()
+        }
+    }
+
+    /// Loads the 16K DOS ROM.
+    pub fn load_rom(self: &mut Drive1541, filename: &str) -> Result<(), String> {
+        let mut bytes = Vec::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|err| format!("Failed to read {}: {}", filename, err))?;
+        if bytes.len() != ROM_SIZE {
+            return Err(format!("{}: expected a {} byte 1541 ROM, got {} bytes", filename, ROM_SIZE, bytes.len()));
+        }
+        self.rom = bytes;
+        Ok(())
+    }
+
+    pub fn insert_disk(self: &mut Drive1541, disk: Option<GcrDisk>) {
+        self.disk = disk;
+        self.byte_position = 0;
+        self.bit_position = 0;
+    }
+
+    pub fn disk(self: &Drive1541) -> Option<&GcrDisk> {
+        self.disk.as_ref()
+    }
+
+    pub fn disk_mut(self: &mut Drive1541) -> Option<&mut GcrDisk> {
+        self.disk.as_mut()
+    }
+
+    /// Resets the CPU and the VIAs, as the RESET line of the serial bus does.
+    pub fn reset(self: &mut Drive1541) {
+        self.via1 = Via::new();
+        self.via2 = Via::new();
+        self.writing = false;
+        self.cpu.reset(&mut DriveMemory::new(&mut self.ram, &self.rom, &mut self.via1, &mut self.via2));
+    }
+
+    /// The CLK and DATA lines the drive pulls low, given the level of ATN.
+    pub fn bus_outputs(self: &Drive1541, atn: bool) -> Lines {
+        let pins = self.via1.port_b_pins();
+        let atn_ack = pins & BUS_ATN_ACK > 0;
+        Lines {
+            clk: pins & BUS_CLK_OUT > 0,
+            data: pins & BUS_DATA_OUT > 0 || atn != atn_ack,
+            ..Lines::default()
+        }
+    }
+
+    /// Sets the serial bus lines that are low.
+    pub fn set_bus_inputs(self: &mut Drive1541, bus: Lines) {
+        self.bus = bus;
+    }
+
+    fn track_length(self: &Drive1541, half_track: usize) -> usize {
+        self.disk.as_ref().map_or(0, |disk| disk.track(half_track).len())
+    }
+
+    // The stepper motor moves the head inwards a half-track when the phase
+    // is increased and outwards when it's decreased
+    fn step_head(self: &mut Drive1541, phase: u8) {
+        let half_track = if phase == (self.stepper_phase + 1) & STEPPER_PHASE && self.half_track < HALF_TRACKS - 1 {
+            self.half_track + 1
+        } else if phase == self.stepper_phase.wrapping_sub(1) & STEPPER_PHASE && self.half_track > 0 {
+            self.half_track - 1
+        } else {
+            self.half_track
+        };
+        self.stepper_phase = phase;
+        if half_track != self.half_track {
+            // Keep the angle of the head, the tracks may differ in length
+            let (old_length, new_length) = (self.track_length(self.half_track), self.track_length(half_track));
+            if new_length > 0 {
+                self.byte_position = (self.byte_position * new_length).checked_div(old_length).unwrap_or(self.byte_position % new_length);
+            }
+            self.half_track = half_track;
+        }
+    }
+
+    fn byte_ready(self: &mut Drive1541) {
+        // The byte ready signal sets the overflow flag when enabled by CA2
+        if self.via2.ca2() {
+            self.cpu.set_overflow();
+        }
+        self.via2.set_ca1(false);
+        self.via2.set_ca1(true);
+    }
+
+    // Reads or writes the bit cell under the head. CB2 selects write mode.
+    fn next_bit(self: &mut Drive1541) {
+        let writing = !self.via2.cb2();
+        if writing && !self.writing {
+            self.write_register = self.via2.port_a_pins();
+            self.bits_in_byte = 0;
+        }
+        self.writing = writing;
+
+        let length = self.track_length(self.half_track);
+        let mut bit = false;
+        if length > 0 {
+            let mask = 0x80 >> self.bit_position;
+            let disk = self.disk.as_mut().unwrap();
+            // The write protect sensor cuts the write current as well
+            if writing && !disk.write_protected() {
+                bit = self.write_register & 0x80 > 0;
+                let track = disk.track_mut(self.half_track);
+                if bit {
+                    track[self.byte_position] |= mask;
+                } else {
+                    track[self.byte_position] &= !mask;
+                }
+            } else if !writing {
+                bit = disk.track(self.half_track)[self.byte_position] & mask > 0;
+            }
+            self.bit_position += 1;
+            if self.bit_position == 8 {
+                self.bit_position = 0;
+                self.byte_position = (self.byte_position + 1) % length;
+            }
+        }
+
+        if writing {
+            self.write_register <<= 1;
+            self.read_register = 0;
+        } else {
+            self.read_register = (self.read_register << 1 | bit as u16) & SYNC_BITS;
+            if self.read_register == SYNC_BITS {
+                self.bits_in_byte = 0;
+                return;
+            }
+        }
+        self.bits_in_byte += 1;
+        if self.bits_in_byte == 8 {
+            self.bits_in_byte = 0;
+            if writing {
+                self.write_register = self.via2.port_a_pins();
+            } else {
+                self.via2.set_port_a_input(self.read_register as u8);
+            }
+            self.byte_ready();
+        }
+    }
+
+    fn rotate_disk(self: &mut Drive1541) {
+        let pins = self.via2.port_b_pins();
+        self.step_head(pins & STEPPER_PHASE);
+        if pins & MOTOR_ON > 0 {
+            let zone = match self.disk {
+                Some(ref disk) if !self.writing && !disk.track(self.half_track).is_empty() => disk.speed(self.half_track, self.byte_position),
+                _ => (pins & DENSITY) >> DENSITY_SHIFT
+            };
+            let bit_cell = 16 - zone;
+            self.bit_clock += TICKS_PER_CYCLE;
+            if self.bit_clock >= bit_cell {
+                self.bit_clock -= bit_cell;
+                self.next_bit();
+            }
+        }
+        let sync = !self.writing && self.read_register == SYNC_BITS;
+        let write_enabled = !self.disk.as_ref().is_some_and(|disk| disk.write_protected());
+        self.via2.set_port_b_input(if write_enabled { WRITE_ENABLED } else { 0 } | if sync { 0 } else { NO_SYNC });
+    }
+
+    /// Runs the drive for one cycle of its 1 MHz clock.
+    pub fn tick(self: &mut Drive1541) -> Result<(), String> {
+        let mut bus = (self.device - 8) << DEVICE_SHIFT;
+        if self.bus.data {
+            bus |= BUS_DATA_IN;
+        }
+        if self.bus.clk {
+            bus |= BUS_CLK_IN;
+        }
+        if self.bus.atn {
+            bus |= BUS_ATN_IN;
+        }
+        self.via1.set_port_b_input(bus);
+        // ATN reaches CA1 through an inverter
+        self.via1.set_ca1(self.bus.atn);
+
+        let irq = self.via1.tick() | self.via2.tick();
+        self.rotate_disk();
+        self.cpu.tick(&mut DriveMemory::new(&mut self.ram, &self.rom, &mut self.via1, &mut self.via2), irq, false)
+            .map(|_| ())
+            .map_err(|msg| format!("1541: {}", msg))
+    }
+
+    pub fn print_status(self: &Drive1541) {
+        let pins = self.via2.port_b_pins();
+        let disk = match self.disk {
+            Some(ref disk) if disk.modified() => "modified",
+            Some(_) => "inserted",
+            None => "none"
+        };
+        println!("1541 device {}  track {}{}  motor: {}  LED: {}  disk: {}",
+            self.device,
+            self.half_track / 2 + 1,
+            if self.half_track % 2 == 1 { ".5" } else { "" },
+            if pins & MOTOR_ON > 0 { "on" } else { "off" },
+            if pins & LED_ON > 0 { "on" } else { "off" },
+            disk);
+        self.cpu.print_status();
+        self.via1.print_status();
+        self.via2.print_status();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use d64::DiskImage;
+
+    // Loads an image from a file made read-only or left writable
+    fn load(name: &str, read_only: bool, save: fn(&str)) -> GcrDisk {
+        let path = std::env::temp_dir().join(name);
+        let filename = path.to_str().unwrap();
+        save(filename);
+        let mut permissions = fs::metadata(filename).unwrap().permissions();
+        permissions.set_readonly(read_only);
+        fs::set_permissions(filename, permissions).unwrap();
+        let disk = if filename.ends_with(".d64") {
+            DiskImage::load(filename).map(|disk| GcrDisk::from_d64(&disk))
+        } else {
+            GcrDisk::load(filename)
+        };
+        fs::remove_file(filename).unwrap();
+        disk.unwrap()
+    }
+
+    fn save_d64(filename: &str) {
+        let _ = fs::remove_file(filename);
+        fs::write(filename, vec![0; 683 * 256]).unwrap();
+    }
+
+    fn save_g64(filename: &str) {
+        let _ = fs::remove_file(filename);
+        GcrDisk::from_d64(&DiskImage::parse(filename, vec![0; 683 * 256]).unwrap()).write_g64(filename).unwrap();
+    }
+
+    // Spins the disk with the head writing zeros, returns the port B input
+    // bits of the sensors and whether the track changed
+    fn write_zeros(disk: GcrDisk) -> (u8, bool) {
+        let mut drive = Drive1541::new(8);
+        drive.insert_disk(Some(disk));
+        let before = drive.disk().unwrap().track(INITIAL_HALF_TRACK).to_vec();
+        drive.via2.write(0x2, MOTOR_ON);
+        drive.via2.write(0x0, MOTOR_ON);
+        drive.via2.write(0x3, 0xFF);
+        drive.via2.write(0x1, 0x00);
+        // CB2 low selects write mode
+        drive.via2.write(0xC, 0b1100_0000);
+        for _ in 0..1000 {
+            drive.rotate_disk();
+        }
+        let changed = drive.disk().unwrap().track(INITIAL_HALF_TRACK) != &before[..];
+        (drive.via2.port_b_pins() & WRITE_ENABLED, changed)
+    }
+
+    #[test]
+    fn senses_read_only_images_as_write_protected() {
+        assert_eq!(write_zeros(load("c64emu-drive-rw.d64", false, save_d64)), (WRITE_ENABLED, true));
+        assert_eq!(write_zeros(load("c64emu-drive-ro.d64", true, save_d64)), (0, false));
+        assert_eq!(write_zeros(load("c64emu-drive-rw.g64", false, save_g64)), (WRITE_ENABLED, true));
+        assert_eq!(write_zeros(load("c64emu-drive-ro.g64", true, save_g64)), (0, false));
+    }
+}
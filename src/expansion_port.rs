@@ -0,0 +1,166 @@
+// The expansion port, where cartridges pull the EXROM and GAME lines low
+// to change the memory map decoded by the PLA, and answer reads of the
+// ROML and ROMH windows and of the IO1 and IO2 areas. An REU takes IO2
+// and the bus for its DMA transfers, a GeoRAM IO1 and the top of IO2, a
+// cartridge can sit behind them.
+
+use std::mem;
+
+use cartridge;
+use cartridge::Cartridge;
+use georam::GeoRam;
+use reu::{DmaAccess, Reu};
+
+pub struct ExpansionPort {
+    cartridge: Option<Cartridge>,
+    reu: Option<Reu>,
+    georam: Option<GeoRam>
+}
+
+impl ExpansionPort {
+    pub fn new() -> ExpansionPort {
+        ExpansionPort {
+            cartridge: None,
+            reu: None,
+            georam: None
+        }
+    }
+
+    /// Plugs in a cartridge and returns the one pulled out.
+    pub fn attach(self: &mut ExpansionPort, cartridge: Option<Cartridge>) -> Option<Cartridge> {
+        mem::replace(&mut self.cartridge, cartridge)
+    }
+
+    pub fn cartridge_mut(self: &mut ExpansionPort) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+
+    /// Plugs in an REU and returns the one pulled out.
+    pub fn attach_reu(self: &mut ExpansionPort, reu: Option<Reu>) -> Option<Reu> {
+        mem::replace(&mut self.reu, reu)
+    }
+
+    pub fn reu(self: &ExpansionPort) -> Option<&Reu> {
+        self.reu.as_ref()
+    }
+
+    /// Plugs in a GeoRAM and returns the one pulled out.
+    pub fn attach_georam(self: &mut ExpansionPort, georam: Option<GeoRam>) -> Option<GeoRam> {
+        mem::replace(&mut self.georam, georam)
+    }
+
+    pub fn georam(self: &ExpansionPort) -> Option<&GeoRam> {
+        self.georam.as_ref()
+    }
+
+    pub fn georam_mut(self: &mut ExpansionPort) -> Option<&mut GeoRam> {
+        self.georam.as_mut()
+    }
+
+    /// Whether nothing is plugged in.
+    pub fn is_empty(self: &ExpansionPort) -> bool {
+        self.cartridge.is_none() && self.reu.is_none() && self.georam.is_none()
+    }
+
+    pub fn reset(self: &mut ExpansionPort) {
+        if let Some(ref mut cartridge) = self.cartridge {
+            cartridge.reset();
+        }
+        if let Some(ref mut reu) = self.reu {
+            reu.reset();
+        }
+        if let Some(ref mut georam) = self.georam {
+            georam.reset();
+        }
+    }
+
+    pub fn irq(self: &ExpansionPort) -> bool {
+        self.reu.as_ref().is_some_and(|reu| reu.irq())
+    }
+
+    pub fn write_ff00(self: &mut ExpansionPort) {
+        if let Some(ref mut reu) = self.reu {
+            reu.write_ff00();
+        }
+    }
+
+    /// The access of the next DMA cycle, during which the CPU is halted.
+    pub fn dma_access(self: &ExpansionPort) -> Option<DmaAccess> {
+        self.reu.as_ref().and_then(|reu| reu.dma_access())
+    }
+
+    pub fn dma_done(self: &mut ExpansionPort, value: u8) {
+        if let Some(ref mut reu) = self.reu {
+            reu.dma_done(value);
+        }
+    }
+
+    /// The level of the EXROM line, which is pulled up without a cartridge.
+    pub fn exrom(self: &ExpansionPort) -> bool {
+        self.cartridge.as_ref().is_none_or(|cartridge| cartridge.exrom())
+    }
+
+    pub fn game(self: &ExpansionPort) -> bool {
+        self.cartridge.as_ref().is_none_or(|cartridge| cartridge.game())
+    }
+
+    /// GAME low with EXROM high replaces the KERNAL by ROMH and leaves most
+    /// of the RAM unmapped.
+    pub fn ultimax(self: &ExpansionPort) -> bool {
+        !self.game() && self.exrom()
+    }
+
+    pub fn read_roml(self: &ExpansionPort, addr: u16) -> u8 {
+        self.cartridge.as_ref().map_or(cartridge::OPEN_BUS, |cartridge| cartridge.read_roml(addr))
+    }
+
+    pub fn read_romh(self: &ExpansionPort, addr: u16) -> u8 {
+        self.cartridge.as_ref().map_or(cartridge::OPEN_BUS, |cartridge| cartridge.read_romh(addr))
+    }
+
+    pub fn write_roml(self: &mut ExpansionPort, addr: u16, value: u8) {
+        if let Some(ref mut cartridge) = self.cartridge {
+            cartridge.write_roml(addr, value);
+        }
+    }
+
+    pub fn write_romh(self: &mut ExpansionPort, addr: u16, value: u8) {
+        if let Some(ref mut cartridge) = self.cartridge {
+            cartridge.write_romh(addr, value);
+        }
+    }
+
+    /// Reads from $DE00-$DFFF, None if nothing answers.
+    pub fn read_io(self: &mut ExpansionPort, addr: u16) -> Option<u8> {
+        if let Some(ref mut reu) = self.reu {
+            if addr >= cartridge::IO2_START {
+                return Some(reu.read(addr));
+            }
+        }
+        if let Some(value) = self.georam.as_ref().and_then(|georam| georam.read_io(addr)) {
+            return Some(value);
+        }
+        self.cartridge.as_mut().and_then(|cartridge| cartridge.read_io(addr))
+    }
+
+    /// Writes to $DE00-$DFFF, returns false if nothing decodes the address.
+    pub fn write_io(self: &mut ExpansionPort, addr: u16, value: u8) -> bool {
+        if let Some(ref mut reu) = self.reu {
+            if addr >= cartridge::IO2_START {
+                reu.write(addr, value);
+                return true;
+            }
+        }
+        if self.georam.as_mut().is_some_and(|georam| georam.write_io(addr, value)) {
+            return true;
+        }
+        self.cartridge.as_mut().is_some_and(|cartridge| cartridge.write_io(addr, value))
+    }
+
+    pub fn print_cartridge_status(self: &ExpansionPort) {
+        match self.cartridge {
+            Some(ref cartridge) => cartridge.print_status(),
+            None => println!("No cartridge attached")
+        }
+    }
+}
@@ -0,0 +1,695 @@
+// The disk surface as the 1541 head sees it: a stream of GCR encoded bytes
+// for every half-track. Every 4 bits of data are written as 5 bits with no
+// more than two zeros in a row, so that the drive can keep its bit clock in
+// step, and ten or more ones in a row form a sync mark. A sector is a header
+// block and a data block, each preceded by a sync mark:
+//
+//   header: $08, checksum, sector, track, ID 2, ID 1, $0F, $0F
+//   data:   $07, 256 bytes, checksum, $00, $00
+//
+// The tracks are divided into four speed zones. The outer ones are longer
+// and are written at a higher bit rate to fit more sectors. Copy protections
+// vary the speed within a track as well.
+//
+// Besides the D64 sector images, which are encoded as a freshly formatted
+// disk would be and decoded back when saved, the surface can be loaded from
+// G64 images and NIB dumps. A G64 image has a table of half-tracks with the
+// GCR bytes and speed zone of each, a NIB dump has the bytes read from every
+// track as they came, with more than one revolution of it.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::iter;
+
+use d64::{self, DiskImage};
+
+/// Half-tracks 0-83 cover tracks 1 to 42, full tracks are at even indices.
+pub const HALF_TRACKS: usize = 84;
+
+// Bytes per track at 300 rpm in speed zones 0-3
+const TRACK_CAPACITY: [usize; 4] = [6250, 6666, 7142, 7692];
+
+const G64_SIGNATURE: &[u8] = b"GCR-1541";
+const G64_HEADER_SIZE: usize = 12;
+// Space reserved for every track, as written by other tools
+const G64_TRACK_SIZE: usize = 7928;
+
+const NIB_SIGNATURE: &[u8] = b"MNIB-1541-RAW";
+const NIB_TRACK_TABLE: usize = 0x10;
+const NIB_HEADER_SIZE: usize = 0x100;
+const NIB_TRACK_SIZE: usize = 0x2000;
+// Bytes compared to find where the revolution of a NIB track repeats
+const NIB_MATCH_LENGTH: usize = 32;
+
+const GCR_CODES: [u8; 16] = [
+    0x0A, 0x0B, 0x12, 0x13, 0x0E, 0x0F, 0x16, 0x17,
+    0x09, 0x19, 0x1A, 0x1B, 0x0D, 0x1D, 0x1E, 0x15
+];
+
+const SYNC_LENGTH: usize = 5;
+// Gap between the header and the data block
+const HEADER_GAP_LENGTH: usize = 9;
+const GAP_BYTE: u8 = 0x55;
+// Lengths of the encoded header and data blocks
+const HEADER_GCR_LENGTH: usize = 10;
+const DATA_GCR_LENGTH: usize = 325;
+const HEADER_BLOCK_ID: u8 = 0x08;
+const DATA_BLOCK_ID: u8 = 0x07;
+
+// D64 error codes that change how a sector is written
+const ERROR_HEADER_NOT_FOUND: u8 = 2;
+const ERROR_NO_SYNC: u8 = 3;
+const ERROR_DATA_NOT_FOUND: u8 = 4;
+const ERROR_DATA_CHECKSUM: u8 = 5;
+const ERROR_HEADER_CHECKSUM: u8 = 9;
+const ERROR_ID_MISMATCH: u8 = 11;
+
+/// Speed zone of a track, 3 for the outermost tracks 1-17.
+pub fn speed_zone(track: u8) -> u8 {
+    match track {
+        1..=17 => 3,
+        18..=24 => 2,
+        25..=30 => 1,
+        _ => 0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ImageFormat {
+    D64,
+    G64,
+    Nib
+}
+
+// Encodes 4 bytes as 5 GCR bytes
+fn encode_group(bytes: &[u8], output: &mut Vec<u8>) {
+    let mut bits: u64 = 0;
+    for &byte in bytes {
+        bits = bits << 10 | (GCR_CODES[(byte >> 4) as usize] as u64) << 5 | GCR_CODES[(byte & 0x0F) as usize] as u64;
+    }
+    for i in (0..5).rev() {
+        output.push((bits >> (i * 8)) as u8);
+    }
+}
+
+fn encode(bytes: &[u8], output: &mut Vec<u8>) {
+    for group in bytes.chunks(4) {
+        encode_group(group, output);
+    }
+}
+
+// Decodes 5 GCR bytes into 4 bytes, None for invalid codes
+fn decode_group(gcr: &[u8], output: &mut Vec<u8>) -> Option<()> {
+    let bits = gcr.iter().fold(0u64, |bits, &byte| bits << 8 | byte as u64);
+    for i in (0..4).rev() {
+        let high = GCR_CODES.iter().position(|&code| code as u64 == (bits >> (i * 10 + 5)) & 0x1F)?;
+        let low = GCR_CODES.iter().position(|&code| code as u64 == (bits >> (i * 10)) & 0x1F)?;
+        output.push((high << 4 | low) as u8);
+    }
+    Some(())
+}
+
+fn decode(gcr: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(gcr.len() * 4 / 5);
+    for group in gcr.chunks(5) {
+        decode_group(group, &mut output)?;
+    }
+    Some(output)
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, &byte| sum ^ byte)
+}
+
+// Reads bytes from a track starting at any bit, wrapping around the end
+fn read_bits(track: &[u8], bit_position: usize, length: usize) -> Vec<u8> {
+    let bits = track.len() * 8;
+    (0..length).map(|i| {
+        (0..8).fold(0, |byte, bit| {
+            let position = (bit_position + i * 8 + bit) % bits;
+            byte << 1 | (track[position / 8] >> (7 - position % 8)) & 1
+        })
+    }).collect()
+}
+
+// Bit positions where the sync marks of a track end
+fn find_syncs(track: &[u8]) -> Vec<usize> {
+    let bits = track.len() * 8;
+    let mut syncs = Vec::new();
+    let mut ones = 0;
+    // The first revolution only counts the ones a sync at the start needs
+    for position in 0..2 * bits {
+        if (track[position % bits / 8] >> (7 - position % 8)) & 1 == 1 {
+            ones += 1;
+        } else {
+            if ones >= 10 && position >= bits {
+                syncs.push(position - bits);
+            }
+            ones = 0;
+        }
+    }
+    syncs
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> usize {
+    bytes[offset] as usize | (bytes[offset + 1] as usize) << 8
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> usize {
+    read_u16(bytes, offset) | read_u16(bytes, offset + 2) << 16
+}
+
+fn push_u32(output: &mut Vec<u8>, value: usize) {
+    output.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+}
+
+// Appends the sync mark, header block, gap, sync mark and data block of a
+// sector, with the damage its error code calls for
+fn encode_sector(track: u8, sector: u8, id: (u8, u8), data: &[u8], error: u8, output: &mut Vec<u8>) {
+    let sync = if error == ERROR_NO_SYNC { GAP_BYTE } else { 0xFF };
+    let (id1, id2) = if error == ERROR_ID_MISMATCH { (id.0 ^ 0xFF, id.1) } else { id };
+
+    let mut header = [HEADER_BLOCK_ID, 0, sector, track, id2, id1, 0x0F, 0x0F];
+    header[1] = checksum(&header[2..6]);
+    if error == ERROR_HEADER_NOT_FOUND {
+        header[0] = 0;
+    }
+    if error == ERROR_HEADER_CHECKSUM {
+        header[1] ^= 0xFF;
+    }
+    output.extend(iter::repeat_n(sync, SYNC_LENGTH));
+    encode(&header, output);
+    output.extend(iter::repeat_n(GAP_BYTE, HEADER_GAP_LENGTH));
+
+    let mut block = Vec::with_capacity(260);
+    block.push(if error == ERROR_DATA_NOT_FOUND { 0 } else { DATA_BLOCK_ID });
+    block.extend_from_slice(data);
+    let data_checksum = checksum(data);
+    block.push(if error == ERROR_DATA_CHECKSUM { data_checksum ^ 0xFF } else { data_checksum });
+    block.extend_from_slice(&[0, 0]);
+    output.extend(iter::repeat_n(sync, SYNC_LENGTH));
+    encode(&block, output);
+}
+
+pub struct GcrDisk {
+    filename: String,
+    format: ImageFormat,
+    // Empty for half-tracks without data
+    tracks: Vec<Vec<u8>>,
+    // Speed zone of every half-track, or of every byte of it
+    speeds: Vec<Vec<u8>>,
+    // The image file can't be written, the drive senses the disk as write
+    // protected and doesn't change it
+    write_protected: bool,
+    modified: bool
+}
+
+impl GcrDisk {
+    /// Encodes the sectors of a D64 image as a freshly formatted disk
+    /// would have them. Tracks beyond those of the image are unformatted.
+    pub fn from_d64(disk: &DiskImage) -> GcrDisk {
+        let id = (disk.disk_id()[0], disk.disk_id()[1]);
+        let mut tracks = vec![Vec::new(); HALF_TRACKS];
+        let mut speeds = vec![vec![0]; HALF_TRACKS];
+        for track in 1..=(HALF_TRACKS / 2) as u8 {
+            let zone = speed_zone(track);
+            let capacity = TRACK_CAPACITY[zone as usize];
+            let mut data = Vec::with_capacity(capacity);
+            if track <= disk.tracks() {
+                let sectors = d64::sectors_per_track(track);
+                // The space left over is spread between the sectors
+                let sector_length = 2 * SYNC_LENGTH + HEADER_GCR_LENGTH + HEADER_GAP_LENGTH + DATA_GCR_LENGTH;
+                let gap_length = (capacity - sectors as usize * sector_length) / sectors as usize;
+                for sector in 0..sectors {
+                    let (contents, error) = disk.raw_sector(track, sector);
+                    encode_sector(track, sector, id, contents, error, &mut data);
+                    data.extend(iter::repeat_n(GAP_BYTE, gap_length));
+                }
+                let padding = capacity - data.len();
+                data.extend(iter::repeat_n(GAP_BYTE, padding));
+            } else {
+                data.resize(capacity, 0);
+            }
+            let half_track = (track as usize - 1) * 2;
+            tracks[half_track] = data;
+            speeds[half_track] = vec![zone];
+        }
+        GcrDisk {
+            filename: disk.filename().to_string(),
+            format: ImageFormat::D64,
+            tracks,
+            speeds,
+            write_protected: disk.read_only(),
+            modified: false
+        }
+    }
+
+    /// Loads a G64 image or a NIB dump.
+    pub fn load(filename: &str) -> Result<GcrDisk, String> {
+        let mut bytes = Vec::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|err| format!("Failed to read {}: {}", filename, err))?;
+        let disk = if bytes.starts_with(G64_SIGNATURE) {
+            // NIB dumps are saved to a new G64 image, only G64 images can
+            // be protected by their file
+            GcrDisk::parse_g64(filename, &bytes).map(|mut disk| {
+                disk.write_protected = d64::is_read_only(filename);
+                disk
+            })
+        } else if bytes.starts_with(NIB_SIGNATURE) {
+            GcrDisk::parse_nib(filename, &bytes)
+        } else {
+            Err("Not a G64 or NIB image".to_string())
+        };
+        disk.map_err(|msg| format!("{}: {}", filename, msg))
+    }
+
+    fn parse_g64(filename: &str, bytes: &[u8]) -> Result<GcrDisk, String> {
+        let truncated = || "Truncated G64 image".to_string();
+        if bytes.len() < G64_HEADER_SIZE {
+            return Err(truncated());
+        }
+        let count = bytes[9] as usize;
+        if count > HALF_TRACKS {
+            return Err(format!("Too many tracks: {}", count));
+        }
+        let speed_table = G64_HEADER_SIZE + count * 4;
+        if bytes.len() < speed_table + count * 4 {
+            return Err(truncated());
+        }
+        let mut tracks = vec![Vec::new(); HALF_TRACKS];
+        let mut speeds = vec![vec![0]; HALF_TRACKS];
+        for half_track in 0..count {
+            let offset = read_u32(bytes, G64_HEADER_SIZE + half_track * 4);
+            if offset == 0 {
+                continue;
+            }
+            if bytes.len() < offset + 2 || bytes.len() < offset + 2 + read_u16(bytes, offset) {
+                return Err(truncated());
+            }
+            let length = read_u16(bytes, offset);
+            tracks[half_track] = bytes[offset + 2..offset + 2 + length].to_vec();
+            // Zones 0-3 apply to the whole track, anything else is the offset
+            // of a map with the zones of every byte, four to a byte
+            speeds[half_track] = match read_u32(bytes, speed_table + half_track * 4) {
+                zone @ 0..=3 => vec![zone as u8],
+                map => {
+                    if bytes.len() < map + length.div_ceil(4) {
+                        return Err(truncated());
+                    }
+                    (0..length).map(|i| (bytes[map + i / 4] >> (6 - 2 * (i % 4))) & 0b11).collect()
+                }
+            };
+        }
+        Ok(GcrDisk {
+            filename: filename.to_string(),
+            format: ImageFormat::G64,
+            tracks,
+            speeds,
+            write_protected: false,
+            modified: false
+        })
+    }
+
+    fn parse_nib(filename: &str, bytes: &[u8]) -> Result<GcrDisk, String> {
+        if bytes.len() < NIB_HEADER_SIZE {
+            return Err("Truncated NIB image".to_string());
+        }
+        let mut tracks = vec![Vec::new(); HALF_TRACKS];
+        let mut speeds = vec![vec![0]; HALF_TRACKS];
+        // Pairs of half-track number, with track 1 at 2, and density
+        for (i, entry) in bytes[NIB_TRACK_TABLE..NIB_HEADER_SIZE].chunks(2).enumerate() {
+            let half_track = entry[0] as usize;
+            if half_track < 2 || half_track - 2 >= HALF_TRACKS {
+                break;
+            }
+            let offset = NIB_HEADER_SIZE + i * NIB_TRACK_SIZE;
+            if bytes.len() < offset + NIB_TRACK_SIZE {
+                return Err("Truncated NIB image".to_string());
+            }
+            let zone = entry[1] & 0b11;
+            tracks[half_track - 2] = GcrDisk::nib_revolution(&bytes[offset..offset + NIB_TRACK_SIZE], zone);
+            speeds[half_track - 2] = vec![zone];
+        }
+        Ok(GcrDisk {
+            filename: filename.to_string(),
+            format: ImageFormat::Nib,
+            tracks,
+            speeds,
+            write_protected: false,
+            modified: false
+        })
+    }
+
+    // One revolution of a NIB track: from the first sync mark to where the
+    // bytes following it come around again. Without a sync mark, or if they
+    // don't, the length the speed zone gives.
+    fn nib_revolution(data: &[u8], zone: u8) -> Vec<u8> {
+        let capacity = TRACK_CAPACITY[zone as usize];
+        let start = data.windows(2).position(|pair| pair[0] == 0xFF && pair[1] != 0xFF).map_or(0, |i| i + 1);
+        if start + capacity + NIB_MATCH_LENGTH <= data.len() {
+            let pattern = &data[start..start + NIB_MATCH_LENGTH];
+            let shortest = capacity * 9 / 10;
+            let repeat = data[start + shortest..].windows(NIB_MATCH_LENGTH).position(|window| window == pattern);
+            if let Some(length) = repeat.map(|i| i + shortest) {
+                return data[start..start + length].to_vec();
+            }
+        }
+        data[..capacity.min(data.len())].to_vec()
+    }
+
+    /// GCR bytes of a half-track, empty if it has no data.
+    pub fn track(self: &GcrDisk, half_track: usize) -> &[u8] {
+        &self.tracks[half_track]
+    }
+
+    pub fn track_mut(self: &mut GcrDisk, half_track: usize) -> &mut [u8] {
+        self.modified = true;
+        &mut self.tracks[half_track]
+    }
+
+    /// Speed zone the byte at the given position was written in.
+    pub fn speed(self: &GcrDisk, half_track: usize, byte_position: usize) -> u8 {
+        let speeds = &self.speeds[half_track];
+        speeds[if speeds.len() > 1 { byte_position } else { 0 }]
+    }
+
+    pub fn write_protected(self: &GcrDisk) -> bool {
+        self.write_protected
+    }
+
+    /// Whether the drive has written to the disk since it was loaded.
+    pub fn modified(self: &GcrDisk) -> bool {
+        self.modified
+    }
+
+    pub fn filename(self: &GcrDisk) -> &str {
+        &self.filename
+    }
+
+    /// Whether the disk can be saved as a D64 image without losing anything
+    /// but the gaps, as for disks converted from one.
+    pub fn is_d64(self: &GcrDisk) -> bool {
+        self.format == ImageFormat::D64
+    }
+
+    /// Writes the disk back to its file. D64 images are decoded back to
+    /// sectors, NIB dumps are saved as G64 images next to them.
+    pub fn save(self: &mut GcrDisk) -> Result<(), String> {
+        match self.format {
+            ImageFormat::D64 => self.to_d64(&self.filename)?.save()?,
+            ImageFormat::G64 => self.write_g64(&self.filename)?,
+            ImageFormat::Nib => {
+                let filename = format!("{}.g64", self.filename.trim_end_matches(".nib").trim_end_matches(".NIB"));
+                self.write_g64(&filename)?;
+                self.filename = filename;
+                self.format = ImageFormat::G64;
+            }
+        }
+        self.modified = false;
+        Ok(())
+    }
+
+    /// Writes a G64 image of the disk.
+    pub fn write_g64(self: &GcrDisk, filename: &str) -> Result<(), String> {
+        let track_size = self.tracks.iter().map(|track| track.len()).max().unwrap_or(0).max(G64_TRACK_SIZE);
+        let mut bytes = G64_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0, HALF_TRACKS as u8, track_size as u8, (track_size >> 8) as u8]);
+        let mut offset = G64_HEADER_SIZE + HALF_TRACKS * 8;
+        for track in self.tracks.iter() {
+            push_u32(&mut bytes, if track.is_empty() { 0 } else { offset });
+            if !track.is_empty() {
+                offset += 2 + track_size;
+            }
+        }
+        // The maps of tracks with more than one speed follow the tracks
+        for (track, speeds) in self.tracks.iter().zip(self.speeds.iter()) {
+            if speeds.len() > 1 && !track.is_empty() {
+                push_u32(&mut bytes, offset);
+                offset += speeds.len().div_ceil(4);
+            } else {
+                push_u32(&mut bytes, speeds[0] as usize);
+            }
+        }
+        for track in self.tracks.iter().filter(|track| !track.is_empty()) {
+            bytes.extend_from_slice(&[track.len() as u8, (track.len() >> 8) as u8]);
+            bytes.extend_from_slice(track);
+            bytes.extend(iter::repeat_n(0, track_size - track.len()));
+        }
+        for (track, speeds) in self.tracks.iter().zip(self.speeds.iter()) {
+            if speeds.len() > 1 && !track.is_empty() {
+                bytes.extend(speeds.chunks(4).map(|zones| {
+                    zones.iter().enumerate().fold(0, |byte, (i, &zone)| byte | zone << (6 - 2 * i))
+                }));
+            }
+        }
+        File::create(filename)
+            .and_then(|mut f| f.write_all(&bytes))
+            .map_err(|err| format!("Failed to write {}: {}", filename, err))
+    }
+
+    // Sectors of a track decoded from the headers and data blocks that
+    // follow the sync marks. Sectors that can't be read have the D64 error
+    // code of what went wrong.
+    fn decode_track(self: &GcrDisk, track: u8, id: Option<(u8, u8)>) -> Vec<(Vec<u8>, u8)> {
+        let sectors = d64::sectors_per_track(track) as usize;
+        let data = self.track((track as usize - 1) * 2);
+        let syncs = if data.is_empty() { Vec::new() } else { find_syncs(data) };
+        let no_header = if syncs.is_empty() { ERROR_NO_SYNC } else { ERROR_HEADER_NOT_FOUND };
+        let mut result = vec![(vec![0; 256], no_header); sectors];
+        let mut found = vec![false; sectors];
+        for (i, &sync) in syncs.iter().enumerate() {
+            let header = match decode(&read_bits(data, sync, HEADER_GCR_LENGTH)) {
+                Some(ref header) if header[0] == HEADER_BLOCK_ID && header[3] == track => header.clone(),
+                _ => continue
+            };
+            let sector = header[2] as usize;
+            if sector >= sectors || found[sector] {
+                continue;
+            }
+            found[sector] = true;
+            let error = if header[1] != checksum(&header[2..6]) {
+                ERROR_HEADER_CHECKSUM
+            } else if id.map_or(false, |id| (header[5], header[4]) != id) {
+                ERROR_ID_MISMATCH
+            } else {
+                let next_sync = syncs[(i + 1) % syncs.len()];
+                match decode(&read_bits(data, next_sync, DATA_GCR_LENGTH)) {
+                    Some(ref block) if block[0] == DATA_BLOCK_ID => {
+                        result[sector].0 = block[1..257].to_vec();
+                        if block[257] == checksum(&block[1..257]) { d64::NO_ERROR } else { ERROR_DATA_CHECKSUM }
+                    }
+                    _ => ERROR_DATA_NOT_FOUND
+                }
+            };
+            result[sector].1 = error;
+        }
+        result
+    }
+
+    /// Decodes the sectors of the disk into a D64 image, with error bytes if
+    /// any sector can't be read. Tracks 36-40 are included when formatted.
+    pub fn to_d64(self: &GcrDisk, filename: &str) -> Result<DiskImage, String> {
+        // The ID of the directory track is the one of the disk
+        let id = self.decode_header_id(18);
+        let extended = self.decode_track(36, None).iter().any(|&(_, error)| error != ERROR_HEADER_NOT_FOUND && error != ERROR_NO_SYNC);
+        let tracks = if extended { 40 } else { 35 };
+        let mut data = Vec::new();
+        let mut errors = Vec::new();
+        for track in 1..=tracks {
+            for (contents, error) in self.decode_track(track, id) {
+                data.extend_from_slice(&contents);
+                errors.push(error);
+            }
+        }
+        if errors.iter().any(|&error| error != d64::NO_ERROR) {
+            data.extend_from_slice(&errors);
+        }
+        DiskImage::parse(filename, data)
+    }
+
+    // ID in the header of the first readable sector of a track
+    fn decode_header_id(self: &GcrDisk, track: u8) -> Option<(u8, u8)> {
+        let data = self.track((track as usize - 1) * 2);
+        if data.is_empty() {
+            return None;
+        }
+        find_syncs(data).iter()
+            .filter_map(|&sync| decode(&read_bits(data, sync, HEADER_GCR_LENGTH)))
+            .find(|header| header[0] == HEADER_BLOCK_ID && header[1] == checksum(&header[2..6]))
+            .map(|header| (header[5], header[4]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bytes that look random enough not to repeat within a track
+    fn noise(seed: u32, length: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..length).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    // A 35-track D64 image with different contents in every sector and the
+    // given error codes, ID AB
+    fn d64_image(errors: &[(u8, u8, u8)]) -> DiskImage {
+        let mut bytes = noise(1, 683 * 256);
+        // The BAM sector follows the 17 tracks of 21 sectors
+        let bam = 17 * 21 * 256;
+        bytes[bam + 0xA2] = b'A';
+        bytes[bam + 0xA3] = b'B';
+        if !errors.is_empty() {
+            let mut codes = vec![d64::NO_ERROR; 683];
+            for &(track, sector, error) in errors {
+                let preceding: usize = (1..track).map(|t| d64::sectors_per_track(t) as usize).sum();
+                codes[preceding + sector as usize] = error;
+            }
+            bytes.extend_from_slice(&codes);
+        }
+        DiskImage::parse("test.d64", bytes).unwrap()
+    }
+
+    fn sectors(disk: &DiskImage) -> Vec<(Vec<u8>, u8)> {
+        (1..=disk.tracks())
+            .flat_map(|track| (0..d64::sectors_per_track(track)).map(move |sector| (track, sector)))
+            .map(|(track, sector)| {
+                let (contents, error) = disk.raw_sector(track, sector);
+                (contents.to_vec(), error)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let mut gcr = Vec::new();
+        encode(&bytes, &mut gcr);
+        assert_eq!(gcr.len(), 320);
+        assert_eq!(decode(&gcr), Some(bytes));
+        // No more than two zeros in a row, and never a sync mark
+        let bits: String = gcr.iter().map(|byte| format!("{:08b}", byte)).collect();
+        assert!(!bits.contains("000"));
+        assert!(!bits.contains("1111111111"));
+        assert_eq!(decode(&[0; 5]), None);
+    }
+
+    #[test]
+    fn finds_a_sector_behind_its_sync_marks() {
+        let data = noise(2, 256);
+        let mut track = vec![GAP_BYTE; 3];
+        encode_sector(5, 7, (b'A', b'B'), &data, d64::NO_ERROR, &mut track);
+        let syncs = find_syncs(&track);
+        assert_eq!(syncs, vec![(3 + SYNC_LENGTH) * 8, (3 + 2 * SYNC_LENGTH + HEADER_GCR_LENGTH + HEADER_GAP_LENGTH) * 8]);
+        let header = decode(&read_bits(&track, syncs[0], HEADER_GCR_LENGTH)).unwrap();
+        assert_eq!(header, vec![HEADER_BLOCK_ID, 7 ^ 5 ^ b'B' ^ b'A', 7, 5, b'B', b'A', 0x0F, 0x0F]);
+        let block = decode(&read_bits(&track, syncs[1], DATA_GCR_LENGTH)).unwrap();
+        assert_eq!(block[0], DATA_BLOCK_ID);
+        assert_eq!(&block[1..257], &data[..]);
+        assert_eq!(block[257], checksum(&data));
+    }
+
+    #[test]
+    fn converts_a_d64_image_to_gcr_and_back() {
+        let disk = d64_image(&[]);
+        let gcr = GcrDisk::from_d64(&disk);
+        assert_eq!(gcr.track(0).len(), TRACK_CAPACITY[3]);
+        assert_eq!(gcr.track(34 * 2).len(), TRACK_CAPACITY[0]);
+        assert!(gcr.track(1).is_empty());
+        let back = gcr.to_d64("back.d64").unwrap();
+        assert_eq!(back.tracks(), 35);
+        assert!(sectors(&back) == sectors(&disk));
+    }
+
+    #[test]
+    fn keeps_the_error_bytes_through_gcr() {
+        let errors = [
+            (1, 0, ERROR_HEADER_NOT_FOUND),
+            (2, 3, ERROR_DATA_NOT_FOUND),
+            (3, 20, ERROR_DATA_CHECKSUM),
+            (17, 10, ERROR_HEADER_CHECKSUM),
+            (20, 1, ERROR_ID_MISMATCH),
+            (35, 16, ERROR_DATA_CHECKSUM)
+        ];
+        let disk = d64_image(&errors);
+        let back = GcrDisk::from_d64(&disk).to_d64("back.d64").unwrap();
+        for (i, ((contents, error), (original, original_error))) in sectors(&back).into_iter().zip(sectors(&disk)).enumerate() {
+            assert_eq!(error, original_error, "error of sector {}", i);
+            // Sectors with a readable header keep their data
+            if error == d64::NO_ERROR || error == ERROR_DATA_CHECKSUM {
+                assert_eq!(contents, original, "contents of sector {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn writes_g64_images_that_load_back() {
+        let mut gcr = GcrDisk::from_d64(&d64_image(&[]));
+        // A half-track with a speed for every byte
+        gcr.tracks[3] = noise(3, 7000);
+        gcr.speeds[3] = (0..7000).map(|i| (i / 1000 % 4) as u8).collect();
+        let path = std::env::temp_dir().join("c64emu-gcr-test.g64");
+        let filename = path.to_str().unwrap();
+        gcr.write_g64(filename).unwrap();
+        let loaded = GcrDisk::load(filename);
+        std::fs::remove_file(filename).unwrap();
+        let loaded = loaded.unwrap();
+        assert!(!loaded.is_d64());
+        for half_track in 0..HALF_TRACKS {
+            assert_eq!(loaded.track(half_track), gcr.track(half_track), "half-track {}", half_track);
+            assert_eq!(loaded.speeds[half_track], gcr.speeds[half_track], "speeds of half-track {}", half_track);
+        }
+        assert_eq!(loaded.speed(3, 2500), 2);
+        assert_eq!(loaded.speed(0, 2500), 3);
+    }
+
+    #[test]
+    fn refuses_truncated_g64_images() {
+        let mut bytes = G64_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0, 2, 0, 0x1F]);
+        assert!(GcrDisk::parse_g64("test.g64", &bytes).is_err());
+        // The first half-track points past the end of the image
+        bytes.extend_from_slice(&[0x1C, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(GcrDisk::parse_g64("test.g64", &bytes).is_err());
+        bytes.extend_from_slice(&[4, 0, 1, 2, 3, 4]);
+        let disk = GcrDisk::parse_g64("test.g64", &bytes).unwrap();
+        assert_eq!(disk.track(0), &[1, 2, 3, 4]);
+        assert!(disk.track(1).is_empty());
+    }
+
+    #[test]
+    fn cuts_one_revolution_from_a_nib_track() {
+        let mut revolution = vec![0xFF; SYNC_LENGTH];
+        revolution.extend(noise(4, 6300 - SYNC_LENGTH));
+        let mut data = vec![GAP_BYTE; 10];
+        while data.len() < NIB_TRACK_SIZE {
+            data.extend_from_slice(&revolution);
+        }
+        data.truncate(NIB_TRACK_SIZE);
+        let track = GcrDisk::nib_revolution(&data, 0);
+        assert_eq!(track.len(), 6300);
+        assert_eq!(&track[..], &data[10 + SYNC_LENGTH..10 + SYNC_LENGTH + 6300]);
+        // Without a repeat the track is as long as its speed zone allows
+        let unformatted = noise(5, NIB_TRACK_SIZE);
+        assert_eq!(GcrDisk::nib_revolution(&unformatted, 3).len(), TRACK_CAPACITY[3]);
+    }
+
+    #[test]
+    fn parses_the_tracks_of_a_nib_dump() {
+        let mut bytes = NIB_SIGNATURE.to_vec();
+        bytes.resize(NIB_HEADER_SIZE, 0);
+        bytes[NIB_TRACK_TABLE..NIB_TRACK_TABLE + 4].copy_from_slice(&[2, 3, 5, 1]);
+        bytes.extend(noise(6, 2 * NIB_TRACK_SIZE));
+        let disk = GcrDisk::parse_nib("test.nib", &bytes).unwrap();
+        assert_eq!(disk.track(0).len(), TRACK_CAPACITY[3]);
+        assert_eq!(disk.track(3).len(), TRACK_CAPACITY[1]);
+        assert_eq!((disk.speed(0, 0), disk.speed(3, 0)), (3, 1));
+        assert!(disk.track(1).is_empty());
+        bytes.truncate(NIB_HEADER_SIZE + NIB_TRACK_SIZE);
+        assert!(GcrDisk::parse_nib("test.nib", &bytes).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+// GeoRAM and NeoRAM, RAM seen a page of 256 bytes at a time through IO1 at
+// $DE00-$DEFF. The RAM is divided into blocks of 16K with 64 pages each:
+// the page is selected by writing to $DFFE and the block by writing to
+// $DFFF, both repeated at the even and odd addresses of $DF80-$DFFF. They
+// are write-only. Sizes go from 64K to 4MB, the contents can be kept in an
+// image file.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+use cartridge;
+
+pub const MIN_SIZE: usize = 64 * 1024;
+pub const MAX_SIZE: usize = 4 * 1024 * 1024;
+const BLOCK_SIZE: usize = 16 * 1024;
+const PAGE_SIZE: usize = 256;
+const PAGE_MASK: u8 = 0x3F;
+const REGISTERS_START: u16 = 0xDF80;
+
+pub struct GeoRam {
+    ram: Vec<u8>,
+    filename: Option<String>,
+    modified: bool,
+    block: usize,
+    page: usize
+}
+
+impl GeoRam {
+    pub fn new(size: usize) -> GeoRam {
+        GeoRam {
+            ram: vec![0; size],
+            filename: None,
+            modified: false,
+            block: 0,
+            page: 0
+        }
+    }
+
+    /// A GeoRAM kept in an image file, which is read if it exists and has
+    /// to be of the given size then.
+    pub fn with_image(size: usize, filename: &str) -> Result<GeoRam, String> {
+        let mut georam = GeoRam::new(size);
+        georam.filename = Some(filename.to_string());
+        if Path::new(filename).exists() {
+            let mut bytes = Vec::new();
+            File::open(filename)
+                .and_then(|mut f| f.read_to_end(&mut bytes))
+                .map_err(|err| format!("Failed to read {}: {}", filename, err))?;
+            if bytes.len() != size {
+                return Err(format!("{} holds {}K, not {}K", filename, bytes.len() / 1024, size / 1024));
+            }
+            georam.ram = bytes;
+        }
+        Ok(georam)
+    }
+
+    pub fn filename(self: &GeoRam) -> Option<&str> {
+        self.filename.as_ref().map(|filename| filename.as_str())
+    }
+
+    pub fn modified(self: &GeoRam) -> bool {
+        self.modified
+    }
+
+    /// Writes the RAM to the image file, if there is one.
+    pub fn save(self: &mut GeoRam) -> Result<(), String> {
+        if let Some(ref filename) = self.filename {
+            File::create(filename)
+                .and_then(|mut f| f.write_all(&self.ram))
+                .map_err(|err| format!("Failed to write {}: {}", filename, err))?;
+        }
+        self.modified = false;
+        Ok(())
+    }
+
+    pub fn reset(self: &mut GeoRam) {
+        self.block = 0;
+        self.page = 0;
+    }
+
+    fn offset(self: &GeoRam, addr: u16) -> usize {
+        self.block * BLOCK_SIZE + self.page * PAGE_SIZE + (addr as usize & (PAGE_SIZE - 1))
+    }
+
+    /// Reads from IO1 or IO2, None where the registers are.
+    pub fn read_io(self: &GeoRam, addr: u16) -> Option<u8> {
+        if addr < cartridge::IO2_START {
+            Some(self.ram[self.offset(addr)])
+        } else {
+            None
+        }
+    }
+
+    /// Writes to IO1 or IO2, returns false outside the window and the
+    /// registers.
+    pub fn write_io(self: &mut GeoRam, addr: u16, value: u8) -> bool {
+        if addr < cartridge::IO2_START {
+            let offset = self.offset(addr);
+            self.ram[offset] = value;
+            self.modified = true;
+        } else if addr >= REGISTERS_START && addr & 0x01 == 0 {
+            self.page = (value & PAGE_MASK) as usize;
+        } else if addr >= REGISTERS_START {
+            // Smaller units ignore the upper bits of the block
+            self.block = value as usize % (self.ram.len() / BLOCK_SIZE);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    pub fn print_status(self: &GeoRam) {
+        println!("GeoRAM with {}K{}", self.ram.len() / 1024, self.filename.as_ref().map_or(String::new(), |filename| format!(" in {}", filename)));
+        println!("Block {}, page {}", self.block, self.page);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn select(georam: &mut GeoRam, block: u8, page: u8) {
+        assert!(georam.write_io(0xDFFF, block));
+        assert!(georam.write_io(0xDFFE, page));
+    }
+
+    #[test]
+    fn selects_blocks_and_pages() {
+        let mut georam = GeoRam::new(512 * 1024);
+        select(&mut georam, 3, 17);
+        georam.write_io(0xDE42, 0x99);
+        assert_eq!(georam.ram[3 * BLOCK_SIZE + 17 * PAGE_SIZE + 0x42], 0x99);
+        assert!(georam.modified());
+        // The registers repeat at the even and odd addresses from $DF80
+        georam.write_io(0xDF81, 3);
+        georam.write_io(0xDF80, 17);
+        assert_eq!(georam.read_io(0xDE42), Some(0x99));
+        select(&mut georam, 3, 17 | 0xC0);
+        assert_eq!(georam.read_io(0xDE42), Some(0x99));
+        select(&mut georam, 3, 18);
+        assert_eq!(georam.read_io(0xDE42), Some(0x00));
+        assert_eq!(georam.read_io(0xDFFF), None);
+        assert!(!georam.write_io(0xDF7F, 0));
+        georam.reset();
+        assert_eq!((georam.block, georam.page), (0, 0));
+    }
+
+    #[test]
+    fn wraps_the_block_around_the_size() {
+        let mut georam = GeoRam::new(MIN_SIZE);
+        select(&mut georam, 5, 0);
+        georam.write_io(0xDE00, 0x55);
+        assert_eq!(georam.ram[BLOCK_SIZE], 0x55);
+    }
+
+    #[test]
+    fn loads_and_writes_back_the_image() {
+        let path = std::env::temp_dir().join("c64emu-georam-test.img");
+        let filename = path.to_str().unwrap();
+        let _ = fs::remove_file(filename);
+        let mut georam = GeoRam::with_image(MIN_SIZE, filename).unwrap();
+        assert_eq!(georam.filename(), Some(filename));
+        select(&mut georam, 2, 5);
+        georam.write_io(0xDE10, 0xAB);
+        georam.save().unwrap();
+        assert!(!georam.modified());
+
+        let mut loaded = GeoRam::with_image(MIN_SIZE, filename).unwrap();
+        select(&mut loaded, 2, 5);
+        assert_eq!(loaded.read_io(0xDE10), Some(0xAB));
+        let wrong_size = GeoRam::with_image(2 * MIN_SIZE, filename);
+        fs::remove_file(filename).unwrap();
+        assert_eq!(wrong_size.err().unwrap(), format!("{} holds 64K, not 128K", filename));
+    }
+}
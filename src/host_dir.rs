@@ -0,0 +1,621 @@
+// A directory of the host served as a disk drive, for developing without
+// rebuilding disk images. Files appear under their name without the
+// extension, which gives the file type: .prg, .seq and .usr, anything else
+// is listed as PRG under its full name. Subdirectories and hidden files
+// are left out.
+//
+// PETSCII and host names are converted letter by letter: unshifted letters,
+// the upper case ones on the screen at power on, are lower case on the
+// host, shifted letters upper case.
+//
+// Like a real drive the directory has 15 data channels, opened with the
+// secondary address and a name like "NAME,S,W", and a command channel 15
+// that reports the status and takes commands: S:NAME to scratch, R:NEW=OLD
+// to rename, I to initialize and UJ to reset.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use d64::{self, FileType};
+use kernal_traps;
+
+const COMMAND_CHANNEL: u8 = 15;
+
+// Secondary address of SAVE, which writes a PRG file
+const SAVE_CHANNEL: u8 = 1;
+
+// Bytes of a block on a 1541, for the sizes in the listing
+const BLOCK_SIZE: u64 = 254;
+// The host decides how much fits, the listing shows an empty disk
+const BLOCKS_FREE: u16 = 664;
+const DISK_ID: &[u8] = b"HD 2A";
+
+// Status codes of the command channel, which also reports what went wrong
+// on the host
+const OK: u8 = 0;
+const FILES_SCRATCHED: u8 = 1;
+const READ_ERROR: u8 = 20;
+const SYNTAX_ERROR: u8 = 31;
+const INVALID_FILENAME: u8 = 33;
+const WRITE_ERROR: u8 = 25;
+const FILE_NOT_FOUND: u8 = 62;
+const FILE_EXISTS: u8 = 63;
+const FILE_TYPE_MISMATCH: u8 = 64;
+const DOS_VERSION: u8 = 73;
+
+fn status_text(code: u8) -> &'static str {
+    match code {
+        OK => " OK",
+        FILES_SCRATCHED => "FILES SCRATCHED",
+        READ_ERROR => "READ ERROR",
+        SYNTAX_ERROR | INVALID_FILENAME => "SYNTAX ERROR",
+        WRITE_ERROR => "WRITE ERROR",
+        FILE_NOT_FOUND => "FILE NOT FOUND",
+        FILE_EXISTS => "FILE EXISTS",
+        FILE_TYPE_MISMATCH => "FILE TYPE MISMATCH",
+        _ => "CBM DOS V2.6 1541"
+    }
+}
+
+fn extension(file_type: FileType) -> &'static str {
+    match file_type {
+        FileType::Seq => "seq",
+        FileType::Usr => "usr",
+        _ => "prg"
+    }
+}
+
+/// The host file name for a PETSCII name, None if it has characters that
+/// can't be in one.
+fn host_name(name: &[u8]) -> Option<String> {
+    name.iter().map(|&c| match c {
+        0x41..=0x5A => Some((c + 0x20) as char),
+        0xC1..=0xDA => Some((c - 0x80) as char),
+        0x61..=0x7A => Some((c - 0x20) as char),
+        b'/' | b'\\' => None,
+        0x20..=0x7E => Some(c as char),
+        _ => None
+    }).collect()
+}
+
+/// The PETSCII name for a host file name. Characters without a PETSCII
+/// equivalent become '?', which still matches them as a wildcard.
+fn petscii_name(name: &str) -> Vec<u8> {
+    name.chars().map(|c| match c {
+        'a'..='z' => c as u8 - 0x20,
+        'A'..='Z' => c as u8 + 0x80,
+        ' '..='~' => c as u8,
+        _ => b'?'
+    }).collect()
+}
+
+/// A file of the directory.
+pub struct HostFile {
+    pub name: Vec<u8>,
+    pub file_type: FileType,
+    pub blocks: u16,
+    path: PathBuf
+}
+
+enum Channel {
+    Read { data: Vec<u8>, position: usize },
+    Write { path: PathBuf, data: Vec<u8> }
+}
+
+// What the bytes sent to the drive while listening are for
+#[derive(PartialEq)]
+enum Transfer {
+    None,
+    Data,
+    Open
+}
+
+pub struct HostDirectory {
+    path: PathBuf,
+    channels: Vec<Option<Channel>>,
+    // Message of the command channel and how much of it has been read
+    status: Vec<u8>,
+    status_position: usize,
+    listening: bool,
+    talking: bool,
+    // Secondary address of the current transfer
+    channel: u8,
+    transfer: Transfer,
+    // File name of an OPEN or a command for the command channel
+    received: Vec<u8>
+}
+
+impl HostDirectory {
+    pub fn new(path: &str) -> Result<HostDirectory, String> {
+        if !Path::new(path).is_dir() {
+            return Err(format!("Not a directory: {}", path));
+        }
+        let mut dir = HostDirectory {
+            path: PathBuf::from(path),
+            channels: (0..COMMAND_CHANNEL).map(|_| None).collect(),
+            status: Vec::new(),
+            status_position: 0,
+            listening: false,
+            talking: false,
+            channel: 0,
+            transfer: Transfer::None,
+            received: Vec::new()
+        };
+        dir.reset();
+        Ok(dir)
+    }
+
+    pub fn path(self: &HostDirectory) -> &str {
+        self.path.to_str().unwrap_or("?")
+    }
+
+    /// Closes all channels without writing the files, as the RESET line
+    /// of the serial bus does.
+    pub fn reset(self: &mut HostDirectory) {
+        for channel in self.channels.iter_mut() {
+            *channel = None;
+        }
+        self.listening = false;
+        self.talking = false;
+        self.transfer = Transfer::None;
+        self.received.clear();
+        self.set_status(DOS_VERSION, 0);
+    }
+
+    fn set_status(self: &mut HostDirectory, code: u8, track: u8) {
+        self.status = format!("{:02},{},{:02},00\r", code, status_text(code), track).into_bytes();
+        self.status_position = 0;
+    }
+
+    /// The files of the directory, sorted by their host name.
+    pub fn files(self: &HostDirectory) -> Result<Vec<HostFile>, String> {
+        let entries = fs::read_dir(&self.path).map_err(|err| format!("Failed to read {}: {}", self.path(), err))?;
+        let mut files = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|err| format!("Failed to read {}: {}", self.path(), err))?;
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue
+            };
+            let host_name = entry.file_name().to_string_lossy().into_owned();
+            if !metadata.is_file() || host_name.starts_with('.') {
+                continue;
+            }
+            let dot = host_name.rfind('.').unwrap_or(host_name.len());
+            let (stem, file_type) = match host_name[dot..].to_lowercase().as_str() {
+                ".prg" => (&host_name[..dot], FileType::Prg),
+                ".seq" => (&host_name[..dot], FileType::Seq),
+                ".usr" => (&host_name[..dot], FileType::Usr),
+                _ => (&host_name[..], FileType::Prg)
+            };
+            files.push(HostFile {
+                name: petscii_name(stem),
+                file_type,
+                blocks: metadata.len().div_ceil(BLOCK_SIZE).min(0xFFFF) as u16,
+                path: entry.path()
+            });
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    /// The first file matching a pattern with wildcards.
+    pub fn find(self: &HostDirectory, pattern: &[u8]) -> Result<Option<HostFile>, String> {
+        Ok(self.files()?.into_iter().find(|file| d64::matches(pattern, &file.name)))
+    }
+
+    pub fn read_file(self: &HostDirectory, file: &HostFile) -> Result<Vec<u8>, String> {
+        fs::read(&file.path).map_err(|err| format!("Failed to read {}: {}", file.path.display(), err))
+    }
+
+    /// Writes a file, replacing an existing one of the same name if asked
+    /// to.
+    pub fn write_file(self: &HostDirectory, name: &[u8], file_type: FileType, data: &[u8], replace: bool) -> Result<(), String> {
+        let path = self.new_file_path(name, file_type, replace).map_err(|(_, msg)| msg)?;
+        fs::write(&path, data).map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+    }
+
+    // Where a new file goes: an existing file of the same name when it's
+    // replaced, otherwise the name with the extension of the type
+    fn new_file_path(self: &HostDirectory, name: &[u8], file_type: FileType, replace: bool) -> Result<PathBuf, (u8, String)> {
+        let host_name = match host_name(name) {
+            Some(ref host_name) if !host_name.is_empty() && !host_name.contains(|c| c == '*' || c == '?') => host_name.clone(),
+            _ => return Err((INVALID_FILENAME, "Invalid file name".to_string()))
+        };
+        let existing = self.files().map_err(|msg| (READ_ERROR, msg))?.into_iter().find(|file| file.name == name);
+        match existing {
+            Some(file) if replace => Ok(file.path),
+            Some(_) => Err((FILE_EXISTS, format!("File exists: {}", host_name))),
+            None => Ok(self.path.join(format!("{}.{}", host_name, extension(file_type))))
+        }
+    }
+
+    /// The directory as the BASIC program LOAD"$" gets.
+    pub fn directory_listing(self: &HostDirectory) -> Result<Vec<u8>, String> {
+        let lines = self.files()?.iter()
+            .map(|file| d64::listing_line(&file.name, file.file_type, file.blocks, true, false))
+            .collect();
+        let dir_name = self.path.canonicalize().ok()
+            .and_then(|path| path.file_name().map(|name| name.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        let mut disk_name = petscii_name(&dir_name);
+        disk_name.resize(16, b' ');
+        Ok(d64::listing_program(&disk_name, DISK_ID, lines, BLOCKS_FREE))
+    }
+
+    pub fn listening(self: &HostDirectory) -> bool {
+        self.listening
+    }
+
+    pub fn talking(self: &HostDirectory) -> bool {
+        self.talking
+    }
+
+    /// LISTEN with the device number of the directory.
+    pub fn listen(self: &mut HostDirectory) {
+        self.listening = true;
+        self.talking = false;
+        self.transfer = Transfer::None;
+    }
+
+    /// TALK with the device number of the directory.
+    pub fn talk(self: &mut HostDirectory) {
+        self.talking = true;
+        self.listening = false;
+    }
+
+    /// LISTEN or TALK for another device.
+    pub fn unaddress(self: &mut HostDirectory) {
+        self.listening = false;
+        self.talking = false;
+    }
+
+    /// The secondary address after LISTEN or TALK: 0x6N selects channel
+    /// N for data, 0xEN closes it and 0xFN opens it with the name that
+    /// follows.
+    pub fn second(self: &mut HostDirectory, secondary: u8) {
+        self.channel = secondary & 0x0F;
+        self.received.clear();
+        self.transfer = match secondary & 0xF0 {
+            0x60 => Transfer::Data,
+            0xF0 => Transfer::Open,
+            0xE0 => {
+                self.close(secondary & 0x0F);
+                Transfer::None
+            }
+            _ => Transfer::None
+        };
+    }
+
+    /// A byte sent to the directory while listening.
+    pub fn receive(self: &mut HostDirectory, byte: u8) {
+        if self.transfer == Transfer::Open || (self.transfer == Transfer::Data && self.channel == COMMAND_CHANNEL) {
+            self.received.push(byte);
+        } else if self.transfer == Transfer::Data {
+            if let Some(Channel::Write { ref mut data, .. }) = self.channels[self.channel as usize] {
+                data.push(byte);
+            }
+        }
+    }
+
+    /// The next byte of the channel talking, and whether it is the last,
+    /// None when there is nothing to read.
+    pub fn send(self: &mut HostDirectory) -> Option<(u8, bool)> {
+        if self.channel == COMMAND_CHANNEL {
+            let byte = self.status[self.status_position];
+            self.status_position += 1;
+            let last = self.status_position == self.status.len();
+            // The message is read once, then the drive reports OK
+            if last {
+                self.set_status(OK, 0);
+            }
+            return Some((byte, last));
+        }
+        match self.channels[self.channel as usize] {
+            Some(Channel::Read { ref data, ref mut position }) if *position < data.len() => {
+                *position += 1;
+                Some((data[*position - 1], *position == data.len()))
+            }
+            _ => None
+        }
+    }
+
+    /// UNLISTEN, which completes an OPEN or a command.
+    pub fn unlisten(self: &mut HostDirectory) {
+        let received = self.received.split_off(0);
+        match self.transfer {
+            Transfer::Open if self.channel == COMMAND_CHANNEL => self.command(&received),
+            Transfer::Open => self.open(self.channel, &received),
+            Transfer::Data if self.channel == COMMAND_CHANNEL => self.command(&received),
+            _ => ()
+        }
+        self.transfer = Transfer::None;
+        self.listening = false;
+    }
+
+    pub fn untalk(self: &mut HostDirectory) {
+        self.talking = false;
+    }
+
+    // Opens a channel with a name like "@0:NAME,S,W"
+    fn open(self: &mut HostDirectory, channel: u8, name: &[u8]) {
+        self.channels[channel as usize] = None;
+        if name.is_empty() {
+            self.set_status(OK, 0);
+            return;
+        }
+        let mut parts = name.split(|&c| c == b',');
+        let (name, replace) = kernal_traps::parse_filename(parts.next().unwrap_or(b""));
+        let mut file_type = None;
+        let mut write = channel == SAVE_CHANNEL;
+        let mut append = false;
+        for part in parts {
+            match part.first() {
+                Some(&b'P') => file_type = Some(FileType::Prg),
+                Some(&b'S') => file_type = Some(FileType::Seq),
+                Some(&b'U') => file_type = Some(FileType::Usr),
+                Some(&b'W') => write = true,
+                Some(&b'A') => append = true,
+                Some(&b'R') | Some(&b'M') => write = false,
+                _ => return self.set_status(SYNTAX_ERROR, 0)
+            }
+        }
+        if write || append {
+            let default_type = if channel == SAVE_CHANNEL { FileType::Prg } else { FileType::Seq };
+            let file_type = file_type.unwrap_or(default_type);
+            let opened = if append {
+                match self.find(name) {
+                    Ok(Some(file)) => self.read_file(&file).map(|data| (file.path, data)).map_err(|msg| (READ_ERROR, msg)),
+                    Ok(None) => Err((FILE_NOT_FOUND, String::new())),
+                    Err(msg) => Err((READ_ERROR, msg))
+                }
+            } else {
+                self.new_file_path(name, file_type, replace).map(|path| (path, Vec::new()))
+            };
+            match opened {
+                Ok((path, data)) => {
+                    self.channels[channel as usize] = Some(Channel::Write { path, data });
+                    self.set_status(OK, 0);
+                }
+                Err((code, _)) => self.set_status(code, 0)
+            }
+            return;
+        }
+
+        let data = if name.first() == Some(&b'$') {
+            self.directory_listing()
+        } else {
+            match self.find(name) {
+                Ok(Some(ref file)) if file_type.map_or(false, |file_type| file_type != file.file_type) => {
+                    return self.set_status(FILE_TYPE_MISMATCH, 0);
+                }
+                Ok(Some(file)) => self.read_file(&file),
+                Ok(None) => return self.set_status(FILE_NOT_FOUND, 0),
+                Err(msg) => Err(msg)
+            }
+        };
+        match data {
+            Ok(data) => {
+                self.channels[channel as usize] = Some(Channel::Read { data, position: 0 });
+                self.set_status(OK, 0);
+            }
+            Err(_) => self.set_status(READ_ERROR, 0)
+        }
+    }
+
+    // Closing a write channel writes the file, closing the command channel
+    // closes all of them
+    fn close(self: &mut HostDirectory, channel: u8) {
+        if channel == COMMAND_CHANNEL {
+            for channel in 0..COMMAND_CHANNEL {
+                self.close(channel);
+            }
+            return;
+        }
+        if let Some(Channel::Write { path, data }) = self.channels[channel as usize].take() {
+            if fs::write(&path, &data).is_err() {
+                self.set_status(WRITE_ERROR, 0);
+            }
+        }
+    }
+
+    // Executes a command sent to the command channel
+    fn command(self: &mut HostDirectory, command: &[u8]) {
+        let command = match command.iter().rposition(|&c| c != b'\r') {
+            Some(end) => &command[..end + 1],
+            None => return
+        };
+        let arguments = command.iter().position(|&c| c == b':').map(|colon| &command[colon + 1..]);
+        match (command[0], arguments) {
+            (b'S', Some(patterns)) => self.scratch(patterns),
+            (b'R', Some(names)) => self.rename(names),
+            (b'I', _) => self.set_status(OK, 0),
+            (b'U', _) if command.len() > 1 && (command[1] == b'J' || command[1] == b':') => self.reset(),
+            _ => self.set_status(SYNTAX_ERROR, 0)
+        }
+    }
+
+    fn scratch(self: &mut HostDirectory, patterns: &[u8]) {
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(_) => return self.set_status(READ_ERROR, 0)
+        };
+        let mut scratched = 0;
+        let mut failed = false;
+        for file in files {
+            if patterns.split(|&c| c == b',').any(|pattern| d64::matches(pattern, &file.name)) {
+                match fs::remove_file(&file.path) {
+                    Ok(()) => scratched += 1,
+                    Err(_) => failed = true
+                }
+            }
+        }
+        if failed {
+            self.set_status(WRITE_ERROR, 0);
+        } else {
+            self.set_status(FILES_SCRATCHED, scratched.min(0xFF) as u8);
+        }
+    }
+
+    // R:NEW=OLD keeps the extension of the old file
+    fn rename(self: &mut HostDirectory, names: &[u8]) {
+        let (new_name, old_name) = match names.iter().position(|&c| c == b'=') {
+            Some(equals) => (&names[..equals], kernal_traps::parse_filename(&names[equals + 1..]).0),
+            None => return self.set_status(SYNTAX_ERROR, 0)
+        };
+        let old = match self.find(old_name) {
+            Ok(Some(file)) => file,
+            Ok(None) => return self.set_status(FILE_NOT_FOUND, 0),
+            Err(_) => return self.set_status(READ_ERROR, 0)
+        };
+        let path = match self.new_file_path(new_name, old.file_type, false) {
+            Ok(path) => path,
+            Err((code, _)) => return self.set_status(code, 0)
+        };
+        match fs::rename(&old.path, &path) {
+            Ok(()) => self.set_status(OK, 0),
+            Err(_) => self.set_status(WRITE_ERROR, 0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty directory for a test, removed again by the test
+    fn directory(name: &str) -> (PathBuf, HostDirectory) {
+        let path = std::env::temp_dir().join(format!("c64emu-host-dir-{}", name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        let dir = HostDirectory::new(path.to_str().unwrap()).unwrap();
+        (path, dir)
+    }
+
+    fn send_command(dir: &mut HostDirectory, command: &[u8]) {
+        dir.listen();
+        dir.second(0x60 | COMMAND_CHANNEL);
+        for &byte in command {
+            dir.receive(byte);
+        }
+        dir.unlisten();
+    }
+
+    fn read_status(dir: &mut HostDirectory) -> String {
+        dir.talk();
+        dir.second(0x60 | COMMAND_CHANNEL);
+        let mut status = Vec::new();
+        while let Some((byte, last)) = dir.send() {
+            status.push(byte);
+            if last {
+                break;
+            }
+        }
+        dir.untalk();
+        String::from_utf8(status).unwrap()
+    }
+
+    fn names(dir: &HostDirectory) -> Vec<Vec<u8>> {
+        dir.files().unwrap().into_iter().map(|file| file.name).collect()
+    }
+
+    #[test]
+    fn converts_names_letter_by_letter() {
+        assert_eq!(host_name(b"HELLO 2"), Some("hello 2".to_string()));
+        assert_eq!(host_name(b"\xC8ELLO"), Some("Hello".to_string()));
+        assert_eq!(host_name(b"hi"), Some("HI".to_string()));
+        assert_eq!(host_name(b"A/B"), None);
+        assert_eq!(host_name(b"\x93"), None);
+        assert_eq!(petscii_name("Hello 2"), b"\xC8ELLO 2".to_vec());
+        assert_eq!(petscii_name("caf\u{e9}"), b"CAF?".to_vec());
+        for name in ["demo", "Intro-Part 2", "x_y.z"].iter() {
+            assert_eq!(host_name(&petscii_name(name)), Some(name.to_string()));
+        }
+    }
+
+    #[test]
+    fn finds_files_by_wildcards() {
+        let (path, dir) = directory("wildcards");
+        fs::write(path.join("game.prg"), [1]).unwrap();
+        fs::write(path.join("gamma.seq"), [2]).unwrap();
+        fs::write(path.join("README"), [3]).unwrap();
+        let find = |pattern: &[u8]| dir.find(pattern).unwrap().map(|file| (file.name, file.file_type));
+        assert_eq!(find(b"GAME"), Some((b"GAME".to_vec(), FileType::Prg)));
+        assert_eq!(find(b"GAM?A"), Some((b"GAMMA".to_vec(), FileType::Seq)));
+        assert_eq!(find(b"G*"), Some((b"GAME".to_vec(), FileType::Prg)));
+        assert_eq!(find(b"\xD2*"), Some((b"\xD2\xC5\xC1\xC4\xCD\xC5".to_vec(), FileType::Prg)));
+        assert_eq!(find(b"GAM"), None);
+        assert_eq!(find(b"GAME?"), None);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn scratches_the_files_matching_the_patterns() {
+        let (path, mut dir) = directory("scratch");
+        for name in ["one.prg", "two.prg", "three.seq", "four.usr"].iter() {
+            fs::write(path.join(name), []).unwrap();
+        }
+        send_command(&mut dir, b"S:T*,FOUR\r");
+        assert_eq!(read_status(&mut dir), "01,FILES SCRATCHED,03,00\r");
+        assert_eq!(names(&dir), vec![b"ONE".to_vec()]);
+        send_command(&mut dir, b"S0:NONE");
+        assert_eq!(read_status(&mut dir), "01,FILES SCRATCHED,00,00\r");
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn renames_files_keeping_their_type() {
+        let (path, mut dir) = directory("rename");
+        fs::write(path.join("old.seq"), [1, 2]).unwrap();
+        fs::write(path.join("other.prg"), []).unwrap();
+        send_command(&mut dir, b"R:NEW=OLD");
+        assert_eq!(read_status(&mut dir), "00, OK,00,00\r");
+        assert_eq!(fs::read(path.join("new.seq")).unwrap(), vec![1, 2]);
+        send_command(&mut dir, b"R0:OTHER=NEW");
+        assert_eq!(read_status(&mut dir), "63,FILE EXISTS,00,00\r");
+        send_command(&mut dir, b"R:ANY=MISSING");
+        assert_eq!(read_status(&mut dir), "62,FILE NOT FOUND,00,00\r");
+        send_command(&mut dir, b"R:NEW");
+        assert_eq!(read_status(&mut dir), "31,SYNTAX ERROR,00,00\r");
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn initializes_and_resets() {
+        let (path, mut dir) = directory("reset");
+        assert_eq!(read_status(&mut dir), "73,CBM DOS V2.6 1541,00,00\r");
+        assert_eq!(read_status(&mut dir), "00, OK,00,00\r");
+        send_command(&mut dir, b"X");
+        assert_eq!(read_status(&mut dir), "31,SYNTAX ERROR,00,00\r");
+        send_command(&mut dir, b"I0");
+        assert_eq!(read_status(&mut dir), "00, OK,00,00\r");
+        send_command(&mut dir, b"UJ");
+        assert_eq!(read_status(&mut dir), "73,CBM DOS V2.6 1541,00,00\r");
+        send_command(&mut dir, b"U");
+        assert_eq!(read_status(&mut dir), "31,SYNTAX ERROR,00,00\r");
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn reports_failed_writes_on_the_command_channel() {
+        let (path, mut dir) = directory("write-error");
+        dir.listen();
+        dir.second(0xF2);
+        for &byte in b"DATA,S,W" {
+            dir.receive(byte);
+        }
+        dir.unlisten();
+        assert_eq!(read_status(&mut dir), "00, OK,00,00\r");
+        dir.listen();
+        dir.second(0x62);
+        dir.receive(0x41);
+        dir.unlisten();
+        fs::remove_dir_all(&path).unwrap();
+        dir.listen();
+        dir.second(0xE2);
+        dir.unlisten();
+        assert_eq!(read_status(&mut dir), "25,WRITE ERROR,00,00\r");
+        send_command(&mut dir, b"S:DATA");
+        assert_eq!(read_status(&mut dir), "20,READ ERROR,00,00\r");
+    }
+}
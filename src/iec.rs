@@ -0,0 +1,119 @@
+// The serial bus connecting the C64 to its disk drives and printers. ATN,
+// CLK, DATA and SRQ are open collector lines: any device can pull them low
+// and a line is only high while nobody does. The C64 drives ATN to address
+// the devices, the devices answer on CLK and DATA. SRQ goes to the FLAG
+// input of CIA1 and is unused by the 1541.
+//
+// Changes of the lines can be logged to a file, with the cycle of the change
+// and which devices pull each line low.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+/// Device numbers of the disk drives that can be attached.
+pub const FIRST_DRIVE: u8 = 8;
+pub const LAST_DRIVE: u8 = 11;
+
+/// The lines a device pulls low, or the lines that are low on the bus.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct Lines {
+    pub atn: bool,
+    pub clk: bool,
+    pub data: bool,
+    pub srq: bool
+}
+
+impl Lines {
+    fn merge(self: Lines, other: Lines) -> Lines {
+        Lines {
+            atn: self.atn || other.atn,
+            clk: self.clk || other.clk,
+            data: self.data || other.data,
+            srq: self.srq || other.srq
+        }
+    }
+
+    fn names(self: Lines) -> String {
+        let names: Vec<&str> = [(self.atn, "ATN"), (self.clk, "CLK"), (self.data, "DATA"), (self.srq, "SRQ")].iter()
+            .filter(|&&(low, _)| low)
+            .map(|&(_, name)| name)
+            .collect();
+        if names.is_empty() { "-".to_string() } else { names.join(",") }
+    }
+}
+
+struct BusTrace {
+    filename: String,
+    writer: BufWriter<File>
+}
+
+pub struct IecBus {
+    lines: Lines,
+    // The lines pulled low by the C64 and by each device in the last cycle
+    c64: Lines,
+    devices: Vec<(u8, Lines)>,
+    trace: Option<BusTrace>
+}
+
+impl IecBus {
+    pub fn new() -> IecBus {
+        IecBus {
+            lines: Lines::default(),
+            c64: Lines::default(),
+            devices: Vec::new(),
+            trace: None
+        }
+    }
+
+    /// Sets the lines pulled low by the C64 and by the devices, and logs
+    /// the change if the bus is traced. Returns the resulting levels.
+    pub fn update(self: &mut IecBus, cycle: u64, c64: Lines, devices: &[(u8, Lines)]) -> Result<Lines, String> {
+        let lines = devices.iter().fold(c64, |lines, &(_, device)| lines.merge(device));
+        if lines != self.lines || c64 != self.c64 || devices != self.devices.as_slice() {
+            self.c64 = c64;
+            self.devices.clear();
+            self.devices.extend_from_slice(devices);
+            if let Some(ref mut trace) = self.trace {
+                let mut line = format!("{:>12}  ATN {}  CLK {}  DATA {}  SRQ {}  C64: {}",
+                    cycle, !lines.atn as u8, !lines.clk as u8, !lines.data as u8, !lines.srq as u8, c64.names());
+                for &(device, pulled) in devices.iter() {
+                    line.push_str(&format!("  {}: {}", device, pulled.names()));
+                }
+                writeln!(trace.writer, "{}", line).map_err(|err| format!("Failed to write {}: {}", trace.filename, err))?;
+            }
+        }
+        self.lines = lines;
+        Ok(lines)
+    }
+
+    /// Logs the lines to a file from now on, one line per change.
+    pub fn start_trace(self: &mut IecBus, filename: &str) -> Result<(), String> {
+        self.stop_trace()?;
+        let mut writer = BufWriter::new(File::create(filename).map_err(|err| format!("Failed to create {}: {}", filename, err))?);
+        writeln!(writer, "# cycle, line levels with 1 for high, lines pulled low by the C64 and by each device")
+            .map_err(|err| format!("Failed to write {}: {}", filename, err))?;
+        self.trace = Some(BusTrace { filename: filename.to_string(), writer });
+        Ok(())
+    }
+
+    pub fn stop_trace(self: &mut IecBus) -> Result<(), String> {
+        match self.trace.take() {
+            Some(mut trace) => trace.writer.flush().map_err(|err| format!("Failed to write {}: {}", trace.filename, err)),
+            None => Ok(())
+        }
+    }
+
+    pub fn trace_filename(self: &IecBus) -> Option<&str> {
+        self.trace.as_ref().map(|trace| trace.filename.as_str())
+    }
+
+    pub fn print_status(self: &IecBus) {
+        let lines = self.lines;
+        println!("ATN: {}  CLK: {}  DATA: {}  SRQ: {}", !lines.atn as u8, !lines.clk as u8, !lines.data as u8, !lines.srq as u8);
+        println!("Pulled low by the C64: {}", self.c64.names());
+        for &(device, pulled) in self.devices.iter() {
+            println!("Pulled low by device {}: {}", device, pulled.names());
+        }
+    }
+}
@@ -0,0 +1,326 @@
+extern crate sdl2;
+
+use joystick::sdl2::controller::{Axis, Button, GameController};
+use joystick::sdl2::event::Event;
+use joystick::sdl2::keyboard::Keycode;
+use joystick::sdl2::GameControllerSubsystem;
+
+bitflags! {
+    pub struct JoystickState: u8 {
+        const UP    = 0b0000_0001;
+        const DOWN  = 0b0000_0010;
+        const LEFT  = 0b0000_0100;
+        const RIGHT = 0b0000_1000;
+        const FIRE  = 0b0001_0000;
+    }
+}
+
+// Frames between fire button toggles when autofire is enabled
+const AUTOFIRE_PERIOD: u32 = 4;
+// Analog stick deflection needed before it counts as a direction
+const AXIS_THRESHOLD: i16 = 16384;
+
+#[derive(Clone, Copy)]
+pub struct KeyMapping {
+    pub up: Keycode,
+    pub down: Keycode,
+    pub left: Keycode,
+    pub right: Keycode,
+    pub fire: Keycode
+}
+
+impl KeyMapping {
+    pub fn cursor_keys() -> KeyMapping {
+        KeyMapping {
+            up: Keycode::Up,
+            down: Keycode::Down,
+            left: Keycode::Left,
+            right: Keycode::Right,
+            fire: Keycode::RCtrl
+        }
+    }
+
+    pub fn numpad() -> KeyMapping {
+        KeyMapping {
+            up: Keycode::Kp8,
+            down: Keycode::Kp2,
+            left: Keycode::Kp4,
+            right: Keycode::Kp6,
+            fire: Keycode::Kp0
+        }
+    }
+
+    fn direction(self: &KeyMapping, keycode: Keycode) -> Option<JoystickState> {
+        if keycode == self.up {
+            Some(JoystickState::UP)
+        } else if keycode == self.down {
+            Some(JoystickState::DOWN)
+        } else if keycode == self.left {
+            Some(JoystickState::LEFT)
+        } else if keycode == self.right {
+            Some(JoystickState::RIGHT)
+        } else if keycode == self.fire {
+            Some(JoystickState::FIRE)
+        } else {
+            None
+        }
+    }
+}
+
+struct Joystick {
+    key_mapping: Option<KeyMapping>,
+    controller: Option<GameController>,
+    keyboard_state: JoystickState,
+    controller_state: JoystickState,
+    autofire: bool
+}
+
+impl Joystick {
+    fn new(key_mapping: Option<KeyMapping>) -> Joystick {
+        Joystick {
+            key_mapping,
+            controller: None,
+            keyboard_state: JoystickState::empty(),
+            controller_state: JoystickState::empty(),
+            autofire: false
+        }
+    }
+
+    fn controller_id(self: &Joystick) -> Option<i32> {
+        self.controller.as_ref().map(|controller| controller.instance_id())
+    }
+
+    fn state(self: &Joystick, frame: u32) -> JoystickState {
+        let mut state = self.keyboard_state | self.controller_state;
+        if self.autofire && (frame / AUTOFIRE_PERIOD) % 2 == 1 {
+            state.remove(JoystickState::FIRE);
+        }
+        state
+    }
+}
+
+/// The two control ports. Port 1 is read through CIA1 port B and port 2
+/// through CIA1 port A.
+pub struct ControlPorts {
+    // Indexed by physical joystick, not by port; see `swapped`
+    joysticks: [Joystick; 2],
+    // Programmatically injected state, indexed by port
+    injected: [JoystickState; 2],
+    swapped: bool,
+    frame: u32,
+    // None when running headless
+    controller_subsystem: Option<GameControllerSubsystem>
+}
+
+impl ControlPorts {
+    pub fn new(controller_subsystem: Option<GameControllerSubsystem>) -> ControlPorts {
+        ControlPorts {
+            joysticks: [
+                Joystick::new(Some(KeyMapping::numpad())),
+                Joystick::new(Some(KeyMapping::cursor_keys()))
+            ],
+            injected: [JoystickState::empty(); 2],
+            swapped: false,
+            frame: 0,
+            controller_subsystem
+        }
+    }
+
+    fn joystick_index(self: &ControlPorts, port: usize) -> usize {
+        assert!(port == 1 || port == 2, "no such control port: {}", port);
+        if self.swapped { 2 - port } else { port - 1 }
+    }
+
+    pub fn set_key_mapping(self: &mut ControlPorts, port: usize, key_mapping: Option<KeyMapping>) {
+        let index = self.joystick_index(port);
+        self.joysticks[index].key_mapping = key_mapping;
+        self.joysticks[index].keyboard_state = JoystickState::empty();
+    }
+
+    pub fn toggle_autofire(self: &mut ControlPorts, port: usize) {
+        let index = self.joystick_index(port);
+        let autofire = !self.joysticks[index].autofire;
+        self.joysticks[index].autofire = autofire;
+        println!("Autofire in port {} {}", port, if autofire { "enabled" } else { "disabled" });
+    }
+
+    pub fn swap(self: &mut ControlPorts) {
+        self.swapped = !self.swapped;
+        println!("Control ports {}", if self.swapped { "swapped" } else { "restored" });
+    }
+
+    /// Overrides the state of the joystick in the given port regardless of
+    /// what the keyboard and game controllers do, e.g. for scripted input.
+    pub fn inject(self: &mut ControlPorts, port: usize, state: JoystickState) {
+        assert!(port == 1 || port == 2, "no such control port: {}", port);
+        self.injected[port - 1] = state;
+    }
+
+    pub fn state(self: &ControlPorts, port: usize) -> JoystickState {
+        let index = self.joystick_index(port);
+        self.joysticks[index].state(self.frame) | self.injected[port - 1]
+    }
+
+    /// Value of the joystick lines as seen on the CIA port pins. The lines
+    /// are active low and only bits 0-4 are connected.
+    pub fn port_bits(self: &ControlPorts, port: usize) -> u8 {
+        !self.state(port).bits
+    }
+
+    pub fn end_frame(self: &mut ControlPorts) {
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    fn attach_controller(self: &mut ControlPorts, joystick_index: u32) {
+        let controller_subsystem = match self.controller_subsystem {
+            Some(ref controller_subsystem) if controller_subsystem.is_game_controller(joystick_index) => controller_subsystem.clone(),
+            _ => return
+        };
+        // Fill port 2 first, most games are played with a joystick in port 2
+        let free = [self.joystick_index(2), self.joystick_index(1)].iter()
+            .cloned()
+            .find(|&index| self.joysticks[index].controller.is_none());
+        if let Some(index) = free {
+            match controller_subsystem.open(joystick_index) {
+                Ok(controller) => {
+                    println!("Attached game controller {}", controller.name());
+                    self.joysticks[index].controller = Some(controller);
+                }
+                Err(err) => {
+                    println!("Failed to open game controller {}: {}", joystick_index, err);
+                }
+            }
+        }
+    }
+
+    fn joystick_for_controller(self: &mut ControlPorts, id: i32) -> Option<&mut Joystick> {
+        self.joysticks.iter_mut().find(|joystick| joystick.controller_id() == Some(id))
+    }
+
+    pub fn handle_event(self: &mut ControlPorts, event: &Event) {
+        match *event {
+            Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                for joystick in self.joysticks.iter_mut() {
+                    if let Some(direction) = joystick.key_mapping.and_then(|mapping| mapping.direction(keycode)) {
+                        joystick.keyboard_state.insert(direction);
+                    }
+                }
+            }
+            Event::KeyUp { keycode: Some(keycode), .. } => {
+                for joystick in self.joysticks.iter_mut() {
+                    if let Some(direction) = joystick.key_mapping.and_then(|mapping| mapping.direction(keycode)) {
+                        joystick.keyboard_state.remove(direction);
+                    }
+                }
+            }
+            Event::ControllerDeviceAdded { which, .. } => {
+                self.attach_controller(which);
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(joystick) = self.joystick_for_controller(which) {
+                    joystick.controller = None;
+                    joystick.controller_state = JoystickState::empty();
+                }
+            }
+            Event::ControllerButtonDown { which, button, .. } | Event::ControllerButtonUp { which, button, .. } => {
+                let pressed = match *event {
+                    Event::ControllerButtonDown { .. } => true,
+                    _ => false
+                };
+                let direction = match button {
+                    Button::DPadUp => JoystickState::UP,
+                    Button::DPadDown => JoystickState::DOWN,
+                    Button::DPadLeft => JoystickState::LEFT,
+                    Button::DPadRight => JoystickState::RIGHT,
+                    Button::A | Button::B | Button::X | Button::Y => JoystickState::FIRE,
+                    _ => return
+                };
+                if let Some(joystick) = self.joystick_for_controller(which) {
+                    joystick.controller_state.set(direction, pressed);
+                }
+            }
+            Event::ControllerAxisMotion { which, axis, value, .. } => {
+                let (negative, positive) = match axis {
+                    Axis::LeftX => (JoystickState::LEFT, JoystickState::RIGHT),
+                    Axis::LeftY => (JoystickState::UP, JoystickState::DOWN),
+                    _ => return
+                };
+                if let Some(joystick) = self.joystick_for_controller(which) {
+                    joystick.controller_state.set(negative, value < -AXIS_THRESHOLD);
+                    joystick.controller_state.set(positive, value > AXIS_THRESHOLD);
+                }
+            }
+            _ => ()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use joystick::sdl2::keyboard::Mod;
+
+    fn key_event(keycode: Keycode, pressed: bool) -> Event {
+        if pressed {
+            Event::KeyDown { timestamp: 0, window_id: 0, keycode: Some(keycode), scancode: None, keymod: Mod::empty(), repeat: false }
+        } else {
+            Event::KeyUp { timestamp: 0, window_id: 0, keycode: Some(keycode), scancode: None, keymod: Mod::empty(), repeat: false }
+        }
+    }
+
+    #[test]
+    fn works_without_a_controller_subsystem() {
+        let mut ports = ControlPorts::new(None);
+        assert_eq!(ports.port_bits(1), 0xFF);
+        assert_eq!(ports.port_bits(2), 0xFF);
+        ports.handle_event(&Event::ControllerDeviceAdded { timestamp: 0, which: 0 });
+        assert_eq!(ports.port_bits(1), 0xFF);
+    }
+
+    #[test]
+    fn injected_state_is_active_low() {
+        let mut ports = ControlPorts::new(None);
+        ports.inject(2, JoystickState::UP | JoystickState::FIRE);
+        assert_eq!(ports.port_bits(2), 0b1110_1110);
+        assert_eq!(ports.port_bits(1), 0xFF);
+        ports.inject(1, JoystickState::LEFT);
+        assert_eq!(ports.port_bits(1), 0b1111_1011);
+        ports.inject(2, JoystickState::empty());
+        assert_eq!(ports.port_bits(2), 0xFF);
+    }
+
+    #[test]
+    fn keys_follow_the_joystick_when_swapped() {
+        let mut ports = ControlPorts::new(None);
+        ports.handle_event(&key_event(Keycode::Up, true));
+        assert_eq!(ports.state(2), JoystickState::UP);
+        assert_eq!(ports.state(1), JoystickState::empty());
+        ports.swap();
+        assert_eq!(ports.state(1), JoystickState::UP);
+        assert_eq!(ports.state(2), JoystickState::empty());
+        ports.handle_event(&key_event(Keycode::Kp6, true));
+        assert_eq!(ports.state(2), JoystickState::RIGHT);
+        ports.swap();
+        assert_eq!(ports.state(1), JoystickState::RIGHT);
+        ports.handle_event(&key_event(Keycode::Up, false));
+        assert_eq!(ports.state(2), JoystickState::empty());
+    }
+
+    #[test]
+    fn autofire_toggles_fire_every_period() {
+        let mut ports = ControlPorts::new(None);
+        ports.handle_event(&key_event(Keycode::RCtrl, true));
+        ports.toggle_autofire(2);
+        let fire: Vec<bool> = (0..AUTOFIRE_PERIOD * 4).map(|_| {
+            let pressed = ports.state(2).contains(JoystickState::FIRE);
+            ports.end_frame();
+            pressed
+        }).collect();
+        let period = AUTOFIRE_PERIOD as usize;
+        for (frame, &pressed) in fire.iter().enumerate() {
+            assert_eq!(pressed, frame % (2 * period) < period, "frame {}", frame);
+        }
+        ports.toggle_autofire(2);
+        assert!(ports.state(2).contains(JoystickState::FIRE));
+    }
+}
@@ -0,0 +1,280 @@
+// Fast LOAD and SAVE for a disk image attached as a drive, and LOAD for a
+// T64 archive attached as the tape. The routines the
+// ILOAD and ISAVE vectors ($0330, $0332) point to by default are replaced:
+// when the CPU arrives there for the drive's device number, the transfer is
+// done at once and the routine returns as the KERNAL one would. Programs
+// that hook the vectors, like fast loaders, keep their own routines.
+//
+// For host directories the routines that talk to the serial bus are
+// replaced as well, so that OPEN, CLOSE and the channels opened with them
+// reach the directory: LISTEN and TALK address a device, SECOND and TKSA
+// send it the secondary address, CIOUT and ACPTR send and receive bytes,
+// UNLSN and UNTLK end the transfer.
+
+use d64::{self, DiskImage, FileType};
+use host_dir::HostDirectory;
+use t64::T64Archive;
+use memory::{ReadView, WriteView};
+use mos6510::Mos6510;
+
+/// Entry of the KERNAL LOAD routine after the ILOAD vector.
+pub const LOAD_ADDR: u16 = 0xF4A5;
+/// Entry of the KERNAL SAVE routine after the ISAVE vector.
+pub const SAVE_ADDR: u16 = 0xF5ED;
+
+// Entries of the KERNAL serial bus routines
+const TALK_ADDR: u16 = 0xED09;
+const LISTEN_ADDR: u16 = 0xED0C;
+const SECOND_ADDR: u16 = 0xEDB9;
+const TKSA_ADDR: u16 = 0xEDC7;
+const CIOUT_ADDR: u16 = 0xEDDD;
+const UNTLK_ADDR: u16 = 0xEDEF;
+const UNLSN_ADDR: u16 = 0xEDFE;
+const ACPTR_ADDR: u16 = 0xEE13;
+
+/// Device number of the datasette.
+pub const TAPE_DEVICE: u8 = 1;
+
+// KERNAL variables in the zero page
+const STATUS: u16 = 0x90;
+const FILENAME_LENGTH: u16 = 0xB7;
+const SECONDARY_ADDRESS: u16 = 0xB9;
+const DEVICE_NUMBER: u16 = 0xBA;
+const FILENAME: u16 = 0xBB;
+// Start address of SAVE
+const SAVE_START: u16 = 0xC1;
+// Address given to LOAD in X and Y, used with secondary address 0
+const LOAD_START: u16 = 0xC3;
+// End address of LOAD and SAVE
+const END: u16 = 0xAE;
+
+// Status bits
+const STATUS_READ_TIMEOUT: u8 = 0x02;
+const STATUS_VERIFY_ERROR: u8 = 0x10;
+const STATUS_END_OF_FILE: u8 = 0x40;
+
+// KERNAL error codes, returned in A with the carry set
+const FILE_NOT_FOUND: u8 = 4;
+const MISSING_FILENAME: u8 = 8;
+
+fn read_pointer<M: ReadView>(mem: &mut M, addr: u16) -> u16 {
+    mem.read(addr) as u16 | (mem.read(addr + 1) as u16) << 8
+}
+
+fn write_pointer<M: WriteView>(mem: &mut M, addr: u16, value: u16) {
+    mem.write(addr, value as u8);
+    mem.write(addr + 1, (value >> 8) as u8);
+}
+
+fn filename<M: ReadView>(mem: &mut M) -> Vec<u8> {
+    let addr = read_pointer(mem, FILENAME);
+    let length = mem.read(FILENAME_LENGTH) as u16;
+    (0..length).map(|i| mem.read(addr.wrapping_add(i))).collect()
+}
+
+// PETSCII file name for messages
+fn display_name(name: &[u8]) -> String {
+    name.iter().map(|&c| if (0x20..0x7F).contains(&c) { c as char } else { '?' }).collect()
+}
+
+/// Splits "@0:NAME" into the name and whether to replace an existing file.
+pub fn parse_filename(name: &[u8]) -> (&[u8], bool) {
+    let (name, replace) = if name.first() == Some(&b'@') { (&name[1..], true) } else { (name, false) };
+    match name.iter().position(|&c| c == b':') {
+        Some(colon) => (&name[colon + 1..], replace),
+        None => (name, replace)
+    }
+}
+
+/// The files of a device served by the traps.
+pub trait FileStore {
+    /// The contents of the first file matching a pattern with wildcards.
+    fn load_file(self: &Self, pattern: &[u8]) -> Result<Option<Vec<u8>>, String>;
+    /// The directory as the BASIC program LOAD"$" gets.
+    fn listing(self: &Self) -> Result<Vec<u8>, String>;
+    /// Writes a PRG file, replacing an existing one if asked to.
+    fn save_file(self: &mut Self, name: &[u8], data: &[u8], replace: bool) -> Result<(), String>;
+}
+
+impl FileStore for DiskImage {
+    fn load_file(self: &DiskImage, pattern: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        match self.find(pattern)? {
+            Some(entry) => self.read_file(&entry).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn listing(self: &DiskImage) -> Result<Vec<u8>, String> {
+        self.directory_listing()
+    }
+
+    // The image file is written right away
+    fn save_file(self: &mut DiskImage, name: &[u8], data: &[u8], replace: bool) -> Result<(), String> {
+        self.write_file(name, FileType::Prg, data, replace).and_then(|_| self.save())
+    }
+}
+
+impl FileStore for T64Archive {
+    fn load_file(self: &T64Archive, pattern: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.files().iter().find(|file| d64::matches(pattern, &file.name)).map(|file| file.data.clone()))
+    }
+
+    fn listing(self: &T64Archive) -> Result<Vec<u8>, String> {
+        Err("Tapes have no directory".to_string())
+    }
+
+    fn save_file(self: &mut T64Archive, _name: &[u8], _data: &[u8], _replace: bool) -> Result<(), String> {
+        Err(format!("{} is read-only", self.filename()))
+    }
+}
+
+impl FileStore for HostDirectory {
+    fn load_file(self: &HostDirectory, pattern: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        match self.find(pattern)? {
+            Some(file) => self.read_file(&file).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn listing(self: &HostDirectory) -> Result<Vec<u8>, String> {
+        self.directory_listing()
+    }
+
+    fn save_file(self: &mut HostDirectory, name: &[u8], data: &[u8], replace: bool) -> Result<(), String> {
+        self.write_file(name, FileType::Prg, data, replace)
+    }
+}
+
+/// Device number of the LOAD or SAVE in progress.
+pub fn device<M: ReadView>(mem: &mut M) -> u8 {
+    mem.read(DEVICE_NUMBER)
+}
+
+/// Performs LOAD, or VERIFY if A is not zero, and returns to the caller.
+pub fn load<M: ReadView + WriteView>(cpu: &mut Mos6510, mem: &mut M, files: &dyn FileStore) {
+    let verify = cpu.accumulator() != 0;
+    let name = filename(mem);
+    // Without a name the tape loads the next file, here the first one
+    let tape = device(mem) == TAPE_DEVICE;
+    if name.is_empty() && !tape {
+        cpu.return_from_trap(mem, MISSING_FILENAME, 0, 0, true);
+        return;
+    }
+    let file = if tape {
+        files.load_file(if name.is_empty() { b"*" } else { &name })
+    } else if name[0] == b'$' {
+        files.listing().map(Some)
+    } else {
+        files.load_file(parse_filename(&name).0)
+    };
+    let data = match file {
+        Ok(Some(ref data)) if data.len() >= 2 => data,
+        Ok(_) => {
+            println!("File not found: {}", display_name(&name));
+            cpu.return_from_trap(mem, FILE_NOT_FOUND, 0, 0, true);
+            return;
+        }
+        Err(msg) => {
+            // BASIC reports a status other than end of file as LOAD ERROR
+            println!("Reading {} failed: {}", display_name(&name), msg);
+            mem.write(STATUS, STATUS_READ_TIMEOUT);
+            let end = read_pointer(mem, LOAD_START);
+            cpu.return_from_trap(mem, 0, end as u8, (end >> 8) as u8, false);
+            return;
+        }
+    };
+
+    let start = if mem.read(SECONDARY_ADDRESS) == 0 {
+        read_pointer(mem, LOAD_START)
+    } else {
+        data[0] as u16 | (data[1] as u16) << 8
+    };
+    let mut status = STATUS_END_OF_FILE;
+    let mut addr = start;
+    for &byte in data[2..].iter() {
+        if verify {
+            if mem.read(addr) != byte {
+                status |= STATUS_VERIFY_ERROR;
+            }
+        } else {
+            mem.write(addr, byte);
+        }
+        addr = addr.wrapping_add(1);
+    }
+    println!("{} {} at 0x{:04X}-0x{:04X}", if verify { "Verified" } else { "Loaded" }, display_name(&name), start, addr.wrapping_sub(1));
+    mem.write(STATUS, status);
+    write_pointer(mem, END, addr);
+    cpu.return_from_trap(mem, 0, addr as u8, (addr >> 8) as u8, false);
+}
+
+/// Performs SAVE and returns to the caller.
+pub fn save<M: ReadView + WriteView>(cpu: &mut Mos6510, mem: &mut M, files: &mut dyn FileStore) {
+    let name = filename(mem);
+    if name.is_empty() {
+        cpu.return_from_trap(mem, MISSING_FILENAME, 0, 0, true);
+        return;
+    }
+    let (name, replace) = parse_filename(&name);
+    let start = read_pointer(mem, SAVE_START);
+    let end = read_pointer(mem, END);
+    let mut data = vec![start as u8, (start >> 8) as u8];
+    let mut addr = start;
+    while addr != end {
+        data.push(mem.read(addr));
+        addr = addr.wrapping_add(1);
+    }
+    // The drive reports errors on its command channel, not to SAVE
+    match files.save_file(name, &data, replace) {
+        Ok(()) => println!("Saved {} from 0x{:04X}-0x{:04X}", display_name(name), start, end.wrapping_sub(1)),
+        Err(msg) => println!("Saving {} failed: {}", display_name(name), msg)
+    }
+    mem.write(STATUS, 0);
+    cpu.return_from_trap(mem, 0, end as u8, (end >> 8) as u8, false);
+}
+
+/// Whether the CPU is at the entry of a KERNAL serial bus routine.
+pub fn serial_routine(pc: u16) -> bool {
+    match pc {
+        TALK_ADDR | LISTEN_ADDR | SECOND_ADDR | TKSA_ADDR | CIOUT_ADDR | UNTLK_ADDR | UNLSN_ADDR | ACPTR_ADDR => true,
+        _ => false
+    }
+}
+
+/// Serves a call of a KERNAL serial bus routine for a host directory if
+/// the call addresses its device or the directory was addressed before.
+/// Returns whether it did.
+pub fn serial<M: ReadView + WriteView>(cpu: &mut Mos6510, mem: &mut M, pc: u16, device: u8, dir: &mut HostDirectory) -> bool {
+    let mut a = cpu.accumulator();
+    match pc {
+        LISTEN_ADDR | TALK_ADDR if a & 0x1F != device => {
+            dir.unaddress();
+            return false;
+        }
+        LISTEN_ADDR => dir.listen(),
+        TALK_ADDR => dir.talk(),
+        SECOND_ADDR | TKSA_ADDR if dir.listening() || dir.talking() => dir.second(a),
+        CIOUT_ADDR if dir.listening() => dir.receive(a),
+        ACPTR_ADDR if dir.talking() => {
+            let status = mem.read(STATUS);
+            a = match dir.send() {
+                Some((byte, last)) => {
+                    if last {
+                        mem.write(STATUS, status | STATUS_END_OF_FILE);
+                    }
+                    byte
+                }
+                // Like a drive that doesn't answer, which LOAD reports as FILE NOT FOUND
+                None => {
+                    mem.write(STATUS, status | STATUS_READ_TIMEOUT | STATUS_END_OF_FILE);
+                    b'\r'
+                }
+            };
+        }
+        UNLSN_ADDR if dir.listening() => dir.unlisten(),
+        UNTLK_ADDR if dir.talking() => dir.untalk(),
+        _ => return false
+    }
+    let (x, y) = (cpu.index_x(), cpu.index_y());
+    cpu.return_from_trap(mem, a, x, y, false);
+    true
+}
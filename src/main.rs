@@ -3,104 +3,346 @@ extern crate regex;
 extern crate rustyline;
 #[macro_use]
 extern crate bitflags;
+extern crate sdl2;
 
 use std::collections::HashSet;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
 
 use regex::Regex;
 use rustyline::error::ReadlineError;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
 
 mod memory;
 use memory::{ReadView, WriteView};
 
 mod mos6510;
-use mos6510::Mos6510;
+use mos6510::{Mos6510, ProcessorPort};
 use mos6510::Effect;
 
 mod vic_ii;
-use vic_ii::VicII;
+use vic_ii::{VicII, VideoStandard};
+
+mod cia;
+use cia::CiaModel;
 
 mod cia1;
 use cia1::Cia1;
 
+mod cia2;
+use cia2::Cia2;
+
+mod sid;
+use sid::SidModel;
+
+mod sid_chips;
+use sid_chips::SidChips;
+
+mod joystick;
+use joystick::{ControlPorts, JoystickState, KeyMapping};
+
+mod audio;
+use audio::{AudioOutput, AudioRecorder};
+
+mod psid;
+use psid::{SidTune, TuneFormat};
+
+mod digi_test;
+
+mod prg;
+use prg::{Autostart, Program};
+
+mod d64;
+use d64::DiskImage;
+
+mod kernal_traps;
+
+mod via;
+
+mod gcr;
+use gcr::GcrDisk;
+
+mod drive1541;
+use drive1541::Drive1541;
+
+mod iec;
+use iec::IecBus;
+
+mod host_dir;
+use host_dir::HostDirectory;
+
+mod datasette;
+use datasette::{Datasette, Tape};
+
+mod t64;
+use t64::T64Archive;
+
+mod am29f040;
+
+mod cartridge;
+use cartridge::Cartridge;
+
+mod reu;
+use reu::{DmaAccess, Reu};
+
+mod georam;
+use georam::GeoRam;
+
+mod expansion_port;
+use expansion_port::ExpansionPort;
+
+mod snapshot;
+use snapshot::Snapshot;
+
+mod vsf;
+use vsf::VsfSnapshot;
+
+mod rewind;
+use rewind::RewindBuffer;
+
+// Where the KERNAL waits for a key at the READY prompt
+const READY_LOOP_ADDR: u16 = 0xE5CD;
+
+const DRIVE_ROM: &str = "1541.rom";
+
+// Frames between the snapshots kept for rewinding, a second, and how many
+// are kept
+const REWIND_INTERVAL: u32 = 50;
+const REWIND_SNAPSHOTS: usize = 60;
+
+// The modules of a VICE snapshot that are imported
+const VSF_MODULES: [&str; 6] = ["C64MEM", "MAINCPU", "VIC-II", "CIA1", "CIA2", "SID"];
+
+// A disk drive on the serial bus
+struct DiskUnit {
+    disk: Option<DiskImage>,
+    // The 1541 when emulated, otherwise the disk is served by the KERNAL traps
+    drive: Option<Drive1541>,
+    // A host directory instead of a disk, always served by the traps
+    host_dir: Option<HostDirectory>
+}
+
 struct Machine {
     ram: [u8; 65536],
     io: [u8; 65536],
+    basic_rom: [u8; 8192],
+    kernal_rom: [u8; 8192],
     char_rom: [u8; 4096],
-    char_rom_enabled: bool,
     color_ram: [u8; 1024],
-    vic_bank_start: u16,
     mos6510: Mos6510,
+    processor_port: ProcessorPort,
     vic: VicII,
-    cia1: Cia1
+    cia1: Cia1,
+    cia2: Cia2,
+    sids: SidChips,
+    control_ports: ControlPorts,
+    // None when running headless
+    event_pump: Option<sdl2::EventPump>,
+    audio_output: Option<AudioOutput>,
+    audio_recorder: Option<AudioRecorder>,
+    sample_rate: u32,
+    // PAL or NTSC
+    standard: VideoStandard,
+    sid_tune: Option<SidTune>,
+    subtune: u16,
+    // The tune is installed once the KERNAL reaches the READY prompt
+    tune_pending: bool,
+    // Likewise a program loaded from the command line. It is kept, as
+    // going back to before the prompt installs it again.
+    program: Option<(Program, Autostart)>,
+    program_pending: bool,
+    // Devices 8 to 11
+    disk_units: Vec<DiskUnit>,
+    iec_bus: IecBus,
+    // Drive clock cycles owed, in units of the C64 clock
+    drive_clock: u32,
+    // Cycles run since power on, for the bus trace
+    cycles: u64,
+    datasette: Datasette,
+    // A T64 archive instead of a tape, loaded through the KERNAL traps
+    t64: Option<T64Archive>,
+    expansion_port: ExpansionPort,
+    // Whether flashed cartridges are written back to their images
+    cartridge_write_back: bool,
+    rewind: RewindBuffer,
+    // Set by the hotkey, the machine goes back at the start of the next cycle
+    rewind_requested: bool,
+    // Running forward again after going back, without sound or events
+    replaying: bool
 }
 
 
 #[derive(PartialEq)]
 enum MemoryRegion {
-    Rom,
+    BasicRom,
+    KernalRom,
     CharRom
 }
 
 struct Mos6510Memory<'a> {
     ram: &'a mut [u8],
     io: &'a mut [u8],
+    basic_rom: &'a [u8],
+    kernal_rom: &'a [u8],
+    char_rom: &'a [u8],
+    processor_port: &'a mut ProcessorPort,
     vic_registers: &'a mut vic_ii::Registers,
-    vic_bank_start: u16,
-    char_rom_enabled: &'a mut bool,
     color_ram: &'a mut [u8],
-    cia1: &'a mut Cia1
+    cia1: &'a mut Cia1,
+    cia2: &'a mut Cia2,
+    sids: &'a mut SidChips,
+    expansion_port: &'a mut ExpansionPort
 }
 
-impl<'a> Mos6510Memory<'a> {
-    fn new(ram: &'a mut [u8], io: &'a mut [u8], vic_registers: &'a mut vic_ii::Registers, vic_bank_start: u16, char_rom_enabled: &'a mut bool, color_ram: &'a mut [u8], cia1: &'a mut Cia1) -> Mos6510Memory<'a> {
+// The memory as the CPU of the machine sees it. A macro rather than a
+// method of Machine, as the CPU and the drives are borrowed alongside it.
+macro_rules! cpu_memory {
+    ($machine:expr) => {
         Mos6510Memory {
-            ram,
-            io,
-            vic_registers,
-            vic_bank_start,
-            char_rom_enabled,
-            color_ram,
-            cia1
+            ram: &mut $machine.ram,
+            io: &mut $machine.io,
+            basic_rom: &$machine.basic_rom,
+            kernal_rom: &$machine.kernal_rom,
+            char_rom: &$machine.char_rom,
+            processor_port: &mut $machine.processor_port,
+            vic_registers: &mut $machine.vic.registers,
+            color_ram: &mut $machine.color_ram,
+            cia1: &mut $machine.cia1,
+            cia2: &mut $machine.cia2,
+            sids: &mut $machine.sids,
+            expansion_port: &mut $machine.expansion_port
         }
     }
 }
 
-impl<'a> ReadView for Mos6510Memory<'a> {
-    fn read(self: &mut Mos6510Memory<'a>, addr: u16) -> u8 {
-         if addr >= 0xD000 && addr < 0xD400 {
-             // TODO: Read VIC-II registers
-            self.io[addr as usize]
-        } else if addr >= 0xD400 && addr < 0xDC00 || addr >= 0xDD00 && addr < 0xE000 {
+// Processor port bits selecting the memory configuration
+const LORAM: u8  = 0b0000_0001;
+const HIRAM: u8  = 0b0000_0010;
+const CHAREN: u8 = 0b0000_0100;
+
+impl<'a> Mos6510Memory<'a> {
+    // The EXROM and GAME lines of the expansion port take part in the
+    // decoding as well: EXROM low alone is the 8K mode, both low the 16K
+    // mode and GAME low alone the Ultimax mode
+    fn sixteen_k_mode(self: &Mos6510Memory<'a>) -> bool {
+        !self.expansion_port.exrom() && !self.expansion_port.game()
+    }
+
+    fn basic_visible(self: &Mos6510Memory<'a>) -> bool {
+        self.processor_port.pins() & (LORAM | HIRAM) == LORAM | HIRAM && self.expansion_port.game()
+    }
+
+    fn kernal_visible(self: &Mos6510Memory<'a>) -> bool {
+        self.processor_port.pins() & HIRAM > 0 && !self.expansion_port.ultimax()
+    }
+
+    fn roml_visible(self: &Mos6510Memory<'a>) -> bool {
+        self.expansion_port.ultimax() || (!self.expansion_port.exrom() && self.processor_port.pins() & (LORAM | HIRAM) == LORAM | HIRAM)
+    }
+
+    // ROMH at $A000-$BFFF, in Ultimax mode it replaces the KERNAL instead
+    fn romh_visible(self: &Mos6510Memory<'a>) -> bool {
+        self.sixteen_k_mode() && self.processor_port.pins() & HIRAM > 0
+    }
+
+    // $D000-$DFFF is RAM only when both LORAM and HIRAM are low, otherwise
+    // CHAREN selects between I/O and the character ROM. In Ultimax mode
+    // I/O is always there, in 16K mode HIRAM low leaves no character ROM.
+    fn io_visible(self: &Mos6510Memory<'a>) -> bool {
+        let pins = self.processor_port.pins();
+        self.expansion_port.ultimax() || (pins & (LORAM | HIRAM) > 0 && pins & CHAREN > 0)
+    }
+
+    fn char_rom_visible(self: &Mos6510Memory<'a>) -> bool {
+        let pins = self.processor_port.pins();
+        pins & (LORAM | HIRAM) > 0 && pins & CHAREN == 0 && !self.expansion_port.ultimax()
+            && !(self.sixteen_k_mode() && pins & HIRAM == 0)
+    }
+
+    // Ultimax mode only has RAM at $0000-$0FFF, the rest is ROML, ROMH,
+    // I/O or nothing at all
+    fn ram_unmapped(self: &Mos6510Memory<'a>, addr: u16) -> bool {
+        addr >= 0x1000 && self.expansion_port.ultimax()
+    }
+
+    fn read_io(self: &mut Mos6510Memory<'a>, addr: u16) -> u8 {
+        if addr >= 0xD000 && addr < 0xD400 {
+            // TODO: Read VIC-II registers
             self.io[addr as usize]
         } else if addr >= 0xDC00 && addr < 0xDD00 {
             self.cia1.read(addr)
+        } else if addr >= 0xDD00 && addr < 0xDE00 {
+            self.cia2.read(addr)
+        } else if let Some(value) = self.sids.read(addr) {
+            value
+        } else if let Some(value) = self.expansion_port.read_io(addr) {
+            value
         } else {
-            self.ram[addr as usize]
+            self.io[addr as usize]
         }
     }
-}
 
-impl<'a> WriteView for Mos6510Memory<'a> {
-    fn write(self: &mut Mos6510Memory<'a>, addr: u16, value: u8) -> () {
-        // TODO: implement bank switching
-        if (addr >= 0xA000 && addr < 0xC000) || addr >= 0xE000 {
-            println!("Tried to write 0x{:02X} to ROM at 0x{:04X}, ignoring", value, addr);
-        } else if addr >= 0xD000 && addr < 0xD400 {
+    fn write_io(self: &mut Mos6510Memory<'a>, addr: u16, value: u8) {
+        if addr >= 0xD000 && addr < 0xD400 {
             self.vic_registers.write(addr, value);
         } else if addr >= 0xD800 && addr < 0xDC00 {
             self.color_ram[addr as usize - 0xD800] = value;
-        } else if (addr >= 0xD400 && addr < 0xD800) || (addr >= 0xDC00 && addr < 0xE000) {
+        } else if addr >= 0xDC00 && addr < 0xDD00 {
+            self.cia1.write(addr, value);
+        } else if addr >= 0xDD00 && addr < 0xDE00 {
+            self.cia2.write(addr, value);
+        } else if !self.sids.write(addr, value) && !self.expansion_port.write_io(addr, value) {
             self.io[addr as usize] = value;
-            if addr >= 0xDC00 && addr < 0xDD00 {
-                self.cia1.write(addr, value);
-            }
-            if addr == 0xDD00 {
-                self.vic_bank_start = 16384 * (0b11 - (value as u16 & 0b11));
-                *self.char_rom_enabled = value & 1 > 0;
-            }
+        }
+    }
+}
+
+impl<'a> ReadView for Mos6510Memory<'a> {
+    fn read(self: &mut Mos6510Memory<'a>, addr: u16) -> u8 {
+        if addr < 0x0002 {
+            self.processor_port.read(addr)
+        } else if addr >= 0x8000 && addr < 0xA000 && self.roml_visible() {
+            self.expansion_port.read_roml(addr)
+        } else if addr >= 0xA000 && addr < 0xC000 && self.romh_visible() {
+            self.expansion_port.read_romh(addr)
+        } else if addr >= 0xA000 && addr < 0xC000 && self.basic_visible() {
+            self.basic_rom[addr as usize - 0xA000]
+        } else if addr >= 0xD000 && addr < 0xE000 && self.io_visible() {
+            self.read_io(addr)
+        } else if addr >= 0xD000 && addr < 0xE000 && self.char_rom_visible() {
+            self.char_rom[addr as usize - 0xD000]
+        } else if addr >= 0xE000 && self.expansion_port.ultimax() {
+            self.expansion_port.read_romh(addr)
+        } else if addr >= 0xE000 && self.kernal_visible() {
+            self.kernal_rom[addr as usize - 0xE000]
+        } else if self.ram_unmapped(addr) {
+            cartridge::OPEN_BUS
+        } else {
+            self.ram[addr as usize]
+        }
+    }
+}
+
+impl<'a> WriteView for Mos6510Memory<'a> {
+    fn write(self: &mut Mos6510Memory<'a>, addr: u16, value: u8) -> () {
+        if addr == 0xFF00 {
+            self.expansion_port.write_ff00();
+        }
+        if addr >= 0xD000 && addr < 0xE000 && self.io_visible() {
+            self.write_io(addr, value);
+        } else if addr >= 0x8000 && addr < 0xA000 && self.expansion_port.ultimax() {
+            self.expansion_port.write_roml(addr, value);
+        } else if addr >= 0xE000 && self.expansion_port.ultimax() {
+            self.expansion_port.write_romh(addr, value);
+        } else if self.ram_unmapped(addr) {
+            // Lost in the gaps of the Ultimax mode
         } else {
+            // Writes to the processor port and to ROM areas go to the RAM underneath as well
+            if addr < 0x0002 {
+                self.processor_port.write(addr, value);
+            }
             self.ram[addr as usize] = value;
         }
     }
@@ -109,65 +351,809 @@ impl<'a> WriteView for Mos6510Memory<'a> {
 struct VicMemory<'a> {
     ram: &'a [u8],
     char_rom: &'a [u8],
-    char_rom_enabled: bool,
+    bank_start: u16,
+    expansion_port: &'a ExpansionPort
 }
 
 impl<'a> VicMemory<'a> {
-    fn new(ram: &'a [u8], char_rom: &'a [u8], char_rom_enabled: bool) -> VicMemory<'a> {
+    fn new(ram: &'a [u8], char_rom: &'a [u8], bank_start: u16, expansion_port: &'a ExpansionPort) -> VicMemory<'a> {
         VicMemory {
             ram,
             char_rom,
-            char_rom_enabled
+            bank_start,
+            expansion_port
         }
     }
 }
 
 impl<'a> ReadView for VicMemory<'a> {
+    // The VIC-II addresses 16K at a time, the bank is selected by CIA2
     fn read(self: &mut VicMemory<'a>, addr: u16) -> u8 {
-        if self.char_rom_enabled && addr >= 0x1000 && addr < 0x2000 {
+        let addr = addr & 0x3FFF;
+        let ultimax = self.expansion_port.ultimax();
+        // The character ROM is visible at $1000-$1FFF of banks 0 and 2. In
+        // Ultimax mode the upper half of ROMH is visible at $3000-$3FFF of
+        // every bank instead.
+        if ultimax && addr >= 0x3000 {
+            self.expansion_port.read_romh(0xF000 | addr)
+        } else if !ultimax && (self.bank_start == 0x0000 || self.bank_start == 0x8000) && addr >= 0x1000 && addr < 0x2000 {
             self.char_rom[addr as usize - 0x1000]
         } else {
-            self.ram[addr as usize]
+            self.ram[(self.bank_start + addr) as usize]
         }
     }
 }
 
 impl Machine {
-    fn new() -> Machine {
+    fn new(sdl_context: Option<&sdl2::Sdl>, sample_rate: u32, standard: VideoStandard) -> Machine {
         Machine {
             ram: [0; 65536],
             io: [0; 65536],
+            basic_rom: [0; 8192],
+            kernal_rom: [0; 8192],
             char_rom: [0; 4096],
-            char_rom_enabled: false,
             color_ram: [0; 1024],
             mos6510: Mos6510::new(),
-            vic_bank_start: 0xC000,
-            vic: VicII::new(),
-            cia1: Cia1::new()
+            processor_port: ProcessorPort::new(),
+            vic: VicII::new(sdl_context, standard),
+            cia1: Cia1::new(CiaModel::Mos6526, standard.tod_pulse_cycles()),
+            cia2: Cia2::new(CiaModel::Mos6526, standard.tod_pulse_cycles()),
+            sids: SidChips::new(SidModel::Mos6581, standard.clock_frequency),
+            control_ports: ControlPorts::new(sdl_context.map(|sdl_context| sdl_context.game_controller().unwrap())),
+            event_pump: sdl_context.map(|sdl_context| sdl_context.event_pump().unwrap()),
+            audio_output: sdl_context.and_then(|sdl_context| match AudioOutput::new(sdl_context, standard.clock_frequency) {
+                Ok(audio_output) => Some(audio_output),
+                Err(err) => {
+                    println!("Audio output not available: {}", err);
+                    None
+                }
+            }),
+            audio_recorder: None,
+            sample_rate,
+            standard,
+            sid_tune: None,
+            subtune: 0,
+            tune_pending: false,
+            program: None,
+            program_pending: false,
+            disk_units: (iec::FIRST_DRIVE..iec::LAST_DRIVE + 1).map(|_| DiskUnit { disk: None, drive: None, host_dir: None }).collect(),
+            iec_bus: IecBus::new(),
+            drive_clock: 0,
+            cycles: 0,
+            datasette: Datasette::new(),
+            t64: None,
+            expansion_port: ExpansionPort::new(),
+            cartridge_write_back: false,
+            rewind: RewindBuffer::new(REWIND_INTERVAL, REWIND_SNAPSHOTS),
+            rewind_requested: false,
+            replaying: false
         }
     }
 
     fn reset(self: &mut Machine) {
-        self.mos6510.reset(&mut Mos6510Memory::new(&mut self.ram, &mut self.io, &mut self.vic.registers, self.vic_bank_start, &mut self.char_rom_enabled, &mut self.color_ram, &mut self.cia1));
+        self.processor_port = ProcessorPort::new();
+        self.cia1 = Cia1::new(self.cia1.model(), self.standard.tod_pulse_cycles());
+        self.cia2 = Cia2::new(self.cia2.model(), self.standard.tod_pulse_cycles());
+        self.sids.reset();
+        self.expansion_port.reset();
+        for unit in self.disk_units.iter_mut() {
+            if let Some(ref mut drive) = unit.drive {
+                drive.reset();
+            }
+            if let Some(ref mut host_dir) = unit.host_dir {
+                host_dir.reset();
+            }
+        }
+        self.mos6510.reset(&mut cpu_memory!(self));
     }
 
     fn load_file(self: &mut Machine, filename: &str, memory_region: MemoryRegion, offset: usize) {
         let f = File::open(filename).expect(&format!("file not found: {}", filename));
         let target =
             match memory_region {
-                MemoryRegion::Rom => &mut self.ram[offset..],
+                MemoryRegion::BasicRom => &mut self.basic_rom[offset..],
+                MemoryRegion::KernalRom => &mut self.kernal_rom[offset..],
                 MemoryRegion::CharRom => &mut self.char_rom[offset..]
             };
         f.bytes().zip(target).for_each(|(byte, memory_byte)| *memory_byte = byte.unwrap());
     }
 
+    fn start_audio_recording(self: &mut Machine, filename: &str) {
+        self.stop_audio_recording();
+        match AudioRecorder::create(filename, self.standard.clock_frequency, self.sample_rate) {
+            Ok(recorder) => {
+                println!("Recording audio to {} at {} Hz", filename, self.sample_rate);
+                self.audio_recorder = Some(recorder);
+            }
+            Err(err) => {
+                println!("Failed to create {}: {}", filename, err);
+            }
+        }
+    }
+
+    fn stop_audio_recording(self: &mut Machine) {
+        if let Some(recorder) = self.audio_recorder.take() {
+            let filename = recorder.filename().to_string();
+            match recorder.finish() {
+                Ok(()) => println!("Audio written to {}", filename),
+                Err(err) => println!("Failed to write {}: {}", filename, err)
+            }
+        }
+    }
+
+    fn load_sid_tune(self: &mut Machine, tune: SidTune, song: u16) {
+        if let Some(model) = tune.sid_model() {
+            self.sids.built_in().set_model(model);
+        }
+        // Extra chips of the tune replace any configured ones. Two chips are
+        // played left and right, a third one in the middle.
+        let extra_sids = tune.extra_sids();
+        if !extra_sids.is_empty() {
+            let model = self.sids.built_in().model();
+            self.sids.remove_extra();
+            self.sids.set_pan(0xD400, -1.0);
+            for (i, &(addr, extra_model)) in extra_sids.iter().enumerate() {
+                let pan = if i == 0 { 1.0 } else { 0.0 };
+                self.sids.add(addr, extra_model.unwrap_or(model), pan).unwrap();
+            }
+        }
+        self.sid_tune = Some(tune);
+        self.subtune = song;
+        self.reset();
+        self.tune_pending = true;
+    }
+
+    // PSID tunes switch songs in place, RSID tunes get a freshly booted machine
+    fn play_subtune(self: &mut Machine, song: u16) {
+        let format = match self.sid_tune {
+            Some(ref tune) if song >= 1 && song <= tune.songs() => tune.format(),
+            _ => return
+        };
+        self.subtune = song;
+        if format == TuneFormat::Rsid && !self.tune_pending {
+            self.reset();
+            self.tune_pending = true;
+        } else if !self.tune_pending {
+            self.install_sid_tune();
+        }
+    }
+
+    fn install_sid_tune(self: &mut Machine) {
+        if let Some(ref tune) = self.sid_tune {
+            self.sids.reset();
+            if let Some(pc) = tune.install(&mut self.ram, self.subtune, self.standard) {
+                self.mos6510.set_pc(pc);
+            }
+            println!("Playing song {}/{}", self.subtune, tune.songs());
+        }
+    }
+
+    // Device numbers are checked by the callers
+    fn disk_unit(self: &Machine, device: u8) -> &DiskUnit {
+        &self.disk_units[(device - iec::FIRST_DRIVE) as usize]
+    }
+
+    fn disk_unit_mut(self: &mut Machine, device: u8) -> &mut DiskUnit {
+        &mut self.disk_units[(device - iec::FIRST_DRIVE) as usize]
+    }
+
+    // D64 images work with both the KERNAL traps and the emulated 1541, G64
+    // and NIB images only with the 1541, host directories only with the traps
+    fn attach_disk(self: &mut Machine, device: u8, filename: &str) -> Result<(), String> {
+        self.save_drive_disk(device);
+        let unit = self.disk_unit_mut(device);
+        let lowercase = filename.to_lowercase();
+        if Path::new(filename).is_dir() {
+            if unit.drive.is_some() {
+                return Err(format!("Host directories can't go into the emulated 1541, see drive {} off", device));
+            }
+            unit.host_dir = Some(HostDirectory::new(filename)?);
+            unit.disk = None;
+            println!("Attached directory {} as device {}", filename, device);
+            return Ok(());
+        } else if lowercase.ends_with(".g64") || lowercase.ends_with(".nib") {
+            let disk = GcrDisk::load(filename)?;
+            match unit.drive {
+                Some(ref mut drive) => drive.insert_disk(Some(disk)),
+                None => return Err(format!("G64 and NIB images need the 1541 emulation, see drive {} on", device))
+            }
+            unit.disk = None;
+        } else {
+            let disk = DiskImage::load(filename)?;
+            if let Some(ref mut drive) = unit.drive {
+                drive.insert_disk(Some(GcrDisk::from_d64(&disk)));
+            }
+            unit.disk = Some(disk);
+        }
+        unit.host_dir = None;
+        println!("Attached {} as device {}", filename, device);
+        Ok(())
+    }
+
+    // Writes what the 1541 has written to its disk back to the image file
+    fn save_drive_disk(self: &mut Machine, device: u8) {
+        let unit = self.disk_unit_mut(device);
+        let disk = match unit.drive.as_mut().and_then(|drive| drive.disk_mut()) {
+            Some(disk) if disk.modified() => disk,
+            _ => return
+        };
+        match disk.save() {
+            Ok(()) => {
+                println!("Saved {}", disk.filename());
+                // The KERNAL traps get the new contents
+                if disk.is_d64() {
+                    unit.disk = DiskImage::load(disk.filename()).ok();
+                }
+            }
+            Err(msg) => println!("{}", msg)
+        }
+    }
+
+    fn save_drive_disks(self: &mut Machine) {
+        for device in iec::FIRST_DRIVE..iec::LAST_DRIVE + 1 {
+            self.save_drive_disk(device);
+        }
+    }
+
+    // Writes the disk of a device to a D64 or G64 image, by the extension
+    fn export_disk(self: &Machine, device: u8, filename: &str) -> Result<(), String> {
+        let unit = self.disk_unit(device);
+        if unit.host_dir.is_some() {
+            return Err(format!("Device {} is a host directory", device));
+        }
+        let converted;
+        let disk = match (unit.drive.as_ref().and_then(|drive| drive.disk()), unit.disk.as_ref()) {
+            (Some(disk), _) => disk,
+            (None, Some(disk)) => {
+                converted = GcrDisk::from_d64(disk);
+                &converted
+            }
+            (None, None) => return Err(format!("No disk attached to device {}", device))
+        };
+        if filename.to_lowercase().ends_with(".g64") {
+            disk.write_g64(filename)
+        } else {
+            disk.to_d64(filename)?.save()
+        }
+    }
+
+    // Switches between the emulated 1541 and the KERNAL traps
+    fn set_true_drive(self: &mut Machine, device: u8, enabled: bool) -> Result<(), String> {
+        if !enabled {
+            self.save_drive_disk(device);
+            self.disk_unit_mut(device).drive = None;
+        } else if self.disk_unit(device).host_dir.is_some() {
+            return Err(format!("Device {} is a host directory, the 1541 needs a disk image", device));
+        } else if self.disk_unit(device).drive.is_none() {
+            let unit = self.disk_unit_mut(device);
+            let mut drive = Drive1541::new(device);
+            drive.load_rom(DRIVE_ROM)?;
+            drive.insert_disk(unit.disk.as_ref().map(GcrDisk::from_d64));
+            drive.reset();
+            unit.drive = Some(drive);
+        }
+        Ok(())
+    }
+
+    // TAP images go into the datasette, T64 archives are loaded by the traps
+    fn attach_tape(self: &mut Machine, filename: &str) -> Result<(), String> {
+        if filename.to_lowercase().ends_with(".t64") {
+            let archive = T64Archive::load(filename)?;
+            self.save_tape();
+            self.datasette.insert(None);
+            self.t64 = Some(archive);
+        } else {
+            let tape = Tape::load(filename)?;
+            self.save_tape();
+            self.datasette.insert(Some(tape));
+            self.t64 = None;
+        }
+        println!("Attached {} as the tape", filename);
+        Ok(())
+    }
+
+    // Presses RECORD, on a blank tape if a file is given
+    fn record_tape(self: &mut Machine, filename: Option<&str>) -> Result<(), String> {
+        if let Some(filename) = filename {
+            self.save_tape();
+            self.datasette.insert(Some(Tape::blank(filename)));
+            self.t64 = None;
+        }
+        self.datasette.record()
+    }
+
+    // Writes what has been recorded to the image file
+    fn save_tape(self: &mut Machine) {
+        if let Some(tape) = self.datasette.tape_mut() {
+            if tape.modified() {
+                match tape.save() {
+                    Ok(()) => println!("Saved {}", tape.filename()),
+                    Err(msg) => println!("{}", msg)
+                }
+            }
+        }
+    }
+
+    // Plugging a cartridge in or pulling it out resets the machine
+    fn attach_cartridge(self: &mut Machine, filename: &str) -> Result<(), String> {
+        let cartridge = Cartridge::load(filename)?;
+        self.save_cartridge();
+        self.expansion_port.attach(Some(cartridge));
+        self.reset();
+        Ok(())
+    }
+
+    fn remove_cartridge(self: &mut Machine) {
+        self.save_cartridge();
+        if self.expansion_port.attach(None).is_some() {
+            self.reset();
+        }
+    }
+
+    // Plugs in an REU of the given size, or pulls it out
+    fn set_reu(self: &mut Machine, size: Option<usize>) {
+        self.expansion_port.attach_reu(size.map(Reu::new));
+        self.reset();
+    }
+
+    // Plugs in a GeoRAM of the given size, or pulls it out
+    fn set_georam(self: &mut Machine, size: Option<usize>) {
+        self.save_georam();
+        self.expansion_port.attach_georam(size.map(GeoRam::new));
+        self.reset();
+    }
+
+    fn save_georam(self: &mut Machine) {
+        if let Some(georam) = self.expansion_port.georam_mut() {
+            if let Some(filename) = georam.filename().map(|filename| filename.to_string()) {
+                if georam.modified() {
+                    match georam.save() {
+                        Ok(()) => println!("Saved {}", filename),
+                        Err(msg) => println!("{}", msg)
+                    }
+                }
+            }
+        }
+    }
+
+    // Writes what has been flashed to the image file, if requested
+    fn save_cartridge(self: &mut Machine) {
+        if !self.cartridge_write_back {
+            return;
+        }
+        if let Some(cartridge) = self.expansion_port.cartridge_mut() {
+            if cartridge.modified() {
+                match cartridge.save() {
+                    Ok(()) => println!("Saved {}", cartridge.filename()),
+                    Err(msg) => println!("{}", msg)
+                }
+            }
+        }
+    }
+
+    // The C64 itself: the memory, the CPU, the VIC-II, the CIAs and the
+    // built-in SID, and whether the program from the command line is still
+    // to be installed. Extra SIDs, the drives, the datasette and the
+    // expansion port are not part of a snapshot yet.
+    fn snapshot(self: &Machine) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        {
+            let c64 = snapshot.add("C64");
+            c64.write_u64(self.cycles);
+            c64.write_bytes(&self.ram);
+            c64.write_bytes(&self.color_ram);
+            c64.write_bytes(&self.io[0xD000..0xE000]);
+        }
+        {
+            let cpu = snapshot.add("CPU");
+            self.mos6510.save_state(cpu);
+            self.processor_port.save_state(cpu);
+        }
+        self.vic.save_state(snapshot.add("VIC-II"));
+        self.cia1.save_state(snapshot.add("CIA1"));
+        self.cia2.save_state(snapshot.add("CIA2"));
+        self.sids.save_state(snapshot.add("SID"));
+        snapshot.add("PROGRAM").write_bool(self.program_pending);
+        snapshot
+    }
+
+    // Every module is read before the machine changes, so a broken snapshot
+    // leaves it running as it was. Snapshots from before the SID was saved
+    // leave it as it is.
+    fn restore(self: &mut Machine, snapshot: &Snapshot) -> Result<(), String> {
+        let mut ram = [0; 65536];
+        let mut color_ram = [0; 1024];
+        let mut io = [0; 0x1000];
+        let mut c64 = snapshot.module("C64")?;
+        let cycles = c64.read_u64()?;
+        c64.read_bytes(&mut ram)?;
+        c64.read_bytes(&mut color_ram)?;
+        c64.read_bytes(&mut io)?;
+        let mut mos6510 = Mos6510::new();
+        let mut processor_port = ProcessorPort::new();
+        let mut cpu = snapshot.module("CPU")?;
+        mos6510.load_state(&mut cpu)?;
+        processor_port.load_state(&mut cpu)?;
+        let mut cia1 = Cia1::new(self.cia1.model(), self.standard.tod_pulse_cycles());
+        cia1.load_state(&mut snapshot.module("CIA1")?)?;
+        let mut cia2 = Cia2::new(self.cia2.model(), self.standard.tod_pulse_cycles());
+        cia2.load_state(&mut snapshot.module("CIA2")?)?;
+        let mut vic = VicII::new(None, self.standard);
+        vic.load_state(&mut snapshot.module("VIC-II")?)?;
+        let program_pending = if snapshot.contains("PROGRAM") { snapshot.module("PROGRAM")?.read_bool()? } else { false };
+        // The last one that can fail, it loads all or nothing
+        if snapshot.contains("SID") {
+            self.sids.load_state(&mut snapshot.module("SID")?)?;
+        }
+        self.cycles = cycles;
+        self.ram = ram;
+        self.color_ram = color_ram;
+        self.io[0xD000..0xE000].copy_from_slice(&io);
+        self.mos6510 = mos6510;
+        self.processor_port = processor_port;
+        self.cia1 = cia1;
+        self.cia2 = cia2;
+        self.vic.take_state(vic);
+        self.program_pending = program_pending;
+        Ok(())
+    }
+
+    fn save_snapshot(self: &Machine, filename: &str) -> Result<(), String> {
+        self.snapshot().save(filename)
+    }
+
+    // Snapshots of VICE are imported, by the extension. What was kept for
+    // rewinding belongs to another run then.
+    fn load_snapshot(self: &mut Machine, filename: &str) -> Result<(), String> {
+        let result = if filename.to_lowercase().ends_with(".vsf") {
+            let vsf = VsfSnapshot::load(filename)?;
+            self.import_vsf(&vsf)
+        } else {
+            let snapshot = Snapshot::load(filename)?;
+            self.restore(&snapshot)
+        };
+        result.map_err(|msg| format!("{}: {}", filename, msg))?;
+        self.rewind.clear();
+        Ok(())
+    }
+
+    // What keeps running on when the machine goes back, as it is not part
+    // of a snapshot. Disks, directories and archives served through the
+    // KERNAL traps keep their channels and what was saved, and songs are
+    // changed from the keyboard, which is not logged.
+    fn not_rewound(self: &Machine) -> Option<&'static str> {
+        if self.disk_units.iter().any(|unit| unit.drive.is_some()) {
+            Some("a 1541 emulated")
+        } else if self.disk_units.iter().any(|unit| unit.disk.is_some()) {
+            Some("a disk image attached")
+        } else if self.disk_units.iter().any(|unit| unit.host_dir.is_some()) {
+            Some("a host directory attached")
+        } else if self.t64.is_some() {
+            Some("a T64 archive attached")
+        } else if self.sid_tune.is_some() {
+            Some("a SID tune loaded")
+        } else if !self.expansion_port.is_empty() {
+            Some("the expansion port in use")
+        } else if self.datasette.tape().is_some() {
+            Some("a tape inserted")
+        } else if self.sids.has_extra() {
+            Some("extra SIDs")
+        } else {
+            None
+        }
+    }
+
+    // Goes back to a cycle: the last snapshot taken before it is restored
+    // and the machine runs forward from there to the cycle, with the input
+    // it had then
+    fn rewind_to(self: &mut Machine, cycle: u64) -> Result<(), String> {
+        if !self.rewind.enabled() {
+            return Err("Rewinding is off".to_string());
+        }
+        if let Some(reason) = self.not_rewound() {
+            return Err(format!("Can't rewind with {}", reason));
+        }
+        let snapshot = match self.rewind.go_back(cycle) {
+            Some(snapshot) => Snapshot::from_bytes(&snapshot)?,
+            None => return Err(format!("Can't go back before cycle {}", self.rewind.oldest_cycle().unwrap_or(self.cycles)))
+        };
+        self.restore(&snapshot)?;
+        self.replaying = true;
+        let mut result = Ok(());
+        while self.cycles < cycle && result.is_ok() {
+            result = self.tick().map(|_| ());
+        }
+        self.replaying = false;
+        self.rewind.truncate_inputs(self.cycles);
+        result
+    }
+
+    // The memory, the CPU, the VIC-II, the CIAs and the registers of the SID
+    // are imported, the other modules and the state of the SID behind its
+    // registers are listed as left out. Like a snapshot of our own, the
+    // machine only changes once all of them have been read.
+    fn import_vsf(self: &mut Machine, vsf: &VsfSnapshot) -> Result<(), String> {
+        if vsf.machine() != "C64" && vsf.machine() != "C64SC" {
+            return Err(format!("Snapshot of a {}, not of a C64", vsf.machine()));
+        }
+        let module = |name| vsf.module(name).ok_or(format!("The snapshot has no {} module", name));
+        let mut ram = [0; 65536];
+        let mut processor_port = ProcessorPort::new();
+        {
+            let mut c64_mem = module("C64MEM")?.reader();
+            let data = c64_mem.read_u8()?;
+            let direction = c64_mem.read_u8()?;
+            processor_port.write(1, data);
+            processor_port.write(0, direction);
+            // EXROM and GAME, driven by a cartridge
+            c64_mem.skip(2)?;
+            c64_mem.read_bytes(&mut ram)?;
+        }
+        let mut mos6510 = Mos6510::new();
+        let cycles = mos6510.load_vice_state(&mut module("MAINCPU")?.reader())?;
+        let mut cia1 = Cia1::new(self.cia1.model(), self.standard.tod_pulse_cycles());
+        cia1.load_vice_state(&mut module("CIA1")?.reader())?;
+        let mut cia2 = Cia2::new(self.cia2.model(), self.standard.tod_pulse_cycles());
+        cia2.load_vice_state(&mut module("CIA2")?.reader())?;
+        // Whether sound was on, the SID engine and the registers
+        let mut sid_registers = None;
+        if let Some(sid) = vsf.module("SID") {
+            let mut sid = sid.reader();
+            if sid.read_u8()? > 0 {
+                let mut registers = [0; 32];
+                sid.skip(1)?;
+                sid.read_bytes(&mut registers)?;
+                sid_registers = Some(registers);
+            }
+        }
+        let mut color_ram = [0; 1024];
+        let mut vic = VicII::new(None, self.standard);
+        vic.load_vice_state(&mut module("VIC-II")?.reader(), &mut color_ram)?;
+        self.cycles = cycles as u64;
+        self.ram = ram;
+        self.color_ram = color_ram;
+        self.mos6510 = mos6510;
+        self.processor_port = processor_port;
+        self.cia1 = cia1;
+        self.cia2 = cia2;
+        self.vic.take_state(vic);
+        if let Some(registers) = sid_registers {
+            let sid = self.sids.built_in();
+            sid.reset();
+            for (reg, &value) in registers[..0x19].iter().enumerate() {
+                sid.write(0xD400 + reg as u16, value);
+            }
+        }
+        let mut left_out: Vec<&str> = vsf.modules().iter()
+            .map(|module| module.name())
+            .filter(|name| !VSF_MODULES.contains(name))
+            .collect();
+        if sid_registers.is_some() {
+            left_out.push("SID envelopes and oscillators");
+        }
+        if !left_out.is_empty() {
+            println!("Not supported, left out of the snapshot: {}", left_out.join(", "));
+        }
+        Ok(())
+    }
+
+    fn stop_iec_trace(self: &mut Machine) {
+        if let Some(filename) = self.iec_bus.trace_filename().map(|filename| filename.to_string()) {
+            match self.iec_bus.stop_trace() {
+                Ok(()) => println!("Bus trace written to {}", filename),
+                Err(msg) => println!("{}", msg)
+            }
+        }
+    }
+
+    // LOAD and SAVE for the attached disks and directories, and the serial
+    // bus for the directories, see kernal_traps
+    fn handle_kernal_traps(self: &mut Machine) {
+        let pc = self.mos6510.get_pc();
+        let load_or_save = pc == kernal_traps::LOAD_ADDR || pc == kernal_traps::SAVE_ADDR;
+        if (!load_or_save && !kernal_traps::serial_routine(pc)) || self.processor_port.pins() & HIRAM == 0 || self.expansion_port.ultimax() {
+            return;
+        }
+        let mut mem = cpu_memory!(self);
+        if !load_or_save {
+            // Every directory sees LISTEN and TALK, to stop listening or talking
+            for (i, unit) in self.disk_units.iter_mut().enumerate() {
+                if let Some(ref mut host_dir) = unit.host_dir {
+                    kernal_traps::serial(&mut self.mos6510, &mut mem, pc, iec::FIRST_DRIVE + i as u8, host_dir);
+                }
+            }
+            return;
+        }
+        let device = kernal_traps::device(&mut mem);
+        if device == kernal_traps::TAPE_DEVICE && pc == kernal_traps::LOAD_ADDR {
+            if let Some(ref archive) = self.t64 {
+                kernal_traps::load(&mut self.mos6510, &mut mem, archive);
+            }
+            return;
+        }
+        if !(iec::FIRST_DRIVE..=iec::LAST_DRIVE).contains(&device) {
+            return;
+        }
+        let files: &mut dyn kernal_traps::FileStore = match self.disk_units[(device - iec::FIRST_DRIVE) as usize] {
+            DiskUnit { host_dir: Some(ref mut host_dir), .. } => host_dir,
+            DiskUnit { disk: Some(ref mut disk), drive: None, .. } => disk,
+            _ => return
+        };
+        if pc == kernal_traps::LOAD_ADDR {
+            kernal_traps::load(&mut self.mos6510, &mut mem, files);
+        } else {
+            kernal_traps::save(&mut self.mos6510, &mut mem, files);
+        }
+    }
+
+    fn handle_events(self: &mut Machine) {
+        let mut subtune_change = 0;
+        let event_pump = match self.event_pump {
+            Some(ref mut event_pump) => event_pump,
+            None => return
+        };
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    panic!("exit");
+                }
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => {
+                    self.control_ports.swap();
+                }
+                Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } => {
+                    self.control_ports.toggle_autofire(1);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => {
+                    self.control_ports.toggle_autofire(2);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } => {
+                    self.rewind_requested = true;
+                }
+                Event::KeyDown { keycode: Some(Keycode::PageUp), repeat: false, .. } => {
+                    subtune_change += 1;
+                }
+                Event::KeyDown { keycode: Some(Keycode::PageDown), repeat: false, .. } => {
+                    subtune_change -= 1;
+                }
+                _ => {
+                    self.control_ports.handle_event(&event);
+                }
+            }
+        }
+        if subtune_change != 0 {
+            let song = self.subtune as i32 + subtune_change;
+            self.play_subtune(song.max(1) as u16);
+        }
+    }
+
     fn tick(self: &mut Machine) -> Result<(Option<String>, Option<Effect>), String> {
+        // Snapshots are taken and restored between cycles
+        if self.rewind_requested {
+            self.rewind_requested = false;
+            if let Some(cycle) = self.rewind.previous_cycle() {
+                if let Err(msg) = self.rewind_to(cycle) {
+                    println!("{}", msg);
+                }
+            }
+        }
+        if self.rewind.snapshot_due() {
+            let snapshot = self.snapshot().to_bytes();
+            self.rewind.push(self.cycles, snapshot);
+        }
         let cia1_irq = match self.cia1.tick() {
             Some(cia1::Effect::IRQ) => true,
             None => false
         };
-        self.vic.tick(&mut VicMemory::new(&self.ram, &self.char_rom, self.char_rom_enabled), &self.color_ram);
-        self.mos6510.tick(&mut Mos6510Memory::new(&mut self.ram, &mut self.io, &mut self.vic.registers, self.vic_bank_start, &mut self.char_rom_enabled, &mut self.color_ram, &mut self.cia1), cia1_irq)
+        let cia2_nmi = match self.cia2.tick() {
+            Some(cia2::Effect::NMI) => true,
+            None => false
+        };
+        self.sids.tick();
+        let (left, right) = self.sids.output();
+        // The cycles run again after going back are not heard
+        if !self.replaying {
+            if let Some(ref mut audio_output) = self.audio_output {
+                audio_output.push(left, right);
+            }
+            if let Some(ref mut recorder) = self.audio_recorder {
+                if let Err(err) = recorder.push(left, right) {
+                    return Err(format!("Failed to write audio: {}", err));
+                }
+            }
+        }
+        if let Some(vic_ii::Effect::FrameDone) = self.vic.tick(&mut VicMemory::new(&self.ram, &self.char_rom, self.cia2.vic_bank_start(), &self.expansion_port), &self.color_ram) {
+            // Nor do they wait for the audio or see new input
+            if !self.replaying {
+                self.handle_events();
+                if let Some(ref mut audio_output) = self.audio_output {
+                    audio_output.end_frame();
+                }
+                self.control_ports.end_frame();
+            }
+            self.rewind.end_frame();
+        }
+        // Running forward again, the machine sees the input logged the first time
+        let logged = if self.replaying { self.rewind.input_at(self.cycles) } else { None };
+        let ports = logged.unwrap_or_else(|| [self.control_ports.port_bits(1), self.control_ports.port_bits(2)]);
+        if !self.replaying {
+            self.rewind.record_input(self.cycles, ports);
+        }
+        // Joystick 2 shares port A with the keyboard columns, joystick 1 port B with the rows
+        self.cia1.set_port_a_input(ports[1]);
+        self.cia1.set_port_b_input(ports[0]);
+        // The datasette turns while the motor is on and a button is pressed
+        self.processor_port.set_cassette_button(self.datasette.sense());
+        let tape_read = self.datasette.tick(self.processor_port.cassette_motor(), self.processor_port.cassette_write());
+        // The serial bus lines are pulled low by the C64 or the drives. Only the C64 drives ATN.
+        let c64_lines = iec::Lines {
+            atn: self.cia2.iec_atn_out(),
+            clk: self.cia2.iec_clk_out(),
+            data: self.cia2.iec_data_out(),
+            srq: false
+        };
+        let mut drive_lines = [(0, iec::Lines::default()); 4];
+        let mut drive_count = 0;
+        for (i, unit) in self.disk_units.iter().enumerate() {
+            if let Some(ref drive) = unit.drive {
+                drive_lines[drive_count] = (iec::FIRST_DRIVE + i as u8, drive.bus_outputs(c64_lines.atn));
+                drive_count += 1;
+            }
+        }
+        let lines = self.iec_bus.update(self.cycles, c64_lines, &drive_lines[..drive_count])?;
+        self.cia2.set_iec_inputs(!lines.clk, !lines.data);
+        // SRQ and the tape read line share the FLAG input
+        self.cia1.set_flag(!lines.srq && tape_read);
+        // The drives run at 1 MHz, slightly faster than the C64
+        self.drive_clock += drive1541::CLOCK_FREQUENCY;
+        let drive_cycles = self.drive_clock / self.standard.clock_frequency;
+        self.drive_clock %= self.standard.clock_frequency;
+        for unit in self.disk_units.iter_mut() {
+            if let Some(ref mut drive) = unit.drive {
+                drive.set_bus_inputs(lines);
+                for _ in 0..drive_cycles {
+                    drive.tick()?;
+                }
+            }
+        }
+        self.cycles += 1;
+        // The REU halts the CPU for its transfers once the instruction
+        // starting them has completed, one cycle per byte
+        if self.mos6510.cycles_left() <= 1 {
+            if let Some(access) = self.expansion_port.dma_access() {
+                let value = {
+                    let mut mem = cpu_memory!(self);
+                    match access {
+                        DmaAccess::Read(addr) => mem.read(addr),
+                        DmaAccess::Write(addr, value) => {
+                            mem.write(addr, value);
+                            value
+                        }
+                    }
+                };
+                self.expansion_port.dma_done(value);
+                return Ok((None, None));
+            }
+        }
+        let irq = cia1_irq || self.expansion_port.irq();
+        let result = self.mos6510.tick(&mut cpu_memory!(self), irq, cia2_nmi);
+        // Stores and read-modify-write instructions write on their last cycle
+        self.sids.delay_writes(self.mos6510.cycles_left().saturating_sub(1));
+        self.handle_kernal_traps();
+        if self.mos6510.get_pc() == READY_LOOP_ADDR {
+            if self.tune_pending {
+                self.tune_pending = false;
+                self.install_sid_tune();
+            }
+            match self.program {
+                Some((ref program, autostart)) if self.program_pending => {
+                    self.program_pending = false;
+                    program.install(&mut self.ram, autostart);
+                    println!("Loaded 0x{:04X}-0x{:04X}", program.load_address(), program.end_address().wrapping_sub(1));
+                }
+                _ => ()
+            }
+        }
+        result
     }
 }
 
@@ -177,7 +1163,72 @@ enum DebuggerCommand {
     AddWatchpoint { addr: u16 },
     Run { verbose: bool },
     Exit,
-    Inspect { addr: u16 }
+    Inspect { addr: u16 },
+    SetJoystick { port: usize, state: JoystickState },
+    SetJoystickKeys { port: usize, key_mapping: Option<KeyMapping> },
+    ToggleAutofire { port: usize },
+    SwapControlPorts,
+    UserPort { input: Option<u8> },
+    ShiftIn { cia: u8, value: u8 },
+    SetCiaModel { cia: u8, model: Option<CiaModel> },
+    Sid { model: Option<SidModel> },
+    AddSid { addr: u16, model: Option<SidModel>, pan: f32 },
+    RemoveSid { addr: u16 },
+    SetSidPan { addr: u16, pan: f32 },
+    SetSidDcOffset { addr: u16, dc_offset: f32 },
+    SetDigiBoost { addr: u16, digi_boost: bool },
+    SetSidFilterBias { bias: f32 },
+    SetPots { x: u8, y: u8 },
+    RecordAudio { filename: Option<String> },
+    AudioStatus,
+    Disk { device: u8, filename: Option<String> },
+    SaveDisk { device: u8, filename: String },
+    Drive { device: u8, enabled: Option<bool> },
+    Iec,
+    TraceIec { filename: Option<String> },
+    Tape { filename: Option<String> },
+    PlayTape,
+    RecordTape { filename: Option<String> },
+    StopTape,
+    RewindTape,
+    Cartridge { filename: Option<String> },
+    RemoveCartridge,
+    Reu { size: Option<Option<usize>> },
+    GeoRam { size: Option<Option<usize>> },
+    SaveSnapshot { filename: String },
+    LoadSnapshot { filename: String },
+    Rewind { cycles: Option<u64> },
+    Tune { song: Option<u16> }
+}
+
+// Device number of a disk command, 8 if none is given
+fn disk_device(device: Option<regex::Match>) -> u8 {
+    device.map_or(iec::FIRST_DRIVE, |device| device.as_str().parse().unwrap())
+}
+
+fn parse_joystick_state(input: &str) -> JoystickState {
+    input.chars().fold(JoystickState::empty(), |state, c| state | match c {
+        'u' => JoystickState::UP,
+        'd' => JoystickState::DOWN,
+        'l' => JoystickState::LEFT,
+        'r' => JoystickState::RIGHT,
+        _ => JoystickState::FIRE
+    })
+}
+
+fn parse_key_mapping(input: &str) -> Option<KeyMapping> {
+    let keys: Vec<Keycode> = input.split(',').filter_map(|name| Keycode::from_name(name.trim())).collect();
+    match keys.as_slice() {
+        &[up, down, left, right, fire] => Some(KeyMapping { up, down, left, right, fire }),
+        _ => None
+    }
+}
+
+fn parse_sid_model(input: &str) -> SidModel {
+    match input {
+        "6581" => SidModel::Mos6581,
+        _ => SidModel::Mos8580
+    }
 }
 
 fn parse_debugger_command(input: &str) -> Option<DebuggerCommand> {
@@ -187,6 +1238,39 @@ fn parse_debugger_command(input: &str) -> Option<DebuggerCommand> {
         static ref ADD_BREAKPOINT: Regex = Regex::new(r"b ([0-9a-fA-F]{1,4})").unwrap();
         static ref ADD_WATCHPOINT: Regex = Regex::new(r"w ([0-9a-fA-F]{1,4})").unwrap();
         static ref INSPECT: Regex = Regex::new(r"i ([0-9a-fA-F]{1,4})").unwrap();
+        static ref SET_JOYSTICK: Regex = Regex::new(r"^j ([12]) ?([udlrf]*)$").unwrap();
+        static ref SET_JOYSTICK_KEYS: Regex = Regex::new(r"^jk ([12]) (.+)$").unwrap();
+        static ref TOGGLE_AUTOFIRE: Regex = Regex::new(r"^ja ([12])$").unwrap();
+        static ref SWAP_CONTROL_PORTS: Regex = Regex::new(r"^js$").unwrap();
+        static ref USER_PORT: Regex = Regex::new(r"^u( ([0-9a-fA-F]{1,2}))?$").unwrap();
+        static ref SET_CIA_MODEL: Regex = Regex::new(r"^cia ([12])( (6526|6526a|8521))?$").unwrap();
+        static ref SID: Regex = Regex::new(r"^sid( (6581|8580))?$").unwrap();
+        static ref ADD_SID: Regex = Regex::new(r"^sid add ([0-9a-fA-F]{4})( (6581|8580))?( (-?[0-9]+(\.[0-9]+)?))?$").unwrap();
+        static ref REMOVE_SID: Regex = Regex::new(r"^sid rm ([0-9a-fA-F]{4})$").unwrap();
+        static ref SET_SID_PAN: Regex = Regex::new(r"^sid pan ([0-9a-fA-F]{4}) (-?[0-9]+(\.[0-9]+)?)$").unwrap();
+        static ref SET_SID_DC_OFFSET: Regex = Regex::new(r"^sid dc ([0-9a-fA-F]{4}) ([0-9]+(\.[0-9]+)?)$").unwrap();
+        static ref SET_DIGI_BOOST: Regex = Regex::new(r"^sid boost ([0-9a-fA-F]{4}) (on|off)$").unwrap();
+        static ref SET_SID_FILTER_BIAS: Regex = Regex::new(r"^sid bias (-?[0-9]+(\.[0-9]+)?)$").unwrap();
+        static ref SET_POTS: Regex = Regex::new(r"^pot ([0-9a-fA-F]{1,2}) ([0-9a-fA-F]{1,2})$").unwrap();
+        static ref SHIFT_IN: Regex = Regex::new(r"^sr ([12]) ([0-9a-fA-F]{1,2})$").unwrap();
+        static ref TUNE: Regex = Regex::new(r"^tune( ([0-9]{1,5}))?$").unwrap();
+        static ref AUDIO_STATUS: Regex = Regex::new(r"^audio$").unwrap();
+        static ref DISK: Regex = Regex::new(r"^disk( (8|9|10|11))?( (.+))?$").unwrap();
+        static ref SAVE_DISK: Regex = Regex::new(r"^disk save( (8|9|10|11))? (.+)$").unwrap();
+        static ref DRIVE: Regex = Regex::new(r"^drive( (8|9|10|11))?( (on|off))?$").unwrap();
+        static ref IEC: Regex = Regex::new(r"^iec$").unwrap();
+        static ref TRACE_IEC: Regex = Regex::new(r"^iec trace (.+)$").unwrap();
+        static ref TAPE: Regex = Regex::new(r"^tape( (.+))?$").unwrap();
+        static ref TAPE_CONTROL: Regex = Regex::new(r"^tape (play|stop|rewind)$").unwrap();
+        static ref RECORD_TAPE: Regex = Regex::new(r"^tape record( (.+))?$").unwrap();
+        static ref CARTRIDGE: Regex = Regex::new(r"^cart( (.+))?$").unwrap();
+        static ref REMOVE_CARTRIDGE: Regex = Regex::new(r"^cart remove$").unwrap();
+        static ref REU: Regex = Regex::new(r"^reu( ([0-9]+|off))?$").unwrap();
+        static ref GEORAM: Regex = Regex::new(r"^georam( ([0-9]+|off))?$").unwrap();
+        static ref REWIND: Regex = Regex::new(r"^rewind( ([0-9]{1,18}))?$").unwrap();
+        static ref REVERSE_STEP: Regex = Regex::new(r"^rs$").unwrap();
+        static ref SNAPSHOT: Regex = Regex::new(r"^snapshot (save|load) (.+)$").unwrap();
+        static ref RECORD_AUDIO: Regex = Regex::new(r"^rec (.+)$").unwrap();
     }
 
     if RUN.is_match(input) {
@@ -213,44 +1297,560 @@ fn parse_debugger_command(input: &str) -> Option<DebuggerCommand> {
             Ok(addr) => Some(DebuggerCommand::Inspect { addr }),
             Err(_) => None
         }
+    } else if let Some(captures) = SET_JOYSTICK.captures(input) {
+        let port = captures[1].parse().unwrap();
+        Some(DebuggerCommand::SetJoystick { port, state: parse_joystick_state(&captures[2]) })
+    } else if let Some(captures) = SET_JOYSTICK_KEYS.captures(input) {
+        let port = captures[1].parse().unwrap();
+        if &captures[2] == "none" {
+            Some(DebuggerCommand::SetJoystickKeys { port, key_mapping: None })
+        } else {
+            parse_key_mapping(&captures[2]).map(|key_mapping| DebuggerCommand::SetJoystickKeys { port, key_mapping: Some(key_mapping) })
+        }
+    } else if let Some(captures) = TOGGLE_AUTOFIRE.captures(input) {
+        Some(DebuggerCommand::ToggleAutofire { port: captures[1].parse().unwrap() })
+    } else if SWAP_CONTROL_PORTS.is_match(input) {
+        Some(DebuggerCommand::SwapControlPorts)
+    } else if let Some(captures) = USER_PORT.captures(input) {
+        match captures.get(2).map(|value| u8::from_str_radix(value.as_str(), 16)) {
+            Some(Ok(value)) => Some(DebuggerCommand::UserPort { input: Some(value) }),
+            Some(Err(_)) => None,
+            None => Some(DebuggerCommand::UserPort { input: None })
+        }
+    } else if let Some(captures) = SET_CIA_MODEL.captures(input) {
+        let cia = captures[1].parse().unwrap();
+        let model = captures.get(3).map(|model| match model.as_str() {
+            "6526" => CiaModel::Mos6526,
+            _ => CiaModel::Mos6526A
+        });
+        Some(DebuggerCommand::SetCiaModel { cia, model })
+    } else if let Some(captures) = SID.captures(input) {
+        let model = captures.get(2).map(|model| parse_sid_model(model.as_str()));
+        Some(DebuggerCommand::Sid { model })
+    } else if let Some(captures) = ADD_SID.captures(input) {
+        let addr = u16::from_str_radix(&captures[1], 16).unwrap();
+        let model = captures.get(3).map(|model| parse_sid_model(model.as_str()));
+        match captures.get(5).map(|pan| pan.as_str().parse()) {
+            Some(Ok(pan)) => Some(DebuggerCommand::AddSid { addr, model, pan }),
+            Some(Err(_)) => None,
+            None => Some(DebuggerCommand::AddSid { addr, model, pan: 0.0 })
+        }
+    } else if let Some(captures) = REMOVE_SID.captures(input) {
+        Some(DebuggerCommand::RemoveSid { addr: u16::from_str_radix(&captures[1], 16).unwrap() })
+    } else if let Some(captures) = SET_SID_PAN.captures(input) {
+        let addr = u16::from_str_radix(&captures[1], 16).unwrap();
+        match captures[2].parse() {
+            Ok(pan) => Some(DebuggerCommand::SetSidPan { addr, pan }),
+            Err(_) => None
+        }
+    } else if let Some(captures) = SET_SID_DC_OFFSET.captures(input) {
+        let addr = u16::from_str_radix(&captures[1], 16).unwrap();
+        match captures[2].parse() {
+            Ok(dc_offset) => Some(DebuggerCommand::SetSidDcOffset { addr, dc_offset }),
+            Err(_) => None
+        }
+    } else if let Some(captures) = SET_DIGI_BOOST.captures(input) {
+        let addr = u16::from_str_radix(&captures[1], 16).unwrap();
+        Some(DebuggerCommand::SetDigiBoost { addr, digi_boost: &captures[2] == "on" })
+    } else if let Some(captures) = SET_SID_FILTER_BIAS.captures(input) {
+        match captures[1].parse() {
+            Ok(bias) => Some(DebuggerCommand::SetSidFilterBias { bias }),
+            Err(_) => None
+        }
+    } else if let Some(captures) = SET_POTS.captures(input) {
+        match (u8::from_str_radix(&captures[1], 16), u8::from_str_radix(&captures[2], 16)) {
+            (Ok(x), Ok(y)) => Some(DebuggerCommand::SetPots { x, y }),
+            _ => None
+        }
+    } else if let Some(captures) = SHIFT_IN.captures(input) {
+        let cia = captures[1].parse().unwrap();
+        match u8::from_str_radix(&captures[2], 16) {
+            Ok(value) => Some(DebuggerCommand::ShiftIn { cia, value }),
+            Err(_) => None
+        }
+    } else if let Some(captures) = TUNE.captures(input) {
+        match captures.get(2).map(|song| song.as_str().parse()) {
+            Some(Ok(song)) => Some(DebuggerCommand::Tune { song: Some(song) }),
+            Some(Err(_)) => None,
+            None => Some(DebuggerCommand::Tune { song: None })
+        }
+    } else if AUDIO_STATUS.is_match(input) {
+        Some(DebuggerCommand::AudioStatus)
+    } else if let Some(captures) = SAVE_DISK.captures(input) {
+        Some(DebuggerCommand::SaveDisk { device: disk_device(captures.get(2)), filename: captures[3].to_string() })
+    } else if let Some(captures) = DISK.captures(input) {
+        Some(DebuggerCommand::Disk { device: disk_device(captures.get(2)), filename: captures.get(4).map(|filename| filename.as_str().to_string()) })
+    } else if let Some(captures) = DRIVE.captures(input) {
+        Some(DebuggerCommand::Drive { device: disk_device(captures.get(2)), enabled: captures.get(4).map(|enabled| enabled.as_str() == "on") })
+    } else if IEC.is_match(input) {
+        Some(DebuggerCommand::Iec)
+    } else if let Some(captures) = TRACE_IEC.captures(input) {
+        match &captures[1] {
+            "stop" => Some(DebuggerCommand::TraceIec { filename: None }),
+            filename => Some(DebuggerCommand::TraceIec { filename: Some(filename.to_string()) })
+        }
+    } else if let Some(captures) = TAPE_CONTROL.captures(input) {
+        match &captures[1] {
+            "play" => Some(DebuggerCommand::PlayTape),
+            "stop" => Some(DebuggerCommand::StopTape),
+            _ => Some(DebuggerCommand::RewindTape)
+        }
+    } else if let Some(captures) = RECORD_TAPE.captures(input) {
+        Some(DebuggerCommand::RecordTape { filename: captures.get(2).map(|filename| filename.as_str().to_string()) })
+    } else if let Some(captures) = TAPE.captures(input) {
+        Some(DebuggerCommand::Tape { filename: captures.get(2).map(|filename| filename.as_str().to_string()) })
+    } else if REMOVE_CARTRIDGE.is_match(input) {
+        Some(DebuggerCommand::RemoveCartridge)
+    } else if let Some(captures) = CARTRIDGE.captures(input) {
+        Some(DebuggerCommand::Cartridge { filename: captures.get(2).map(|filename| filename.as_str().to_string()) })
+    } else if let Some(captures) = REU.captures(input) {
+        match captures.get(2).map(|size| size.as_str()) {
+            Some("off") => Some(DebuggerCommand::Reu { size: Some(None) }),
+            Some(size) => match parse_ram_size(size, reu::MIN_SIZE, reu::MAX_SIZE) {
+                Ok(size) => Some(DebuggerCommand::Reu { size: Some(Some(size)) }),
+                Err(msg) => {
+                    println!("{}", msg);
+                    None
+                }
+            },
+            None => Some(DebuggerCommand::Reu { size: None })
+        }
+    } else if let Some(captures) = GEORAM.captures(input) {
+        match captures.get(2).map(|size| size.as_str()) {
+            Some("off") => Some(DebuggerCommand::GeoRam { size: Some(None) }),
+            Some(size) => match parse_ram_size(size, georam::MIN_SIZE, georam::MAX_SIZE) {
+                Ok(size) => Some(DebuggerCommand::GeoRam { size: Some(Some(size)) }),
+                Err(msg) => {
+                    println!("{}", msg);
+                    None
+                }
+            },
+            None => Some(DebuggerCommand::GeoRam { size: None })
+        }
+    } else if let Some(captures) = REWIND.captures(input) {
+        Some(DebuggerCommand::Rewind { cycles: captures.get(2).map(|cycles| cycles.as_str().parse().unwrap()) })
+    } else if REVERSE_STEP.is_match(input) {
+        // Back by the cycle a step goes forward
+        Some(DebuggerCommand::Rewind { cycles: Some(1) })
+    } else if let Some(captures) = SNAPSHOT.captures(input) {
+        let filename = captures[2].to_string();
+        match &captures[1] {
+            "save" => Some(DebuggerCommand::SaveSnapshot { filename }),
+            _ => Some(DebuggerCommand::LoadSnapshot { filename })
+        }
+    } else if let Some(captures) = RECORD_AUDIO.captures(input) {
+        match &captures[1] {
+            "stop" => Some(DebuggerCommand::RecordAudio { filename: None }),
+            filename => Some(DebuggerCommand::RecordAudio { filename: Some(filename.to_string()) })
+        }
     } else {
         None
     }
 }
 
-#[derive(Clone, Copy)]
-enum DebuggerState {
-    Pause,
-    Step,
-    Run { verbose: bool }
+#[derive(Clone, Copy)]
+enum DebuggerState {
+    Pause,
+    Step,
+    Run { verbose: bool }
+}
+
+struct Debugger {
+    state: DebuggerState,
+    breakpoints: HashSet<u16>,
+    watchpoints: HashSet<u16>
+}
+
+impl Debugger {
+    fn new() -> Debugger {
+        Debugger {
+            state: DebuggerState::Pause,
+            breakpoints: HashSet::new(),
+            watchpoints: HashSet::new()
+        }
+    }
+}
+
+struct Options {
+    headless: bool,
+    record_audio: Option<String>,
+    record_seconds: Option<u32>,
+    sample_rate: u32,
+    ntsc: bool,
+    extra_sids: Vec<(u16, Option<SidModel>, f32)>,
+    digi_boost: bool,
+    digi_test: bool,
+    sid_file: Option<String>,
+    subtune: Option<u16>,
+    prg_file: Option<String>,
+    autostart: Autostart,
+    disk_files: Vec<(u8, String)>,
+    true_drive: bool,
+    iec_trace: Option<String>,
+    tape_file: Option<String>,
+    cart_file: Option<String>,
+    cart_write_back: bool,
+    reu_size: Option<usize>,
+    georam_size: Option<usize>,
+    georam_image: Option<String>,
+    snapshot_file: Option<String>,
+    rewind_interval: u32
+}
+
+const USAGE: &str = "Usage: c64emu [--headless] [--record-audio FILE] [--record-seconds N] [--sample-rate HZ] [--ntsc] [--extra-sid ADDR[,MODEL[,PAN]]]... [--digi-boost] [--sid FILE [--subtune N]] [--prg FILE [--run | --sys ADDR]] [--disk [N:]FILE]... [--true-drive] [--iec-trace FILE] [--tape FILE] [--cart FILE [--cart-write-back]] [--reu KB] [--georam KB [--georam-image FILE]] [--snapshot FILE] [--rewind-interval FRAMES] [--digi-test]";
+
+// Decimal, or hexadecimal with a $ or 0x prefix
+fn parse_address(input: &str) -> Option<u16> {
+    if let Some(hex) = input.strip_prefix('$') {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = input.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        input.parse().ok()
+    }
+}
+
+// N:FILE attaches to device N, FILE alone to device 8
+fn parse_disk(input: &str) -> Result<(u8, String), String> {
+    match input.find(':') {
+        Some(colon) if colon > 0 && input[..colon].bytes().all(|c| c.is_ascii_digit()) => match input[..colon].parse() {
+            Ok(device) if device >= iec::FIRST_DRIVE && device <= iec::LAST_DRIVE => Ok((device, input[colon + 1..].to_string())),
+            _ => Err(format!("Invalid device: {}", &input[..colon]))
+        },
+        _ => Ok((iec::FIRST_DRIVE, input.to_string()))
+    }
+}
+
+// A power of two in KB, of an REU or a GeoRAM
+fn parse_ram_size(input: &str, min: usize, max: usize) -> Result<usize, String> {
+    match input.parse::<usize>() {
+        Ok(kb) if kb.is_power_of_two() && kb >= min / 1024 && kb <= max / 1024 => Ok(kb * 1024),
+        _ => Err(format!("Invalid size: {}, it has to be a power of two from {} to {} KB", input, min / 1024, max / 1024))
+    }
 }
 
-struct Debugger {
-    state: DebuggerState,
-    breakpoints: HashSet<u16>,
-    watchpoints: HashSet<u16>
+// ADDR[,MODEL[,PAN]] with a hexadecimal address, e.g. d420,8580,1.0
+fn parse_extra_sid(input: &str) -> Result<(u16, Option<SidModel>, f32), String> {
+    let invalid = || format!("Invalid SID: {}", input);
+    let mut fields = input.split(',');
+    let addr = u16::from_str_radix(fields.next().unwrap().trim_start_matches('$'), 16).map_err(|_| invalid())?;
+    let model = match fields.next() {
+        Some(model @ "6581") | Some(model @ "8580") => Some(parse_sid_model(model)),
+        Some(_) => return Err(invalid()),
+        None => None
+    };
+    let pan = match fields.next() {
+        Some(pan) => pan.parse().map_err(|_| invalid())?,
+        None => 0.0
+    };
+    if fields.next().is_some() {
+        return Err(invalid());
+    }
+    Ok((addr, model, pan))
 }
 
-impl Debugger {
-    fn new() -> Debugger {
-        Debugger {
-            state: DebuggerState::Pause,
-            breakpoints: HashSet::new(),
-            watchpoints: HashSet::new()
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        headless: false,
+        record_audio: None,
+        record_seconds: None,
+        sample_rate: 44100,
+        ntsc: false,
+        extra_sids: Vec::new(),
+        digi_boost: false,
+        digi_test: false,
+        sid_file: None,
+        subtune: None,
+        prg_file: None,
+        autostart: Autostart::None,
+        disk_files: Vec::new(),
+        true_drive: false,
+        iec_trace: None,
+        tape_file: None,
+        cart_file: None,
+        cart_write_back: false,
+        reu_size: None,
+        georam_size: None,
+        georam_image: None,
+        snapshot_file: None,
+        rewind_interval: REWIND_INTERVAL
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => {
+                options.headless = true;
+            }
+            "--record-audio" => {
+                options.record_audio = Some(args.next().ok_or("--record-audio needs a file name")?);
+            }
+            "--record-seconds" => {
+                let value = args.next().ok_or("--record-seconds needs a number of seconds")?;
+                options.record_seconds = Some(value.parse().map_err(|_| format!("Invalid number of seconds: {}", value))?);
+            }
+            "--sample-rate" => {
+                let value = args.next().ok_or("--sample-rate needs a rate in Hz")?;
+                match value.parse() {
+                    Ok(sample_rate) if sample_rate > 0 => {
+                        options.sample_rate = sample_rate;
+                    }
+                    _ => return Err(format!("Invalid sample rate: {}", value))
+                }
+            }
+            "--ntsc" => {
+                options.ntsc = true;
+            }
+            "--extra-sid" => {
+                let value = args.next().ok_or("--extra-sid needs an address")?;
+                options.extra_sids.push(parse_extra_sid(&value)?);
+            }
+            "--digi-boost" => {
+                options.digi_boost = true;
+            }
+            "--digi-test" => {
+                options.digi_test = true;
+            }
+            "--sid" => {
+                options.sid_file = Some(args.next().ok_or("--sid needs a file name")?);
+            }
+            "--subtune" => {
+                let value = args.next().ok_or("--subtune needs a song number")?;
+                options.subtune = Some(value.parse().map_err(|_| format!("Invalid song number: {}", value))?);
+            }
+            "--prg" => {
+                options.prg_file = Some(args.next().ok_or("--prg needs a file name")?);
+            }
+            "--disk" => {
+                let value = args.next().ok_or("--disk needs a file name")?;
+                options.disk_files.push(parse_disk(&value)?);
+            }
+            "--true-drive" => {
+                options.true_drive = true;
+            }
+            "--tape" => {
+                options.tape_file = Some(args.next().ok_or("--tape needs a file name")?);
+            }
+            "--cart" => {
+                options.cart_file = Some(args.next().ok_or("--cart needs a file name")?);
+            }
+            "--cart-write-back" => {
+                options.cart_write_back = true;
+            }
+            "--reu" => {
+                let value = args.next().ok_or("--reu needs a size in KB")?;
+                options.reu_size = Some(parse_ram_size(&value, reu::MIN_SIZE, reu::MAX_SIZE)?);
+            }
+            "--georam" => {
+                let value = args.next().ok_or("--georam needs a size in KB")?;
+                options.georam_size = Some(parse_ram_size(&value, georam::MIN_SIZE, georam::MAX_SIZE)?);
+            }
+            "--georam-image" => {
+                options.georam_image = Some(args.next().ok_or("--georam-image needs a file name")?);
+            }
+            "--snapshot" => {
+                options.snapshot_file = Some(args.next().ok_or("--snapshot needs a file name")?);
+            }
+            "--rewind-interval" => {
+                let value = args.next().ok_or("--rewind-interval needs a number of frames")?;
+                options.rewind_interval = value.parse().map_err(|_| format!("Invalid number of frames: {}", value))?;
+            }
+            "--iec-trace" => {
+                options.iec_trace = Some(args.next().ok_or("--iec-trace needs a file name")?);
+            }
+            "--run" => {
+                options.autostart = Autostart::Run;
+            }
+            "--sys" => {
+                let value = args.next().ok_or("--sys needs an address")?;
+                options.autostart = Autostart::Sys(parse_address(&value).ok_or(format!("Invalid address: {}", value))?);
+            }
+            _ => return Err(format!("Unknown option: {}", arg))
         }
     }
+    if options.record_seconds.is_some() && options.record_audio.is_none() {
+        return Err("--record-seconds needs --record-audio".to_string());
+    }
+    if options.autostart != Autostart::None && options.prg_file.is_none() {
+        return Err("--run and --sys need --prg".to_string());
+    }
+    if options.cart_write_back && options.cart_file.is_none() {
+        return Err("--cart-write-back needs --cart".to_string());
+    }
+    if options.georam_image.is_some() && options.georam_size.is_none() {
+        return Err("--georam-image needs --georam".to_string());
+    }
+    if options.prg_file.is_some() && options.sid_file.is_some() {
+        return Err("--prg and --sid can't be used together".to_string());
+    }
+    if options.snapshot_file.is_some() && (options.prg_file.is_some() || options.sid_file.is_some()) {
+        return Err("--snapshot can't be used with --prg or --sid".to_string());
+    }
+    let standard = if options.ntsc { vic_ii::NTSC } else { vic_ii::PAL };
+    if options.sample_rate > standard.clock_frequency {
+        return Err(format!("Invalid sample rate: {}", options.sample_rate));
+    }
+    Ok(options)
 }
 
 fn main() {
-    let mut machine = Machine::new();
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(msg) => {
+            println!("{}", msg);
+            println!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+    if options.digi_test {
+        std::process::exit(if digi_test::run() { 0 } else { 1 });
+    }
+    let sdl_context = if options.headless { None } else { Some(sdl2::init().unwrap()) };
+    let standard = if options.ntsc { vic_ii::NTSC } else { vic_ii::PAL };
+    let mut machine = Machine::new(sdl_context.as_ref(), options.sample_rate, standard);
+    machine.rewind = RewindBuffer::new(options.rewind_interval, REWIND_SNAPSHOTS);
     let mut debugger = Debugger::new();
 
-    machine.load_file("basic.rom", MemoryRegion::Rom, 0xA000);
-    machine.load_file("kernal.rom", MemoryRegion::Rom, 0xE000);
+    machine.load_file("basic.rom", MemoryRegion::BasicRom, 0);
+    machine.load_file("kernal.rom", MemoryRegion::KernalRom, 0);
     machine.load_file("char.rom", MemoryRegion::CharRom, 0);
 
+    machine.cartridge_write_back = options.cart_write_back;
+    machine.expansion_port.attach_reu(options.reu_size.map(Reu::new));
+    if let Some(size) = options.georam_size {
+        let georam = match options.georam_image {
+            Some(ref filename) => GeoRam::with_image(size, filename),
+            None => Ok(GeoRam::new(size))
+        };
+        match georam {
+            Ok(georam) => {
+                machine.expansion_port.attach_georam(Some(georam));
+            }
+            Err(msg) => {
+                println!("{}", msg);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(ref filename) = options.cart_file {
+        match Cartridge::load(filename) {
+            Ok(cartridge) => {
+                machine.expansion_port.attach(Some(cartridge));
+            }
+            Err(msg) => {
+                println!("{}", msg);
+                std::process::exit(1);
+            }
+        }
+    }
     machine.reset();
 
+    for &(addr, model, pan) in options.extra_sids.iter() {
+        let model = model.unwrap_or(machine.sids.built_in().model());
+        if let Err(msg) = machine.sids.add(addr, model, pan) {
+            println!("{}", msg);
+            std::process::exit(1);
+        }
+    }
+    machine.sids.set_digi_boost(options.digi_boost);
+
+    if let Some(ref filename) = options.sid_file {
+        match SidTune::load(filename) {
+            Ok(tune) => {
+                let song = options.subtune.unwrap_or(tune.start_song());
+                if song < 1 || song > tune.songs() {
+                    println!("No song {} in {}, it has {} songs", song, filename, tune.songs());
+                    std::process::exit(1);
+                }
+                tune.print_info(song);
+                machine.load_sid_tune(tune, song);
+                // Start playing right away
+                debugger.state = DebuggerState::Run { verbose: false };
+            }
+            Err(msg) => {
+                println!("{}", msg);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if options.true_drive {
+        // A 1541 for every device given a disk image, device 8 if there are none
+        let mut devices: Vec<u8> = options.disk_files.iter()
+            .filter(|&&(_, ref filename)| !Path::new(filename).is_dir())
+            .map(|&(device, _)| device)
+            .collect();
+        if devices.is_empty() {
+            devices.push(iec::FIRST_DRIVE);
+        }
+        for device in devices {
+            if let Err(msg) = machine.set_true_drive(device, true) {
+                println!("{}", msg);
+                std::process::exit(1);
+            }
+        }
+    }
+    for &(device, ref filename) in options.disk_files.iter() {
+        if let Err(msg) = machine.attach_disk(device, filename) {
+            println!("{}", msg);
+            std::process::exit(1);
+        }
+    }
+    if let Some(ref filename) = options.iec_trace {
+        if let Err(msg) = machine.iec_bus.start_trace(filename) {
+            println!("{}", msg);
+            std::process::exit(1);
+        }
+    }
+    if let Some(ref filename) = options.tape_file {
+        // PLAY is pressed, the tape starts turning with LOAD
+        if let Err(msg) = machine.attach_tape(filename).and_then(|_| if machine.t64.is_none() { machine.datasette.play() } else { Ok(()) }) {
+            println!("{}", msg);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(ref filename) = options.prg_file {
+        match Program::load(filename) {
+            Ok(program) => {
+                machine.program = Some((program, options.autostart));
+                machine.program_pending = true;
+                // Run to the READY prompt, where the program is loaded
+                debugger.state = DebuggerState::Run { verbose: false };
+            }
+            Err(msg) => {
+                println!("{}", msg);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(ref filename) = options.snapshot_file {
+        if let Err(msg) = machine.load_snapshot(filename) {
+            println!("{}", msg);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(ref filename) = options.record_audio {
+        machine.start_audio_recording(filename);
+    }
+    if let Some(seconds) = options.record_seconds {
+        // Run without the debugger for exactly the requested number of cycles
+        for _ in 0..seconds as u64 * machine.standard.clock_frequency as u64 {
+            if let Err(msg) = machine.tick() {
+                println!("{}", msg);
+                break;
+            }
+        }
+        machine.stop_audio_recording();
+        machine.save_drive_disks();
+        machine.save_tape();
+        machine.save_cartridge();
+        machine.save_georam();
+        machine.stop_iec_trace();
+        return;
+    }
+
     let mut rl = rustyline::Editor::<()>::new();
     let history_path = "history.txt";
     if let Err(err) = rl.load_history(history_path) {
@@ -358,15 +1958,505 @@ fn main() {
                 debugger.state = DebuggerState::Pause;
             }
             DebuggerCommand::Inspect { addr } => {
-                let mut mem = Mos6510Memory::new(&mut machine.ram, &mut machine.io, &mut machine.vic.registers, machine.vic_bank_start, &mut machine.char_rom_enabled, &mut machine.color_ram, &mut machine.cia1);
+                let mut mem = cpu_memory!(machine);
                 println!("Memory at 0x{:04X}: 0x{:02X}", addr, mem.read(addr));
                 debugger.state = DebuggerState::Pause;
             }
+            DebuggerCommand::SetJoystick { port, state } => {
+                println!("Joystick in port {}: {:?}", port, state);
+                machine.control_ports.inject(port, state);
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::SetJoystickKeys { port, key_mapping } => {
+                machine.control_ports.set_key_mapping(port, key_mapping);
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::ToggleAutofire { port } => {
+                machine.control_ports.toggle_autofire(port);
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::SwapControlPorts => {
+                machine.control_ports.swap();
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::UserPort { input } => {
+                if let Some(value) = input {
+                    machine.cia2.set_user_port_input(value);
+                }
+                println!("User port: 0x{:02X}  ATN out: {}  CLK out: {}  DATA out: {}",
+                    machine.cia2.user_port(),
+                    machine.cia2.iec_atn_out() as u8,
+                    machine.cia2.iec_clk_out() as u8,
+                    machine.cia2.iec_data_out() as u8);
+                println!("CNT1: {}  SP1: {}  CNT2: {}  SP2: {}",
+                    machine.cia1.cnt() as u8,
+                    machine.cia1.sp() as u8,
+                    machine.cia2.cnt() as u8,
+                    machine.cia2.sp() as u8);
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::ShiftIn { cia, value } => {
+                // Clock the byte in MSB first, SP is sampled on the rising edge of CNT
+                for bit in (0..8).rev() {
+                    let level = value & (1 << bit) > 0;
+                    if cia == 1 {
+                        machine.cia1.set_sp(level);
+                        machine.cia1.set_cnt(false);
+                        machine.cia1.set_cnt(true);
+                    } else {
+                        machine.cia2.set_sp(level);
+                        machine.cia2.set_cnt(false);
+                        machine.cia2.set_cnt(true);
+                    }
+                }
+                println!("Shifted 0x{:02X} into the serial port of CIA{}", value, cia);
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::SetCiaModel { cia, model } => {
+                if let Some(model) = model {
+                    if cia == 1 {
+                        machine.cia1.set_model(model);
+                    } else {
+                        machine.cia2.set_model(model);
+                    }
+                }
+                let model = if cia == 1 { machine.cia1.model() } else { machine.cia2.model() };
+                println!("CIA{} model: {:?}", cia, model);
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::Sid { model } => {
+                if let Some(model) = model {
+                    machine.sids.built_in().set_model(model);
+                }
+                machine.sids.print_status();
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::AddSid { addr, model, pan } => {
+                let model = model.unwrap_or(machine.sids.built_in().model());
+                match machine.sids.add(addr, model, pan) {
+                    Ok(()) => println!("Added a {:?} at 0x{:04X}", model, addr),
+                    Err(msg) => println!("{}", msg)
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::RemoveSid { addr } => {
+                if !machine.sids.remove(addr) {
+                    println!("No extra SID at 0x{:04X}", addr);
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::SetSidPan { addr, pan } => {
+                if !machine.sids.set_pan(addr, pan) {
+                    println!("Cannot pan a SID at 0x{:04X} to {}", addr, pan);
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::SetSidDcOffset { addr, dc_offset } => {
+                match machine.sids.get_mut(addr) {
+                    Some(sid) => {
+                        sid.set_dc_offset(dc_offset);
+                        sid.print_status();
+                    }
+                    None => println!("No SID at 0x{:04X}", addr)
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::SetDigiBoost { addr, digi_boost } => {
+                match machine.sids.get_mut(addr) {
+                    Some(sid) => {
+                        sid.set_digi_boost(digi_boost);
+                        sid.print_status();
+                    }
+                    None => println!("No SID at 0x{:04X}", addr)
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::SetSidFilterBias { bias } => {
+                machine.sids.built_in().set_filter_bias(bias);
+                machine.sids.built_in().print_status();
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::SetPots { x, y } => {
+                machine.sids.built_in().set_pots(x, y);
+                println!("POTX: 0x{:02X}  POTY: 0x{:02X}", x, y);
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::RecordAudio { filename } => {
+                match filename {
+                    Some(filename) => machine.start_audio_recording(&filename),
+                    None => machine.stop_audio_recording()
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::Tune { song } => {
+                if let Some(song) = song {
+                    machine.play_subtune(song);
+                }
+                match machine.sid_tune {
+                    Some(ref tune) => tune.print_info(machine.subtune),
+                    None => println!("No tune loaded")
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::AudioStatus => {
+                match machine.audio_output {
+                    Some(ref audio_output) => println!("Audio output: {} Hz, latency {} ms", audio_output.sample_rate(), audio_output.latency()),
+                    None => println!("Audio output: none")
+                }
+                if let Some(ref recorder) = machine.audio_recorder {
+                    println!("Recording to {}", recorder.filename());
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::Disk { device, filename } => {
+                if let Some(filename) = filename {
+                    if let Err(msg) = machine.attach_disk(device, &filename) {
+                        println!("{}", msg);
+                    }
+                }
+                let unit = machine.disk_unit(device);
+                // With the 1541 the directory is read from its disk surface
+                let decoded = unit.drive.as_ref().and_then(|drive| drive.disk()).map(|disk| disk.to_d64(disk.filename()));
+                let disk = match decoded {
+                    Some(Ok(ref disk)) => Some(disk),
+                    Some(Err(ref msg)) => {
+                        println!("{}", msg);
+                        None
+                    }
+                    None => unit.disk.as_ref()
+                };
+                match (disk, &unit.host_dir) {
+                    (Some(disk), _) => match disk.directory() {
+                        Ok(entries) => {
+                            println!("{}: \"{}\"", disk.filename(), String::from_utf8_lossy(disk.disk_name()));
+                            for entry in entries {
+                                println!("{:<5} \"{}\" {:?}{}", entry.blocks, String::from_utf8_lossy(&entry.name), entry.file_type, if entry.closed { "" } else { " (not closed)" });
+                            }
+                            println!("{} blocks free", disk.blocks_free());
+                        }
+                        Err(msg) => println!("{}", msg)
+                    },
+                    (None, &Some(ref host_dir)) => match host_dir.files() {
+                        Ok(files) => {
+                            println!("{}: host directory", host_dir.path());
+                            for file in files {
+                                println!("{:<5} \"{}\" {:?}", file.blocks, String::from_utf8_lossy(&file.name), file.file_type);
+                            }
+                        }
+                        Err(msg) => println!("{}", msg)
+                    },
+                    (None, &None) => println!("No disk attached to device {}", device)
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::SaveDisk { device, filename } => {
+                match machine.export_disk(device, &filename) {
+                    Ok(()) => println!("Wrote {}", filename),
+                    Err(msg) => println!("{}", msg)
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::Drive { device, enabled } => {
+                if let Some(enabled) = enabled {
+                    if let Err(msg) = machine.set_true_drive(device, enabled) {
+                        println!("{}", msg);
+                    }
+                }
+                match machine.disk_unit(device).drive {
+                    Some(ref drive) => drive.print_status(),
+                    None => println!("1541 emulation off for device {}, LOAD and SAVE go to the disk image directly", device)
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::Iec => {
+                machine.iec_bus.print_status();
+                for (i, unit) in machine.disk_units.iter().enumerate() {
+                    let device = iec::FIRST_DRIVE + i as u8;
+                    match (&unit.drive, &unit.disk, &unit.host_dir) {
+                        (&Some(ref drive), _, _) => println!("Device {}: 1541 with {}", device, drive.disk().map_or("no disk", |disk| disk.filename())),
+                        (&None, &Some(ref disk), _) => println!("Device {}: {} through the KERNAL traps", device, disk.filename()),
+                        (&None, &None, &Some(ref host_dir)) => println!("Device {}: host directory {}", device, host_dir.path()),
+                        (&None, &None, &None) => {}
+                    }
+                }
+                if let Some(filename) = machine.iec_bus.trace_filename() {
+                    println!("Tracing to {}", filename);
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::Tape { filename } => {
+                if let Some(filename) = filename {
+                    if let Err(msg) = machine.attach_tape(&filename) {
+                        println!("{}", msg);
+                    }
+                }
+                match machine.t64 {
+                    Some(ref archive) => {
+                        println!("{}: \"{}\"", archive.filename(), String::from_utf8_lossy(archive.name()));
+                        for file in archive.files() {
+                            println!("\"{}\" at 0x{:04X}, {} bytes", String::from_utf8_lossy(&file.name), file.start, file.data.len() - 2);
+                        }
+                    }
+                    None => machine.datasette.print_status(machine.standard.clock_frequency)
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::PlayTape => {
+                if let Err(msg) = machine.datasette.play() {
+                    println!("{}", msg);
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::RecordTape { filename } => {
+                if let Err(msg) = machine.record_tape(filename.as_ref().map(|filename| filename.as_str())) {
+                    println!("{}", msg);
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::StopTape => {
+                machine.datasette.stop();
+                machine.save_tape();
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::RewindTape => {
+                machine.datasette.rewind();
+                machine.save_tape();
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::Cartridge { filename } => {
+                if let Some(filename) = filename {
+                    if let Err(msg) = machine.attach_cartridge(&filename) {
+                        println!("{}", msg);
+                    }
+                }
+                machine.expansion_port.print_cartridge_status();
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::RemoveCartridge => {
+                machine.remove_cartridge();
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::Reu { size } => {
+                if let Some(size) = size {
+                    machine.set_reu(size);
+                }
+                match machine.expansion_port.reu() {
+                    Some(reu) => reu.print_status(),
+                    None => println!("No REU attached")
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::GeoRam { size } => {
+                if let Some(size) = size {
+                    machine.set_georam(size);
+                }
+                match machine.expansion_port.georam() {
+                    Some(georam) => georam.print_status(),
+                    None => println!("No GeoRAM attached")
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::SaveSnapshot { filename } => {
+                match machine.save_snapshot(&filename) {
+                    Ok(()) => println!("Wrote {}", filename),
+                    Err(msg) => println!("{}", msg)
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::LoadSnapshot { filename } => {
+                if let Err(msg) = machine.load_snapshot(&filename) {
+                    println!("{}", msg);
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::Rewind { cycles } => {
+                match cycles {
+                    Some(cycles) => {
+                        let cycle = machine.cycles.saturating_sub(cycles);
+                        if let Err(msg) = machine.rewind_to(cycle) {
+                            println!("{}", msg);
+                        }
+                    }
+                    None => machine.rewind.print_status(machine.cycles)
+                }
+                debugger.state = DebuggerState::Pause;
+            }
+            DebuggerCommand::TraceIec { filename } => {
+                match filename {
+                    Some(filename) => match machine.iec_bus.start_trace(&filename) {
+                        Ok(()) => println!("Tracing the serial bus to {}", filename),
+                        Err(msg) => println!("{}", msg)
+                    },
+                    None => machine.stop_iec_trace()
+                }
+                debugger.state = DebuggerState::Pause;
+            }
             DebuggerCommand::Exit => {
                 break;
             }
         }
     }
 
+    machine.stop_audio_recording();
+    machine.save_drive_disks();
+    machine.save_tape();
+    machine.save_cartridge();
+    machine.save_georam();
+    machine.stop_iec_trace();
     rl.save_history(history_path).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> Machine {
+        Machine::new(None, 44100, vic_ii::PAL)
+    }
+
+    fn busy_machine() -> Machine {
+        let mut machine = machine();
+        for (i, byte) in machine.ram.iter_mut().enumerate() {
+            *byte = (i * 7) as u8;
+        }
+        machine.color_ram[10] = 0x0E;
+        machine.cycles = 123_456;
+        machine.vic.registers.write(0xD020, 0x06);
+        machine.cia1.write(0xDC04, 0x34);
+        machine.cia1.write(0xDC0E, 0x01);
+        machine.sids.write(0xD418, 0x0F);
+        machine.sids.write(0xD404, 0x11);
+        for _ in 0..100 {
+            machine.cia1.tick();
+            machine.sids.tick();
+        }
+        machine
+    }
+
+    #[test]
+    fn restores_what_was_saved() {
+        let bytes = busy_machine().snapshot().to_bytes();
+        let mut machine = machine();
+        machine.restore(&Snapshot::from_bytes(&bytes).unwrap()).unwrap();
+        assert!(machine.snapshot().to_bytes() == bytes);
+    }
+
+    #[test]
+    fn leaves_the_machine_as_it_was_when_a_module_is_broken() {
+        let mut bytes = busy_machine().snapshot().to_bytes();
+        // The SID comes last, keep half of it
+        let header = bytes.windows(4).rposition(|window| window == b"\x03SID").unwrap() + 4;
+        let length = (bytes.len() - header - 4) / 2;
+        bytes[header] = length as u8;
+        bytes[header + 1] = (length >> 8) as u8;
+        bytes.truncate(header + 4 + length);
+        let mut machine = machine();
+        let before = machine.snapshot().to_bytes();
+        assert!(machine.restore(&Snapshot::from_bytes(&bytes).unwrap()).is_err());
+        assert!(machine.snapshot().to_bytes() == before);
+    }
+
+    // The modules imported from a VICE snapshot of a machine at the given
+    // raster line
+    fn vice_modules(raster_line: u16) -> Vec<(&'static str, Vec<u8>)> {
+        let mut c64_mem = vec![0x37, 0x2F, 0, 0];
+        c64_mem.extend((0..65536).map(|i| (i * 3) as u8));
+        let main_cpu = vec![0x40, 0xE2, 0x01, 0x00, 0x11, 0x22, 0x33, 0xF0, 0x34, 0x12, 0x24];
+        let mut cia1 = vec![0; 27];
+        // Timer A counter and latch
+        cia1[4] = 0x99;
+        cia1[17] = 0xAA;
+        let mut vic = vec![0; 3 + 40];
+        vic.extend((0..1024).map(|i| i as u8 & 0x0F));
+        vic.extend(vec![0; 4 + 40 + 1 + 4]);
+        vic.push(10);
+        vic.extend_from_slice(&[raster_line as u8, (raster_line >> 8) as u8]);
+        vic.extend((0..64).map(|i| i as u8));
+        let mut sid = vec![1, 0];
+        sid.extend(vec![0; 32]);
+        sid[2 + 0x18] = 0x0F;
+        vec![("C64MEM", c64_mem), ("MAINCPU", main_cpu), ("CIA1", cia1), ("CIA2", vec![0; 27]), ("VIC-II", vic), ("SID", sid), ("DRIVE8", vec![0; 5])]
+    }
+
+    fn load_vice_snapshot(machine: &mut Machine, name: &str, modules: &[(&str, Vec<u8>)]) -> Result<(), String> {
+        let modules: Vec<(&str, &[u8])> = modules.iter().map(|&(name, ref data)| (name, &data[..])).collect();
+        let path = std::env::temp_dir().join(name);
+        let filename = path.to_str().unwrap();
+        File::create(filename).unwrap().write_all(&vsf::file("C64SC", &modules)).unwrap();
+        let result = machine.load_snapshot(filename);
+        std::fs::remove_file(filename).unwrap();
+        result
+    }
+
+    #[test]
+    fn imports_a_vice_snapshot() {
+        let mut machine = machine();
+        load_vice_snapshot(&mut machine, "c64emu-import.vsf", &vice_modules(100)).unwrap();
+        assert_eq!(machine.cycles, 123_456);
+        assert_eq!((machine.ram[0x1234], machine.ram[0xFFFF]), (0x9C, 0xFD));
+        assert_eq!(machine.color_ram[0x3FF], 0x0F);
+        assert_eq!(machine.processor_port.pins() & 0x07, 0x07);
+        assert_eq!(machine.mos6510.get_pc(), 0x1234);
+        assert_eq!(machine.cia1.read(0xDC04), 0x99);
+        let snapshot = machine.snapshot();
+        let mut vic = snapshot.module("VIC-II").unwrap();
+        assert_eq!((vic.read_u16(), vic.read_u16()), (Ok(100), Ok(80)));
+        let mut registers = [0; 47];
+        vic.read_bytes(&mut registers).unwrap();
+        assert_eq!(registers[0x20], 0x20);
+    }
+
+    #[test]
+    fn leaves_the_machine_as_it_was_when_a_vice_module_is_broken() {
+        let mut machine = busy_machine();
+        let before = machine.snapshot().to_bytes();
+        assert!(load_vice_snapshot(&mut machine, "c64emu-broken.vsf", &vice_modules(400)).is_err());
+        assert!(machine.snapshot().to_bytes() == before);
+    }
+
+    // A machine idling at the READY prompt, with a KERNAL that is nothing
+    // but the loop there
+    fn ready_machine() -> Machine {
+        let mut machine = machine();
+        let ready = READY_LOOP_ADDR as usize - 0xE000;
+        machine.kernal_rom[ready..ready + 3].copy_from_slice(&[0x4C, READY_LOOP_ADDR as u8, (READY_LOOP_ADDR >> 8) as u8]);
+        machine.kernal_rom[0x1FFC] = READY_LOOP_ADDR as u8;
+        machine.kernal_rom[0x1FFD] = (READY_LOOP_ADDR >> 8) as u8;
+        machine.reset();
+        machine
+    }
+
+    #[test]
+    fn installs_the_program_again_when_going_back_to_before_the_prompt() {
+        let mut machine = ready_machine();
+        machine.program = Some((Program::parse(&[0x00, 0x10, 1, 2, 3]).unwrap(), Autostart::None));
+        machine.program_pending = true;
+        let mut at_cycle_50 = Vec::new();
+        while machine.cycles < 100 {
+            if machine.cycles == 50 {
+                at_cycle_50 = machine.snapshot().to_bytes();
+            }
+            machine.tick().unwrap();
+        }
+        assert_eq!(&machine.ram[0x1000..0x1003], &[1, 2, 3]);
+        assert_eq!(machine.rewind.oldest_cycle(), Some(0));
+        machine.rewind_to(50).unwrap();
+        assert_eq!(machine.cycles, 50);
+        assert!(!machine.program_pending);
+        assert!(machine.snapshot().to_bytes() == at_cycle_50);
+    }
+
+    #[test]
+    fn refuses_to_go_back_with_disks_served_by_the_kernal_traps() {
+        let mut machine = ready_machine();
+        machine.disk_units[0].disk = Some(DiskImage::parse("test.d64", vec![0; 174848]).unwrap());
+        while machine.cycles < 100 {
+            machine.tick().unwrap();
+        }
+        assert_eq!(machine.rewind_to(50), Err("Can't rewind with a disk image attached".to_string()));
+        assert_eq!(machine.cycles, 100);
+        machine.disk_units[0].disk = None;
+        machine.disk_units[0].host_dir = Some(HostDirectory::new(&std::env::temp_dir().to_string_lossy()).unwrap());
+        assert_eq!(machine.rewind_to(50), Err("Can't rewind with a host directory attached".to_string()));
+    }
+}
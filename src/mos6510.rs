@@ -1,4 +1,5 @@
 use memory::{ReadView, WriteView};
+use snapshot::{Reader, Writer};
 
 struct StatusRegister {
     negative_flag: bool,
@@ -23,10 +24,92 @@ struct State {
 pub struct Mos6510 {
     state: State,
     wait_cycles: i8,
-    irq: bool
+    // Level of the IRQ line
+    irq: bool,
+    // Level of the NMI line, NMI is triggered on its falling edge
+    nmi_line: bool,
+    nmi: bool
 }
 
+const NMI_VECTOR_ADDR: u16 = 0xfffa;
 const RESET_VECTOR_ADDR: u16 = 0xfffc;
+const IRQ_VECTOR_ADDR: u16 = 0xfffe;
+
+// Bits of the processor port with pull-up resistors, read as 1 when
+// configured as inputs: LORAM, HIRAM, CHAREN and the cassette sense line
+const PROCESSOR_PORT_PULL_UPS: u8 = 0b0001_0111;
+
+// Processor port bits of the datasette
+const CASSETTE_WRITE: u8 = 0b0000_1000;
+const CASSETTE_SENSE: u8 = 0b0001_0000;
+const CASSETTE_MOTOR: u8 = 0b0010_0000;
+
+/// The I/O port of the 6510 at $00 (data direction) and $01 (data). Bits
+/// 0-2 select the memory configuration, bits 3-5 drive the datasette.
+pub struct ProcessorPort {
+    direction: u8,
+    data: u8,
+    // The sense line is pulled low while a datasette button is pressed
+    cassette_button: bool
+}
+
+impl ProcessorPort {
+    pub fn new() -> ProcessorPort {
+        ProcessorPort {
+            direction: 0,
+            data: 0,
+            cassette_button: false
+        }
+    }
+
+    /// Levels of the port lines. Inputs with a pull-up read high.
+    pub fn pins(self: &ProcessorPort) -> u8 {
+        let inputs = if self.cassette_button { PROCESSOR_PORT_PULL_UPS & !CASSETTE_SENSE } else { PROCESSOR_PORT_PULL_UPS };
+        (self.data & self.direction) | (inputs & !self.direction)
+    }
+
+    pub fn set_cassette_button(self: &mut ProcessorPort, pressed: bool) {
+        self.cassette_button = pressed;
+    }
+
+    /// Whether the datasette motor is powered, which takes a low output.
+    pub fn cassette_motor(self: &ProcessorPort) -> bool {
+        self.direction & CASSETTE_MOTOR > 0 && self.data & CASSETTE_MOTOR == 0
+    }
+
+    /// Level of the datasette write line.
+    pub fn cassette_write(self: &ProcessorPort) -> bool {
+        self.pins() & CASSETTE_WRITE > 0
+    }
+
+    pub fn read(self: &ProcessorPort, addr: u16) -> u8 {
+        if addr == 0 {
+            self.direction
+        } else {
+            self.pins()
+        }
+    }
+
+    pub fn write(self: &mut ProcessorPort, addr: u16, value: u8) {
+        if addr == 0 {
+            self.direction = value;
+        } else {
+            self.data = value;
+        }
+    }
+
+    pub fn save_state(self: &ProcessorPort, writer: &mut Writer) {
+        writer.write_u8(self.direction);
+        writer.write_u8(self.data);
+    }
+
+    /// The buttons of the datasette are sensed again on the next cycle.
+    pub fn load_state(self: &mut ProcessorPort, reader: &mut Reader) -> Result<(), String> {
+        self.direction = reader.read_u8()?;
+        self.data = reader.read_u8()?;
+        Ok(())
+    }
+}
 
 fn same_page(a: u16, b: u16) -> bool {
     a & 0xFF00 == b & 0xFF00
@@ -57,7 +140,9 @@ impl Mos6510 {
                 index_y: 0
             },
             wait_cycles: 0,
-            irq: false
+            irq: false,
+            nmi_line: false,
+            nmi: false
          }
     }
 
@@ -81,6 +166,55 @@ impl Mos6510 {
         );
     }
 
+    /// Saves the registers, the cycles left of the current instruction and
+    /// the interrupt lines.
+    pub fn save_state(self: &Mos6510, writer: &mut Writer) {
+        writer.write_u16(self.state.program_counter);
+        writer.write_u8(self.state.stack_pointer);
+        writer.write_u8(self.status_register_value());
+        writer.write_u8(self.state.accumulator);
+        writer.write_u8(self.state.index_x);
+        writer.write_u8(self.state.index_y);
+        writer.write_u8(self.wait_cycles as u8);
+        writer.write_bool(self.irq);
+        writer.write_bool(self.nmi_line);
+        writer.write_bool(self.nmi);
+    }
+
+    pub fn load_state(self: &mut Mos6510, reader: &mut Reader) -> Result<(), String> {
+        self.state.program_counter = reader.read_u16()?;
+        self.state.stack_pointer = reader.read_u8()?;
+        let status_register = reader.read_u8()?;
+        self.set_status_register(status_register);
+        self.state.accumulator = reader.read_u8()?;
+        self.state.index_x = reader.read_u8()?;
+        self.state.index_y = reader.read_u8()?;
+        self.wait_cycles = reader.read_u8()? as i8;
+        self.irq = reader.read_bool()?;
+        self.nmi_line = reader.read_bool()?;
+        self.nmi = reader.read_bool()?;
+        Ok(())
+    }
+
+    /// Loads the MAINCPU module of a VICE snapshot and returns the clock.
+    /// VICE saves between instructions, so the next cycle starts one.
+    pub fn load_vice_state(self: &mut Mos6510, reader: &mut Reader) -> Result<u32, String> {
+        let clock = reader.read_u32()?;
+        self.state.accumulator = reader.read_u8()?;
+        self.state.index_x = reader.read_u8()?;
+        self.state.index_y = reader.read_u8()?;
+        self.state.stack_pointer = reader.read_u8()?;
+        self.state.program_counter = reader.read_u16()?;
+        let status_register = reader.read_u8()?;
+        self.set_status_register(status_register);
+        self.wait_cycles = 0;
+        self.irq = false;
+        // An NMI active when the snapshot was taken has been taken already
+        self.nmi_line = true;
+        self.nmi = false;
+        Ok(clock)
+    }
+
     pub fn reset<M: ReadView>(self: &mut Mos6510, mem: &mut M) {
         self.state.program_counter = mem.read(RESET_VECTOR_ADDR) as u16 | ((mem.read(RESET_VECTOR_ADDR + 1) as u16) << 8);
     }
@@ -89,31 +223,69 @@ impl Mos6510 {
         self.state.program_counter
     }
 
+    /// Cycles left of the current instruction, including the current one.
+    pub fn cycles_left(self: &Mos6510) -> u8 {
+        self.wait_cycles.max(0) as u8
+    }
+
+    pub fn set_pc(self: &mut Mos6510, pc: u16) {
+        self.state.program_counter = pc;
+    }
+
+    /// Sets the overflow flag, as a falling edge on the SO input of the 6502
+    /// does. The 1541 signals bytes read from the disk this way.
+    pub fn set_overflow(self: &mut Mos6510) {
+        self.state.status_register.overflow_flag = true;
+    }
+
+    pub fn accumulator(self: &Mos6510) -> u8 {
+        self.state.accumulator
+    }
+
+    pub fn index_x(self: &Mos6510) -> u8 {
+        self.state.index_x
+    }
+
+    pub fn index_y(self: &Mos6510) -> u8 {
+        self.state.index_y
+    }
+
+    /// Returns from a subroutine emulated outside the CPU with the given
+    /// registers and carry flag, as its RTS would.
+    pub fn return_from_trap<M: ReadView>(self: &mut Mos6510, mem: &mut M, a: u8, x: u8, y: u8, carry: bool) {
+        self.state.accumulator = a;
+        self.state.index_x = x;
+        self.state.index_y = y;
+        self.state.status_register.carry_flag = carry;
+        self.state.program_counter = self.pop16(mem).wrapping_add(1);
+        self.wait_cycles += 6;
+    }
+
     fn effective_stack_pointer(self: &Mos6510) -> u16 {
         0x100 + self.state.stack_pointer as u16
     }
 
     fn push8<M: WriteView>(self: &mut Mos6510, mem: &mut M, value: u8) {
         mem.write(self.effective_stack_pointer(), value);
-        self.state.stack_pointer -= 1;
+        self.state.stack_pointer = self.state.stack_pointer.wrapping_sub(1);
     }
 
     fn push16<M: WriteView>(self: &mut Mos6510, mem: &mut M, value: u16) {
         mem.write(self.effective_stack_pointer(), ((value & 0xFF00) >> 8) as u8);
-        self.state.stack_pointer -= 1;
+        self.state.stack_pointer = self.state.stack_pointer.wrapping_sub(1);
         mem.write(self.effective_stack_pointer(), (value & 0x00FF) as u8);
-        self.state.stack_pointer -= 1;
+        self.state.stack_pointer = self.state.stack_pointer.wrapping_sub(1);
     }
 
     fn pop8<M: ReadView>(self: &mut Mos6510, mem: &mut M) -> u8 {
-        self.state.stack_pointer += 1;
+        self.state.stack_pointer = self.state.stack_pointer.wrapping_add(1);
         mem.read(self.effective_stack_pointer())
     }
 
     fn pop16<M: ReadView>(self: &mut Mos6510, mem: &mut M) -> u16 {
-        self.state.stack_pointer += 1;
+        self.state.stack_pointer = self.state.stack_pointer.wrapping_add(1);
         let lo = mem.read(self.effective_stack_pointer());
-        self.state.stack_pointer += 1;
+        self.state.stack_pointer = self.state.stack_pointer.wrapping_add(1);
         let hi = mem.read(self.effective_stack_pointer());
         ((hi as u16) << 8) + lo as u16
     }
@@ -141,9 +313,9 @@ impl Mos6510 {
     fn read_indirect_y_indexed_addr<M: ReadView>(self: &Mos6510, mem: &mut M) -> (u16, u16) {
         let vector_addr = self.read_zeropage_addr(mem);
         let vector_lo = mem.read(vector_addr);
-        let vector_hi = mem.read(vector_addr + 1);
+        let vector_hi = mem.read((vector_addr + 1) & 0xFF);
         let vector = ((vector_hi as u16) << 8) + vector_lo as u16;
-        (vector_addr, vector + self.state.index_y as u16)
+        (vector_addr, vector.wrapping_add(self.state.index_y as u16))
     }
 
     fn read_indexed_zeropage_x<M: ReadView>(self: &Mos6510, mem: &mut M) -> (u16, u16) {
@@ -152,6 +324,30 @@ impl Mos6510 {
         (base_addr as u16, addr as u16)
     }
 
+    fn read_indexed_zeropage_y<M: ReadView>(self: &Mos6510, mem: &mut M) -> (u16, u16) {
+        let base_addr = self.read_immediate(mem);
+        let addr = base_addr.wrapping_add(self.state.index_y);
+        (base_addr as u16, addr as u16)
+    }
+
+    fn read_absolute_x_indexed_addr<M: ReadView>(self: &Mos6510, mem: &mut M) -> (u16, u16) {
+        let abs_addr = self.read_absolute_addr(mem);
+        (abs_addr, abs_addr.wrapping_add(self.state.index_x as u16))
+    }
+
+    fn read_absolute_y_indexed_addr<M: ReadView>(self: &Mos6510, mem: &mut M) -> (u16, u16) {
+        let abs_addr = self.read_absolute_addr(mem);
+        (abs_addr, abs_addr.wrapping_add(self.state.index_y as u16))
+    }
+
+    fn read_x_indexed_indirect_addr<M: ReadView>(self: &Mos6510, mem: &mut M) -> (u16, u16) {
+        let base_addr = self.read_immediate(mem);
+        let vector_addr = base_addr.wrapping_add(self.state.index_x);
+        let vector_lo = mem.read(vector_addr as u16);
+        let vector_hi = mem.read(vector_addr.wrapping_add(1) as u16);
+        (base_addr as u16, ((vector_hi as u16) << 8) + vector_lo as u16)
+    }
+
     fn set_negative_flag(self: &mut Mos6510, value: u8) {
         self.state.status_register.negative_flag = value & (1 << 7) != 0;
     }
@@ -169,24 +365,58 @@ impl Mos6510 {
 
     fn add_with_carry(self: &mut Mos6510, operand: u8) {
         let accumulator = self.state.accumulator;
-        let added = accumulator as u16 + operand as u16 + if self.state.status_register.carry_flag { 1 } else { 0 };
-        let value = added as u8;
-        self.state.accumulator = value as u8;
-        self.state.status_register.carry_flag = added & 0x0100 > 0;
-        self.set_negative_flag(value);
-        self.set_zero_flag(value);
-        self.state.status_register.overflow_flag = (accumulator as i8) >= 0 && (operand as i8) >= 0 && (value as i8) < 0;
+        let carry = if self.state.status_register.carry_flag { 1 } else { 0 };
+        let added = accumulator as u16 + operand as u16 + carry;
+        // Z is based on the binary sum in decimal mode too
+        self.set_zero_flag(added as u8);
+        let value = if self.state.status_register.decimal_mode_flag {
+            let mut lo = (accumulator & 0x0F) as u16 + (operand & 0x0F) as u16 + carry;
+            if lo > 0x09 {
+                lo += 0x06;
+            }
+            let mut value = (accumulator & 0xF0) as u16 + (operand & 0xF0) as u16 + (lo & 0x0F) + if lo > 0x0F { 0x10 } else { 0 };
+            // N and V are computed before the high digit is adjusted
+            self.set_negative_flag(value as u8);
+            self.state.status_register.overflow_flag = (accumulator ^ value as u8) & !(accumulator ^ operand) & 0x80 > 0;
+            if value & 0x1F0 > 0x90 {
+                value += 0x60;
+            }
+            self.state.status_register.carry_flag = value & 0xFF0 > 0xF0;
+            value as u8
+        } else {
+            let value = added as u8;
+            self.set_negative_flag(value);
+            self.state.status_register.overflow_flag = (accumulator ^ value) & (operand ^ value) & 0x80 > 0;
+            self.state.status_register.carry_flag = added & 0x0100 > 0;
+            value
+        };
+        self.state.accumulator = value;
     }
 
     fn subtract_with_carry(self: &mut Mos6510, operand: u8) {
         let accumulator = self.state.accumulator;
-        let subtracted = accumulator as i8 as i16 - operand as i8 as i16 - if self.state.status_register.carry_flag { 0 } else { 1 };
-        let value = subtracted as u8;
-        self.state.accumulator = value;
-        self.state.status_register.carry_flag = (accumulator as u8) >= (operand as u8);
-        self.set_negative_flag(value);
-        self.set_zero_flag(value);
-        self.state.status_register.overflow_flag = subtracted < -128 || subtracted > 127;
+        let borrow = if self.state.status_register.carry_flag { 0 } else { 1 };
+        let subtracted = (accumulator as u16).wrapping_sub(operand as u16).wrapping_sub(borrow);
+        // The flags are based on the binary difference in decimal mode too
+        let binary = subtracted as u8;
+        self.state.status_register.carry_flag = subtracted < 0x100;
+        self.set_negative_flag(binary);
+        self.set_zero_flag(binary);
+        self.state.status_register.overflow_flag = (accumulator ^ binary) & (accumulator ^ operand) & 0x80 > 0;
+        self.state.accumulator = if self.state.status_register.decimal_mode_flag {
+            let lo = ((accumulator & 0x0F) as u16).wrapping_sub((operand & 0x0F) as u16).wrapping_sub(borrow);
+            let mut value = if lo & 0x10 > 0 {
+                (lo.wrapping_sub(0x06) & 0x0F) | ((accumulator & 0xF0) as u16).wrapping_sub((operand & 0xF0) as u16).wrapping_sub(0x10)
+            } else {
+                (lo & 0x0F) | ((accumulator & 0xF0) as u16).wrapping_sub((operand & 0xF0) as u16)
+            };
+            if value & 0x100 > 0 {
+                value = value.wrapping_sub(0x60);
+            }
+            value as u8
+        } else {
+            binary
+        };
     }
 
     fn shift_left_memory<M: ReadView + WriteView>(self: &mut Mos6510, mem: &mut M, addr: u16) -> Effect {
@@ -219,6 +449,49 @@ impl Mos6510 {
         Effect::WriteMem { addr, value }
     }
 
+    fn shift_right_memory<M: ReadView + WriteView>(self: &mut Mos6510, mem: &mut M, addr: u16) -> Effect {
+        let operand = mem.read(addr);
+        let value = operand >> 1;
+        mem.write(addr, value);
+        self.state.status_register.carry_flag = operand & 1 > 0;
+        self.set_negative_flag(value);
+        self.set_zero_flag(value);
+        Effect::WriteMem { addr, value }
+    }
+
+    fn rotate_left_memory<M: ReadView + WriteView>(self: &mut Mos6510, mem: &mut M, addr: u16) -> Effect {
+        let operand = mem.read(addr);
+        let value = operand << 1 | if self.state.status_register.carry_flag { 1 } else { 0 };
+        mem.write(addr, value);
+        self.state.status_register.carry_flag = operand & 0x80 > 0;
+        self.set_negative_flag(value);
+        self.set_zero_flag(value);
+        Effect::WriteMem { addr, value }
+    }
+
+    fn increment_memory<M: ReadView + WriteView>(self: &mut Mos6510, mem: &mut M, addr: u16) -> Effect {
+        let operand = mem.read(addr);
+        let value = operand.wrapping_add(1);
+        mem.write(addr, value);
+        self.set_negative_flag(value);
+        self.set_zero_flag(value);
+        Effect::WriteMem { addr, value }
+    }
+
+    fn and_with_accumulator(self: &mut Mos6510, operand: u8) {
+        let value = self.state.accumulator & operand;
+        self.state.accumulator = value;
+        self.set_negative_flag(value);
+        self.set_zero_flag(value);
+    }
+
+    fn exclusive_or_with_accumulator(self: &mut Mos6510, operand: u8) {
+        let value = self.state.accumulator ^ operand;
+        self.state.accumulator = value;
+        self.set_negative_flag(value);
+        self.set_zero_flag(value);
+    }
+
     fn or_with_accumulator(self: &mut Mos6510, operand: u8) {
         let value = self.state.accumulator | operand;
         self.state.accumulator = value;
@@ -248,19 +521,31 @@ impl Mos6510 {
         self.state.status_register.negative_flag          = value & 0b1000_0000 > 0;
     }
 
-    pub fn tick<M: ReadView + WriteView>(self: &mut Mos6510, mem: &mut M, irq: bool) -> Result<(Option<String>, Option<Effect>), String> {
-        if irq {
-            self.irq = true;
+    fn interrupt<M: ReadView + WriteView>(self: &mut Mos6510, mem: &mut M, vector_addr: u16) {
+        let pc = self.state.program_counter;
+        // The break flag is only set in the pushed status register by BRK and PHP
+        let sr = (self.status_register_value() & !0b0001_0000) | 0b0010_0000;
+        self.push16(mem, pc);
+        self.push8(mem, sr);
+        self.state.status_register.interrupt_disable_flag = true;
+        self.state.program_counter = mem.read(vector_addr) as u16 | ((mem.read(vector_addr + 1) as u16) << 8);
+        self.wait_cycles = 7;
+    }
+
+    pub fn tick<M: ReadView + WriteView>(self: &mut Mos6510, mem: &mut M, irq: bool, nmi: bool) -> Result<(Option<String>, Option<Effect>), String> {
+        self.irq = irq;
+        if nmi && !self.nmi_line {
+            self.nmi = true;
         }
+        self.nmi_line = nmi;
         self.wait_cycles -= 1;
         if self.wait_cycles <= 0 {
-            if self.irq && !self.state.status_register.interrupt_disable_flag {
-                let pc = self.state.program_counter;
-                let sr = self.status_register_value();
-                self.push16(mem, pc);
-                self.push8(mem, sr);
-                self.state.program_counter = 0xFF48;
-                self.irq = false;
+            if self.nmi {
+                self.nmi = false;
+                self.interrupt(mem, NMI_VECTOR_ADDR);
+                Ok((None, None))
+            } else if self.irq && !self.state.status_register.interrupt_disable_flag {
+                self.interrupt(mem, IRQ_VECTOR_ADDR);
                 Ok((None, None))
             } else {
                 self.run_instruction(mem).map(|(name, eff_opt)| (Some(name), eff_opt))
@@ -274,6 +559,52 @@ impl Mos6510 {
         let opcode = mem.read(self.state.program_counter);
 
         match opcode {
+            0x00 => {
+                let pc = self.state.program_counter;
+                let sr = self.status_register_value() | 0b0011_0000;
+                self.push16(mem, pc + 2);
+                self.push8(mem, sr);
+                self.state.status_register.interrupt_disable_flag = true;
+                self.state.program_counter = mem.read(IRQ_VECTOR_ADDR) as u16 | ((mem.read(IRQ_VECTOR_ADDR + 1) as u16) << 8);
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("BRK"),
+                    None
+                ));
+            }
+            0x01 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let operand = mem.read(addr);
+                self.or_with_accumulator(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("ORA (${:02X},X)", base_addr),
+                    None
+                ));
+            }
+            0x03 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let effect = self.shift_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.or_with_accumulator(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 8;
+                return Ok((
+                    format!("SLO (${:02X},X)", base_addr),
+                    Some(effect)
+                ));
+            }
+            0x04 => {
+                let addr = self.read_zeropage_addr(mem);
+                mem.read(addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 3;
+                return Ok((
+                    format!("NOP ${:02X}", addr),
+                    None
+                ));
+            }
             0x05 => {
                 let addr = self.read_zeropage_addr(mem);
                 let operand = mem.read(addr);
@@ -295,8 +626,21 @@ impl Mos6510 {
                     Some(effect)
                 ))
             }
+            0x07 => {
+                let addr = self.read_zeropage_addr(mem);
+                let effect = self.shift_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.or_with_accumulator(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 5;
+                return Ok((
+                    format!("SLO ${:02X}", addr),
+                    Some(effect)
+                ));
+            }
             0x08 => {
-                let value = self.status_register_value();
+                // The break flag is always set in the pushed value
+                let value = self.status_register_value() | 0b0011_0000;
                 self.push8(mem, value);
                 self.state.program_counter += 1;
                 self.wait_cycles = 3;
@@ -318,6 +662,7 @@ impl Mos6510 {
             0x0A => {
                 let operand = self.state.accumulator;
                 let value = operand << 1;
+                self.state.accumulator = value;
                 self.state.status_register.carry_flag = operand & 0x80 > 0;
                 self.set_negative_flag(value);
                 self.set_zero_flag(value);
@@ -328,6 +673,27 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x0B => {
+                let operand = self.read_immediate(mem);
+                self.and_with_accumulator(operand);
+                self.state.status_register.carry_flag = self.state.status_register.negative_flag;
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("ANC #${:02X}", operand),
+                    None
+                ));
+            }
+            0x0C => {
+                let addr = self.read_absolute_addr(mem);
+                mem.read(addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("NOP ${:04X}", addr),
+                    None
+                ));
+            }
             0x0D => {
                 let addr = self.read_absolute_addr(mem);
                 let operand = mem.read(addr);
@@ -339,11 +705,33 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x0E => {
+                let addr = self.read_absolute_addr(mem);
+                let effect = self.shift_left_memory(mem, addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("ASL ${:04X}", addr),
+                    Some(effect)
+                ));
+            }
+            0x0F => {
+                let addr = self.read_absolute_addr(mem);
+                let effect = self.shift_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.or_with_accumulator(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("SLO ${:04X}", addr),
+                    Some(effect)
+                ));
+            }
             0x10 => {
                 let addr = self.read_relative_addr(mem) + 2;
                 if self.state.status_register.negative_flag == false {
+                    self.wait_cycles = if same_page(self.state.program_counter + 2, addr) { 3 } else { 4 };
                     self.state.program_counter = addr;
-                    self.wait_cycles = if same_page(self.state.program_counter, addr) { 3 } else { 4 };
                 } else {
                     self.state.program_counter += 2;
                     self.wait_cycles = 2;
@@ -353,6 +741,50 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x11 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.or_with_accumulator(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = if same_page(addr.wrapping_sub(self.state.index_y as u16), addr) { 5 } else { 6 };
+                return Ok((
+                    format!("ORA (${:02X}),Y", vector_addr),
+                    None
+                ));
+            }
+            0x13 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let effect = self.shift_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.or_with_accumulator(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 8;
+                return Ok((
+                    format!("SLO (${:02X}),Y", vector_addr),
+                    Some(effect)
+                ));
+            }
+            0x14 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                mem.read(addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("NOP ${:02X},X", base_addr),
+                    None
+                ));
+            }
+            0x15 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let operand = mem.read(addr);
+                self.or_with_accumulator(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("ORA ${:02X},X", base_addr),
+                    None
+                ));
+            }
             0x16 => {
                 let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
                 let effect = self.shift_left_memory(mem, addr);
@@ -363,6 +795,18 @@ impl Mos6510 {
                     Some(effect)
                 ));
             }
+            0x17 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let effect = self.shift_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.or_with_accumulator(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("SLO ${:02X},X", base_addr),
+                    Some(effect)
+                ));
+            }
             0x18 => {
                 self.state.status_register.carry_flag = false;
                 self.state.program_counter += 1;
@@ -372,52 +816,182 @@ impl Mos6510 {
                     None
                 ));
             }
-            0x20 => {
-                let pc = self.state.program_counter;
-                self.push16(mem, pc + 2);
-                let addr = self.read_absolute_addr(mem);
-                self.state.program_counter = addr;
-                self.wait_cycles = 6;
-                return Ok((
-                    format!("JSR ${:04X}", addr),
-                    None
-                ));
-            }
-            0x24 => {
-                let addr = self.read_zeropage_addr(mem);
+            0x19 => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
                 let operand = mem.read(addr);
-                let value = self.state.accumulator & operand;
-                self.set_zero_flag(value);
-                self.state.status_register.negative_flag = operand & 0b1000_0000 > 0;
-                self.state.status_register.overflow_flag = operand & 0b0100_0000 > 0;
-                self.state.program_counter += 2;
-                self.wait_cycles = 3;
+                self.or_with_accumulator(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
                 return Ok((
-                    format!("BIT ${:02X}", addr),
+                    format!("ORA ${:04X},Y", abs_addr),
                     None
-                ))
+                ));
             }
-            0x28 => {
-                let value = self.pop8(mem);
-                self.set_status_register(value);
+            0x1A => {
                 self.state.program_counter += 1;
-                self.wait_cycles = 4;
+                self.wait_cycles = 2;
                 return Ok((
-                    format!("PLP"),
+                    format!("NOP"),
                     None
                 ));
             }
-            0x29 => {
-                let operand = self.read_immediate(mem);
-                let value = self.state.accumulator & operand;
-                self.state.accumulator = value;
-                self.set_negative_flag(value);
-                self.set_zero_flag(value);
-                self.state.program_counter += 2;
-                self.wait_cycles = 2;
+            0x1B => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let effect = self.shift_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.or_with_accumulator(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
                 return Ok((
-                    format!("AND #${:02X}", operand),
-                    None
+                    format!("SLO ${:04X},Y", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0x1C => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                mem.read(addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("NOP ${:04X},X", abs_addr),
+                    None
+                ));
+            }
+            0x1D => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.or_with_accumulator(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("ORA ${:04X},X", abs_addr),
+                    None
+                ));
+            }
+            0x1E => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let effect = self.shift_left_memory(mem, addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("ASL ${:04X},X", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0x1F => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let effect = self.shift_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.or_with_accumulator(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("SLO ${:04X},X", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0x20 => {
+                let pc = self.state.program_counter;
+                self.push16(mem, pc + 2);
+                let addr = self.read_absolute_addr(mem);
+                self.state.program_counter = addr;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("JSR ${:04X}", addr),
+                    None
+                ));
+            }
+            0x21 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let operand = mem.read(addr);
+                self.and_with_accumulator(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("AND (${:02X},X)", base_addr),
+                    None
+                ));
+            }
+            0x23 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let effect = self.rotate_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.and_with_accumulator(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 8;
+                return Ok((
+                    format!("RLA (${:02X},X)", base_addr),
+                    Some(effect)
+                ));
+            }
+            0x24 => {
+                let addr = self.read_zeropage_addr(mem);
+                let operand = mem.read(addr);
+                let value = self.state.accumulator & operand;
+                self.set_zero_flag(value);
+                self.state.status_register.negative_flag = operand & 0b1000_0000 > 0;
+                self.state.status_register.overflow_flag = operand & 0b0100_0000 > 0;
+                self.state.program_counter += 2;
+                self.wait_cycles = 3;
+                return Ok((
+                    format!("BIT ${:02X}", addr),
+                    None
+                ))
+            }
+            0x25 => {
+                let addr = self.read_zeropage_addr(mem);
+                let operand = mem.read(addr);
+                self.and_with_accumulator(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 3;
+                return Ok((
+                    format!("AND ${:02X}", addr),
+                    None
+                ));
+            }
+            0x26 => {
+                let addr = self.read_zeropage_addr(mem);
+                let effect = self.rotate_left_memory(mem, addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 5;
+                return Ok((
+                    format!("ROL ${:02X}", addr),
+                    Some(effect)
+                ));
+            }
+            0x27 => {
+                let addr = self.read_zeropage_addr(mem);
+                let effect = self.rotate_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.and_with_accumulator(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 5;
+                return Ok((
+                    format!("RLA ${:02X}", addr),
+                    Some(effect)
+                ));
+            }
+            0x28 => {
+                let value = self.pop8(mem);
+                self.set_status_register(value);
+                self.state.program_counter += 1;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("PLP"),
+                    None
+                ));
+            }
+            0x29 => {
+                let operand = self.read_immediate(mem);
+                let value = self.state.accumulator & operand;
+                self.state.accumulator = value;
+                self.set_negative_flag(value);
+                self.set_zero_flag(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("AND #${:02X}", operand),
+                    None
                 ));
             }
             0x2A => {
@@ -434,6 +1008,17 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x2B => {
+                let operand = self.read_immediate(mem);
+                self.and_with_accumulator(operand);
+                self.state.status_register.carry_flag = self.state.status_register.negative_flag;
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("ANC #${:02X}", operand),
+                    None
+                ));
+            }
             0x2C => {
                 let addr = self.read_absolute_addr(mem);
                 let operand = mem.read(addr);
@@ -448,11 +1033,44 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x2D => {
+                let addr = self.read_absolute_addr(mem);
+                let operand = mem.read(addr);
+                self.and_with_accumulator(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("AND ${:04X}", addr),
+                    None
+                ));
+            }
+            0x2E => {
+                let addr = self.read_absolute_addr(mem);
+                let effect = self.rotate_left_memory(mem, addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("ROL ${:04X}", addr),
+                    Some(effect)
+                ));
+            }
+            0x2F => {
+                let addr = self.read_absolute_addr(mem);
+                let effect = self.rotate_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.and_with_accumulator(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("RLA ${:04X}", addr),
+                    Some(effect)
+                ));
+            }
             0x30 => {
                 let addr = self.read_relative_addr(mem) + 2;
                 if self.state.status_register.negative_flag {
+                    self.wait_cycles = if same_page(self.state.program_counter + 2, addr) { 3 } else { 4 };
                     self.state.program_counter = addr;
-                    self.wait_cycles = if same_page(self.state.program_counter, addr) { 3 } else { 4 };
                 } else {
                     self.state.program_counter += 2;
                     self.wait_cycles = 2;
@@ -462,6 +1080,72 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x31 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.and_with_accumulator(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = if same_page(addr.wrapping_sub(self.state.index_y as u16), addr) { 5 } else { 6 };
+                return Ok((
+                    format!("AND (${:02X}),Y", vector_addr),
+                    None
+                ));
+            }
+            0x33 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let effect = self.rotate_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.and_with_accumulator(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 8;
+                return Ok((
+                    format!("RLA (${:02X}),Y", vector_addr),
+                    Some(effect)
+                ));
+            }
+            0x34 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                mem.read(addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("NOP ${:02X},X", base_addr),
+                    None
+                ));
+            }
+            0x35 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let operand = mem.read(addr);
+                self.and_with_accumulator(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("AND ${:02X},X", base_addr),
+                    None
+                ));
+            }
+            0x36 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let effect = self.rotate_left_memory(mem, addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("ROL ${:02X},X", base_addr),
+                    Some(effect)
+                ));
+            }
+            0x37 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let effect = self.rotate_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.and_with_accumulator(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("RLA ${:02X},X", base_addr),
+                    Some(effect)
+                ));
+            }
             0x38 => {
                 self.state.status_register.carry_flag = true;
                 self.state.program_counter += 1;
@@ -471,16 +1155,124 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x39 => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.and_with_accumulator(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("AND ${:04X},Y", abs_addr),
+                    None
+                ));
+            }
+            0x3A => {
+                self.state.program_counter += 1;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("NOP"),
+                    None
+                ));
+            }
+            0x3B => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let effect = self.rotate_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.and_with_accumulator(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("RLA ${:04X},Y", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0x3C => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                mem.read(addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("NOP ${:04X},X", abs_addr),
+                    None
+                ));
+            }
+            0x3D => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.and_with_accumulator(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("AND ${:04X},X", abs_addr),
+                    None
+                ));
+            }
+            0x3E => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let effect = self.rotate_left_memory(mem, addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("ROL ${:04X},X", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0x3F => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let effect = self.rotate_left_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.and_with_accumulator(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("RLA ${:04X},X", abs_addr),
+                    Some(effect)
+                ));
+            }
             0x40 => {
                 let sr = self.pop8(mem);
                 let pc = self.pop16(mem);
                 self.set_status_register(sr);
                 self.state.program_counter = pc;
+                self.wait_cycles = 6;
                 return Ok((
                     format!("RTI"),
                     None
                 ));
             }
+            0x41 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let operand = mem.read(addr);
+                self.exclusive_or_with_accumulator(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("EOR (${:02X},X)", base_addr),
+                    None
+                ));
+            }
+            0x43 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let effect = self.shift_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.exclusive_or_with_accumulator(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 8;
+                return Ok((
+                    format!("SRE (${:02X},X)", base_addr),
+                    Some(effect)
+                ));
+            }
+            0x44 => {
+                let addr = self.read_zeropage_addr(mem);
+                mem.read(addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 3;
+                return Ok((
+                    format!("NOP ${:02X}", addr),
+                    None
+                ));
+            }
             0x45 => {
                 let addr = self.read_zeropage_addr(mem);
                 let operand = mem.read(addr);
@@ -489,7 +1281,7 @@ impl Mos6510 {
                 self.set_negative_flag(value);
                 self.set_zero_flag(value);
                 self.state.program_counter += 2;
-                self.wait_cycles = 2;
+                self.wait_cycles = 3;
                 return Ok((
                     format!("EOR ${:02X}", addr),
                     None
@@ -510,6 +1302,18 @@ impl Mos6510 {
                     Some(Effect::WriteMem { addr, value })
                 ));
             }
+            0x47 => {
+                let addr = self.read_zeropage_addr(mem);
+                let effect = self.shift_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.exclusive_or_with_accumulator(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 5;
+                return Ok((
+                    format!("SRE ${:02X}", addr),
+                    Some(effect)
+                ));
+            }
             0x48 => {
                 let value = self.state.accumulator;
                 self.push8(mem, value);
@@ -546,6 +1350,20 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x4B => {
+                let operand = self.read_immediate(mem);
+                let value = self.state.accumulator & operand;
+                self.state.status_register.carry_flag = value & 1 > 0;
+                self.state.accumulator = value >> 1;
+                self.set_negative_flag(value >> 1);
+                self.set_zero_flag(value >> 1);
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("ALR #${:02X}", operand),
+                    None
+                ));
+            }
             0x4C => {
                 let addr = self.read_absolute_addr(mem);
                 self.state.program_counter = addr;
@@ -555,6 +1373,97 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x4D => {
+                let addr = self.read_absolute_addr(mem);
+                let operand = mem.read(addr);
+                self.exclusive_or_with_accumulator(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("EOR ${:04X}", addr),
+                    None
+                ));
+            }
+            0x4E => {
+                let addr = self.read_absolute_addr(mem);
+                let effect = self.shift_right_memory(mem, addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("LSR ${:04X}", addr),
+                    Some(effect)
+                ));
+            }
+            0x4F => {
+                let addr = self.read_absolute_addr(mem);
+                let effect = self.shift_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.exclusive_or_with_accumulator(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("SRE ${:04X}", addr),
+                    Some(effect)
+                ));
+            }
+            0x50 => {
+                let addr = self.read_relative_addr(mem) + 2;
+                if self.state.status_register.overflow_flag == false {
+                    self.wait_cycles = if same_page(self.state.program_counter + 2, addr) { 3 } else { 4 };
+                    self.state.program_counter = addr;
+                } else {
+                    self.state.program_counter += 2;
+                    self.wait_cycles = 2;
+                }
+                return Ok((
+                    format!("BVC ${:04X}", addr),
+                    None
+                ));
+            }
+            0x51 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.exclusive_or_with_accumulator(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = if same_page(addr.wrapping_sub(self.state.index_y as u16), addr) { 5 } else { 6 };
+                return Ok((
+                    format!("EOR (${:02X}),Y", vector_addr),
+                    None
+                ));
+            }
+            0x53 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let effect = self.shift_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.exclusive_or_with_accumulator(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 8;
+                return Ok((
+                    format!("SRE (${:02X}),Y", vector_addr),
+                    Some(effect)
+                ));
+            }
+            0x54 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                mem.read(addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("NOP ${:02X},X", base_addr),
+                    None
+                ));
+            }
+            0x55 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let operand = mem.read(addr);
+                self.exclusive_or_with_accumulator(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("EOR ${:02X},X", base_addr),
+                    None
+                ));
+            }
             0x56 => {
                 let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
                 let operand = mem.read(addr);
@@ -564,12 +1473,24 @@ impl Mos6510 {
                 self.set_zero_flag(value);
                 self.state.status_register.carry_flag = operand & 1 > 0;
                 self.state.program_counter += 2;
-                self.wait_cycles = 5;
+                self.wait_cycles = 6;
                 return Ok((
                     format!("LSR ${:02X},X", base_addr),
                     Some(Effect::WriteMem { addr, value })
                 ));
             }
+            0x57 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let effect = self.shift_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.exclusive_or_with_accumulator(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("SRE ${:02X},X", base_addr),
+                    Some(effect)
+                ));
+            }
             0x58 => {
                 self.state.status_register.interrupt_disable_flag = false;
                 self.state.program_counter += 1;
@@ -579,6 +1500,80 @@ impl Mos6510 {
                     None
                 ))
             }
+            0x59 => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.exclusive_or_with_accumulator(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("EOR ${:04X},Y", abs_addr),
+                    None
+                ));
+            }
+            0x5A => {
+                self.state.program_counter += 1;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("NOP"),
+                    None
+                ));
+            }
+            0x5B => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let effect = self.shift_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.exclusive_or_with_accumulator(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("SRE ${:04X},Y", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0x5C => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                mem.read(addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("NOP ${:04X},X", abs_addr),
+                    None
+                ));
+            }
+            0x5D => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.exclusive_or_with_accumulator(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("EOR ${:04X},X", abs_addr),
+                    None
+                ));
+            }
+            0x5E => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let effect = self.shift_right_memory(mem, addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("LSR ${:04X},X", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0x5F => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let effect = self.shift_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.exclusive_or_with_accumulator(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("SRE ${:04X},X", abs_addr),
+                    Some(effect)
+                ));
+            }
             0x60 => {
                 self.state.program_counter = self.pop16(mem) + 1;
                 self.wait_cycles = 6;
@@ -587,29 +1582,77 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x61 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let operand = mem.read(addr);
+                self.add_with_carry(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("ADC (${:02X},X)", base_addr),
+                    None
+                ));
+            }
+            0x63 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let effect = self.rotate_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.add_with_carry(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 8;
+                return Ok((
+                    format!("RRA (${:02X},X)", base_addr),
+                    Some(effect)
+                ));
+            }
+            0x64 => {
+                let addr = self.read_zeropage_addr(mem);
+                mem.read(addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 3;
+                return Ok((
+                    format!("NOP ${:02X}", addr),
+                    None
+                ));
+            }
             0x65 => {
                 let addr = self.read_zeropage_addr(mem);
                 let operand = mem.read(addr);
                 self.add_with_carry(operand);
                 self.state.program_counter += 2;
-                self.wait_cycles = 2;
+                self.wait_cycles = 3;
+                return Ok((
+                    format!("ADC ${:02X}", addr),
+                    None
+                ));
+            }
+            0x66 => {
+                let addr = self.read_zeropage_addr(mem);
+                let effect = self.rotate_right_memory(mem, addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 5;
                 return Ok((
-                    format!("ADC ${:02X}", addr),
-                    None
+                    format!("ROR ${:02X}", addr),
+                    Some(effect)
                 ));
             }
-            0x66 => {
+            0x67 => {
                 let addr = self.read_zeropage_addr(mem);
                 let effect = self.rotate_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.add_with_carry(value);
                 self.state.program_counter += 2;
                 self.wait_cycles = 5;
                 return Ok((
-                    format!("ROR ${:02X}", addr),
+                    format!("RRA ${:02X}", addr),
                     Some(effect)
                 ));
             }
             0x68 => {
-                self.state.accumulator = self.pop8(mem);
+                let value = self.pop8(mem);
+                self.state.accumulator = value;
+                self.set_negative_flag(value);
+                self.set_zero_flag(value);
                 self.state.program_counter += 1;
                 self.wait_cycles = 4;
                 return Ok((
@@ -641,10 +1684,45 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x6B => {
+                let operand = self.read_immediate(mem);
+                let carry = if self.state.status_register.carry_flag { 0x80 } else { 0 };
+                let and = self.state.accumulator & operand;
+                let value = and >> 1 | carry;
+                self.set_negative_flag(value);
+                self.set_zero_flag(value);
+                if self.state.status_register.decimal_mode_flag {
+                    // Each digit of the rotated value is adjusted as in ADC,
+                    // judged by the digits before the rotation, and C is the
+                    // carry out of the high digit
+                    self.state.status_register.overflow_flag = (value ^ and) & 0b0100_0000 > 0;
+                    let mut adjusted = value;
+                    if (and & 0x0F) + (and & 0x01) > 0x05 {
+                        adjusted = (adjusted & 0xF0) | (adjusted.wrapping_add(0x06) & 0x0F);
+                    }
+                    let high_carry = (and as u16 & 0xF0) + (and as u16 & 0x10) > 0x50;
+                    if high_carry {
+                        adjusted = (adjusted & 0x0F) | (adjusted.wrapping_add(0x60) & 0xF0);
+                    }
+                    self.state.status_register.carry_flag = high_carry;
+                    self.state.accumulator = adjusted;
+                } else {
+                    self.state.status_register.carry_flag = value & 0b0100_0000 > 0;
+                    self.state.status_register.overflow_flag = (value ^ (value << 1)) & 0b0100_0000 > 0;
+                    self.state.accumulator = value;
+                }
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("ARR #${:02X}", operand),
+                    None
+                ));
+            }
             0x6C => {
                 let vector_addr = self.read_absolute_addr(mem);
                 let vector_lo = mem.read(vector_addr);
-                let vector_hi = mem.read(vector_addr + 1);
+                // The high byte is read from the same page when the vector crosses a page boundary
+                let vector_hi = mem.read((vector_addr & 0xFF00) | (vector_addr.wrapping_add(1) & 0x00FF));
                 let addr = ((vector_hi as u16) << 8) + vector_lo as u16;
                 self.state.program_counter = addr;
                 self.wait_cycles = 5;
@@ -653,11 +1731,44 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x6D => {
+                let addr = self.read_absolute_addr(mem);
+                let operand = mem.read(addr);
+                self.add_with_carry(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("ADC ${:04X}", addr),
+                    None
+                ));
+            }
+            0x6E => {
+                let addr = self.read_absolute_addr(mem);
+                let effect = self.rotate_right_memory(mem, addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("ROR ${:04X}", addr),
+                    Some(effect)
+                ));
+            }
+            0x6F => {
+                let addr = self.read_absolute_addr(mem);
+                let effect = self.rotate_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.add_with_carry(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("RRA ${:04X}", addr),
+                    Some(effect)
+                ));
+            }
             0x70 => {
                 let addr = self.read_relative_addr(mem) + 2;
                 if self.state.status_register.overflow_flag {
+                    self.wait_cycles = if same_page(self.state.program_counter + 2, addr) { 3 } else { 4 };
                     self.state.program_counter = addr;
-                    self.wait_cycles = if same_page(self.state.program_counter, addr) { 3 } else { 4 };
                 } else {
                     self.state.program_counter += 2;
                     self.wait_cycles = 2;
@@ -667,6 +1778,50 @@ impl Mos6510 {
                     None
                 ));
             }
+            0x71 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.add_with_carry(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = if same_page(addr.wrapping_sub(self.state.index_y as u16), addr) { 5 } else { 6 };
+                return Ok((
+                    format!("ADC (${:02X}),Y", vector_addr),
+                    None
+                ));
+            }
+            0x73 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let effect = self.rotate_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.add_with_carry(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 8;
+                return Ok((
+                    format!("RRA (${:02X}),Y", vector_addr),
+                    Some(effect)
+                ));
+            }
+            0x74 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                mem.read(addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("NOP ${:02X},X", base_addr),
+                    None
+                ));
+            }
+            0x75 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let operand = mem.read(addr);
+                self.add_with_carry(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("ADC ${:02X},X", base_addr),
+                    None
+                ));
+            }
             0x76 => {
                 let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
                 let effect = self.rotate_right_memory(mem, addr);
@@ -677,6 +1832,18 @@ impl Mos6510 {
                     Some(effect)
                 ));
             }
+            0x77 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let effect = self.rotate_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.add_with_carry(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("RRA ${:02X},X", base_addr),
+                    Some(effect)
+                ));
+            }
             0x78 => {
                 self.state.status_register.interrupt_disable_flag = true;
                 self.state.program_counter += 1;
@@ -688,16 +1855,152 @@ impl Mos6510 {
             }
             0x79 => {
                 let abs_addr = self.read_absolute_addr(mem);
-                let addr = abs_addr + self.state.index_y as u16;
+                let addr = abs_addr.wrapping_add(self.state.index_y as u16);
                 let operand = mem.read(addr);
                 self.add_with_carry(operand);
                 self.state.program_counter += 3;
-                self.wait_cycles = if same_page(self.state.program_counter, addr) { 4 } else { 5 };
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
                 return Ok((
                     format!("ADC ${:04X},Y", abs_addr),
                     None
                 ));
             }
+            0x7A => {
+                self.state.program_counter += 1;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("NOP"),
+                    None
+                ));
+            }
+            0x7B => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let effect = self.rotate_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.add_with_carry(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("RRA ${:04X},Y", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0x7C => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                mem.read(addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("NOP ${:04X},X", abs_addr),
+                    None
+                ));
+            }
+            0x7D => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.add_with_carry(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("ADC ${:04X},X", abs_addr),
+                    None
+                ));
+            }
+            0x7E => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let effect = self.rotate_right_memory(mem, addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("ROR ${:04X},X", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0x7F => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let effect = self.rotate_right_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.add_with_carry(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("RRA ${:04X},X", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0x80 => {
+                let operand = self.read_immediate(mem);
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("NOP #${:02X}", operand),
+                    None
+                ));
+            }
+            0x81 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let value = self.state.accumulator;
+                mem.write(addr, value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("STA (${:02X},X)", base_addr),
+                    Some(Effect::WriteMem { addr, value })
+                ));
+            }
+            0x82 => {
+                let operand = self.read_immediate(mem);
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("NOP #${:02X}", operand),
+                    None
+                ));
+            }
+            0x83 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let value = self.state.accumulator & self.state.index_x;
+                mem.write(addr, value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("SAX (${:02X},X)", base_addr),
+                    Some(Effect::WriteMem { addr, value })
+                ));
+            }
+            0x87 => {
+                let addr = self.read_zeropage_addr(mem);
+                let value = self.state.accumulator & self.state.index_x;
+                mem.write(addr, value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 3;
+                return Ok((
+                    format!("SAX ${:02X}", addr),
+                    Some(Effect::WriteMem { addr, value })
+                ));
+            }
+            0x89 => {
+                let operand = self.read_immediate(mem);
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("NOP #${:02X}", operand),
+                    None
+                ));
+            }
+            0x8B => {
+                let operand = self.read_immediate(mem);
+                let value = (self.state.accumulator | 0xEE) & self.state.index_x & operand;
+                self.state.accumulator = value;
+                self.set_negative_flag(value);
+                self.set_zero_flag(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("ANE #${:02X}", operand),
+                    None
+                ));
+            }
             0x8D => {
                 let addr = self.read_absolute_addr(mem);
                 let value = self.state.accumulator;
@@ -788,11 +2091,22 @@ impl Mos6510 {
                     Some(Effect::WriteMem { addr, value })
                 ));
             }
+            0x8F => {
+                let addr = self.read_absolute_addr(mem);
+                let value = self.state.accumulator & self.state.index_x;
+                mem.write(addr, value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("SAX ${:04X}", addr),
+                    Some(Effect::WriteMem { addr, value })
+                ));
+            }
             0x90 => {
                 let addr = self.read_relative_addr(mem) + 2;
                 if self.state.status_register.carry_flag == false {
+                    self.wait_cycles = if same_page(self.state.program_counter + 2, addr) { 3 } else { 4 };
                     self.state.program_counter = addr;
-                    self.wait_cycles = if same_page(self.state.program_counter, addr) { 3 } else { 4 };
                 } else {
                     self.state.program_counter += 2;
                     self.wait_cycles = 2;
@@ -813,6 +2127,20 @@ impl Mos6510 {
                     Some(Effect::WriteMem { addr, value })
                 ));
             }
+            0x93 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let base_addr_hi = (addr.wrapping_sub(self.state.index_y as u16) >> 8) as u8;
+                let value = self.state.accumulator & self.state.index_x & base_addr_hi.wrapping_add(1);
+                // The stored value replaces the high byte of the address when indexing crosses a page
+                let addr = if (addr >> 8) as u8 == base_addr_hi { addr } else { ((value as u16) << 8) | (addr & 0xFF) };
+                mem.write(addr, value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("SHA (${:02X}),Y", vector_addr),
+                    Some(Effect::WriteMem { addr, value })
+                ));
+            }
             0x94 => {
                 let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
                 let value = self.state.index_y;
@@ -835,6 +2163,28 @@ impl Mos6510 {
                     Some(Effect::WriteMem { addr, value })
                 ));
             }
+            0x96 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_y(mem);
+                let value = self.state.index_x;
+                mem.write(addr, value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("STX ${:02X},Y", base_addr),
+                    Some(Effect::WriteMem { addr, value })
+                ));
+            }
+            0x97 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_y(mem);
+                let value = self.state.accumulator & self.state.index_x;
+                mem.write(addr, value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("SAX ${:02X},Y", base_addr),
+                    Some(Effect::WriteMem { addr, value })
+                ));
+            }
             0x98 => {
                 let value = self.state.index_y;
                 self.state.accumulator = value;
@@ -847,37 +2197,121 @@ impl Mos6510 {
                     None
                 ));
             }
-            0x99 => {
-                let abs_addr = self.read_absolute_addr(mem);
-                let addr = abs_addr + self.state.index_y as u16;
-                let value = self.state.accumulator;
+            0x99 => {
+                let abs_addr = self.read_absolute_addr(mem);
+                let addr = abs_addr.wrapping_add(self.state.index_y as u16);
+                let value = self.state.accumulator;
+                mem.write(addr, value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 5;
+                return Ok((
+                    format!("STA ${:04X},Y", abs_addr),
+                    Some(Effect::WriteMem { addr, value })
+                ));
+            }
+            0x9A => {
+                self.state.stack_pointer = self.state.index_x;
+                self.state.program_counter += 1;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("TXS"),
+                    None
+                ));
+            }
+            0x9B => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                self.state.stack_pointer = self.state.accumulator & self.state.index_x;
+                let base_addr_hi = (abs_addr >> 8) as u8;
+                let value = self.state.stack_pointer & base_addr_hi.wrapping_add(1);
+                // The stored value replaces the high byte of the address when indexing crosses a page
+                let addr = if (addr >> 8) as u8 == base_addr_hi { addr } else { ((value as u16) << 8) | (addr & 0xFF) };
+                mem.write(addr, value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 5;
+                return Ok((
+                    format!("TAS ${:04X},Y", abs_addr),
+                    Some(Effect::WriteMem { addr, value })
+                ));
+            }
+            0x9C => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let base_addr_hi = (abs_addr >> 8) as u8;
+                let value = self.state.index_y & base_addr_hi.wrapping_add(1);
+                // The stored value replaces the high byte of the address when indexing crosses a page
+                let addr = if (addr >> 8) as u8 == base_addr_hi { addr } else { ((value as u16) << 8) | (addr & 0xFF) };
+                mem.write(addr, value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 5;
+                return Ok((
+                    format!("SHY ${:04X},X", abs_addr),
+                    Some(Effect::WriteMem { addr, value })
+                ));
+            }
+            0x9D => {
+                let abs_addr = self.read_absolute_addr(mem);
+                let addr = abs_addr.wrapping_add(self.state.index_x as u16);
+                let value = self.state.accumulator;
+                mem.write(addr, value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 5;
+                return Ok((
+                    format!("STA ${:04X},X", abs_addr),
+                    Some(Effect::WriteMem { addr, value })
+                ));
+            }
+            0x9E => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let base_addr_hi = (abs_addr >> 8) as u8;
+                let value = self.state.index_x & base_addr_hi.wrapping_add(1);
+                // The stored value replaces the high byte of the address when indexing crosses a page
+                let addr = if (addr >> 8) as u8 == base_addr_hi { addr } else { ((value as u16) << 8) | (addr & 0xFF) };
+                mem.write(addr, value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 5;
+                return Ok((
+                    format!("SHX ${:04X},Y", abs_addr),
+                    Some(Effect::WriteMem { addr, value })
+                ));
+            }
+            0x9F => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let base_addr_hi = (abs_addr >> 8) as u8;
+                let value = self.state.accumulator & self.state.index_x & base_addr_hi.wrapping_add(1);
+                // The stored value replaces the high byte of the address when indexing crosses a page
+                let addr = if (addr >> 8) as u8 == base_addr_hi { addr } else { ((value as u16) << 8) | (addr & 0xFF) };
                 mem.write(addr, value);
                 self.state.program_counter += 3;
                 self.wait_cycles = 5;
                 return Ok((
-                    format!("STA ${:04X},Y", abs_addr),
+                    format!("SHA ${:04X},Y", abs_addr),
                     Some(Effect::WriteMem { addr, value })
                 ));
             }
-            0x9A => {
-                self.state.stack_pointer = self.state.index_x;
-                self.state.program_counter += 1;
-                self.wait_cycles = 2;
+            0xA1 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let operand = mem.read(addr);
+                self.state.accumulator = operand;
+                self.set_negative_flag(operand);
+                self.set_zero_flag(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
                 return Ok((
-                    format!("TXS"),
+                    format!("LDA (${:02X},X)", base_addr),
                     None
                 ));
             }
-            0x9D => {
-                let abs_addr = self.read_absolute_addr(mem);
-                let addr = abs_addr + self.state.index_x as u16;
-                let value = self.state.accumulator;
-                mem.write(addr, value);
-                self.state.program_counter += 3;
-                self.wait_cycles = 5;
+            0xA3 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let operand = mem.read(addr);
+                self.state.accumulator = operand;
+                self.state.index_x = operand;
+                self.set_negative_flag(operand);
+                self.set_zero_flag(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
                 return Ok((
-                    format!("STA ${:04X},X", addr),
-                    Some(Effect::WriteMem { addr, value })
+                    format!("LAX (${:02X},X)", base_addr),
+                    None
                 ));
             }
             0xA5 => {
@@ -893,6 +2327,20 @@ impl Mos6510 {
                     None
                 ));
             }
+            0xA7 => {
+                let addr = self.read_zeropage_addr(mem);
+                let operand = mem.read(addr);
+                self.state.accumulator = operand;
+                self.state.index_x = operand;
+                self.set_negative_flag(operand);
+                self.set_zero_flag(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 3;
+                return Ok((
+                    format!("LAX ${:02X}", addr),
+                    None
+                ));
+            }
             0xAA => {
                 let value = self.state.accumulator;
                 self.state.index_x = value;
@@ -979,6 +2427,20 @@ impl Mos6510 {
                     None
                 ));
             }
+            0xAB => {
+                let operand = self.read_immediate(mem);
+                let value = (self.state.accumulator | 0xEE) & operand;
+                self.state.accumulator = value;
+                self.state.index_x = value;
+                self.set_negative_flag(value);
+                self.set_zero_flag(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("LXA #${:02X}", operand),
+                    None
+                ));
+            }
             0xAC => {
                 let addr = self.read_absolute_addr(mem);
                 let value = mem.read(addr);
@@ -1018,11 +2480,25 @@ impl Mos6510 {
                     None
                 ));
             }
+            0xAF => {
+                let addr = self.read_absolute_addr(mem);
+                let operand = mem.read(addr);
+                self.state.accumulator = operand;
+                self.state.index_x = operand;
+                self.set_negative_flag(operand);
+                self.set_zero_flag(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("LAX ${:04X}", addr),
+                    None
+                ));
+            }
             0xB0 => {
                 let addr = self.read_relative_addr(mem) + 2;
                 if self.state.status_register.carry_flag {
+                    self.wait_cycles = if same_page(self.state.program_counter + 2, addr) { 3 } else { 4 };
                     self.state.program_counter = addr;
-                    self.wait_cycles = if same_page(self.state.program_counter, addr) { 3 } else { 4 };
                 } else {
                     self.state.program_counter += 2;
                     self.wait_cycles = 2;
@@ -1039,12 +2515,26 @@ impl Mos6510 {
                 self.set_negative_flag(value);
                 self.set_zero_flag(value);
                 self.state.program_counter += 2;
-                self.wait_cycles = if same_page(self.state.program_counter, addr) { 5 } else { 6 };
+                self.wait_cycles = if same_page(addr.wrapping_sub(self.state.index_y as u16), addr) { 5 } else { 6 };
                 return Ok((
                     format!("LDA (${:02X}),Y", vector_addr),
                     None
                 ));
             }
+            0xB3 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.state.accumulator = operand;
+                self.state.index_x = operand;
+                self.set_negative_flag(operand);
+                self.set_zero_flag(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = if same_page(addr.wrapping_sub(self.state.index_y as u16), addr) { 5 } else { 6 };
+                return Ok((
+                    format!("LAX (${:02X}),Y", vector_addr),
+                    None
+                ));
+            }
             0xB4 => {
                 let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
                 let value = mem.read(addr);
@@ -1071,15 +2561,51 @@ impl Mos6510 {
                     None
                 ));
             }
+            0xB6 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_y(mem);
+                let operand = mem.read(addr);
+                self.state.index_x = operand;
+                self.set_negative_flag(operand);
+                self.set_zero_flag(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("LDX ${:02X},Y", base_addr),
+                    None
+                ));
+            }
+            0xB7 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_y(mem);
+                let operand = mem.read(addr);
+                self.state.accumulator = operand;
+                self.state.index_x = operand;
+                self.set_negative_flag(operand);
+                self.set_zero_flag(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("LAX ${:02X},Y", base_addr),
+                    None
+                ));
+            }
+            0xB8 => {
+                self.state.status_register.overflow_flag = false;
+                self.state.program_counter += 1;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("CLV"),
+                    None
+                ));
+            }
             0xB9 => {
                 let abs_addr = self.read_absolute_addr(mem);
-                let addr = abs_addr + self.state.index_y as u16;
+                let addr = abs_addr.wrapping_add(self.state.index_y as u16);
                 let value = mem.read(addr);
                 self.state.accumulator = value;
                 self.set_negative_flag(value);
                 self.set_zero_flag(value);
                 self.state.program_counter += 3;
-                self.wait_cycles = if same_page(self.state.program_counter, addr) { 4 } else { 5 };
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
                 return Ok((
                     format!("LDA ${:04X},Y", abs_addr),
                     None
@@ -1097,20 +2623,76 @@ impl Mos6510 {
                     None
                 ));
             }
+            0xBB => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                let value = operand & self.state.stack_pointer;
+                self.state.accumulator = value;
+                self.state.index_x = value;
+                self.state.stack_pointer = value;
+                self.set_negative_flag(value);
+                self.set_zero_flag(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("LAS ${:04X},Y", abs_addr),
+                    None
+                ));
+            }
+            0xBC => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.state.index_y = operand;
+                self.set_negative_flag(operand);
+                self.set_zero_flag(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("LDY ${:04X},X", abs_addr),
+                    None
+                ));
+            }
             0xBD => {
                 let abs_addr = self.read_absolute_addr(mem);
-                let addr = abs_addr + self.state.index_x as u16;
+                let addr = abs_addr.wrapping_add(self.state.index_x as u16);
                 let value = mem.read(addr);
                 self.state.accumulator = value;
                 self.set_negative_flag(value);
                 self.set_zero_flag(value);
                 self.state.program_counter += 3;
-                self.wait_cycles = if same_page(self.state.program_counter, addr) { 4 } else { 5 };
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
                 return Ok((
                     format!("LDA ${:04X},X", abs_addr),
                     None
                 ));
             }
+            0xBE => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.state.index_x = operand;
+                self.set_negative_flag(operand);
+                self.set_zero_flag(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("LDX ${:04X},Y", abs_addr),
+                    None
+                ));
+            }
+            0xBF => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.state.accumulator = operand;
+                self.state.index_x = operand;
+                self.set_negative_flag(operand);
+                self.set_zero_flag(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("LAX ${:04X},Y", abs_addr),
+                    None
+                ));
+            }
             0xC0 => {
                 let operand1 = self.state.index_y;
                 let operand2 = self.read_immediate(mem);
@@ -1122,6 +2704,40 @@ impl Mos6510 {
                     None
                 ));
             }
+            0xC1 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let operand = mem.read(addr);
+                let accumulator = self.state.accumulator;
+                self.compare(accumulator, operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("CMP (${:02X},X)", base_addr),
+                    None
+                ));
+            }
+            0xC2 => {
+                let operand = self.read_immediate(mem);
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("NOP #${:02X}", operand),
+                    None
+                ));
+            }
+            0xC3 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let effect = self.decrement_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                let accumulator = self.state.accumulator;
+                self.compare(accumulator, value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 8;
+                return Ok((
+                    format!("DCP (${:02X},X)", base_addr),
+                    Some(effect)
+                ));
+            }
             0xC4 => {
                 let operand1 = self.state.index_y;
                 let addr = self.read_zeropage_addr(mem);
@@ -1150,12 +2766,25 @@ impl Mos6510 {
                 let addr = self.read_zeropage_addr(mem);
                 let effect = self.decrement_memory(mem, addr);
                 self.state.program_counter += 2;
-                self.wait_cycles = 6;
+                self.wait_cycles = 5;
                 return Ok((
                     format!("DEC ${:02X}", addr),
                     Some(effect)
                 ));
             }
+            0xC7 => {
+                let addr = self.read_zeropage_addr(mem);
+                let effect = self.decrement_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                let accumulator = self.state.accumulator;
+                self.compare(accumulator, value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 5;
+                return Ok((
+                    format!("DCP ${:02X}", addr),
+                    Some(effect)
+                ));
+            }
             0xC8 => {
                 let value = self.state.index_y.wrapping_add(1);
                 self.state.index_y = value;
@@ -1168,89 +2797,260 @@ impl Mos6510 {
                     None
                 ));
             }
-            0xC9 => {
-                let operand1 = self.state.accumulator;
-                let operand2 = self.read_immediate(mem);
-                self.compare(operand1, operand2);
+            0xC9 => {
+                let operand1 = self.state.accumulator;
+                let operand2 = self.read_immediate(mem);
+                self.compare(operand1, operand2);
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("CMP #${:02X}", operand2),
+                    None
+                ));
+            }
+            0xCA => {
+                let value = self.state.index_x.wrapping_sub(1);
+                self.state.index_x = value;
+                self.set_negative_flag(value);
+                self.set_zero_flag(value);
+                self.state.program_counter += 1;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("DEX"),
+                    None
+                ));
+            }
+            0xCB => {
+                let operand = self.read_immediate(mem);
+                let value = self.state.accumulator & self.state.index_x;
+                self.compare(value, operand);
+                self.state.index_x = value.wrapping_sub(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("SBX #${:02X}", operand),
+                    None
+                ));
+            }
+            0xCC => {
+                let addr = self.read_absolute_addr(mem);
+                let operand = mem.read(addr);
+                let index_y = self.state.index_y;
+                self.compare(index_y, operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("CPY ${:04X}", addr),
+                    None
+                ));
+            }
+            0xCD => {
+                let addr = self.read_absolute_addr(mem);
+                let operand1 = self.state.accumulator;
+                let operand2 = mem.read(addr);
+                self.compare(operand1, operand2);
+                self.state.program_counter += 3;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("CMP ${:04X}", addr),
+                    None
+                ));
+            }
+            0xCE => {
+                let addr = self.read_absolute_addr(mem);
+                let effect = self.decrement_memory(mem, addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("DEC ${:04X}", addr),
+                    Some(effect)
+                ));
+            }
+            0xCF => {
+                let addr = self.read_absolute_addr(mem);
+                let effect = self.decrement_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                let accumulator = self.state.accumulator;
+                self.compare(accumulator, value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("DCP ${:04X}", addr),
+                    Some(effect)
+                ));
+            }
+            0xD0 => {
+                let addr = self.read_relative_addr(mem) + 2;
+                if self.state.status_register.zero_flag == false {
+                    self.wait_cycles = if same_page(self.state.program_counter + 2, addr) { 3 } else { 4 };
+                    self.state.program_counter = addr;
+                } else {
+                    self.state.program_counter += 2;
+                    self.wait_cycles = 2;
+                }
+                return Ok((
+                    format!("BNE ${:04X}", addr),
+                    None
+                ));
+            }
+            0xD1 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let operand1 = self.state.accumulator;
+                let operand2 = mem.read(addr);
+                self.compare(operand1, operand2);
+                self.state.program_counter += 2;
+                self.wait_cycles = if same_page(addr.wrapping_sub(self.state.index_y as u16), addr) { 5 } else { 6 };
+                return Ok((
+                    format!("CMP (${:02X}),Y", vector_addr),
+                    None
+                ));
+            }
+            0xD3 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let effect = self.decrement_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                let accumulator = self.state.accumulator;
+                self.compare(accumulator, value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 8;
+                return Ok((
+                    format!("DCP (${:02X}),Y", vector_addr),
+                    Some(effect)
+                ));
+            }
+            0xD4 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                mem.read(addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("NOP ${:02X},X", base_addr),
+                    None
+                ));
+            }
+            0xD5 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let operand = mem.read(addr);
+                let accumulator = self.state.accumulator;
+                self.compare(accumulator, operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("CMP ${:02X},X", base_addr),
+                    None
+                ));
+            }
+            0xD6 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let effect = self.decrement_memory(mem, addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("DEC ${:02X},X", base_addr),
+                    Some(effect)
+                ));
+            }
+            0xD7 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let effect = self.decrement_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                let accumulator = self.state.accumulator;
+                self.compare(accumulator, value);
                 self.state.program_counter += 2;
-                self.wait_cycles = 2;
+                self.wait_cycles = 6;
                 return Ok((
-                    format!("CMP #${:02X}", operand2),
-                    None
+                    format!("DCP ${:02X},X", base_addr),
+                    Some(effect)
                 ));
             }
-            0xCA => {
-                let value = self.state.index_x.wrapping_sub(1);
-                self.state.index_x = value;
-                self.set_negative_flag(value);
-                self.set_zero_flag(value);
+            0xD8 => {
+                self.state.status_register.decimal_mode_flag = false;
                 self.state.program_counter += 1;
                 self.wait_cycles = 2;
                 return Ok((
-                    format!("DEX"),
+                    format!("CLD"),
                     None
                 ));
             }
-            0xCD => {
-                let addr = self.read_absolute_addr(mem);
-                let operand1 = self.state.accumulator;
-                let operand2 = mem.read(addr);
-                self.compare(operand1, operand2);
+            0xD9 => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                let accumulator = self.state.accumulator;
+                self.compare(accumulator, operand);
                 self.state.program_counter += 3;
-                self.wait_cycles = 4;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
                 return Ok((
-                    format!("CMP ${:04X}", addr),
+                    format!("CMP ${:04X},Y", abs_addr),
                     None
                 ));
             }
-            0xD0 => {
-                let addr = self.read_relative_addr(mem) + 2;
-                if self.state.status_register.zero_flag == false {
-                    self.state.program_counter = addr;
-                    self.wait_cycles = if same_page(self.state.program_counter, addr) { 3 } else { 4 };
-                } else {
-                    self.state.program_counter += 2;
-                    self.wait_cycles = 2;
-                }
+            0xDA => {
+                self.state.program_counter += 1;
+                self.wait_cycles = 2;
                 return Ok((
-                    format!("BNE ${:04X}", addr),
+                    format!("NOP"),
                     None
                 ));
             }
-            0xD1 => {
-                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
-                let operand1 = self.state.accumulator;
-                let operand2 = mem.read(addr);
-                self.compare(operand1, operand2);
-                self.state.program_counter += 2;
-                self.wait_cycles = if same_page(self.state.program_counter, addr) { 5 } else { 6 };
+            0xDB => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let effect = self.decrement_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                let accumulator = self.state.accumulator;
+                self.compare(accumulator, value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
                 return Ok((
-                    format!("CMP (${:02X}),Y", vector_addr),
-                    None
+                    format!("DCP ${:04X},Y", abs_addr),
+                    Some(effect)
                 ));
             }
-            0xD8 => {
-                self.state.status_register.decimal_mode_flag = false;
-                self.state.program_counter += 1;
-                self.wait_cycles = 2;
+            0xDC => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                mem.read(addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
                 return Ok((
-                    format!("CLD"),
+                    format!("NOP ${:04X},X", abs_addr),
                     None
                 ));
             }
             0xDD => {
                 let abs_addr = self.read_absolute_addr(mem);
-                let addr = abs_addr + self.state.index_x as u16;
+                let addr = abs_addr.wrapping_add(self.state.index_x as u16);
                 let operand1 = self.state.accumulator;
                 let operand2 = mem.read(addr);
                 self.compare(operand1, operand2);
                 self.state.program_counter += 3;
-                self.wait_cycles = if same_page(self.state.program_counter, addr) { 4 } else { 5 };
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
                 return Ok((
                     format!("CMP ${:04X},X", abs_addr),
                     None
                 ));
             }
+            0xDE => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let effect = self.decrement_memory(mem, addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("DEC ${:04X},X", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0xDF => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let effect = self.decrement_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                let accumulator = self.state.accumulator;
+                self.compare(accumulator, value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("DCP ${:04X},X", abs_addr),
+                    Some(effect)
+                ));
+            }
             0xE0 => {
                 let operand1 = self.state.index_x;
                 let operand2 = self.read_immediate(mem);
@@ -1262,6 +3062,38 @@ impl Mos6510 {
                     None
                 ));
             }
+            0xE1 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let operand = mem.read(addr);
+                self.subtract_with_carry(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("SBC (${:02X},X)", base_addr),
+                    None
+                ));
+            }
+            0xE2 => {
+                let operand = self.read_immediate(mem);
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("NOP #${:02X}", operand),
+                    None
+                ));
+            }
+            0xE3 => {
+                let (base_addr, addr) = self.read_x_indexed_indirect_addr(mem);
+                let effect = self.increment_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.subtract_with_carry(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 8;
+                return Ok((
+                    format!("ISC (${:02X},X)", base_addr),
+                    Some(effect)
+                ));
+            }
             0xE4 => {
                 let addr = self.read_zeropage_addr(mem);
                 let operand1 = self.state.index_x;
@@ -1287,7 +3119,7 @@ impl Mos6510 {
             }
             0xE6 => {
                 let addr = self.read_zeropage_addr(mem);
-                let value = mem.read(addr) + 1;
+                let value = mem.read(addr).wrapping_add(1);
                 mem.write(addr, value);
                 self.set_negative_flag(value);
                 self.set_zero_flag(value);
@@ -1298,7 +3130,19 @@ impl Mos6510 {
                     Some(Effect::WriteMem { addr, value })
                 ));
             }
-                0xE8 => {
+            0xE7 => {
+                let addr = self.read_zeropage_addr(mem);
+                let effect = self.increment_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.subtract_with_carry(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 5;
+                return Ok((
+                    format!("ISC ${:02X}", addr),
+                    Some(effect)
+                ));
+            }
+            0xE8 => {
                 let value = self.state.index_x.wrapping_add(1);
                 self.state.index_x = value;
                 self.set_negative_flag(value);
@@ -1320,6 +3164,24 @@ impl Mos6510 {
                     None
                 ));
             }
+            0xEA => {
+                self.state.program_counter += 1;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("NOP"),
+                    None
+                ));
+            }
+            0xEB => {
+                let operand = self.read_immediate(mem);
+                self.subtract_with_carry(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("SBC #${:02X}", operand),
+                    None
+                ));
+            }
             0xEC => {
                 let addr = self.read_absolute_addr(mem);
                 let operand1 = self.state.index_x;
@@ -1332,11 +3194,44 @@ impl Mos6510 {
                     None
                 ));
             }
+            0xED => {
+                let addr = self.read_absolute_addr(mem);
+                let operand = mem.read(addr);
+                self.subtract_with_carry(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("SBC ${:04X}", addr),
+                    None
+                ));
+            }
+            0xEE => {
+                let addr = self.read_absolute_addr(mem);
+                let effect = self.increment_memory(mem, addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("INC ${:04X}", addr),
+                    Some(effect)
+                ));
+            }
+            0xEF => {
+                let addr = self.read_absolute_addr(mem);
+                let effect = self.increment_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.subtract_with_carry(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("ISC ${:04X}", addr),
+                    Some(effect)
+                ));
+            }
             0xF0 => {
                 let addr = self.read_relative_addr(mem) + 2;
                 if self.state.status_register.zero_flag {
+                    self.wait_cycles = if same_page(self.state.program_counter + 2, addr) { 3 } else { 4 };
                     self.state.program_counter = addr;
-                    self.wait_cycles = if same_page(self.state.program_counter, addr) { 3 } else { 4 };
                 } else {
                     self.state.program_counter += 2;
                     self.wait_cycles = 2;
@@ -1346,10 +3241,327 @@ impl Mos6510 {
                     None
                 ));
             }
+            0xF1 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.subtract_with_carry(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = if same_page(addr.wrapping_sub(self.state.index_y as u16), addr) { 5 } else { 6 };
+                return Ok((
+                    format!("SBC (${:02X}),Y", vector_addr),
+                    None
+                ));
+            }
+            0xF3 => {
+                let (vector_addr, addr) = self.read_indirect_y_indexed_addr(mem);
+                let effect = self.increment_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.subtract_with_carry(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 8;
+                return Ok((
+                    format!("ISC (${:02X}),Y", vector_addr),
+                    Some(effect)
+                ));
+            }
+            0xF4 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                mem.read(addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("NOP ${:02X},X", base_addr),
+                    None
+                ));
+            }
+            0xF5 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let operand = mem.read(addr);
+                self.subtract_with_carry(operand);
+                self.state.program_counter += 2;
+                self.wait_cycles = 4;
+                return Ok((
+                    format!("SBC ${:02X},X", base_addr),
+                    None
+                ));
+            }
+            0xF6 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let effect = self.increment_memory(mem, addr);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("INC ${:02X},X", base_addr),
+                    Some(effect)
+                ));
+            }
+            0xF7 => {
+                let (base_addr, addr) = self.read_indexed_zeropage_x(mem);
+                let effect = self.increment_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.subtract_with_carry(value);
+                self.state.program_counter += 2;
+                self.wait_cycles = 6;
+                return Ok((
+                    format!("ISC ${:02X},X", base_addr),
+                    Some(effect)
+                ));
+            }
+            0xF8 => {
+                self.state.status_register.decimal_mode_flag = true;
+                self.state.program_counter += 1;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("SED"),
+                    None
+                ));
+            }
+            0xF9 => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.subtract_with_carry(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("SBC ${:04X},Y", abs_addr),
+                    None
+                ));
+            }
+            0xFA => {
+                self.state.program_counter += 1;
+                self.wait_cycles = 2;
+                return Ok((
+                    format!("NOP"),
+                    None
+                ));
+            }
+            0xFB => {
+                let (abs_addr, addr) = self.read_absolute_y_indexed_addr(mem);
+                let effect = self.increment_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.subtract_with_carry(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("ISC ${:04X},Y", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0xFC => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                mem.read(addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("NOP ${:04X},X", abs_addr),
+                    None
+                ));
+            }
+            0xFD => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let operand = mem.read(addr);
+                self.subtract_with_carry(operand);
+                self.state.program_counter += 3;
+                self.wait_cycles = if same_page(abs_addr, addr) { 4 } else { 5 };
+                return Ok((
+                    format!("SBC ${:04X},X", abs_addr),
+                    None
+                ));
+            }
+            0xFE => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let effect = self.increment_memory(mem, addr);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("INC ${:04X},X", abs_addr),
+                    Some(effect)
+                ));
+            }
+            0xFF => {
+                let (abs_addr, addr) = self.read_absolute_x_indexed_addr(mem);
+                let effect = self.increment_memory(mem, addr);
+                let value = match effect { Effect::WriteMem { value, .. } => value };
+                self.subtract_with_carry(value);
+                self.state.program_counter += 3;
+                self.wait_cycles = 7;
+                return Ok((
+                    format!("ISC ${:04X},X", abs_addr),
+                    Some(effect)
+                ));
+            }
             _ => {
                 let msg = format!("UNKNOWN OPCODE: 0x{:02X}", opcode);
                 return Err(msg);
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snapshot::Snapshot;
+
+    const PROGRAM_ADDR: u16 = 0x0200;
+
+    struct TestMemory {
+        ram: Vec<u8>
+    }
+
+    impl ReadView for TestMemory {
+        fn read(self: &mut TestMemory, addr: u16) -> u8 {
+            self.ram[addr as usize]
+        }
+    }
+
+    impl WriteView for TestMemory {
+        fn write(self: &mut TestMemory, addr: u16, value: u8) {
+            self.ram[addr as usize] = value;
+        }
+    }
+
+    // Runs a program to its end, with the status flags set as given
+    fn run(program: &[u8], status: u8, a: u8, x: u8, zeropage: &[(u16, u8)]) -> (Mos6510, TestMemory) {
+        let mut mem = TestMemory { ram: vec![0; 65536] };
+        mem.ram[PROGRAM_ADDR as usize..PROGRAM_ADDR as usize + program.len()].copy_from_slice(program);
+        for &(addr, value) in zeropage.iter() {
+            mem.ram[addr as usize] = value;
+        }
+        let mut cpu = Mos6510::new();
+        cpu.state.program_counter = PROGRAM_ADDR;
+        cpu.state.stack_pointer = 0xFF;
+        cpu.set_status_register(status);
+        cpu.state.accumulator = a;
+        cpu.state.index_x = x;
+        while cpu.state.program_counter < PROGRAM_ADDR + program.len() as u16 {
+            cpu.run_instruction(&mut mem).unwrap();
+        }
+        (cpu, mem)
+    }
+
+    const C: u8 = 0b0000_0001;
+    const Z: u8 = 0b0000_0010;
+    const D: u8 = 0b0000_1000;
+    const V: u8 = 0b0100_0000;
+    const N: u8 = 0b1000_0000;
+
+    // Accumulator and the N, V, Z and C flags after an immediate operation
+    fn immediate(opcode: u8, status: u8, a: u8, operand: u8) -> (u8, u8) {
+        let (cpu, _) = run(&[opcode, operand], status, a, 0, &[]);
+        (cpu.state.accumulator, cpu.status_register_value() & (N | V | Z | C))
+    }
+
+    #[test]
+    fn adds_in_decimal_mode() {
+        assert_eq!(immediate(0x69, D, 0x09, 0x01), (0x10, 0));
+        assert_eq!(immediate(0x69, D, 0x58, 0x46), (0x04, N | V | C));
+        assert_eq!(immediate(0x69, D | C, 0x12, 0x34), (0x47, 0));
+        // N and V come from the result before the high digit is adjusted,
+        // Z from the binary sum
+        assert_eq!(immediate(0x69, D | C, 0x79, 0x00), (0x80, N | V));
+        assert_eq!(immediate(0x69, D | C, 0x99, 0x00), (0x00, N | C));
+        assert_eq!(immediate(0x69, D, 0x50, 0x50), (0x00, N | V | C));
+    }
+
+    #[test]
+    fn subtracts_in_decimal_mode() {
+        assert_eq!(immediate(0xE9, D | C, 0x46, 0x12), (0x34, C));
+        assert_eq!(immediate(0xE9, D | C, 0x40, 0x13), (0x27, C));
+        assert_eq!(immediate(0xE9, D, 0x32, 0x02), (0x29, C));
+        assert_eq!(immediate(0xE9, D | C, 0x12, 0x21), (0x91, N));
+        assert_eq!(immediate(0xE9, D | C, 0x00, 0x01), (0x99, N));
+        // The flags come from the binary difference
+        assert_eq!(immediate(0xE9, D | C, 0x21, 0x21), (0x00, Z | C));
+    }
+
+    #[test]
+    fn adds_and_subtracts_in_binary_mode() {
+        assert_eq!(immediate(0x69, 0, 0x7F, 0x01), (0x80, N | V));
+        assert_eq!(immediate(0x69, C, 0xFF, 0x00), (0x00, Z | C));
+        assert_eq!(immediate(0xE9, C, 0x80, 0x01), (0x7F, V | C));
+        assert_eq!(immediate(0xE9, 0, 0x00, 0x00), (0xFF, N));
+    }
+
+    #[test]
+    fn runs_undocumented_immediate_opcodes() {
+        // ANC
+        assert_eq!(immediate(0x0B, 0, 0xFF, 0x80), (0x80, N | C));
+        // ALR
+        assert_eq!(immediate(0x4B, 0, 0xFF, 0x03), (0x01, C));
+        // ARR takes C from bit 6 and V from bits 6 and 5
+        assert_eq!(immediate(0x6B, C, 0xC0, 0xFF), (0xE0, N | C));
+        assert_eq!(immediate(0x6B, 0, 0x40, 0xFF), (0x20, V));
+        // In decimal mode N and Z come from the rotated value, V from bit 6
+        // changing, and the digits are adjusted by those of A AND operand
+        assert_eq!(immediate(0x6B, D, 0xFF, 0xFF), (0xD5, C));
+        assert_eq!(immediate(0x6B, D | C, 0x04, 0xFF), (0x82, N));
+        assert_eq!(immediate(0x6B, D, 0x06, 0xFF), (0x09, 0));
+        assert_eq!(immediate(0x6B, D, 0x40, 0xFF), (0x20, V));
+        assert_eq!(immediate(0x6B, D, 0x01, 0xFF), (0x00, Z));
+        // SBX
+        let (cpu, _) = run(&[0xCB, 0x02], 0, 0x0F, 0xF3, &[]);
+        assert_eq!((cpu.state.accumulator, cpu.state.index_x), (0x0F, 0x01));
+        assert!(cpu.state.status_register.carry_flag);
+    }
+
+    #[test]
+    fn runs_undocumented_read_modify_write_opcodes() {
+        // SLO shifts left and ORs the result into A
+        let (cpu, mem) = run(&[0x07, 0x10], 0, 0x01, 0, &[(0x10, 0x81)]);
+        assert_eq!((mem.ram[0x10], cpu.state.accumulator), (0x02, 0x03));
+        assert!(cpu.state.status_register.carry_flag);
+        // RLA rotates left and ANDs
+        let (cpu, mem) = run(&[0x27, 0x10], C, 0x0F, 0, &[(0x10, 0x84)]);
+        assert_eq!((mem.ram[0x10], cpu.state.accumulator), (0x09, 0x09));
+        assert!(cpu.state.status_register.carry_flag);
+        // SRE shifts right and EORs
+        let (cpu, mem) = run(&[0x47, 0x10], 0, 0xFF, 0, &[(0x10, 0x03)]);
+        assert_eq!((mem.ram[0x10], cpu.state.accumulator), (0x01, 0xFE));
+        assert!(cpu.state.status_register.carry_flag);
+        // RRA rotates right and adds with the carry rotated out
+        let (cpu, mem) = run(&[0x67, 0x10], C, 0x01, 0, &[(0x10, 0x02)]);
+        assert_eq!((mem.ram[0x10], cpu.state.accumulator), (0x81, 0x82));
+        assert!(!cpu.state.status_register.carry_flag);
+        // DCP decrements and compares
+        let (cpu, mem) = run(&[0xC7, 0x10], 0, 0x04, 0, &[(0x10, 0x05)]);
+        assert_eq!(mem.ram[0x10], 0x04);
+        assert_eq!(cpu.status_register_value() & (Z | C), Z | C);
+        // ISC increments and subtracts
+        let (cpu, mem) = run(&[0xE7, 0x10], C, 0x05, 0, &[(0x10, 0xFF)]);
+        assert_eq!((mem.ram[0x10], cpu.state.accumulator), (0x00, 0x05));
+        assert!(cpu.state.status_register.carry_flag);
+    }
+
+    #[test]
+    fn runs_undocumented_load_and_store_opcodes() {
+        let (cpu, _) = run(&[0xA7, 0x10], 0, 0, 0, &[(0x10, 0x80)]);
+        assert_eq!((cpu.state.accumulator, cpu.state.index_x), (0x80, 0x80));
+        assert!(cpu.state.status_register.negative_flag);
+        assert_eq!(cpu.wait_cycles, 3);
+        let (_, mem) = run(&[0x87, 0x10], 0, 0xF0, 0x3C, &[]);
+        assert_eq!(mem.ram[0x10], 0x30);
+        // Undocumented NOPs skip their operands
+        let (cpu, _) = run(&[0x0C, 0x34, 0x12, 0x80, 0x55, 0x1A], 0, 0x42, 0, &[]);
+        assert_eq!(cpu.state.accumulator, 0x42);
+        assert_eq!(cpu.state.program_counter, PROGRAM_ADDR + 6);
+    }
+
+    #[test]
+    fn saves_the_cycles_left_and_the_pending_interrupts() {
+        let (mut cpu, _) = run(&[0xA9, 0x42, 0xA2, 0x17], D | C, 0, 0, &[]);
+        cpu.wait_cycles = 3;
+        cpu.irq = true;
+        cpu.nmi_line = true;
+        cpu.nmi = true;
+        let mut snapshot = Snapshot::new();
+        cpu.save_state(snapshot.add("CPU"));
+        let mut loaded = Mos6510::new();
+        loaded.load_state(&mut snapshot.module("CPU").unwrap()).unwrap();
+        assert_eq!(loaded.state.program_counter, PROGRAM_ADDR + 4);
+        assert_eq!(loaded.state.stack_pointer, 0xFF);
+        assert_eq!(loaded.status_register_value(), cpu.status_register_value());
+        assert_eq!((loaded.state.accumulator, loaded.state.index_x), (0x42, 0x17));
+        assert_eq!(loaded.wait_cycles, 3);
+        assert!(loaded.irq && loaded.nmi_line && loaded.nmi);
+    }
+}
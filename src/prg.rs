@@ -0,0 +1,107 @@
+// Program files as written by the KERNAL SAVE routine: the load address,
+// low byte first, followed by the saved memory.
+
+use std::fs::File;
+use std::io::prelude::*;
+
+// Zero page pointers of BASIC: start of the program text, start of the
+// variables, start of the arrays and end of the arrays
+const TXTTAB: usize = 0x2B;
+const VARTAB: usize = 0x2D;
+const ARYTAB: usize = 0x2F;
+const STREND: usize = 0x31;
+// End address of the last LOAD
+const EAL: usize = 0xAE;
+
+const KEYBOARD_BUFFER: usize = 0x0277;
+const KEYBOARD_BUFFER_SIZE: usize = 10;
+const KEYBOARD_BUFFER_LENGTH: usize = 0xC6;
+
+/// What to do once a program has been loaded.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Autostart {
+    None,
+    Run,
+    Sys(u16)
+}
+
+pub struct Program {
+    load_address: u16,
+    data: Vec<u8>
+}
+
+impl Program {
+    pub fn load(filename: &str) -> Result<Program, String> {
+        let mut bytes = Vec::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|err| format!("Failed to read {}: {}", filename, err))?;
+        Program::parse(&bytes).map_err(|msg| format!("{}: {}", filename, msg))
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Program, String> {
+        if bytes.len() < 2 {
+            return Err("Missing load address".to_string());
+        }
+        let load_address = bytes[0] as u16 | (bytes[1] as u16) << 8;
+        if load_address as usize + bytes.len() - 2 > 0x10000 {
+            return Err(format!("Program at 0x{:04X} doesn't fit in memory", load_address));
+        }
+        Ok(Program {
+            load_address,
+            data: bytes[2..].to_vec()
+        })
+    }
+
+    pub fn load_address(self: &Program) -> u16 {
+        self.load_address
+    }
+
+    /// Address after the last byte of the program.
+    pub fn end_address(self: &Program) -> u16 {
+        (self.load_address as usize + self.data.len()) as u16
+    }
+
+    /// Copies the program to RAM and updates the pointers as LOAD does in
+    /// BASIC direct mode, then types the start command into the keyboard
+    /// buffer. Expects the KERNAL to be waiting for input at the READY
+    /// prompt.
+    pub fn install(self: &Program, ram: &mut [u8], autostart: Autostart) {
+        let load_address = self.load_address as usize;
+        ram[load_address..load_address + self.data.len()].copy_from_slice(&self.data);
+
+        let end = self.end_address();
+        for &pointer in &[VARTAB, ARYTAB, STREND, EAL] {
+            write_pointer(ram, pointer, end);
+        }
+        if autostart == Autostart::Run && load_address > 0 && load_address != read_pointer(ram, TXTTAB) as usize {
+            // BASIC needs a zero byte in front of the program text
+            write_pointer(ram, TXTTAB, self.load_address);
+            ram[load_address - 1] = 0;
+        }
+
+        match autostart {
+            Autostart::None => (),
+            Autostart::Run => type_keys(ram, "RUN\r"),
+            Autostart::Sys(addr) => type_keys(ram, &format!("SYS{}\r", addr))
+        }
+    }
+}
+
+fn read_pointer(ram: &[u8], pointer: usize) -> u16 {
+    ram[pointer] as u16 | (ram[pointer + 1] as u16) << 8
+}
+
+fn write_pointer(ram: &mut [u8], pointer: usize, value: u16) {
+    ram[pointer] = value as u8;
+    ram[pointer + 1] = (value >> 8) as u8;
+}
+
+// Puts text into the keyboard buffer, as if typed. Letters are unshifted
+// PETSCII, which has them at the same codes as uppercase ASCII.
+fn type_keys(ram: &mut [u8], text: &str) {
+    let keys = text.to_uppercase().into_bytes();
+    let length = keys.len().min(KEYBOARD_BUFFER_SIZE);
+    ram[KEYBOARD_BUFFER..KEYBOARD_BUFFER + length].copy_from_slice(&keys[..length]);
+    ram[KEYBOARD_BUFFER_LENGTH] = length as u8;
+}
@@ -0,0 +1,440 @@
+use std::fs::File;
+use std::io::prelude::*;
+
+use sid::SidModel;
+use vic_ii::{self, VideoStandard};
+
+const HEADER_SIZE_V1: usize = 0x76;
+const HEADER_SIZE_V2: usize = 0x7C;
+
+// Flags of version 2 and later headers
+const FLAG_MUS_DATA: u16 = 0b0000_0000_0000_0001;
+// RSID only: the tune is a BASIC program started with RUN
+const FLAG_BASIC: u16    = 0b0000_0000_0000_0010;
+
+// Rate of the KERNAL's CIA1 timer interrupt, used for CIA tunes which may
+// reprogram the timer in their init routine
+const CIA_TIMER_FREQUENCY: u32 = 60;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TuneFormat {
+    Psid,
+    Rsid
+}
+
+/// A tune in the PSID or RSID format of the High Voltage SID Collection.
+pub struct SidTune {
+    format: TuneFormat,
+    version: u16,
+    load_address: u16,
+    init_address: u16,
+    play_address: u16,
+    songs: u16,
+    start_song: u16,
+    speed: u32,
+    name: String,
+    author: String,
+    released: String,
+    flags: u16,
+    start_page: u8,
+    page_length: u8,
+    second_sid_address: Option<u16>,
+    third_sid_address: Option<u16>,
+    data: Vec<u8>
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    (bytes[offset] as u16) << 8 | bytes[offset + 1] as u16
+}
+
+fn read_string(bytes: &[u8], offset: usize) -> String {
+    bytes[offset..offset + 32].iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as char)
+        .collect()
+}
+
+// Extra SID chips are given as the middle byte of an address $Dxx0. Only
+// even values from $42 up are valid, so the chip doesn't overlap $D400.
+fn sid_address(value: u8) -> Option<u16> {
+    if value >= 0x42 && !(0x80..0xE0).contains(&value) && value & 1 == 0 {
+        Some(0xD000 | (value as u16) << 4)
+    } else {
+        None
+    }
+}
+
+// Memory configuration a PSID routine at the given address runs in
+fn bank_for(addr: u16) -> u8 {
+    if addr < 0xA000 {
+        0x37
+    } else if addr < 0xD000 {
+        0x36
+    } else if addr < 0xE000 {
+        0x34
+    } else {
+        0x35
+    }
+}
+
+// Model of a single chip, None for unknown or either
+fn model_from_bits(bits: u16) -> Option<SidModel> {
+    match bits & 0b11 {
+        1 => Some(SidModel::Mos6581),
+        2 => Some(SidModel::Mos8580),
+        _ => None
+    }
+}
+
+fn model_name(bits: u16, unknown: &'static str) -> &'static str {
+    match bits & 0b11 {
+        1 => "6581",
+        2 => "8580",
+        3 => "6581 and 8580",
+        _ => unknown
+    }
+}
+
+impl SidTune {
+    pub fn load(filename: &str) -> Result<SidTune, String> {
+        let mut bytes = Vec::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|err| format!("Failed to read {}: {}", filename, err))?;
+        SidTune::parse(&bytes)
+    }
+
+    fn parse(bytes: &[u8]) -> Result<SidTune, String> {
+        if bytes.len() < HEADER_SIZE_V1 {
+            return Err("Not a SID file: too short".to_string());
+        }
+        let format = match &bytes[0..4] {
+            b"PSID" => TuneFormat::Psid,
+            b"RSID" => TuneFormat::Rsid,
+            _ => return Err("Not a SID file: no PSID or RSID signature".to_string())
+        };
+        let version = read_u16(bytes, 0x04);
+        let data_offset = read_u16(bytes, 0x06) as usize;
+        match (format, version) {
+            (TuneFormat::Psid, 1..=4) | (TuneFormat::Rsid, 2..=4) => (),
+            _ => return Err(format!("Unsupported SID file version {}", version))
+        }
+        if data_offset < HEADER_SIZE_V1 || data_offset > bytes.len() || (version >= 2 && data_offset < HEADER_SIZE_V2) {
+            return Err(format!("Invalid data offset 0x{:04X}", data_offset));
+        }
+
+        let (flags, start_page, page_length) = if version >= 2 {
+            (read_u16(bytes, 0x76), bytes[0x78], bytes[0x79])
+        } else {
+            (0, 0, 0)
+        };
+        if flags & FLAG_MUS_DATA > 0 {
+            return Err("Compute!'s Sidplayer MUS data is not supported".to_string());
+        }
+
+        // A load address of zero means the data starts with one, as in a PRG file
+        let mut load_address = read_u16(bytes, 0x08);
+        let mut data = &bytes[data_offset..];
+        if load_address == 0 {
+            if data.len() < 2 {
+                return Err("No load address in the data".to_string());
+            }
+            load_address = data[0] as u16 | (data[1] as u16) << 8;
+            data = &data[2..];
+        }
+        if data.is_empty() || load_address as usize + data.len() > 0x10000 {
+            return Err(format!("Data doesn't fit in memory at 0x{:04X}", load_address));
+        }
+
+        let init_address = match read_u16(bytes, 0x0A) {
+            0 => load_address,
+            addr => addr
+        };
+        let songs = read_u16(bytes, 0x0E).max(1);
+        let start_song = match read_u16(bytes, 0x10) {
+            0 => 1,
+            song => song.min(songs)
+        };
+
+        Ok(SidTune {
+            format,
+            version,
+            load_address,
+            init_address,
+            play_address: read_u16(bytes, 0x0C),
+            songs,
+            start_song,
+            speed: (read_u16(bytes, 0x12) as u32) << 16 | read_u16(bytes, 0x14) as u32,
+            name: read_string(bytes, 0x16),
+            author: read_string(bytes, 0x36),
+            released: read_string(bytes, 0x56),
+            flags,
+            start_page,
+            page_length,
+            second_sid_address: if version >= 3 { sid_address(bytes[0x7A]) } else { None },
+            third_sid_address: if version >= 4 { sid_address(bytes[0x7B]) } else { None },
+            data: data.to_vec()
+        })
+    }
+
+    pub fn format(self: &SidTune) -> TuneFormat {
+        self.format
+    }
+
+    pub fn songs(self: &SidTune) -> u16 {
+        self.songs
+    }
+
+    pub fn start_song(self: &SidTune) -> u16 {
+        self.start_song
+    }
+
+    /// The SID model the tune was made for, if it names exactly one.
+    pub fn sid_model(self: &SidTune) -> Option<SidModel> {
+        model_from_bits(self.flags >> 4)
+    }
+
+    /// Addresses of the second and third SID of v3 and v4 tunes, with their
+    /// models if they differ from the first one.
+    pub fn extra_sids(self: &SidTune) -> Vec<(u16, Option<SidModel>)> {
+        let mut sids = Vec::new();
+        if let Some(addr) = self.second_sid_address {
+            sids.push((addr, model_from_bits(self.flags >> 6)));
+        }
+        if let Some(addr) = self.third_sid_address {
+            sids.push((addr, model_from_bits(self.flags >> 8)));
+        }
+        sids
+    }
+
+    fn end_address(self: &SidTune) -> u16 {
+        (self.load_address as usize + self.data.len()) as u16
+    }
+
+    // Songs from 32 on share the speed bit of song 32. RSID tunes always
+    // set up their own interrupts.
+    fn uses_cia_timer(self: &SidTune, song: u16) -> bool {
+        self.format == TuneFormat::Rsid || self.speed & 1 << (song - 1).min(31) > 0
+    }
+
+    // The machine the tune was made for, from the clock flags. Tunes for
+    // either or of unknown clock play at the speed of the machine.
+    fn standard(self: &SidTune, machine: VideoStandard) -> VideoStandard {
+        match (self.flags >> 2) & 0b11 {
+            1 => vic_ii::PAL,
+            2 => vic_ii::NTSC,
+            _ => machine
+        }
+    }
+
+    // CIA1 timer A value that calls the play routine of a VBI tune once per
+    // frame of the machine it was made for, in cycles of the given machine
+    fn vbi_timer(self: &SidTune, machine: VideoStandard) -> u16 {
+        let standard = self.standard(machine);
+        let cycles = standard.frame_cycles() as u64 * machine.clock_frequency as u64 / standard.clock_frequency as u64;
+        (cycles - 1) as u16
+    }
+
+    fn overlaps_data(self: &SidTune, start: u16, length: u16) -> bool {
+        let end = start as usize + length as usize;
+        (start as usize) < self.load_address as usize + self.data.len() && end > self.load_address as usize
+    }
+
+    // Address for the driver: the free page given in the header, or the
+    // tape buffer or a page of RAM below the BASIC ROM or at $C000 the tune
+    // doesn't use.
+    fn driver_address(self: &SidTune) -> u16 {
+        if self.version >= 2 && self.start_page != 0 && self.start_page != 0xFF && self.page_length > 0 {
+            return (self.start_page as u16) << 8;
+        }
+        let candidates = [0x0334].iter().cloned()
+            .chain((0x04..0xA0).chain(0xC0..0xD0).map(|page| page << 8));
+        for addr in candidates {
+            if !self.overlaps_data(addr, 0x100) {
+                return addr;
+            }
+        }
+        0x0334
+    }
+
+    // Machine code that calls the init routine for the song and then the
+    // play routine from a CIA1 timer interrupt, switching in the memory
+    // configuration each routine needs. RSID tunes get their init routine
+    // called in the environment the KERNAL left and handle the rest themselves.
+    fn driver(self: &SidTune, song: u16, base: u16, machine: VideoStandard) -> (Vec<u8>, u16) {
+        let cia_timer = ((machine.clock_frequency + CIA_TIMER_FREQUENCY / 2) / CIA_TIMER_FREQUENCY) as u16;
+        let vbi_timer = self.vbi_timer(machine);
+        let mut code: Vec<u8> = Vec::new();
+        let lo = |addr: u16| addr as u8;
+        let hi = |addr: u16| (addr >> 8) as u8;
+
+        // Interrupt handler, entered directly through the RAM vector at
+        // $FFFE or through the KERNAL which has already saved the registers
+        let ram_entry = base;
+        code.extend_from_slice(&[0x48, 0x8A, 0x48, 0x98, 0x48]);
+        let kernal_entry = base + code.len() as u16;
+        code.extend_from_slice(&[
+            0xA5, 0x01, 0x48,
+            0xA9, bank_for(self.play_address), 0x85, 0x01,
+            0x20, lo(self.play_address), hi(self.play_address),
+            0x68, 0x85, 0x01,
+            0xAD, 0x0D, 0xDC,
+            0x68, 0xA8, 0x68, 0xAA, 0x68,
+            0x40
+        ]);
+
+        let entry = base + code.len() as u16;
+        code.extend_from_slice(&[0xA2, 0xFF, 0x9A]);
+        if self.format == TuneFormat::Psid {
+            code.extend_from_slice(&[
+                0x78, 0xD8,
+                0xA9, 0x7F, 0x8D, 0x0D, 0xDC, 0x8D, 0x0D, 0xDD,
+                0xAD, 0x0D, 0xDC, 0xAD, 0x0D, 0xDD
+            ]);
+            if self.uses_cia_timer(song) {
+                code.extend_from_slice(&[
+                    0xA9, lo(cia_timer), 0x8D, 0x04, 0xDC,
+                    0xA9, hi(cia_timer), 0x8D, 0x05, 0xDC,
+                    0xA9, 0x11, 0x8D, 0x0E, 0xDC
+                ]);
+            }
+            code.extend_from_slice(&[0xA9, bank_for(self.init_address), 0x85, 0x01]);
+        }
+        code.extend_from_slice(&[
+            0xA9, (song - 1) as u8,
+            0x20, lo(self.init_address), hi(self.init_address)
+        ]);
+        if self.format == TuneFormat::Psid {
+            code.extend_from_slice(&[0xA9, 0x37, 0x85, 0x01]);
+            if !self.uses_cia_timer(song) {
+                code.extend_from_slice(&[
+                    0xA9, lo(vbi_timer), 0x8D, 0x04, 0xDC,
+                    0xA9, hi(vbi_timer), 0x8D, 0x05, 0xDC,
+                    0xA9, 0x11, 0x8D, 0x0E, 0xDC
+                ]);
+            }
+            // Without a play routine the init routine sets up its own interrupts
+            if self.play_address != 0 {
+                code.extend_from_slice(&[
+                    0xA9, 0x81, 0x8D, 0x0D, 0xDC,
+                    0xA9, lo(ram_entry), 0x8D, 0xFE, 0xFF,
+                    0xA9, hi(ram_entry), 0x8D, 0xFF, 0xFF,
+                    0xA9, lo(kernal_entry), 0x8D, 0x14, 0x03,
+                    0xA9, hi(kernal_entry), 0x8D, 0x15, 0x03
+                ]);
+            }
+            code.push(0x58);
+        }
+        let idle = base + code.len() as u16;
+        code.extend_from_slice(&[0x4C, lo(idle), hi(idle)]);
+        (code, entry)
+    }
+
+    /// Copies the tune to RAM and prepares the given song. Returns the
+    /// address the CPU should continue from, or None for BASIC tunes which
+    /// are started by typing RUN. Expects the KERNAL of the given machine to
+    /// be waiting for input at the READY prompt.
+    pub fn install(self: &SidTune, ram: &mut [u8], song: u16, machine: VideoStandard) -> Option<u16> {
+        let load_address = self.load_address as usize;
+        ram[load_address..load_address + self.data.len()].copy_from_slice(&self.data);
+
+        if self.format == TuneFormat::Rsid && self.flags & FLAG_BASIC > 0 {
+            // End of the program and start of the variables
+            let end = self.end_address();
+            for &pointer in &[0x2D, 0x2F, 0x31, 0xAE] {
+                ram[pointer] = end as u8;
+                ram[pointer + 1] = (end >> 8) as u8;
+            }
+            // The song number is passed to BASIC tunes in $030C, the A
+            // register of SYS
+            ram[0x030C] = (song - 1) as u8;
+            // "RUN" and RETURN in the keyboard buffer
+            ram[0x0277..0x027B].copy_from_slice(&[0x52, 0x55, 0x4E, 0x0D]);
+            ram[0xC6] = 4;
+            None
+        } else {
+            let base = self.driver_address();
+            let (code, entry) = self.driver(song, base, machine);
+            ram[base as usize..base as usize + code.len()].copy_from_slice(&code);
+            Some(entry)
+        }
+    }
+
+    pub fn print_info(self: &SidTune, song: u16) {
+        let format = match self.format {
+            TuneFormat::Psid => "PSID",
+            TuneFormat::Rsid => "RSID"
+        };
+        println!("Name:      {}", self.name);
+        println!("Author:    {}", self.author);
+        println!("Released:  {}", self.released);
+        println!("Format:    {} v{}{}", format, self.version, if self.flags & FLAG_BASIC > 0 && self.format == TuneFormat::Rsid { ", BASIC" } else { "" });
+        println!("Song:      {}/{} (default {})", song, self.songs, self.start_song);
+        println!("Memory:    0x{:04X}-0x{:04X}  init: 0x{:04X}  play: 0x{:04X}",
+            self.load_address, self.end_address().wrapping_sub(1), self.init_address, self.play_address);
+        if self.format == TuneFormat::Psid {
+            println!("Speed:     {}", if self.uses_cia_timer(song) { "CIA" } else { "VBI" });
+        }
+        let clock = match (self.flags >> 2) & 0b11 {
+            1 => "PAL",
+            2 => "NTSC",
+            3 => "PAL and NTSC",
+            _ => "unknown"
+        };
+        println!("Clock:     {}", clock);
+        println!("SID model: {}", model_name(self.flags >> 4, "unknown"));
+        if let Some(addr) = self.second_sid_address {
+            println!("2nd SID:   0x{:04X} ({})", addr, model_name(self.flags >> 6, "same as the first"));
+        }
+        if let Some(addr) = self.third_sid_address {
+            println!("3rd SID:   0x{:04X} ({})", addr, model_name(self.flags >> 8, "same as the first"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A PSID v2 tune at $1000 with a VBI speed and the given clock flags
+    fn tune(clock: u16) -> SidTune {
+        let mut bytes = vec![0; HEADER_SIZE_V2];
+        bytes[0..4].copy_from_slice(b"PSID");
+        bytes[0x05] = 2;
+        bytes[0x07] = HEADER_SIZE_V2 as u8;
+        bytes[0x08] = 0x10;
+        bytes[0x0C] = 0x10;
+        bytes[0x0D] = 0x03;
+        bytes[0x0F] = 1;
+        bytes[0x77] = (clock << 2) as u8;
+        bytes.extend_from_slice(&[0x60, 0x60, 0x60, 0x60]);
+        SidTune::parse(&bytes).unwrap()
+    }
+
+    // The timer value the driver writes to $DC04/$DC05
+    fn driver_timer(tune: &SidTune, machine: VideoStandard) -> u16 {
+        let (code, _) = tune.driver(1, 0xC000, machine);
+        let store = code.windows(3).position(|bytes| bytes == [0x8D, 0x04, 0xDC]).unwrap();
+        let store_high = code.windows(3).position(|bytes| bytes == [0x8D, 0x05, 0xDC]).unwrap();
+        code[store - 1] as u16 | (code[store_high - 1] as u16) << 8
+    }
+
+    #[test]
+    fn calls_vbi_tunes_once_per_frame_of_their_machine() {
+        assert_eq!(driver_timer(&tune(1), vic_ii::PAL), 312 * 63 - 1);
+        assert_eq!(driver_timer(&tune(2), vic_ii::NTSC), 263 * 65 - 1);
+        assert_eq!(driver_timer(&tune(0), vic_ii::NTSC), 263 * 65 - 1);
+        assert_eq!(driver_timer(&tune(3), vic_ii::PAL), 312 * 63 - 1);
+        // An NTSC tune keeps its 60 Hz on a PAL machine and the other way round
+        assert_eq!(driver_timer(&tune(2), vic_ii::PAL), 16467);
+        assert_eq!(driver_timer(&tune(1), vic_ii::NTSC), 20402);
+    }
+
+    #[test]
+    fn starts_cia_tunes_with_the_kernal_timer() {
+        let mut cia_tune = tune(1);
+        cia_tune.speed = 1;
+        assert_eq!(driver_timer(&cia_tune, vic_ii::PAL), 0x4025);
+        assert_eq!(driver_timer(&cia_tune, vic_ii::NTSC), 0x4295);
+    }
+}
@@ -0,0 +1,466 @@
+// The RAM Expansion Units 1700, 1764 and 1750, with 128K, 256K and 512K,
+// and larger ones up to 16MB. The 8726 DMA controller has its registers at
+// $DF00-$DF0A, repeated every 32 bytes. It moves bytes between the C64 and
+// the expansion RAM one per cycle while the CPU is halted: stash copies to
+// the REU, fetch back to the C64, swap exchanges them in two cycles per
+// byte and verify compares them, stopping at the first difference.
+//
+// The address and length registers are written to shadow registers as
+// well, which autoload copies back once the transfer is done. Without the
+// immediate bit in the command, a transfer waits for a write to $FF00,
+// so that the KERNAL ROM can be switched out in between.
+
+use std::mem;
+
+pub const MIN_SIZE: usize = 128 * 1024;
+pub const MAX_SIZE: usize = 16 * 1024 * 1024;
+// The 8726 only has 19 address lines, bigger units add bank bits
+const ADDRESS_SPACE: usize = 512 * 1024;
+
+// Registers
+const STATUS: u16 = 0x00;
+const COMMAND: u16 = 0x01;
+const C64_ADDRESS_LOW: u16 = 0x02;
+const C64_ADDRESS_HIGH: u16 = 0x03;
+const REU_ADDRESS_LOW: u16 = 0x04;
+const REU_ADDRESS_HIGH: u16 = 0x05;
+const REU_BANK: u16 = 0x06;
+const LENGTH_LOW: u16 = 0x07;
+const LENGTH_HIGH: u16 = 0x08;
+const INTERRUPT_MASK: u16 = 0x09;
+const ADDRESS_CONTROL: u16 = 0x0A;
+const REGISTER_MASK: u16 = 0x1F;
+
+// Status bits, all but the size are cleared by reading
+const INTERRUPT_PENDING: u8 = 0x80;
+const END_OF_BLOCK: u8 = 0x40;
+const FAULT: u8 = 0x20;
+// Set for the units with 256K chips, the 1764 and up
+const SIZE_256K_CHIPS: u8 = 0x10;
+
+// Command bits
+const EXECUTE: u8 = 0x80;
+const AUTOLOAD: u8 = 0x20;
+const NO_FF00_TRIGGER: u8 = 0x10;
+const TRANSFER_TYPE: u8 = 0x03;
+
+// Interrupt mask bits
+const INTERRUPTS_ENABLED: u8 = 0x80;
+const END_OF_BLOCK_INTERRUPT: u8 = 0x40;
+const FAULT_INTERRUPT: u8 = 0x20;
+const UNUSED_MASK_BITS: u8 = 0x1F;
+
+// Address control bits
+const FIX_C64_ADDRESS: u8 = 0x80;
+const FIX_REU_ADDRESS: u8 = 0x40;
+const UNUSED_CONTROL_BITS: u8 = 0x3F;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum TransferType {
+    Stash,
+    Fetch,
+    Swap,
+    Verify
+}
+
+/// What the DMA controller does on the C64 bus in a cycle.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DmaAccess {
+    Read(u16),
+    Write(u16, u8)
+}
+
+// The address and length registers, in the working and the shadow set
+#[derive(Clone, Copy)]
+struct Addresses {
+    c64: u16,
+    reu: u32,
+    length: u16
+}
+
+pub struct Reu {
+    ram: Vec<u8>,
+    status: u8,
+    command: u8,
+    addresses: Addresses,
+    shadow: Addresses,
+    interrupt_mask: u8,
+    address_control: u8,
+    // Waiting for a write to $FF00
+    armed: bool,
+    active: bool,
+    // The byte read from the C64 in the first cycle of a swap
+    swap_byte: Option<u8>
+}
+
+impl Reu {
+    pub fn new(size: usize) -> Reu {
+        let addresses = Addresses { c64: 0, reu: 0, length: 0xFFFF };
+        Reu {
+            ram: vec![0; size],
+            status: 0,
+            command: NO_FF00_TRIGGER,
+            addresses,
+            shadow: addresses,
+            interrupt_mask: 0,
+            address_control: 0,
+            armed: false,
+            active: false,
+            swap_byte: None
+        }
+    }
+
+    /// Resets the registers, the RAM keeps its contents.
+    pub fn reset(self: &mut Reu) {
+        let ram = mem::take(&mut self.ram);
+        *self = Reu { ram, ..Reu::new(0) };
+    }
+
+    // The REU address counts through the address space of the 8726, or of
+    // the bank bits of a bigger unit. Beyond the installed RAM nothing
+    // answers.
+    fn address_mask(self: &Reu) -> u32 {
+        (self.ram.len().max(ADDRESS_SPACE) - 1) as u32
+    }
+
+    fn read_ram(self: &Reu, addr: u32) -> u8 {
+        self.ram.get(addr as usize).cloned().unwrap_or(0xFF)
+    }
+
+    fn write_ram(self: &mut Reu, addr: u32, value: u8) {
+        if let Some(byte) = self.ram.get_mut(addr as usize) {
+            *byte = value;
+        }
+    }
+
+    fn transfer_type(self: &Reu) -> TransferType {
+        match self.command & TRANSFER_TYPE {
+            0 => TransferType::Stash,
+            1 => TransferType::Fetch,
+            2 => TransferType::Swap,
+            _ => TransferType::Verify
+        }
+    }
+
+    fn update_interrupt(self: &mut Reu) {
+        if self.interrupt_mask & INTERRUPTS_ENABLED > 0
+            && ((self.interrupt_mask & END_OF_BLOCK_INTERRUPT > 0 && self.status & END_OF_BLOCK > 0)
+                || (self.interrupt_mask & FAULT_INTERRUPT > 0 && self.status & FAULT > 0)) {
+            self.status |= INTERRUPT_PENDING;
+        }
+    }
+
+    /// The level of the IRQ line, true while an interrupt is pending.
+    pub fn irq(self: &Reu) -> bool {
+        self.status & INTERRUPT_PENDING > 0
+    }
+
+    pub fn read(self: &mut Reu, addr: u16) -> u8 {
+        match addr & REGISTER_MASK {
+            STATUS => {
+                let value = self.status | if self.ram.len() > MIN_SIZE { SIZE_256K_CHIPS } else { 0 };
+                self.status = 0;
+                value
+            }
+            COMMAND => self.command,
+            C64_ADDRESS_LOW => self.addresses.c64 as u8,
+            C64_ADDRESS_HIGH => (self.addresses.c64 >> 8) as u8,
+            REU_ADDRESS_LOW => self.addresses.reu as u8,
+            REU_ADDRESS_HIGH => (self.addresses.reu >> 8) as u8,
+            // The bank bits the 8726 lacks read as 1
+            REU_BANK => (self.addresses.reu >> 16) as u8 | !(self.address_mask() >> 16) as u8,
+            LENGTH_LOW => self.addresses.length as u8,
+            LENGTH_HIGH => (self.addresses.length >> 8) as u8,
+            INTERRUPT_MASK => self.interrupt_mask | UNUSED_MASK_BITS,
+            ADDRESS_CONTROL => self.address_control | UNUSED_CONTROL_BITS,
+            _ => 0xFF
+        }
+    }
+
+    pub fn write(self: &mut Reu, addr: u16, value: u8) {
+        let value16 = value as u16;
+        let value32 = value as u32;
+        match addr & REGISTER_MASK {
+            COMMAND => {
+                self.command = value;
+                if value & EXECUTE > 0 {
+                    if value & NO_FF00_TRIGGER > 0 {
+                        self.active = true;
+                    } else {
+                        self.armed = true;
+                    }
+                }
+            }
+            C64_ADDRESS_LOW => self.shadow.c64 = self.shadow.c64 & 0xFF00 | value16,
+            C64_ADDRESS_HIGH => self.shadow.c64 = self.shadow.c64 & 0x00FF | value16 << 8,
+            REU_ADDRESS_LOW => self.shadow.reu = self.shadow.reu & !0x0000FF | value32,
+            REU_ADDRESS_HIGH => self.shadow.reu = self.shadow.reu & !0x00FF00 | value32 << 8,
+            REU_BANK => self.shadow.reu = (self.shadow.reu & 0x00FFFF | value32 << 16) & self.address_mask(),
+            LENGTH_LOW => self.shadow.length = self.shadow.length & 0xFF00 | value16,
+            LENGTH_HIGH => self.shadow.length = self.shadow.length & 0x00FF | value16 << 8,
+            INTERRUPT_MASK => {
+                self.interrupt_mask = value & !UNUSED_MASK_BITS;
+                self.update_interrupt();
+            }
+            ADDRESS_CONTROL => self.address_control = value & !UNUSED_CONTROL_BITS,
+            _ => {}
+        }
+        // Writing a byte of a register loads all of it from the shadow
+        match addr & REGISTER_MASK {
+            C64_ADDRESS_LOW | C64_ADDRESS_HIGH => self.addresses.c64 = self.shadow.c64,
+            REU_ADDRESS_LOW | REU_ADDRESS_HIGH | REU_BANK => self.addresses.reu = self.shadow.reu,
+            LENGTH_LOW | LENGTH_HIGH => self.addresses.length = self.shadow.length,
+            _ => {}
+        }
+    }
+
+    /// Starts the transfer armed by a command without the immediate bit.
+    pub fn write_ff00(self: &mut Reu) {
+        if self.armed {
+            self.armed = false;
+            self.active = true;
+        }
+    }
+
+    /// The access of the next DMA cycle, None when no transfer is running.
+    pub fn dma_access(self: &Reu) -> Option<DmaAccess> {
+        if !self.active {
+            return None;
+        }
+        let c64 = self.addresses.c64;
+        Some(match (self.transfer_type(), self.swap_byte) {
+            (TransferType::Fetch, _) | (TransferType::Swap, Some(_)) => DmaAccess::Write(c64, self.read_ram(self.addresses.reu)),
+            _ => DmaAccess::Read(c64)
+        })
+    }
+
+    /// Completes a DMA cycle with the byte read or written on the C64 bus.
+    pub fn dma_done(self: &mut Reu, value: u8) {
+        let reu = self.addresses.reu;
+        match self.transfer_type() {
+            TransferType::Stash => self.write_ram(reu, value),
+            TransferType::Fetch => {}
+            TransferType::Swap => match self.swap_byte.take() {
+                Some(c64_byte) => self.write_ram(reu, c64_byte),
+                None => {
+                    self.swap_byte = Some(value);
+                    return;
+                }
+            },
+            TransferType::Verify => {
+                if value != self.read_ram(reu) {
+                    self.status |= FAULT;
+                }
+            }
+        }
+        if self.address_control & FIX_C64_ADDRESS == 0 {
+            self.addresses.c64 = self.addresses.c64.wrapping_add(1);
+        }
+        if self.address_control & FIX_REU_ADDRESS == 0 {
+            self.addresses.reu = (reu + 1) & self.address_mask();
+        }
+        if self.addresses.length == 1 {
+            self.status |= END_OF_BLOCK;
+            self.finish();
+        } else {
+            // A length of 0 stands for 64K
+            self.addresses.length = self.addresses.length.wrapping_sub(1);
+            if self.status & FAULT > 0 {
+                self.finish();
+            }
+        }
+    }
+
+    fn finish(self: &mut Reu) {
+        self.active = false;
+        self.command = self.command & !EXECUTE | NO_FF00_TRIGGER;
+        if self.command & AUTOLOAD > 0 {
+            self.addresses = self.shadow;
+        }
+        self.update_interrupt();
+    }
+
+    pub fn print_status(self: &Reu) {
+        println!("REU with {}K", self.ram.len() / 1024);
+        println!("Status: 0x{:02X}  Command: 0x{:02X}  C64: 0x{:04X}  REU: 0x{:06X}  Length: 0x{:04X}  Interrupt mask: 0x{:02X}  Address control: 0x{:02X}",
+            self.status, self.command, self.addresses.c64, self.addresses.reu, self.addresses.length, self.interrupt_mask, self.address_control);
+        if self.active {
+            println!("{:?} in progress", self.transfer_type());
+        } else if self.armed {
+            println!("{:?} waiting for a write to $FF00", self.transfer_type());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sets up a transfer of the given length between $1000 and the REU
+    // address, with the interrupt mask and address control given
+    fn setup(reu: &mut Reu, reu_address: u32, length: u16, interrupt_mask: u8, address_control: u8) {
+        reu.write(0xDF02, 0x00);
+        reu.write(0xDF03, 0x10);
+        reu.write(0xDF04, reu_address as u8);
+        reu.write(0xDF05, (reu_address >> 8) as u8);
+        reu.write(0xDF06, (reu_address >> 16) as u8);
+        reu.write(0xDF07, length as u8);
+        reu.write(0xDF08, (length >> 8) as u8);
+        reu.write(0xDF09, interrupt_mask);
+        reu.write(0xDF0A, address_control);
+    }
+
+    // Runs the transfer against the C64 memory, returns the cycles taken
+    fn run(reu: &mut Reu, c64: &mut [u8]) -> usize {
+        let mut cycles = 0;
+        while let Some(access) = reu.dma_access() {
+            let value = match access {
+                DmaAccess::Read(addr) => c64[addr as usize],
+                DmaAccess::Write(addr, value) => {
+                    c64[addr as usize] = value;
+                    value
+                }
+            };
+            reu.dma_done(value);
+            cycles += 1;
+        }
+        cycles
+    }
+
+    fn machine() -> (Reu, Vec<u8>) {
+        let mut reu = Reu::new(MIN_SIZE);
+        for (i, byte) in reu.ram.iter_mut().enumerate() {
+            *byte = (i * 7) as u8;
+        }
+        let c64 = (0..0x10000).map(|i| (i * 3) as u8).collect();
+        (reu, c64)
+    }
+
+    // The registers from the C64 address to the length
+    fn addresses(reu: &mut Reu) -> Vec<u8> {
+        (0xDF02..0xDF09).map(|addr| reu.read(addr)).collect()
+    }
+
+    #[test]
+    fn stashes_to_the_reu() {
+        let (mut reu, mut c64) = machine();
+        setup(&mut reu, 0x1_2345, 0x100, 0, 0);
+        reu.write(0xDF01, EXECUTE | NO_FF00_TRIGGER);
+        assert_eq!(run(&mut reu, &mut c64), 0x100);
+        assert_eq!(&reu.ram[0x1_2345..0x1_2445], &c64[0x1000..0x1100]);
+        assert_eq!(reu.ram[0x1_2445], (0x1_2445 * 7) as u8);
+        assert_eq!(reu.read(0xDF00), END_OF_BLOCK);
+        assert_eq!(reu.read(0xDF00), 0);
+        assert_eq!(addresses(&mut reu), vec![0x00, 0x11, 0x45, 0x24, 0xF9, 0x01, 0x00]);
+        assert_eq!(reu.read(0xDF01), NO_FF00_TRIGGER);
+    }
+
+    #[test]
+    fn fetches_from_the_reu() {
+        let (mut reu, mut c64) = machine();
+        setup(&mut reu, 0x300, 0x20, 0, 0);
+        reu.write(0xDF01, EXECUTE | NO_FF00_TRIGGER | 1);
+        assert_eq!(run(&mut reu, &mut c64), 0x20);
+        assert_eq!(&c64[0x1000..0x1020], &reu.ram[0x300..0x320]);
+        assert_eq!(c64[0x1020], (0x1020 * 3) as u8);
+    }
+
+    #[test]
+    fn swaps_in_two_cycles_per_byte() {
+        let (mut reu, mut c64) = machine();
+        let (reu_before, c64_before) = (reu.ram[0x40..0x50].to_vec(), c64[0x1000..0x1010].to_vec());
+        setup(&mut reu, 0x40, 0x10, 0, 0);
+        reu.write(0xDF01, EXECUTE | NO_FF00_TRIGGER | 2);
+        assert_eq!(run(&mut reu, &mut c64), 0x20);
+        assert_eq!(&reu.ram[0x40..0x50], &c64_before[..]);
+        assert_eq!(&c64[0x1000..0x1010], &reu_before[..]);
+    }
+
+    #[test]
+    fn verifies_up_to_the_first_difference() {
+        let (mut reu, mut c64) = machine();
+        reu.ram[0x2000..0x2040].copy_from_slice(&c64[0x1000..0x1040]);
+        setup(&mut reu, 0x2000, 0x40, 0, 0);
+        reu.write(0xDF01, EXECUTE | NO_FF00_TRIGGER | 3);
+        assert_eq!(run(&mut reu, &mut c64), 0x40);
+        assert_eq!(reu.read(0xDF00), END_OF_BLOCK);
+
+        c64[0x1005] ^= 0xFF;
+        setup(&mut reu, 0x2000, 0x40, 0, 0);
+        reu.write(0xDF01, EXECUTE | NO_FF00_TRIGGER | 3);
+        assert_eq!(run(&mut reu, &mut c64), 6);
+        assert_eq!(reu.read(0xDF00), FAULT);
+        // The addresses point past the difference
+        assert_eq!(addresses(&mut reu), vec![0x06, 0x10, 0x06, 0x20, 0xF8, 0x3A, 0x00]);
+    }
+
+    #[test]
+    fn autoloads_the_registers_after_the_transfer() {
+        let (mut reu, mut c64) = machine();
+        setup(&mut reu, 0x1_0000, 0x80, 0, 0);
+        let before = addresses(&mut reu);
+        reu.write(0xDF01, EXECUTE | NO_FF00_TRIGGER | AUTOLOAD);
+        run(&mut reu, &mut c64);
+        assert_eq!(addresses(&mut reu), before);
+        // The next transfer stashes the same bytes again
+        c64[0x1000] = 0x42;
+        reu.write(0xDF01, EXECUTE | NO_FF00_TRIGGER | AUTOLOAD);
+        run(&mut reu, &mut c64);
+        assert_eq!(reu.ram[0x1_0000], 0x42);
+    }
+
+    #[test]
+    fn keeps_fixed_addresses() {
+        let (mut reu, mut c64) = machine();
+        // Fills the REU with the byte of one C64 address
+        setup(&mut reu, 0x500, 0x10, 0, FIX_C64_ADDRESS);
+        reu.write(0xDF01, EXECUTE | NO_FF00_TRIGGER);
+        run(&mut reu, &mut c64);
+        assert!(reu.ram[0x500..0x510].iter().all(|&byte| byte == c64[0x1000]));
+        assert_eq!((reu.read(0xDF02), reu.read(0xDF03), reu.read(0xDF04)), (0x00, 0x10, 0x10));
+        // Fills the C64 with one byte of the REU
+        setup(&mut reu, 0x600, 0x10, 0, FIX_REU_ADDRESS);
+        reu.write(0xDF01, EXECUTE | NO_FF00_TRIGGER | 1);
+        run(&mut reu, &mut c64);
+        assert!(c64[0x1000..0x1010].iter().all(|&byte| byte == reu.ram[0x600]));
+        assert_eq!(reu.read(0xDF0A), FIX_REU_ADDRESS | UNUSED_CONTROL_BITS);
+    }
+
+    #[test]
+    fn waits_for_a_write_to_ff00() {
+        let (mut reu, mut c64) = machine();
+        setup(&mut reu, 0, 0x10, 0, 0);
+        reu.write(0xDF01, EXECUTE);
+        assert_eq!(reu.dma_access(), None);
+        reu.write_ff00();
+        assert_eq!(reu.dma_access(), Some(DmaAccess::Read(0x1000)));
+        assert_eq!(run(&mut reu, &mut c64), 0x10);
+        // Only once
+        reu.write_ff00();
+        assert_eq!(reu.dma_access(), None);
+    }
+
+    #[test]
+    fn interrupts_at_the_end_of_the_block() {
+        let (mut reu, mut c64) = machine();
+        setup(&mut reu, 0, 0x10, 0, 0);
+        reu.write(0xDF01, EXECUTE | NO_FF00_TRIGGER);
+        run(&mut reu, &mut c64);
+        assert!(!reu.irq());
+        reu.read(0xDF00);
+
+        setup(&mut reu, 0, 0x10, INTERRUPTS_ENABLED | END_OF_BLOCK_INTERRUPT, 0);
+        reu.write(0xDF01, EXECUTE | NO_FF00_TRIGGER);
+        run(&mut reu, &mut c64);
+        assert!(reu.irq());
+        assert_eq!(reu.read(0xDF00), INTERRUPT_PENDING | END_OF_BLOCK);
+        assert!(!reu.irq());
+
+        // Enabling the interrupt after the transfer raises it as well
+        setup(&mut reu, 0, 0x10, 0, 0);
+        reu.write(0xDF01, EXECUTE | NO_FF00_TRIGGER);
+        run(&mut reu, &mut c64);
+        reu.write(0xDF09, INTERRUPTS_ENABLED | END_OF_BLOCK_INTERRUPT);
+        assert!(reu.irq());
+    }
+}
@@ -0,0 +1,311 @@
+// Rewinding. Every few frames a snapshot of the machine goes into a ring
+// buffer, stored as its difference to the snapshot before it: XORed with
+// it, which leaves zeros wherever the memory hasn't changed, and run-length
+// encoded. The oldest snapshot is kept whole. To go back to a cycle, the
+// machine is restored from the last snapshot taken before it and runs
+// forward to that cycle again, which brings it to the same state as the
+// emulation is deterministic. What comes from outside, the joysticks, is
+// logged by cycle and fed to it again on the way.
+
+use std::collections::VecDeque;
+
+// Longest runs of literal and repeated bytes in the encoding
+const MAX_LITERALS: usize = 128;
+const MAX_REPEATS: usize = 129;
+
+struct Entry {
+    cycle: u64,
+    // Run-length encoded, XORed with the snapshot before unless it is the oldest
+    data: Vec<u8>
+}
+
+// The control port lines from this cycle on
+struct Input {
+    cycle: u64,
+    ports: [u8; 2]
+}
+
+pub struct RewindBuffer {
+    // Frames between snapshots, 0 when rewinding is off
+    interval: u32,
+    capacity: usize,
+    frames: u32,
+    entries: VecDeque<Entry>,
+    // The newest snapshot as it is, for the difference to the next one
+    last: Vec<u8>,
+    // Changes of the input since the oldest snapshot
+    inputs: VecDeque<Input>
+}
+
+// A control byte n below 128 is followed by n + 1 literal bytes, from 128
+// on it is followed by a byte repeated n - 126 times
+fn push_literals(encoded: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        encoded.push((chunk.len() - 1) as u8);
+        encoded.extend_from_slice(chunk);
+    }
+}
+
+fn encode(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut literals_start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let run = bytes[i..].iter().take(MAX_REPEATS).take_while(|&&b| b == bytes[i]).count();
+        if run >= 2 {
+            push_literals(&mut encoded, &bytes[literals_start..i]);
+            encoded.push((run + 126) as u8);
+            encoded.push(bytes[i]);
+            i += run;
+            literals_start = i;
+        } else {
+            i += 1;
+        }
+    }
+    push_literals(&mut encoded, &bytes[literals_start..]);
+    encoded
+}
+
+fn decode(encoded: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < encoded.len() {
+        let control = encoded[i] as usize;
+        if control < MAX_LITERALS {
+            bytes.extend_from_slice(&encoded[i + 1..i + 2 + control]);
+            i += control + 2;
+        } else {
+            let length = bytes.len() + control - 126;
+            bytes.resize(length, encoded[i + 1]);
+            i += 2;
+        }
+    }
+    bytes
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b.iter()).map(|(x, y)| x ^ y).collect()
+}
+
+impl RewindBuffer {
+    pub fn new(interval: u32, capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            interval,
+            capacity,
+            // The first snapshot is taken right away
+            frames: interval,
+            entries: VecDeque::new(),
+            last: Vec::new(),
+            inputs: VecDeque::new()
+        }
+    }
+
+    pub fn enabled(self: &RewindBuffer) -> bool {
+        self.interval > 0
+    }
+
+    pub fn end_frame(self: &mut RewindBuffer) {
+        self.frames += 1;
+    }
+
+    pub fn snapshot_due(self: &RewindBuffer) -> bool {
+        self.enabled() && self.frames >= self.interval
+    }
+
+    /// Adds a snapshot taken at the given cycle, the oldest one makes room
+    /// if the buffer is full.
+    pub fn push(self: &mut RewindBuffer, cycle: u64, snapshot: Vec<u8>) {
+        self.frames = 0;
+        // Snapshots only differ in length if the format has changed
+        let data = if self.entries.is_empty() || snapshot.len() != self.last.len() {
+            self.entries.clear();
+            encode(&snapshot)
+        } else {
+            encode(&xor(&snapshot, &self.last))
+        };
+        self.entries.push_back(Entry { cycle, data });
+        self.last = snapshot;
+        if self.entries.len() > self.capacity {
+            // The second oldest snapshot becomes whole
+            let oldest = decode(&self.entries.pop_front().unwrap().data);
+            let second = xor(&oldest, &decode(&self.entries[0].data));
+            self.entries[0].data = encode(&second);
+            // The input before the oldest snapshot is needed up to the last change
+            let oldest_cycle = self.entries[0].cycle;
+            while self.inputs.len() > 1 && self.inputs[1].cycle <= oldest_cycle {
+                self.inputs.pop_front();
+            }
+        }
+    }
+
+    /// Logs the input of a cycle, if it has changed.
+    pub fn record_input(self: &mut RewindBuffer, cycle: u64, ports: [u8; 2]) {
+        if self.enabled() && self.inputs.back().map(|input| input.ports) != Some(ports) {
+            self.inputs.push_back(Input { cycle, ports });
+        }
+    }
+
+    /// The input logged for a cycle, for running forward again.
+    pub fn input_at(self: &RewindBuffer, cycle: u64) -> Option<[u8; 2]> {
+        match self.inputs.partition_point(|input| input.cycle <= cycle) {
+            0 => None,
+            count => Some(self.inputs[count - 1].ports)
+        }
+    }
+
+    /// Forgets the input from the cycle on, once the machine has run
+    /// forward to it again and goes on with new input.
+    pub fn truncate_inputs(self: &mut RewindBuffer, cycle: u64) {
+        let count = self.inputs.partition_point(|input| input.cycle < cycle);
+        self.inputs.truncate(count);
+    }
+
+    /// Forgets every snapshot, when the machine has been replaced by
+    /// another state.
+    pub fn clear(self: &mut RewindBuffer) {
+        self.entries.clear();
+        self.last.clear();
+        self.inputs.clear();
+        self.frames = self.interval;
+    }
+
+    /// Cycle of the oldest snapshot, the earliest one that can be reached.
+    pub fn oldest_cycle(self: &RewindBuffer) -> Option<u64> {
+        self.entries.front().map(|entry| entry.cycle)
+    }
+
+    /// Cycle of the snapshot before the newest one, or of the newest if
+    /// there is only one, for going back at least an interval.
+    pub fn previous_cycle(self: &RewindBuffer) -> Option<u64> {
+        let count = self.entries.len();
+        self.entries.get(count.saturating_sub(2)).map(|entry| entry.cycle)
+    }
+
+    /// Returns the last snapshot taken at or before the cycle and forgets
+    /// the ones taken after it, which are taken again as the machine runs
+    /// forward.
+    pub fn go_back(self: &mut RewindBuffer, cycle: u64) -> Option<Vec<u8>> {
+        let count = self.entries.iter().take_while(|entry| entry.cycle <= cycle).count();
+        if count == 0 {
+            return None;
+        }
+        self.entries.truncate(count);
+        let mut snapshot = decode(&self.entries[0].data);
+        for entry in self.entries.iter().skip(1) {
+            snapshot = xor(&snapshot, &decode(&entry.data));
+        }
+        self.last = snapshot.clone();
+        self.frames = 0;
+        Some(snapshot)
+    }
+
+    pub fn print_status(self: &RewindBuffer, cycle: u64) {
+        if !self.enabled() {
+            println!("Rewinding is off");
+            return;
+        }
+        println!("A snapshot every {} frames, {} of {} kept in {} KB", self.interval, self.entries.len(), self.capacity,
+            self.entries.iter().map(|entry| entry.data.len()).sum::<usize>() / 1024);
+        match self.oldest_cycle() {
+            Some(oldest) => println!("At cycle {}, back to cycle {}", cycle, oldest),
+            None => println!("At cycle {}", cycle)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8]) {
+        assert_eq!(decode(&encode(bytes)), bytes);
+    }
+
+    #[test]
+    fn encodes_short_runs() {
+        round_trip(&[]);
+        round_trip(&[1]);
+        round_trip(&[1, 1]);
+        round_trip(&[1, 2, 2, 3, 3, 3, 4]);
+    }
+
+    #[test]
+    fn encodes_long_literal_runs() {
+        for &length in [127, 128, 129, 256, 300].iter() {
+            let bytes: Vec<u8> = (0..length).map(|i| (i % 251) as u8 ^ ((i % 2) as u8 * 0x80)).collect();
+            let encoded = encode(&bytes);
+            assert!(encoded.iter().step_by(MAX_LITERALS + 1).all(|&control| control < MAX_LITERALS as u8));
+            assert_eq!(decode(&encoded), bytes);
+        }
+    }
+
+    #[test]
+    fn encodes_long_repeats() {
+        for &length in [128, 129, 130, 258, 1000].iter() {
+            let bytes = vec![0xAA; length];
+            let encoded = encode(&bytes);
+            assert_eq!(encoded.len(), 2 * length.div_ceil(MAX_REPEATS));
+            assert_eq!(decode(&encoded), bytes);
+        }
+        let mut bytes = vec![7; 200];
+        bytes.push(8);
+        bytes.extend(vec![0; 130]);
+        round_trip(&bytes);
+    }
+
+    fn snapshot(value: u8) -> Vec<u8> {
+        let mut snapshot = vec![0; 1000];
+        snapshot[10] = value;
+        snapshot[500] = value.wrapping_mul(3);
+        snapshot
+    }
+
+    #[test]
+    fn evicts_the_oldest_snapshot_at_capacity() {
+        let mut buffer = RewindBuffer::new(1, 3);
+        for i in 0..5 {
+            buffer.push(i * 100, snapshot(i as u8));
+        }
+        assert_eq!(buffer.entries.len(), 3);
+        assert_eq!(buffer.oldest_cycle(), Some(200));
+        assert_eq!(buffer.previous_cycle(), Some(300));
+        assert_eq!(buffer.go_back(200), Some(snapshot(2)));
+        assert_eq!(buffer.go_back(199), None);
+    }
+
+    #[test]
+    fn going_back_cuts_off_later_snapshots() {
+        let mut buffer = RewindBuffer::new(1, 10);
+        for i in 0..5 {
+            buffer.push(i * 100, snapshot(i as u8));
+        }
+        assert_eq!(buffer.go_back(250), Some(snapshot(2)));
+        assert_eq!(buffer.entries.len(), 3);
+        assert_eq!(buffer.previous_cycle(), Some(100));
+        // The next snapshot is a difference to the one gone back to
+        buffer.push(260, snapshot(9));
+        assert_eq!(buffer.go_back(260), Some(snapshot(9)));
+        assert_eq!(buffer.go_back(150), Some(snapshot(1)));
+    }
+
+    #[test]
+    fn replays_the_logged_input() {
+        let mut buffer = RewindBuffer::new(1, 2);
+        buffer.push(0, snapshot(0));
+        buffer.record_input(0, [0xFF, 0xFF]);
+        buffer.record_input(1, [0xFF, 0xFF]);
+        buffer.record_input(50, [0xEF, 0xFF]);
+        buffer.push(100, snapshot(1));
+        buffer.record_input(150, [0xFF, 0xFE]);
+        assert_eq!(buffer.inputs.len(), 3);
+        assert_eq!(buffer.input_at(49), Some([0xFF, 0xFF]));
+        assert_eq!(buffer.input_at(120), Some([0xEF, 0xFF]));
+        assert_eq!(buffer.input_at(150), Some([0xFF, 0xFE]));
+        // Only the input in effect at the oldest snapshot is kept before it
+        buffer.push(200, snapshot(2));
+        assert_eq!(buffer.input_at(100), Some([0xEF, 0xFF]));
+        assert_eq!(buffer.inputs.len(), 2);
+        buffer.truncate_inputs(150);
+        assert_eq!(buffer.input_at(180), Some([0xEF, 0xFF]));
+    }
+}
@@ -0,0 +1,905 @@
+// MOS 6581/8580 Sound Interface Device. The voices are modelled after the
+// digital parts of the chip: a 24-bit phase accumulator per oscillator, a
+// 23-bit noise LFSR and an envelope generator driven by a 15-bit rate
+// counter. The registers are mirrored every 32 bytes.
+
+use snapshot::{Reader, Writer};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SidModel {
+    Mos6581,
+    Mos8580
+}
+
+bitflags! {
+    struct Control: u8 {
+        const GATE     = 0b0000_0001;
+        const SYNC     = 0b0000_0010;
+        const RING_MOD = 0b0000_0100;
+        const TEST     = 0b0000_1000;
+        const TRIANGLE = 0b0001_0000;
+        const SAWTOOTH = 0b0010_0000;
+        const PULSE    = 0b0100_0000;
+        const NOISE    = 0b1000_0000;
+    }
+}
+
+// Rate counter periods for the 16 attack/decay/release settings, in cycles
+const RATE_PERIODS: [u16; 16] = [
+    9, 32, 63, 95, 149, 220, 267, 313, 392, 977, 1954, 3126, 3907, 11720, 19532, 31251
+];
+
+// CPU cycles a value written to a write-only register can be read back from
+// the data bus, before the charge leaks away
+const BUS_VALUE_TTL_6581: u32 = 0x01D00;
+const BUS_VALUE_TTL_8580: u32 = 0xA2000;
+
+const NOISE_SHIFT_REGISTER_RESET: u32 = 0x7FFFF8;
+
+// DC level of each voice at the mixer input, as a fraction of the waveform
+// DAC range at full envelope. The master volume scales it, which is how
+// writes to $D418 play samples. It varies between individual 6581 chips and
+// is negligible on the 8580.
+const DC_OFFSET_6581: f32 = 0.5;
+const DC_OFFSET_8580: f32 = 0.0;
+// Level of the DC voltage fed to EXT IN by the digi boost modification of
+// 8580 machines, the DC level of the three voices of a 6581
+const DIGI_BOOST_LEVEL: i32 = 3 * 0x800 * 0xFF;
+
+// Parameters of the combined waveform model of reSIDfp, fitted to samples of
+// real chips: bias, pulse strength, top bit, distance and saw/triangle mix.
+// Rows are for waveforms 3 (ST), 5 (PT), 6 (PS) and 7 (PST).
+const COMBINED_WAVEFORMS_6581: [[f32; 5]; 4] = [
+    [0.880815, 0.0, 0.0, 0.3279614, 0.5999545],
+    [0.8924618, 2.014781, 1.003332, 0.02992322, 0.0],
+    [0.8646501, 1.712586, 1.137704, 0.02845423, 0.0],
+    [0.9527834, 1.794777, 0.0, 0.09806272, 0.7752482]
+];
+const COMBINED_WAVEFORMS_8580: [[f32; 5]; 4] = [
+    [0.9781665, 0.0, 0.9899469, 8.087667, 0.8226412],
+    [0.9097769, 2.039997, 0.9584096, 0.1765447, 0.0],
+    [0.9231212, 2.084788, 0.9493895, 0.1712518, 0.0],
+    [0.9845552, 1.415612, 0.9703883, 3.68829, 0.8265008]
+];
+
+// Output of the combined waveform for the upper 12 bits of the accumulator,
+// assuming the pulse output is high.
+fn combined_waveform(params: &[f32; 5], waveform: u8, accumulator: u32) -> u16 {
+    let (bias, pulse_strength, top_bit, distance, saw_tri_mix) = (params[0], params[1], params[2], params[3], params[4]);
+    let mut bits = [0f32; 12];
+    for (i, bit) in bits.iter_mut().enumerate() {
+        *bit = if accumulator & (1 << i) > 0 { 1.0 } else { 0.0 };
+    }
+    if waveform & 0b11 == 0b01 {
+        // Triangle without sawtooth: the bits are XORed with the top bit and shifted
+        let top = accumulator & 0x800 > 0;
+        for i in (1..12).rev() {
+            bits[i] = if top { 1.0 - bits[i - 1] } else { bits[i - 1] };
+        }
+        bits[0] = 0.0;
+    } else if waveform & 0b11 == 0b11 {
+        // Sawtooth and triangle together: neighbouring bits are interconnected
+        bits[0] *= saw_tri_mix;
+        for i in 1..12 {
+            bits[i] = bits[i - 1] * (1.0 - saw_tri_mix) + bits[i] * saw_tri_mix;
+        }
+    }
+    if waveform & 0b10 > 0 {
+        bits[11] *= top_bit;
+    }
+    if waveform == 0b011 || waveform > 0b100 {
+        // Influence of a bit on another falls off with the square of the distance between them
+        let mut weights = [0f32; 25];
+        for (i, weight) in weights.iter_mut().enumerate() {
+            let bit_distance = (i as f32 - 12.0).abs();
+            *weight = 1.0 / (1.0 + bit_distance * bit_distance * distance);
+        }
+        let mut mixed = [0f32; 12];
+        for i in 0..12 {
+            let mut sum = 0.0;
+            let mut n = 0.0;
+            for j in 0..12 {
+                let weight = weights[i + 12 - j];
+                sum += bits[j] * weight;
+                n += weight;
+            }
+            if waveform > 0b100 {
+                let weight = weights[i];
+                sum += pulse_strength * weight;
+                n += weight;
+            }
+            mixed[i] = (bits[i] + sum / n) * 0.5;
+        }
+        bits = mixed;
+    }
+    let mut value = 0;
+    for (i, &bit) in bits.iter().enumerate() {
+        if bit - bias > 0.0 {
+            value |= 1 << i;
+        }
+    }
+    value
+}
+
+// Combined waveform tables, indexed by waveform bits 4-6 and the upper 12
+// bits of the accumulator. Only the entries for combined waveforms are used.
+struct WaveformTables {
+    tables: Vec<Vec<u16>>
+}
+
+impl WaveformTables {
+    fn new(model: SidModel) -> WaveformTables {
+        let params = match model {
+            SidModel::Mos6581 => &COMBINED_WAVEFORMS_6581,
+            SidModel::Mos8580 => &COMBINED_WAVEFORMS_8580
+        };
+        let tables = (0..8u8).map(|waveform| {
+            let row = match waveform {
+                0b011 => 0,
+                0b101 => 1,
+                0b110 => 2,
+                0b111 => 3,
+                _ => return Vec::new()
+            };
+            (0..4096).map(|accumulator| combined_waveform(&params[row], waveform, accumulator)).collect()
+        }).collect();
+        WaveformTables {
+            tables
+        }
+    }
+
+    fn lookup(self: &WaveformTables, waveform: u8, accumulator: u32) -> u16 {
+        self.tables[waveform as usize][(accumulator >> 12) as usize]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum EnvelopeState {
+    Attack,
+    DecaySustain,
+    Release
+}
+
+struct Envelope {
+    state: EnvelopeState,
+    attack: u8,
+    decay: u8,
+    sustain: u8,
+    release: u8,
+    level: u8,
+    rate_counter: u16,
+    exponential_counter: u8,
+    // Decay and release stop when the level reaches zero, until the next attack
+    hold_zero: bool
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            state: EnvelopeState::Release,
+            attack: 0,
+            decay: 0,
+            sustain: 0,
+            release: 0,
+            level: 0,
+            rate_counter: 0,
+            exponential_counter: 0,
+            hold_zero: true
+        }
+    }
+
+    fn set_gate(self: &mut Envelope, gate: bool) {
+        if gate && self.state == EnvelopeState::Release {
+            self.state = EnvelopeState::Attack;
+            self.hold_zero = false;
+        } else if !gate && self.state != EnvelopeState::Release {
+            self.state = EnvelopeState::Release;
+        }
+    }
+
+    fn rate_period(self: &Envelope) -> u16 {
+        RATE_PERIODS[match self.state {
+            EnvelopeState::Attack => self.attack,
+            EnvelopeState::DecaySustain => self.decay,
+            EnvelopeState::Release => self.release
+        } as usize]
+    }
+
+    // Decay and release approximate an exponential curve by slowing down at
+    // fixed envelope levels
+    fn exponential_period(self: &Envelope) -> u8 {
+        match self.level {
+            0x5E..=0xFF => 1,
+            0x37..=0x5D => 2,
+            0x1B..=0x36 => 4,
+            0x0F..=0x1A => 8,
+            0x07..=0x0E => 16,
+            0x01..=0x06 => 30,
+            _ => 1
+        }
+    }
+
+    fn tick(self: &mut Envelope) {
+        // The rate counter is only compared for equality with the period and
+        // wraps at 15 bits. Lowering the period below the current count makes
+        // the counter run all the way around: the ADSR delay bug.
+        self.rate_counter = (self.rate_counter + 1) & 0x7FFF;
+        if self.rate_counter != self.rate_period() {
+            return;
+        }
+        self.rate_counter = 0;
+
+        if self.state == EnvelopeState::Attack {
+            self.exponential_counter = 0;
+            self.level = self.level.wrapping_add(1);
+            if self.level == 0xFF {
+                self.state = EnvelopeState::DecaySustain;
+            }
+            return;
+        }
+
+        self.exponential_counter += 1;
+        if self.exponential_counter < self.exponential_period() {
+            return;
+        }
+        self.exponential_counter = 0;
+        if self.hold_zero {
+            return;
+        }
+        if self.state == EnvelopeState::Release || self.level != self.sustain * 0x11 {
+            self.level = self.level.saturating_sub(1);
+        }
+        if self.level == 0 {
+            self.hold_zero = true;
+        }
+    }
+
+    fn save_state(self: &Envelope, writer: &mut Writer) {
+        writer.write_u8(match self.state {
+            EnvelopeState::Attack => 0,
+            EnvelopeState::DecaySustain => 1,
+            EnvelopeState::Release => 2
+        });
+        writer.write_bytes(&[self.attack, self.decay, self.sustain, self.release, self.level]);
+        writer.write_u16(self.rate_counter);
+        writer.write_u8(self.exponential_counter);
+        writer.write_bool(self.hold_zero);
+    }
+
+    fn load_state(self: &mut Envelope, reader: &mut Reader) -> Result<(), String> {
+        self.state = match reader.read_u8()? {
+            0 => EnvelopeState::Attack,
+            1 => EnvelopeState::DecaySustain,
+            _ => EnvelopeState::Release
+        };
+        self.attack = reader.read_u8()? & 0x0F;
+        self.decay = reader.read_u8()? & 0x0F;
+        self.sustain = reader.read_u8()? & 0x0F;
+        self.release = reader.read_u8()? & 0x0F;
+        self.level = reader.read_u8()?;
+        self.rate_counter = reader.read_u16()? & 0x7FFF;
+        self.exponential_counter = reader.read_u8()?;
+        self.hold_zero = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+struct Voice {
+    accumulator: u32,
+    frequency: u16,
+    pulse_width: u16,
+    control: Control,
+    noise_shift_register: u32,
+    // Set when the top bit of the accumulator went high in the last tick, for hard sync
+    msb_rising: bool,
+    envelope: Envelope
+}
+
+impl Voice {
+    fn new() -> Voice {
+        Voice {
+            accumulator: 0,
+            frequency: 0,
+            pulse_width: 0,
+            control: Control { bits: 0 },
+            noise_shift_register: NOISE_SHIFT_REGISTER_RESET,
+            msb_rising: false,
+            envelope: Envelope::new()
+        }
+    }
+
+    fn write(self: &mut Voice, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.frequency = (self.frequency & 0xFF00) | value as u16;
+            }
+            1 => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16) << 8);
+            }
+            2 => {
+                self.pulse_width = (self.pulse_width & 0x0F00) | value as u16;
+            }
+            3 => {
+                self.pulse_width = (self.pulse_width & 0x00FF) | ((value as u16 & 0x0F) << 8);
+            }
+            4 => {
+                let control = Control::from_bits_truncate(value);
+                if control.contains(Control::TEST) {
+                    // The test bit resets the accumulator and holds the noise shift register
+                    self.accumulator = 0;
+                    self.noise_shift_register = NOISE_SHIFT_REGISTER_RESET;
+                }
+                self.envelope.set_gate(control.contains(Control::GATE));
+                self.control = control;
+            }
+            5 => {
+                self.envelope.attack = value >> 4;
+                self.envelope.decay = value & 0x0F;
+            }
+            _ => {
+                self.envelope.sustain = value >> 4;
+                self.envelope.release = value & 0x0F;
+            }
+        }
+    }
+
+    fn tick_oscillator(self: &mut Voice) {
+        if self.control.contains(Control::TEST) {
+            self.msb_rising = false;
+            return;
+        }
+        let previous = self.accumulator;
+        self.accumulator = (self.accumulator + self.frequency as u32) & 0xFFFFFF;
+        self.msb_rising = previous & 0x800000 == 0 && self.accumulator & 0x800000 > 0;
+        // The noise shift register is clocked by bit 19 of the accumulator
+        if previous & 0x080000 == 0 && self.accumulator & 0x080000 > 0 {
+            let feedback = ((self.noise_shift_register >> 22) ^ (self.noise_shift_register >> 17)) & 1;
+            self.noise_shift_register = ((self.noise_shift_register << 1) | feedback) & 0x7FFFFF;
+        }
+    }
+
+    fn noise_output(self: &Voice) -> u16 {
+        let register = self.noise_shift_register;
+        (((register >> 9) & 0x800) |
+         ((register >> 8) & 0x400) |
+         ((register >> 5) & 0x200) |
+         ((register >> 3) & 0x100) |
+         ((register >> 2) & 0x080) |
+         ((register << 1) & 0x040) |
+         ((register << 3) & 0x020) |
+         ((register << 4) & 0x010)) as u16
+    }
+
+    // 12-bit output of the waveform generator. The ring modulation source is
+    // the accumulator of the preceding voice.
+    fn waveform_output(self: &mut Voice, ring_source: u32, tables: &WaveformTables) -> u16 {
+        let waveform = (self.control.bits >> 4) & 0b0111;
+        let pulse = self.control.contains(Control::TEST) ||
+            (self.accumulator >> 12) as u16 >= self.pulse_width;
+        let output = match waveform {
+            0b000 => 0,
+            0b001 => {
+                let msb = if self.control.contains(Control::RING_MOD) {
+                    (self.accumulator ^ ring_source) & 0x800000
+                } else {
+                    self.accumulator & 0x800000
+                };
+                let value = if msb > 0 { !self.accumulator } else { self.accumulator };
+                ((value >> 11) & 0xFFE) as u16
+            }
+            0b010 => {
+                (self.accumulator >> 12) as u16
+            }
+            0b100 => {
+                if pulse { 0xFFF } else { 0 }
+            }
+            0b011 => {
+                tables.lookup(waveform, self.accumulator)
+            }
+            _ => {
+                if pulse { tables.lookup(waveform, self.accumulator) } else { 0 }
+            }
+        };
+        if !self.control.contains(Control::NOISE) {
+            return output;
+        }
+        let noise = self.noise_output();
+        if waveform == 0 {
+            return noise;
+        }
+        // Combining noise with other waveforms pulls down the shift register
+        // bits through the output, which eventually locks the noise up
+        let combined = output & noise;
+        let register = self.noise_shift_register;
+        self.noise_shift_register = register &
+            !(((!combined as u32 & 0x800) << 9) |
+              ((!combined as u32 & 0x400) << 8) |
+              ((!combined as u32 & 0x200) << 5) |
+              ((!combined as u32 & 0x100) << 3) |
+              ((!combined as u32 & 0x080) << 2) |
+              ((!combined as u32 & 0x040) >> 1) |
+              ((!combined as u32 & 0x020) >> 3) |
+              ((!combined as u32 & 0x010) >> 4));
+        combined
+    }
+
+    fn save_state(self: &Voice, writer: &mut Writer) {
+        writer.write_u32(self.accumulator);
+        writer.write_u16(self.frequency);
+        writer.write_u16(self.pulse_width);
+        writer.write_u8(self.control.bits);
+        writer.write_u32(self.noise_shift_register);
+        writer.write_bool(self.msb_rising);
+        self.envelope.save_state(writer);
+    }
+
+    fn load_state(self: &mut Voice, reader: &mut Reader) -> Result<(), String> {
+        self.accumulator = reader.read_u32()? & 0xFFFFFF;
+        self.frequency = reader.read_u16()?;
+        self.pulse_width = reader.read_u16()? & 0x0FFF;
+        self.control = Control::from_bits_truncate(reader.read_u8()?);
+        self.noise_shift_register = reader.read_u32()? & 0x7FFFFF;
+        self.msb_rising = reader.read_bool()?;
+        self.envelope.load_state(reader)
+    }
+}
+
+// The cutoff frequency of the 6581 is set by a FET used as a voltage
+// controlled resistor, in parallel with a fixed resistance, that charges the
+// 470 pF integrator capacitors. The FET resistance falls exponentially with the
+// cutoff DAC voltage, which makes the curve flat at low cutoff values and
+// steep at high ones. Values are from fits to measured chips.
+const FILTER_6581_BASE_RESISTANCE: f32 = 1147036.4;
+const FILTER_6581_FET_OFFSET: f32 = 274228797.0;
+const FILTER_6581_FET_STEEPNESS: f32 = 1.0066634;
+const FILTER_6581_MINIMUM_FET_RESISTANCE: f32 = 16125.155;
+const FILTER_CAPACITANCE: f32 = 470e-12;
+// Change in FET gate voltage per cutoff step, for converting the bias
+const FILTER_6581_MV_PER_STEP: f32 = 0.26;
+// The 8580 cutoff is close to linear, from 30 Hz to about 12 kHz
+const FILTER_8580_MIN_FREQUENCY: f32 = 30.0;
+const FILTER_8580_HZ_PER_STEP: f32 = 5.87;
+// Cutoff frequencies above this make the integrators unstable at one step per cycle
+const FILTER_MAX_FREQUENCY: f32 = 16000.0;
+
+bitflags! {
+    struct FilterRouting: u8 {
+        const VOICE_1 = 0b0000_0001;
+        const VOICE_2 = 0b0000_0010;
+        const VOICE_3 = 0b0000_0100;
+        const EXT_IN  = 0b0000_1000;
+    }
+}
+
+bitflags! {
+    struct FilterMode: u8 {
+        const LOW_PASS    = 0b0001_0000;
+        const BAND_PASS   = 0b0010_0000;
+        const HIGH_PASS   = 0b0100_0000;
+        const VOICE_3_OFF = 0b1000_0000;
+    }
+}
+
+// State variable filter made of two integrators, giving high-pass,
+// band-pass and low-pass outputs at the same time
+struct Filter {
+    cutoff: u16,
+    resonance: u8,
+    routing: FilterRouting,
+    // Cutoff as angular frequency per cycle, and damping from the resonance
+    w0: f32,
+    one_div_q: f32,
+    // Shift of the 6581 cutoff curve in millivolts
+    bias: f32,
+    clock_frequency: f32,
+    high_pass: f32,
+    band_pass: f32,
+    low_pass: f32
+}
+
+impl Filter {
+    fn new(model: SidModel, clock_frequency: u32) -> Filter {
+        let mut filter = Filter {
+            cutoff: 0,
+            resonance: 0,
+            routing: FilterRouting { bits: 0 },
+            w0: 0.0,
+            one_div_q: 0.0,
+            bias: 0.0,
+            clock_frequency: clock_frequency as f32,
+            high_pass: 0.0,
+            band_pass: 0.0,
+            low_pass: 0.0
+        };
+        filter.update(model);
+        filter
+    }
+
+    fn cutoff_frequency(self: &Filter, model: SidModel) -> f32 {
+        match model {
+            SidModel::Mos6581 => {
+                let control = self.cutoff as f32 + self.bias / FILTER_6581_MV_PER_STEP;
+                let fet_resistance = FILTER_6581_FET_OFFSET / FILTER_6581_FET_STEEPNESS.powf(control);
+                let dynamic_resistance = FILTER_6581_MINIMUM_FET_RESISTANCE + fet_resistance;
+                let resistance = FILTER_6581_BASE_RESISTANCE * dynamic_resistance / (FILTER_6581_BASE_RESISTANCE + dynamic_resistance);
+                1.0 / (2.0 * ::std::f32::consts::PI * resistance * FILTER_CAPACITANCE)
+            }
+            SidModel::Mos8580 => {
+                FILTER_8580_MIN_FREQUENCY + self.cutoff as f32 * FILTER_8580_HZ_PER_STEP
+            }
+        }
+    }
+
+    fn update(self: &mut Filter, model: SidModel) {
+        let frequency = self.cutoff_frequency(model).min(FILTER_MAX_FREQUENCY);
+        self.w0 = 2.0 * ::std::f32::consts::PI * frequency / self.clock_frequency;
+        self.one_div_q = match model {
+            SidModel::Mos6581 => 1.0 / (0.707 + self.resonance as f32 / 15.0),
+            SidModel::Mos8580 => 2f32.powf((4.0 - self.resonance as f32) / 8.0)
+        };
+    }
+
+    fn tick(self: &mut Filter, input: f32) {
+        self.band_pass -= self.w0 * self.high_pass;
+        self.low_pass -= self.w0 * self.band_pass;
+        self.high_pass = self.band_pass * self.one_div_q - self.low_pass - input;
+    }
+
+    fn output(self: &Filter, mode: FilterMode) -> f32 {
+        let mut output = 0.0;
+        if mode.contains(FilterMode::LOW_PASS) {
+            output += self.low_pass;
+        }
+        if mode.contains(FilterMode::BAND_PASS) {
+            output += self.band_pass;
+        }
+        if mode.contains(FilterMode::HIGH_PASS) {
+            output += self.high_pass;
+        }
+        output
+    }
+}
+
+// Cutoff frequencies of the one-pole RC filters formed by the output stage
+// of the C64: a low-pass and a high-pass that removes the DC level
+const EXTERNAL_LOW_PASS_FREQUENCY: f32 = 16000.0;
+const EXTERNAL_HIGH_PASS_FREQUENCY: f32 = 16.0;
+
+struct ExternalFilter {
+    // 2 * pi * f per cycle
+    low_pass_coefficient: f32,
+    high_pass_coefficient: f32,
+    low_pass: f32,
+    high_pass: f32
+}
+
+impl ExternalFilter {
+    fn new(clock_frequency: u32) -> ExternalFilter {
+        let coefficient = |frequency| 2.0 * ::std::f32::consts::PI * frequency / clock_frequency as f32;
+        ExternalFilter {
+            low_pass_coefficient: coefficient(EXTERNAL_LOW_PASS_FREQUENCY),
+            high_pass_coefficient: coefficient(EXTERNAL_HIGH_PASS_FREQUENCY),
+            low_pass: 0.0,
+            high_pass: 0.0
+        }
+    }
+
+    fn tick(self: &mut ExternalFilter, input: f32) -> f32 {
+        self.low_pass += self.low_pass_coefficient * (input - self.low_pass);
+        self.high_pass += self.high_pass_coefficient * (self.low_pass - self.high_pass);
+        self.low_pass - self.high_pass
+    }
+}
+
+pub struct Sid {
+    model: SidModel,
+    clock_frequency: u32,
+    voices: [Voice; 3],
+    waveform_tables: WaveformTables,
+    filter: Filter,
+    mode_volume: u8,
+    pot_x: u8,
+    pot_y: u8,
+    // Last value written to any register, returned when reading write-only registers
+    bus_value: u8,
+    bus_value_ttl: u32,
+    osc3: u8,
+    // DC level of a voice at the mixer input
+    voice_dc: i32,
+    digi_boost: bool,
+    external_filter: ExternalFilter,
+    output: i16
+}
+
+fn default_dc_offset(model: SidModel) -> f32 {
+    match model {
+        SidModel::Mos6581 => DC_OFFSET_6581,
+        SidModel::Mos8580 => DC_OFFSET_8580
+    }
+}
+
+impl Sid {
+    /// A chip clocked at the given CPU clock frequency.
+    pub fn new(model: SidModel, clock_frequency: u32) -> Sid {
+        let mut sid = Sid {
+            model,
+            clock_frequency,
+            voices: [Voice::new(), Voice::new(), Voice::new()],
+            waveform_tables: WaveformTables::new(model),
+            filter: Filter::new(model, clock_frequency),
+            mode_volume: 0,
+            pot_x: 0xFF,
+            pot_y: 0xFF,
+            bus_value: 0,
+            bus_value_ttl: 0,
+            osc3: 0,
+            voice_dc: 0,
+            digi_boost: false,
+            external_filter: ExternalFilter::new(clock_frequency),
+            output: 0
+        };
+        sid.set_dc_offset(default_dc_offset(model));
+        sid
+    }
+
+    pub fn model(self: &Sid) -> SidModel {
+        self.model
+    }
+
+    pub fn set_model(self: &mut Sid, model: SidModel) {
+        if model != self.model {
+            self.model = model;
+            self.waveform_tables = WaveformTables::new(model);
+            self.filter.update(model);
+            self.set_dc_offset(default_dc_offset(model));
+        }
+    }
+
+    /// Resets the chip as the RESET line does, keeping the chip model, the
+    /// filter bias, the DC offset, the digi boost and the paddle positions.
+    pub fn reset(self: &mut Sid) {
+        let bias = self.filter.bias;
+        let dc_offset = self.dc_offset();
+        let digi_boost = self.digi_boost;
+        let (pot_x, pot_y) = (self.pot_x, self.pot_y);
+        *self = Sid::new(self.model, self.clock_frequency);
+        self.set_filter_bias(bias);
+        self.set_dc_offset(dc_offset);
+        self.set_digi_boost(digi_boost);
+        self.set_pots(pot_x, pot_y);
+    }
+
+    /// Sets the DC level of the voices as a fraction of the waveform range,
+    /// to match a particular chip. Switching the model restores its default.
+    pub fn set_dc_offset(self: &mut Sid, dc_offset: f32) {
+        self.voice_dc = (dc_offset * (0x1000 * 0xFF) as f32) as i32;
+    }
+
+    pub fn dc_offset(self: &Sid) -> f32 {
+        self.voice_dc as f32 / (0x1000 * 0xFF) as f32
+    }
+
+    /// Feeds a DC level to EXT IN, which makes volume register samples
+    /// audible on the 8580.
+    pub fn set_digi_boost(self: &mut Sid, digi_boost: bool) {
+        self.digi_boost = digi_boost;
+    }
+
+    /// Shifts the cutoff curve of the 6581 filter by the given voltage in
+    /// millivolts, positive values raising the cutoff frequency. Individual
+    /// 6581 chips differ considerably. Has no effect on the 8580.
+    pub fn set_filter_bias(self: &mut Sid, bias: f32) {
+        self.filter.bias = bias;
+        self.filter.update(self.model);
+    }
+
+    /// Sets the values read from POTX and POTY, i.e. the positions of the
+    /// paddles selected by CIA1. Unconnected inputs read 0xFF.
+    pub fn set_pots(self: &mut Sid, x: u8, y: u8) {
+        self.pot_x = x;
+        self.pot_y = y;
+    }
+
+    pub fn write(self: &mut Sid, addr: u16, value: u8) {
+        let reg = addr & 0x1F;
+        self.bus_value = value;
+        self.bus_value_ttl = match self.model {
+            SidModel::Mos6581 => BUS_VALUE_TTL_6581,
+            SidModel::Mos8580 => BUS_VALUE_TTL_8580
+        };
+        match reg {
+            0x00..=0x06 => self.voices[0].write(reg, value),
+            0x07..=0x0D => self.voices[1].write(reg - 0x07, value),
+            0x0E..=0x14 => self.voices[2].write(reg - 0x0E, value),
+            0x15 => {
+                self.filter.cutoff = (self.filter.cutoff & 0x7F8) | (value as u16 & 0x07);
+                self.filter.update(self.model);
+            }
+            0x16 => {
+                self.filter.cutoff = (self.filter.cutoff & 0x007) | ((value as u16) << 3);
+                self.filter.update(self.model);
+            }
+            0x17 => {
+                self.filter.resonance = value >> 4;
+                self.filter.routing = FilterRouting::from_bits_truncate(value);
+                self.filter.update(self.model);
+            }
+            0x18 => {
+                self.mode_volume = value;
+            }
+            _ => ()
+        }
+    }
+
+    pub fn read(self: &mut Sid, addr: u16) -> u8 {
+        match addr & 0x1F {
+            0x19 => self.pot_x,
+            0x1A => self.pot_y,
+            0x1B => self.osc3,
+            0x1C => self.voices[2].envelope.level,
+            _ => self.bus_value
+        }
+    }
+
+    fn volume(self: &Sid) -> i32 {
+        (self.mode_volume & 0x0F) as i32
+    }
+
+    // Output of a voice before the mixer: the waveform, offset by the DC
+    // level of the waveform DAC, multiplied by the envelope
+    fn voice_output(self: &Sid, waveform: u16, envelope: u8) -> i32 {
+        let wave_zero = match self.model {
+            SidModel::Mos6581 => 0x380,
+            SidModel::Mos8580 => 0x800
+        };
+        (waveform as i32 - wave_zero) * envelope as i32 + self.voice_dc
+    }
+
+    pub fn tick(self: &mut Sid) {
+        for voice in self.voices.iter_mut() {
+            voice.tick_oscillator();
+            voice.envelope.tick();
+        }
+
+        // Hard sync: a voice is reset when the top bit of the preceding voice
+        // goes high, unless that voice was itself synced in the same cycle
+        let rising = [self.voices[0].msb_rising, self.voices[1].msb_rising, self.voices[2].msb_rising];
+        for i in 0..3 {
+            let source = (i + 2) % 3;
+            let source_synced = self.voices[source].control.contains(Control::SYNC) && rising[(source + 2) % 3];
+            if rising[source] && !source_synced && self.voices[i].control.contains(Control::SYNC) {
+                self.voices[i].accumulator = 0;
+            }
+        }
+
+        let accumulators = [self.voices[0].accumulator, self.voices[1].accumulator, self.voices[2].accumulator];
+        let mut outputs = [0i32; 3];
+        for i in 0..3 {
+            let waveform = self.voices[i].waveform_output(accumulators[(i + 2) % 3], &self.waveform_tables);
+            if i == 2 {
+                self.osc3 = (waveform >> 4) as u8;
+            }
+            outputs[i] = self.voice_output(waveform, self.voices[i].envelope.level);
+        }
+
+        let mode = FilterMode::from_bits_truncate(self.mode_volume);
+        // 3OFF disconnects voice 3 from the mixer, but not from the filter
+        if mode.contains(FilterMode::VOICE_3_OFF) && !self.filter.routing.contains(FilterRouting::VOICE_3) {
+            outputs[2] = 0;
+        }
+        let ext_in = if self.digi_boost { DIGI_BOOST_LEVEL } else { 0 };
+        let mut filter_input = 0;
+        let mut unfiltered = 0;
+        let routings = [FilterRouting::VOICE_1, FilterRouting::VOICE_2, FilterRouting::VOICE_3, FilterRouting::EXT_IN];
+        for (&routing, &output) in routings.iter().zip([outputs[0], outputs[1], outputs[2], ext_in].iter()) {
+            if self.filter.routing.contains(routing) {
+                filter_input += output;
+            } else {
+                unfiltered += output;
+            }
+        }
+        self.filter.tick(filter_input as f32);
+        let mixed = (unfiltered as f32 + self.filter.output(mode)) * self.volume() as f32;
+        let filtered = self.external_filter.tick(mixed);
+        self.output = (filtered / 1024.0).clamp(-32768.0, 32767.0) as i16;
+
+        if self.bus_value_ttl > 0 {
+            self.bus_value_ttl -= 1;
+            if self.bus_value_ttl == 0 {
+                self.bus_value = 0;
+            }
+        }
+    }
+
+    /// Saves the registers and what the chip is doing with them: the
+    /// oscillators, the envelopes, the filters and the value left on the
+    /// bus. The model and the settings of the chip are not saved.
+    pub fn save_state(self: &Sid, writer: &mut Writer) {
+        for voice in self.voices.iter() {
+            voice.save_state(writer);
+        }
+        writer.write_u16(self.filter.cutoff);
+        writer.write_u8(self.filter.resonance);
+        writer.write_u8(self.filter.routing.bits);
+        writer.write_f32(self.filter.high_pass);
+        writer.write_f32(self.filter.band_pass);
+        writer.write_f32(self.filter.low_pass);
+        writer.write_bytes(&[self.mode_volume, self.pot_x, self.pot_y, self.bus_value]);
+        writer.write_u32(self.bus_value_ttl);
+        writer.write_u8(self.osc3);
+        writer.write_f32(self.external_filter.low_pass);
+        writer.write_f32(self.external_filter.high_pass);
+        writer.write_u16(self.output as u16);
+    }
+
+    /// Leaves the chip as it was if the state can't be read.
+    pub fn load_state(self: &mut Sid, reader: &mut Reader) -> Result<(), String> {
+        let mut voices = [Voice::new(), Voice::new(), Voice::new()];
+        for voice in voices.iter_mut() {
+            voice.load_state(reader)?;
+        }
+        let cutoff = reader.read_u16()? & 0x7FF;
+        let resonance = reader.read_u8()? & 0x0F;
+        let routing = FilterRouting::from_bits_truncate(reader.read_u8()?);
+        let (high_pass, band_pass, low_pass) = (reader.read_f32()?, reader.read_f32()?, reader.read_f32()?);
+        let mut bytes = [0; 4];
+        reader.read_bytes(&mut bytes)?;
+        let bus_value_ttl = reader.read_u32()?;
+        let osc3 = reader.read_u8()?;
+        let (external_low_pass, external_high_pass) = (reader.read_f32()?, reader.read_f32()?);
+        let output = reader.read_u16()? as i16;
+        self.voices = voices;
+        self.filter.cutoff = cutoff;
+        self.filter.resonance = resonance;
+        self.filter.routing = routing;
+        self.filter.high_pass = high_pass;
+        self.filter.band_pass = band_pass;
+        self.filter.low_pass = low_pass;
+        self.filter.update(self.model);
+        self.mode_volume = bytes[0];
+        self.pot_x = bytes[1];
+        self.pot_y = bytes[2];
+        self.bus_value = bytes[3];
+        self.bus_value_ttl = bus_value_ttl;
+        self.osc3 = osc3;
+        self.external_filter.low_pass = external_low_pass;
+        self.external_filter.high_pass = external_high_pass;
+        self.output = output;
+        Ok(())
+    }
+
+    /// Mixed output of the current cycle after the external filter.
+    pub fn output(self: &Sid) -> i16 {
+        self.output
+    }
+
+    pub fn print_status(self: &Sid) {
+        println!("model: {:?}  volume: {}  output: {}  DC offset: {:.3}  digi boost: {}",
+            self.model, self.volume(), self.output, self.dc_offset(), if self.digi_boost { "on" } else { "off" });
+        println!(
+            "filter cutoff: 0x{:03X} ({:.0} Hz)  resonance: 0x{:X}  routing: 0x{:X}  mode: 0x{:X}  bias: {} mV",
+            self.filter.cutoff,
+            self.filter.cutoff_frequency(self.model),
+            self.filter.resonance,
+            self.filter.routing.bits,
+            self.mode_volume >> 4,
+            self.filter.bias
+        );
+        println!("voice  freq    pw     ctrl  ad    sr    env   state");
+        for (i, voice) in self.voices.iter().enumerate() {
+            println!(
+                "{}      0x{:04X}  0x{:03X}  0x{:02X}  0x{:X}{:X}  0x{:X}{:X}  0x{:02X}  {:?}",
+                i + 1,
+                voice.frequency,
+                voice.pulse_width,
+                voice.control.bits,
+                voice.envelope.attack,
+                voice.envelope.decay,
+                voice.envelope.sustain,
+                voice.envelope.release,
+                voice.envelope.level,
+                voice.envelope.state
+            );
+        }
+    }
+}
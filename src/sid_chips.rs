@@ -0,0 +1,294 @@
+// The SID chips of the machine. The built-in chip at $D400 is mirrored
+// through $D400-$D7FF. Extra chips, as in stereo and triple SID expansions,
+// are decoded at a 32-byte slot in $D420-$D7FF or $DE00-$DFFF and take
+// precedence over the mirrors of the built-in chip.
+//
+// The CPU executes an instruction as a whole on its first cycle, while the
+// write reaches the bus on the last one. Writes are held back until then,
+// which keeps the timing of samples played through the volume register.
+
+use sid::{Sid, SidModel};
+use snapshot::{Reader, Writer};
+
+const BUILT_IN_ADDRESS: u16 = 0xD400;
+
+struct SidChip {
+    address: u16,
+    sid: Sid,
+    // -1.0 is fully left, 1.0 fully right
+    pan: f32
+}
+
+struct PendingWrite {
+    addr: u16,
+    value: u8,
+    // Cycles until the write reaches the chip, None until the instruction
+    // doing it has been timed
+    delay: Option<u8>
+}
+
+pub struct SidChips {
+    // The built-in chip comes first
+    chips: Vec<SidChip>,
+    pending_writes: Vec<PendingWrite>,
+    // For the chips added
+    clock_frequency: u32
+}
+
+impl SidChips {
+    pub fn new(model: SidModel, clock_frequency: u32) -> SidChips {
+        SidChips {
+            chips: vec![SidChip { address: BUILT_IN_ADDRESS, sid: Sid::new(model, clock_frequency), pan: 0.0 }],
+            pending_writes: Vec::new(),
+            clock_frequency
+        }
+    }
+
+    /// The chip at $D400, which also has the paddle inputs.
+    pub fn built_in(self: &mut SidChips) -> &mut Sid {
+        &mut self.chips[0].sid
+    }
+
+    /// Adds a chip at a 32-byte aligned address in $D420-$D7E0 or
+    /// $DE00-$DFE0, replacing a chip already there.
+    pub fn add(self: &mut SidChips, address: u16, model: SidModel, pan: f32) -> Result<(), String> {
+        let in_range = (0xD420..0xD800).contains(&address) || (0xDE00..0xE000).contains(&address);
+        if !in_range || address & 0x1F != 0 {
+            return Err(format!("Invalid SID address: 0x{:04X}", address));
+        }
+        if !(-1.0..=1.0).contains(&pan) {
+            return Err(format!("Invalid panning: {}", pan));
+        }
+        self.remove(address);
+        self.chips.push(SidChip { address, sid: Sid::new(model, self.clock_frequency), pan });
+        Ok(())
+    }
+
+    /// Removes the extra chip at the given address. Returns false if there
+    /// is none.
+    pub fn remove(self: &mut SidChips, address: u16) -> bool {
+        let count = self.chips.len();
+        self.chips.retain(|chip| chip.address == BUILT_IN_ADDRESS || chip.address != address);
+        self.chips.len() < count
+    }
+
+    pub fn has_extra(self: &SidChips) -> bool {
+        self.chips.len() > 1
+    }
+
+    pub fn remove_extra(self: &mut SidChips) {
+        self.chips.truncate(1);
+    }
+
+    pub fn get_mut(self: &mut SidChips, address: u16) -> Option<&mut Sid> {
+        self.chips.iter_mut().find(|chip| chip.address == address).map(|chip| &mut chip.sid)
+    }
+
+    pub fn set_digi_boost(self: &mut SidChips, digi_boost: bool) {
+        for chip in self.chips.iter_mut() {
+            chip.sid.set_digi_boost(digi_boost);
+        }
+    }
+
+    pub fn set_pan(self: &mut SidChips, address: u16, pan: f32) -> bool {
+        match self.chips.iter_mut().find(|chip| chip.address == address) {
+            Some(chip) if (-1.0..=1.0).contains(&pan) => {
+                chip.pan = pan;
+                true
+            }
+            _ => false
+        }
+    }
+
+    fn decode(self: &mut SidChips, addr: u16) -> Option<&mut Sid> {
+        let slot = addr & !0x1F;
+        let index = match self.chips.iter().skip(1).position(|chip| chip.address == slot) {
+            Some(position) => position + 1,
+            None if (0xD400..0xD800).contains(&addr) => 0,
+            None => return None
+        };
+        Some(&mut self.chips[index].sid)
+    }
+
+    /// Returns None when no chip is decoded at the address.
+    pub fn read(self: &mut SidChips, addr: u16) -> Option<u8> {
+        self.decode(addr).map(|sid| sid.read(addr))
+    }
+
+    /// Returns false when no chip is decoded at the address. The write
+    /// takes effect after the delay given to `delay_writes`.
+    pub fn write(self: &mut SidChips, addr: u16, value: u8) -> bool {
+        if self.decode(addr).is_none() {
+            return false;
+        }
+        self.pending_writes.push(PendingWrite { addr, value, delay: None });
+        true
+    }
+
+    /// Delays the writes of the instruction just executed by the given
+    /// number of cycles, to its last cycle.
+    pub fn delay_writes(self: &mut SidChips, cycles: u8) {
+        for write in self.pending_writes.iter_mut() {
+            if write.delay.is_none() {
+                write.delay = Some(cycles);
+            }
+        }
+    }
+
+    pub fn reset(self: &mut SidChips) {
+        self.pending_writes.clear();
+        for chip in self.chips.iter_mut() {
+            chip.sid.reset();
+        }
+    }
+
+    pub fn tick(self: &mut SidChips) {
+        let mut i = 0;
+        while i < self.pending_writes.len() {
+            match self.pending_writes[i].delay {
+                Some(delay) if delay > 0 => {
+                    self.pending_writes[i].delay = Some(delay - 1);
+                    i += 1;
+                }
+                _ => {
+                    let write = self.pending_writes.remove(i);
+                    if let Some(sid) = self.decode(write.addr) {
+                        sid.write(write.addr, write.value);
+                    }
+                }
+            }
+        }
+        for chip in self.chips.iter_mut() {
+            chip.sid.tick();
+        }
+    }
+
+    /// Saves the writes on their way to the chips and the state of the
+    /// built-in chip. Extra chips are not saved.
+    pub fn save_state(self: &SidChips, writer: &mut Writer) {
+        writer.write_u16(self.pending_writes.len() as u16);
+        for write in self.pending_writes.iter() {
+            writer.write_u16(write.addr);
+            writer.write_u8(write.value);
+            writer.write_bool(write.delay.is_some());
+            writer.write_u8(write.delay.unwrap_or(0));
+        }
+        self.chips[0].sid.save_state(writer);
+    }
+
+    /// Leaves the chips as they were if the state can't be read.
+    pub fn load_state(self: &mut SidChips, reader: &mut Reader) -> Result<(), String> {
+        let mut pending_writes = Vec::new();
+        for _ in 0..reader.read_u16()? {
+            let addr = reader.read_u16()?;
+            let value = reader.read_u8()?;
+            let timed = reader.read_bool()?;
+            let delay = reader.read_u8()?;
+            pending_writes.push(PendingWrite { addr, value, delay: if timed { Some(delay) } else { None } });
+        }
+        self.chips[0].sid.load_state(reader)?;
+        self.pending_writes = pending_writes;
+        Ok(())
+    }
+
+    /// Left and right output of the current cycle. A centered chip plays at
+    /// full level on both sides, panning attenuates the opposite side.
+    pub fn output(self: &SidChips) -> (i16, i16) {
+        let (mut left, mut right) = (0.0, 0.0);
+        for chip in self.chips.iter() {
+            let output = chip.sid.output() as f32;
+            left += output * (1.0 - chip.pan).min(1.0);
+            right += output * (1.0 + chip.pan).min(1.0);
+        }
+        (left.clamp(-32768.0, 32767.0) as i16, right.clamp(-32768.0, 32767.0) as i16)
+    }
+
+    pub fn print_status(self: &SidChips) {
+        for chip in self.chips.iter() {
+            println!("SID at 0x{:04X}  pan: {:+.2}", chip.address, chip.pan);
+            chip.sid.print_status();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snapshot::Snapshot;
+    use digi_test::{self, WRITE_INTERVAL, TRIANGLE_STEPS};
+    use vic_ii::PAL;
+
+    // Cycles for the output to settle after power on
+    const WARMUP_CYCLES: usize = 1000;
+    // Samples of the triangle wave, enough for two full turns of direction
+    const SAMPLES: usize = 5 * TRIANGLE_STEPS;
+
+    // Plays the self test's triangle wave through $D418, with the store
+    // instructions alternating between a 4-cycle absolute and a 6-cycle
+    // indirect indexed one that are timed to write WRITE_INTERVAL cycles
+    // apart. Returns the output from two cycles before the first write and
+    // the cycles relative to that at which the writes should reach the chip.
+    fn play(model: SidModel, digi_boost: bool) -> (Vec<i16>, Vec<usize>) {
+        let mut sids = SidChips::new(model, PAL.clock_frequency);
+        sids.set_digi_boost(digi_boost);
+        // The instruction starts 3 or 5 cycles before its write
+        let writes: Vec<(usize, u8, u8)> = (0..SAMPLES).map(|i| {
+            let delay = if i % 2 == 0 { 3 } else { 5 };
+            (WARMUP_CYCLES + i * WRITE_INTERVAL - delay, digi_test::triangle(i + 1), delay as u8)
+        }).collect();
+        let mut output = Vec::new();
+        let mut next = 0;
+        for cycle in 0..WARMUP_CYCLES + SAMPLES * WRITE_INTERVAL {
+            while next < writes.len() && writes[next].0 == cycle {
+                assert!(sids.write(0xD418, writes[next].1));
+                sids.delay_writes(writes[next].2);
+                next += 1;
+            }
+            sids.tick();
+            output.push(sids.output().0);
+        }
+        let expected = writes.iter().map(|&(start, _, delay)| start + delay as usize + 2 - WARMUP_CYCLES).collect();
+        (output.split_off(WARMUP_CYCLES - 2), expected)
+    }
+
+    fn check_samples(model: SidModel, digi_boost: bool) {
+        let (output, expected) = play(model, digi_boost);
+        let cycles: Vec<usize> = digi_test::steps(&output).iter().map(|&(cycle, _)| cycle).collect();
+        assert_eq!(cycles, expected);
+        if let Err(msg) = digi_test::check_samples(&output) {
+            panic!("{}", msg);
+        }
+    }
+
+    #[test]
+    fn plays_samples_on_the_6581() {
+        check_samples(SidModel::Mos6581, false);
+    }
+
+    #[test]
+    fn plays_samples_on_the_8580_with_digi_boost() {
+        check_samples(SidModel::Mos8580, true);
+    }
+
+    #[test]
+    fn plays_no_samples_on_the_8580() {
+        let (output, _) = play(SidModel::Mos8580, false);
+        if let Err(msg) = digi_test::check_silence(&output[2..]) {
+            panic!("{}", msg);
+        }
+    }
+
+    #[test]
+    fn keeps_more_than_256_pending_writes_in_a_snapshot() {
+        let mut sids = SidChips::new(SidModel::Mos6581, PAL.clock_frequency);
+        for i in 0..300 {
+            sids.write(0xD400, i as u8);
+        }
+        let mut snapshot = Snapshot::new();
+        sids.save_state(snapshot.add("SID"));
+        let mut loaded = SidChips::new(SidModel::Mos6581, PAL.clock_frequency);
+        loaded.load_state(&mut snapshot.module("SID").unwrap()).unwrap();
+        assert_eq!(loaded.pending_writes.len(), 300);
+        assert_eq!(loaded.pending_writes[299].value, 43);
+    }
+}
@@ -0,0 +1,260 @@
+// Snapshots of the machine state. A snapshot file starts with a signature
+// and the version of the format, followed by a module for each component:
+// the length of its name, the name, the length of its data and the data,
+// with all numbers little-endian. A component saves and loads its own
+// module, so new components only add modules.
+
+use std::fs::File;
+use std::io::prelude::*;
+
+const SIGNATURE: &[u8] = b"C64EMU SNAPSHOT\x1A";
+/// Version written, older ones are read as well.
+pub const VERSION: u16 = 1;
+
+/// The data of a module while it is saved.
+pub struct Writer {
+    data: Vec<u8>
+}
+
+impl Writer {
+    fn new() -> Writer {
+        Writer {
+            data: Vec::new()
+        }
+    }
+
+    pub fn write_u8(self: &mut Writer, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(self: &mut Writer, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(self: &mut Writer, value: u16) {
+        self.write_bytes(&[value as u8, (value >> 8) as u8]);
+    }
+
+    pub fn write_u32(self: &mut Writer, value: u32) {
+        self.write_u16(value as u16);
+        self.write_u16((value >> 16) as u16);
+    }
+
+    pub fn write_u64(self: &mut Writer, value: u64) {
+        self.write_u32(value as u32);
+        self.write_u32((value >> 32) as u32);
+    }
+
+    pub fn write_f32(self: &mut Writer, value: f32) {
+        self.write_u32(value.to_bits());
+    }
+
+    pub fn write_bytes(self: &mut Writer, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+/// The data of a module while it is loaded. Reading past its end is an
+/// error, as the snapshot is truncated or from another version.
+pub struct Reader<'a> {
+    name: &'a str,
+    data: &'a [u8],
+    offset: usize
+}
+
+impl<'a> Reader<'a> {
+    /// Reads a module of another format, named in the error messages.
+    pub fn new(name: &'a str, data: &'a [u8]) -> Reader<'a> {
+        Reader {
+            name,
+            data,
+            offset: 0
+        }
+    }
+
+    fn take(self: &mut Reader<'a>, length: usize) -> Result<&'a [u8], String> {
+        if self.offset + length > self.data.len() {
+            return Err(format!("The {} module of the snapshot is too short", self.name));
+        }
+        self.offset += length;
+        Ok(&self.data[self.offset - length..self.offset])
+    }
+
+    pub fn read_u8(self: &mut Reader<'a>) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(self: &mut Reader<'a>) -> Result<bool, String> {
+        Ok(self.read_u8()? > 0)
+    }
+
+    pub fn read_u16(self: &mut Reader<'a>) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(bytes[0] as u16 | (bytes[1] as u16) << 8)
+    }
+
+    pub fn read_u32(self: &mut Reader<'a>) -> Result<u32, String> {
+        Ok(self.read_u16()? as u32 | (self.read_u16()? as u32) << 16)
+    }
+
+    pub fn read_u64(self: &mut Reader<'a>) -> Result<u64, String> {
+        Ok(self.read_u32()? as u64 | (self.read_u32()? as u64) << 32)
+    }
+
+    pub fn read_f32(self: &mut Reader<'a>) -> Result<f32, String> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    pub fn read_bytes(self: &mut Reader<'a>, bytes: &mut [u8]) -> Result<(), String> {
+        bytes.copy_from_slice(self.take(bytes.len())?);
+        Ok(())
+    }
+
+    pub fn skip(self: &mut Reader<'a>, length: usize) -> Result<(), String> {
+        self.take(length).map(|_| ())
+    }
+}
+
+pub struct Snapshot {
+    version: u16,
+    modules: Vec<(String, Writer)>
+}
+
+impl Snapshot {
+    pub fn new() -> Snapshot {
+        Snapshot {
+            version: VERSION,
+            modules: Vec::new()
+        }
+    }
+
+    /// Adds a module, to be filled through the returned writer.
+    pub fn add(self: &mut Snapshot, name: &str) -> &mut Writer {
+        self.modules.push((name.to_string(), Writer::new()));
+        &mut self.modules.last_mut().unwrap().1
+    }
+
+    pub fn contains(self: &Snapshot, name: &str) -> bool {
+        self.modules.iter().any(|module| module.0 == name)
+    }
+
+    pub fn module<'a>(self: &'a Snapshot, name: &str) -> Result<Reader<'a>, String> {
+        match self.modules.iter().find(|&&(ref module, _)| module == name) {
+            Some(&(ref name, ref writer)) => Ok(Reader::new(name, &writer.data)),
+            None => Err(format!("The snapshot has no {} module", name))
+        }
+    }
+
+    pub fn to_bytes(self: &Snapshot) -> Vec<u8> {
+        let mut file = Writer::new();
+        file.write_bytes(SIGNATURE);
+        file.write_u16(self.version);
+        for &(ref name, ref module) in self.modules.iter() {
+            file.write_u8(name.len() as u8);
+            file.write_bytes(name.as_bytes());
+            file.write_u32(module.data.len() as u32);
+            file.write_bytes(&module.data);
+        }
+        file.data
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, String> {
+        if !bytes.starts_with(SIGNATURE) {
+            return Err("Not a snapshot".to_string());
+        }
+        let truncated = |_| "The snapshot is truncated".to_string();
+        let mut file = Reader { name: "", data: bytes, offset: SIGNATURE.len() };
+        let version = file.read_u16().map_err(truncated)?;
+        if version > VERSION {
+            return Err(format!("Snapshot version {} is not supported, only up to {}", version, VERSION));
+        }
+        let mut snapshot = Snapshot { version, modules: Vec::new() };
+        while file.offset < bytes.len() {
+            let length = file.read_u8().map_err(truncated)? as usize;
+            let name = String::from_utf8_lossy(file.take(length).map_err(truncated)?).into_owned();
+            let length = file.read_u32().map_err(truncated)? as usize;
+            let data = file.take(length).map_err(truncated)?.to_vec();
+            snapshot.modules.push((name, Writer { data }));
+        }
+        Ok(snapshot)
+    }
+
+    pub fn save(self: &Snapshot, filename: &str) -> Result<(), String> {
+        File::create(filename)
+            .and_then(|mut f| f.write_all(&self.to_bytes()))
+            .map_err(|err| format!("Failed to write {}: {}", filename, err))
+    }
+
+    pub fn load(filename: &str) -> Result<Snapshot, String> {
+        let mut bytes = Vec::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|err| format!("Failed to read {}: {}", filename, err))?;
+        Snapshot::from_bytes(&bytes).map_err(|msg| format!("{}: {}", filename, msg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        {
+            let module = snapshot.add("TEST");
+            module.write_u8(0x12);
+            module.write_bool(true);
+            module.write_u16(0x3456);
+            module.write_u32(0x789A_BCDE);
+            module.write_u64(0x0123_4567_89AB_CDEF);
+            module.write_f32(-1.5);
+            module.write_bytes(b"data");
+        }
+        snapshot.add("EMPTY");
+        snapshot
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let snapshot = Snapshot::from_bytes(&sample().to_bytes()).unwrap();
+        assert!(snapshot.contains("TEST") && snapshot.contains("EMPTY"));
+        assert!(!snapshot.contains("OTHER"));
+        let mut module = snapshot.module("TEST").unwrap();
+        assert_eq!(module.read_u8(), Ok(0x12));
+        assert_eq!(module.read_bool(), Ok(true));
+        assert_eq!(module.read_u16(), Ok(0x3456));
+        assert_eq!(module.read_u32(), Ok(0x789A_BCDE));
+        assert_eq!(module.read_u64(), Ok(0x0123_4567_89AB_CDEF));
+        assert_eq!(module.read_f32(), Ok(-1.5));
+        let mut bytes = [0; 4];
+        assert_eq!(module.read_bytes(&mut bytes), Ok(()));
+        assert_eq!(&bytes, b"data");
+        assert!(module.read_u8().is_err());
+        assert!(snapshot.module("EMPTY").unwrap().read_u8().is_err());
+        assert!(snapshot.module("OTHER").is_err());
+    }
+
+    #[test]
+    fn refuses_another_signature() {
+        let mut bytes = sample().to_bytes();
+        bytes[0] = b'X';
+        assert_eq!(Snapshot::from_bytes(&bytes).err(), Some("Not a snapshot".to_string()));
+    }
+
+    #[test]
+    fn refuses_a_newer_version() {
+        let mut bytes = sample().to_bytes();
+        bytes[SIGNATURE.len()] = VERSION as u8 + 1;
+        assert!(Snapshot::from_bytes(&bytes).err().unwrap().contains("not supported"));
+    }
+
+    #[test]
+    fn refuses_a_truncated_module() {
+        let bytes = sample().to_bytes();
+        // All of the first module but the last byte of its data
+        let end = SIGNATURE.len() + 2 + 1 + 4 + 4 + 24 - 1;
+        assert_eq!(Snapshot::from_bytes(&bytes[..end]).err(), Some("The snapshot is truncated".to_string()));
+        assert!(Snapshot::from_bytes(&bytes[..SIGNATURE.len() + 1]).is_err());
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+// T64 tape archives: the files of a tape without the pulses, behind a
+// 64-byte header and a directory of 32-byte entries with the C64 file
+// type, the start and end address, the offset of the data in the archive
+// and the name. They are loaded through the KERNAL traps, the datasette
+// doesn't see them.
+
+use std::fs::File;
+use std::io::prelude::*;
+
+const HEADER_SIZE: usize = 64;
+const ENTRY_SIZE: usize = 32;
+const SIGNATURE: &[u8] = b"C64";
+const MAX_ENTRIES: usize = 0x22;
+const USED_ENTRIES: usize = 0x24;
+const TAPE_NAME: usize = 0x28;
+const TAPE_NAME_LENGTH: usize = 24;
+
+// Offsets in a directory entry
+const ENTRY_TYPE: usize = 0x00;
+const START_ADDRESS: usize = 0x02;
+const END_ADDRESS: usize = 0x04;
+const DATA_OFFSET: usize = 0x08;
+const NAME: usize = 0x10;
+const NAME_LENGTH: usize = 16;
+
+// Entry types, snapshots and free entries are skipped
+const NORMAL_FILE: u8 = 1;
+
+pub struct T64File {
+    // PETSCII without the padding
+    pub name: Vec<u8>,
+    pub start: u16,
+    // The load address followed by the contents, like a PRG file
+    pub data: Vec<u8>
+}
+
+pub struct T64Archive {
+    filename: String,
+    name: Vec<u8>,
+    files: Vec<T64File>
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+}
+
+fn trim_name(name: &[u8]) -> Vec<u8> {
+    let length = name.iter().rposition(|&c| c != b' ' && c != 0xA0 && c != 0).map_or(0, |last| last + 1);
+    name[..length].to_vec()
+}
+
+impl T64Archive {
+    pub fn load(filename: &str) -> Result<T64Archive, String> {
+        let mut bytes = Vec::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|err| format!("Failed to read {}: {}", filename, err))?;
+        T64Archive::parse(filename, &bytes)
+    }
+
+    pub fn parse(filename: &str, bytes: &[u8]) -> Result<T64Archive, String> {
+        if bytes.len() < HEADER_SIZE || !bytes.starts_with(SIGNATURE) {
+            return Err(format!("Not a T64 archive: {}", filename));
+        }
+        // Some archives claim no used entries, the maximum is more reliable
+        let entries = (read_u16(bytes, MAX_ENTRIES).max(read_u16(bytes, USED_ENTRIES)) as usize)
+            .min((bytes.len() - HEADER_SIZE) / ENTRY_SIZE);
+        let mut located = Vec::new();
+        for i in 0..entries {
+            let entry = &bytes[HEADER_SIZE + i * ENTRY_SIZE..HEADER_SIZE + (i + 1) * ENTRY_SIZE];
+            if entry[ENTRY_TYPE] != NORMAL_FILE {
+                continue;
+            }
+            let offset = read_u16(entry, DATA_OFFSET) as usize | (read_u16(entry, DATA_OFFSET + 2) as usize) << 16;
+            let start = read_u16(entry, START_ADDRESS);
+            let end = read_u16(entry, END_ADDRESS);
+            located.push((offset, start, end, trim_name(&entry[NAME..NAME + NAME_LENGTH])));
+        }
+        // Many archives have wrong end addresses, the data also ends where
+        // the next file or the archive does
+        let mut offsets: Vec<usize> = located.iter().map(|&(offset, _, _, _)| offset).collect();
+        offsets.push(bytes.len());
+        offsets.sort();
+        let mut files = Vec::new();
+        for (offset, start, end, name) in located {
+            if offset >= bytes.len() {
+                return Err(format!("File {} lies beyond the end of {}", String::from_utf8_lossy(&name), filename));
+            }
+            let next = offsets.iter().cloned().find(|&next| next > offset).unwrap_or(bytes.len());
+            let length = (end.wrapping_sub(start) as usize).min(next - offset);
+            let mut data = vec![start as u8, (start >> 8) as u8];
+            data.extend_from_slice(&bytes[offset..offset + length]);
+            files.push(T64File { name, start, data });
+        }
+        Ok(T64Archive {
+            filename: filename.to_string(),
+            name: trim_name(&bytes[TAPE_NAME..TAPE_NAME + TAPE_NAME_LENGTH]),
+            files
+        })
+    }
+
+    pub fn filename(self: &T64Archive) -> &str {
+        &self.filename
+    }
+
+    pub fn name(self: &T64Archive) -> &[u8] {
+        &self.name
+    }
+
+    pub fn files(self: &T64Archive) -> &[T64File] {
+        &self.files
+    }
+}
@@ -0,0 +1,272 @@
+// The 6522 Versatile Interface Adapter of the 1541. Registers are addressed
+// by the low nibble of the address. The shift register only stores its value,
+// timer 2 doesn't count PB6 pulses and there is no CB1 input or port B
+// latching, the drive uses none of them.
+
+bitflags! {
+    struct Interrupts: u8 {
+        const CA2 = 0b0000_0001;
+        const CA1 = 0b0000_0010;
+        const SR  = 0b0000_0100;
+        const CB2 = 0b0000_1000;
+        const CB1 = 0b0001_0000;
+        const T2  = 0b0010_0000;
+        const T1  = 0b0100_0000;
+    }
+}
+
+// Auxiliary control register bits
+const ACR_PA_LATCH: u8 = 0b0000_0001;
+const ACR_T1_CONTINUOUS: u8 = 0b0100_0000;
+
+// Peripheral control register bits. CA2 and CB2 are outputs when the top bit
+// of their 3-bit control field is set, with 110 driving them low and 111 high.
+const PCR_CA1_POSITIVE_EDGE: u8 = 0b0000_0001;
+
+struct Timer {
+    counter: u16,
+    latch: u16,
+    // Cleared once the interrupt of a one-shot run has been raised
+    armed: bool
+}
+
+impl Timer {
+    fn new() -> Timer {
+        Timer {
+            counter: 0xFFFF,
+            latch: 0xFFFF,
+            armed: false
+        }
+    }
+}
+
+pub struct Via {
+    ora: u8,
+    orb: u8,
+    ddra: u8,
+    ddrb: u8,
+    // Levels driven onto the port pins by external devices
+    port_a_input: u8,
+    port_b_input: u8,
+    // Port A input latched on an active edge of CA1
+    port_a_latch: u8,
+    ca1: bool,
+    timer_1: Timer,
+    // Timer 1 reloads from the latch in the cycle after an underflow
+    timer_1_reload: bool,
+    timer_2: Timer,
+    sr: u8,
+    acr: u8,
+    pcr: u8,
+    ifr: Interrupts,
+    ier: Interrupts
+}
+
+impl Via {
+    pub fn new() -> Via {
+        Via {
+            ora: 0,
+            orb: 0,
+            ddra: 0,
+            ddrb: 0,
+            port_a_input: 0xFF,
+            port_b_input: 0xFF,
+            port_a_latch: 0xFF,
+            ca1: true,
+            timer_1: Timer::new(),
+            timer_1_reload: false,
+            timer_2: Timer::new(),
+            sr: 0,
+            acr: 0,
+            pcr: 0,
+            ifr: Interrupts { bits: 0 },
+            ier: Interrupts { bits: 0 }
+        }
+    }
+
+    pub fn set_port_a_input(self: &mut Via, value: u8) {
+        self.port_a_input = value;
+    }
+
+    pub fn set_port_b_input(self: &mut Via, value: u8) {
+        self.port_b_input = value;
+    }
+
+    /// Levels of the port A pins: outputs as written, inputs as driven by
+    /// the devices attached.
+    pub fn port_a_pins(self: &Via) -> u8 {
+        (self.ora & self.ddra) | (self.port_a_input & !self.ddra)
+    }
+
+    /// Levels of the port B pins.
+    pub fn port_b_pins(self: &Via) -> u8 {
+        (self.orb & self.ddrb) | (self.port_b_input & !self.ddrb)
+    }
+
+    /// Sets the level of the CA1 input, raising its interrupt on the edge
+    /// selected in the PCR.
+    pub fn set_ca1(self: &mut Via, level: bool) {
+        if level != self.ca1 && level == (self.pcr & PCR_CA1_POSITIVE_EDGE > 0) {
+            self.ifr.insert(Interrupts::CA1);
+            self.port_a_latch = self.port_a_pins();
+        }
+        self.ca1 = level;
+    }
+
+    // Level of CA2 or CB2 from their control field. Inputs and the
+    // handshake modes read high.
+    fn control_output(control: u8) -> bool {
+        control != 0b110
+    }
+
+    /// Level of the CA2 pin.
+    pub fn ca2(self: &Via) -> bool {
+        Via::control_output((self.pcr >> 1) & 0b111)
+    }
+
+    /// Level of the CB2 pin.
+    pub fn cb2(self: &Via) -> bool {
+        Via::control_output((self.pcr >> 5) & 0b111)
+    }
+
+    // Accessing a port clears its interrupts, CA2 and CB2 only unless they
+    // are set to independent interrupt input mode
+    fn clear_port_a_interrupts(self: &mut Via) {
+        self.ifr.remove(Interrupts::CA1);
+        if self.pcr & 0b0000_1010 != 0b0000_0010 {
+            self.ifr.remove(Interrupts::CA2);
+        }
+    }
+
+    fn clear_port_b_interrupts(self: &mut Via) {
+        self.ifr.remove(Interrupts::CB1);
+        if self.pcr & 0b1010_0000 != 0b0010_0000 {
+            self.ifr.remove(Interrupts::CB2);
+        }
+    }
+
+    pub fn read(self: &mut Via, addr: u16) -> u8 {
+        match addr & 0x0F {
+            0x0 => {
+                self.clear_port_b_interrupts();
+                self.port_b_pins()
+            }
+            0x1 => {
+                self.clear_port_a_interrupts();
+                self.read(0xF)
+            }
+            0x2 => self.ddrb,
+            0x3 => self.ddra,
+            0x4 => {
+                self.ifr.remove(Interrupts::T1);
+                self.timer_1.counter as u8
+            }
+            0x5 => (self.timer_1.counter >> 8) as u8,
+            0x6 => self.timer_1.latch as u8,
+            0x7 => (self.timer_1.latch >> 8) as u8,
+            0x8 => {
+                self.ifr.remove(Interrupts::T2);
+                self.timer_2.counter as u8
+            }
+            0x9 => (self.timer_2.counter >> 8) as u8,
+            0xA => self.sr,
+            0xB => self.acr,
+            0xC => self.pcr,
+            0xD => {
+                let irq = if self.irq() { 0x80 } else { 0 };
+                self.ifr.bits | irq
+            }
+            0xE => self.ier.bits | 0x80,
+            _ => {
+                let input = if self.acr & ACR_PA_LATCH > 0 { self.port_a_latch } else { self.port_a_input };
+                (self.ora & self.ddra) | (input & !self.ddra)
+            }
+        }
+    }
+
+    pub fn write(self: &mut Via, addr: u16, value: u8) {
+        match addr & 0x0F {
+            0x0 => {
+                self.clear_port_b_interrupts();
+                self.orb = value;
+            }
+            0x1 => {
+                self.clear_port_a_interrupts();
+                self.ora = value;
+            }
+            0x2 => self.ddrb = value,
+            0x3 => self.ddra = value,
+            0x4 | 0x6 => self.timer_1.latch = (self.timer_1.latch & 0xFF00) | value as u16,
+            0x5 => {
+                self.timer_1.latch = (self.timer_1.latch & 0x00FF) | (value as u16) << 8;
+                self.timer_1.counter = self.timer_1.latch;
+                self.timer_1.armed = true;
+                self.timer_1_reload = false;
+                self.ifr.remove(Interrupts::T1);
+            }
+            0x7 => {
+                self.timer_1.latch = (self.timer_1.latch & 0x00FF) | (value as u16) << 8;
+                self.ifr.remove(Interrupts::T1);
+            }
+            0x8 => self.timer_2.latch = (self.timer_2.latch & 0xFF00) | value as u16,
+            0x9 => {
+                self.timer_2.counter = (self.timer_2.latch & 0x00FF) | (value as u16) << 8;
+                self.timer_2.armed = true;
+                self.ifr.remove(Interrupts::T2);
+            }
+            0xA => self.sr = value,
+            0xB => self.acr = value,
+            0xC => self.pcr = value,
+            0xD => self.ifr.remove(Interrupts::from_bits_truncate(value)),
+            0xE => {
+                let bits = Interrupts::from_bits_truncate(value);
+                if value & 0x80 > 0 {
+                    self.ier.insert(bits);
+                } else {
+                    self.ier.remove(bits);
+                }
+            }
+            _ => self.ora = value
+        }
+    }
+
+    fn irq(self: &Via) -> bool {
+        self.ifr.intersects(self.ier)
+    }
+
+    /// Advances the chip by one cycle. Returns whether the IRQ output is
+    /// asserted.
+    pub fn tick(self: &mut Via) -> bool {
+        if self.timer_1_reload {
+            self.timer_1.counter = self.timer_1.latch;
+            self.timer_1_reload = false;
+        } else {
+            let (counter, underflow) = self.timer_1.counter.overflowing_sub(1);
+            self.timer_1.counter = counter;
+            if underflow {
+                if self.acr & ACR_T1_CONTINUOUS > 0 {
+                    self.ifr.insert(Interrupts::T1);
+                    self.timer_1_reload = true;
+                } else if self.timer_1.armed {
+                    self.ifr.insert(Interrupts::T1);
+                    self.timer_1.armed = false;
+                }
+            }
+        }
+
+        let (counter, underflow) = self.timer_2.counter.overflowing_sub(1);
+        self.timer_2.counter = counter;
+        if underflow && self.timer_2.armed {
+            self.ifr.insert(Interrupts::T2);
+            self.timer_2.armed = false;
+        }
+
+        self.irq()
+    }
+
+    pub fn print_status(self: &Via) {
+        println!("PA: 0x{:02X}  DDRA: 0x{:02X}  PB: 0x{:02X}  DDRB: 0x{:02X}  ACR: 0x{:02X}  PCR: 0x{:02X}  IFR: 0x{:02X}  IER: 0x{:02X}",
+            self.port_a_pins(), self.ddra, self.port_b_pins(), self.ddrb, self.acr, self.pcr, self.ifr.bits, self.ier.bits);
+        println!("T1: 0x{:04X} latch 0x{:04X}  T2: 0x{:04X}", self.timer_1.counter, self.timer_1.latch, self.timer_2.counter);
+    }
+}
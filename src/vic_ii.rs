@@ -2,6 +2,43 @@ extern crate sdl2;
 extern crate gl;
 
 use memory::ReadView;
+use snapshot::{Reader, Writer};
+
+/// Timing of a PAL or an NTSC machine. The VIC-II divides its dot clock by
+/// eight for the CPU, and the SID produces an output sample every cycle.
+/// The TOD clocks of the CIAs count the mains frequency of the country.
+#[derive(Clone, Copy, PartialEq)]
+pub struct VideoStandard {
+    pub clock_frequency: u32,
+    pub lines: u16,
+    pub cycles_per_line: u16,
+    pub mains_frequency: u32
+}
+
+pub const PAL: VideoStandard = VideoStandard {
+    clock_frequency: 985248,
+    lines: 312,
+    cycles_per_line: 63,
+    mains_frequency: 50
+};
+
+pub const NTSC: VideoStandard = VideoStandard {
+    clock_frequency: 1022727,
+    lines: 263,
+    cycles_per_line: 65,
+    mains_frequency: 60
+};
+
+impl VideoStandard {
+    pub fn frame_cycles(self: &VideoStandard) -> u32 {
+        self.lines as u32 * self.cycles_per_line as u32
+    }
+
+    /// CPU cycles between two pulses of the mains frequency.
+    pub fn tod_pulse_cycles(self: &VideoStandard) -> u32 {
+        (self.clock_frequency + self.mains_frequency / 2) / self.mains_frequency
+    }
+}
 
 pub struct Registers {
     data: [u8; 47]
@@ -48,8 +85,9 @@ const PALETTE: [sdl2::pixels::Color; 16] = [
 ];
 
 pub struct VicII {
-    canvas: sdl2::render::Canvas<sdl2::video::Window>,
-    event_pump: sdl2::EventPump,
+    // None when running headless
+    canvas: Option<sdl2::render::Canvas<sdl2::video::Window>>,
+    standard: VideoStandard,
     raster_line: u16,
     x_coord: u16,
     pub registers: Registers
@@ -64,35 +102,99 @@ fn find_sdl_gl_driver() -> Option<u32> {
     None
 }
 
-impl VicII {
-    pub fn new() -> VicII {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
-        let window = video_subsystem.window("Window", 504, 312)
-            .opengl()
-            .build()
-            .unwrap();
-        let mut canvas = window.into_canvas()
-            .index(find_sdl_gl_driver().unwrap())
-            .build()
-            .unwrap();
-
-        let event_pump = sdl_context.event_pump().unwrap();
-
-        canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
-        canvas.clear();
-        canvas.present();
-        canvas.set_draw_color(sdl2::pixels::Color::RGB(255, 255, 255));
+fn create_canvas(sdl_context: &sdl2::Sdl, standard: VideoStandard) -> sdl2::render::Canvas<sdl2::video::Window> {
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem.window("Window", standard.cycles_per_line as u32 * 8, standard.lines as u32)
+        .opengl()
+        .build()
+        .unwrap();
+    let mut canvas = window.into_canvas()
+        .index(find_sdl_gl_driver().unwrap())
+        .build()
+        .unwrap();
 
+    canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+    canvas.clear();
+    canvas.present();
+    canvas.set_draw_color(sdl2::pixels::Color::RGB(255, 255, 255));
+    canvas
+}
+
+pub enum Effect {
+    FrameDone
+}
+
+impl VicII {
+    pub fn new(sdl_context: Option<&sdl2::Sdl>, standard: VideoStandard) -> VicII {
         VicII {
-            canvas: canvas,
-            event_pump: event_pump,
+            canvas: sdl_context.map(|sdl_context| create_canvas(sdl_context, standard)),
+            standard,
             raster_line: 0,
             x_coord: 0,
             registers: Registers::new()
         }
     }
 
+    pub fn save_state(self: &VicII, writer: &mut Writer) {
+        writer.write_u16(self.raster_line);
+        writer.write_u16(self.x_coord);
+        writer.write_bytes(&self.registers.data);
+    }
+
+    /// Leaves the chip as it was if the state can't be read.
+    pub fn load_state(self: &mut VicII, reader: &mut Reader) -> Result<(), String> {
+        let raster_line = reader.read_u16()?;
+        let x_coord = reader.read_u16()?;
+        let mut data = [0; 47];
+        reader.read_bytes(&mut data)?;
+        if raster_line >= self.standard.lines || x_coord >= self.standard.cycles_per_line * 8 || x_coord % 8 != 0 {
+            return Err(format!("Invalid raster position in the snapshot: line {}, x {}", raster_line, x_coord));
+        }
+        self.raster_line = raster_line;
+        self.x_coord = x_coord;
+        self.registers.data = data;
+        Ok(())
+    }
+
+    /// Takes the raster position and the registers of a chip that was
+    /// loaded on its own, keeping the window.
+    pub fn take_state(self: &mut VicII, other: VicII) {
+        self.raster_line = other.raster_line;
+        self.x_coord = other.x_coord;
+        self.registers = other.registers;
+    }
+
+    /// Loads the VIC-II module of a VICE snapshot, which also holds the
+    /// color RAM: the bad line and blanking state in 3 bytes, 40 bytes of
+    /// colors fetched, the color RAM, the idle state and light pen in 4
+    /// bytes, 40 bytes of characters fetched, the sprite DMA mask, the RAM
+    /// base in 4 bytes, the raster cycle, the raster line in 2 bytes and the
+    /// 64 registers. The sprite and fetch state behind them is not needed.
+    pub fn load_vice_state(self: &mut VicII, reader: &mut Reader, color_ram: &mut [u8]) -> Result<(), String> {
+        reader.skip(3 + 40)?;
+        reader.read_bytes(color_ram)?;
+        reader.skip(4 + 40 + 1 + 4)?;
+        let cycle = reader.read_u8()? as u16;
+        let raster_line = reader.read_u16()?;
+        let mut registers = [0; 64];
+        reader.read_bytes(&mut registers)?;
+        if raster_line >= self.standard.lines || cycle >= self.standard.cycles_per_line {
+            return Err(format!("Invalid raster position in the VICE snapshot: line {}, cycle {}", raster_line, cycle));
+        }
+        self.raster_line = raster_line;
+        self.x_coord = cycle * 8;
+        self.registers.data.copy_from_slice(&registers[..47]);
+        Ok(())
+    }
+
+    fn draw_point(self: &mut VicII, x: i32, color_index: u8) {
+        let y = self.raster_line as i32;
+        if let Some(ref mut canvas) = self.canvas {
+            canvas.set_draw_color(PALETTE[color_index as usize]);
+            canvas.draw_point((x, y)).unwrap();
+        }
+    }
+
     fn first_line(self: &VicII) -> u16 {
         // TODO: Choose according to RSEL
         51
@@ -113,7 +215,7 @@ impl VicII {
         415
     }
 
-    pub fn tick<M: ReadView>(self: &mut VicII, mem: &mut M, color_ram: &[u8]) {
+    pub fn tick<M: ReadView>(self: &mut VicII, mem: &mut M, color_ram: &[u8]) -> Option<Effect> {
         if self.raster_line >= self.first_line() && self.raster_line <= self.last_line() &&
             self.x_coord >= self.first_x_coord() && self.x_coord <= self.last_x_coord() {
 
@@ -130,8 +232,8 @@ impl VicII {
                 } else {
                     self.registers.background_color_0()
                 };
-                self.canvas.set_draw_color(PALETTE[color_index as usize]);
-                self.canvas.draw_point((self.x_coord as i32 + i, self.raster_line as i32)).unwrap();
+                let x = self.x_coord as i32 + i;
+                self.draw_point(x, color_index);
             }
         }
 
@@ -140,31 +242,54 @@ impl VicII {
             (self.x_coord >= 52 && self.x_coord < self.first_x_coord()) ||
             (self.x_coord > self.last_x_coord() && self.x_coord <= 454) {
 
-            self.canvas.set_draw_color(PALETTE[self.registers.border_color() as usize]);
+            let border_color = self.registers.border_color();
             for i in 0..8 {
-                self.canvas.draw_point((self.x_coord as i32 + i, self.raster_line as i32)).unwrap();
+                let x = self.x_coord as i32 + i;
+                self.draw_point(x, border_color);
             }
         }
 
         self.x_coord += 8;
-        if self.x_coord >= 504 {
+        if self.x_coord >= self.standard.cycles_per_line * 8 {
             self.raster_line += 1;
             self.x_coord = 0;
         }
-        if self.raster_line >= 312 {
+        if self.raster_line >= self.standard.lines {
             self.raster_line = 0;
-            self.canvas.present();
+            if let Some(ref mut canvas) = self.canvas {
+                canvas.present();
+            }
+            Some(Effect::FrameDone)
+        } else {
+            None
         }
+    }
+}
 
-        for event in self.event_pump.poll_iter() {
-            use vic_ii::sdl2::event::Event;
-            use vic_ii::sdl2::keyboard::Keycode;
-            match event {
-                Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    panic!("exit");
-                }
-                _ => ()
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoMemory;
+
+    impl ReadView for NoMemory {
+        fn read(self: &mut NoMemory, _addr: u16) -> u8 {
+            0
         }
     }
+
+    fn frame_cycles(standard: VideoStandard) -> u32 {
+        let mut vic = VicII::new(None, standard);
+        let mut cycles = 1;
+        while vic.tick(&mut NoMemory, &[0; 1024]).is_none() {
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn draws_frames_of_the_video_standard() {
+        assert_eq!(frame_cycles(PAL), 312 * 63);
+        assert_eq!(frame_cycles(NTSC), 263 * 65);
+    }
 }
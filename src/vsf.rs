@@ -0,0 +1,162 @@
+// Snapshot files of VICE (.vsf). After the signature come the version of
+// the format, the name of the machine in 16 bytes and, from VICE 3.0 on,
+// the version of VICE that wrote the file. Every module starts with a
+// header of 22 bytes: the name in 16 bytes, the major and minor version of
+// its layout and the length of the module including the header. Numbers are
+// little-endian.
+//
+// What is in the modules depends on the emulator that wrote them, the
+// layouts of the modules imported are at their readers.
+
+use std::fs::File;
+use std::io::prelude::*;
+
+use snapshot::Reader;
+
+const SIGNATURE: &[u8] = b"VICE Snapshot File\x1A";
+const VERSION_SIGNATURE: &[u8] = b"VICE Version\x1A";
+const NAME_LENGTH: usize = 16;
+const MODULE_HEADER_SIZE: usize = NAME_LENGTH + 6;
+// The VICE version and its SVN revision
+const VERSION_SIZE: usize = 8;
+
+pub struct VsfModule {
+    name: String,
+    data: Vec<u8>
+}
+
+impl VsfModule {
+    pub fn name(self: &VsfModule) -> &str {
+        &self.name
+    }
+
+    pub fn reader<'a>(self: &'a VsfModule) -> Reader<'a> {
+        Reader::new(&self.name, &self.data)
+    }
+}
+
+pub struct VsfSnapshot {
+    machine: String,
+    modules: Vec<VsfModule>
+}
+
+// A name padded with zero bytes
+fn read_name(bytes: &[u8]) -> String {
+    let length = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..length]).into_owned()
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> usize {
+    bytes[offset] as usize | (bytes[offset + 1] as usize) << 8 | (bytes[offset + 2] as usize) << 16 | (bytes[offset + 3] as usize) << 24
+}
+
+impl VsfSnapshot {
+    pub fn load(filename: &str) -> Result<VsfSnapshot, String> {
+        let mut bytes = Vec::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|err| format!("Failed to read {}: {}", filename, err))?;
+        VsfSnapshot::parse(&bytes).map_err(|msg| format!("{}: {}", filename, msg))
+    }
+
+    fn parse(bytes: &[u8]) -> Result<VsfSnapshot, String> {
+        let header_size = SIGNATURE.len() + 2 + NAME_LENGTH;
+        if !bytes.starts_with(SIGNATURE) || bytes.len() < header_size {
+            return Err("Not a VICE snapshot".to_string());
+        }
+        let machine = read_name(&bytes[header_size - NAME_LENGTH..header_size]);
+        let mut offset = header_size;
+        if bytes[offset..].starts_with(VERSION_SIGNATURE) {
+            offset += VERSION_SIGNATURE.len() + VERSION_SIZE;
+        }
+        let mut modules = Vec::new();
+        while offset < bytes.len() {
+            if offset + MODULE_HEADER_SIZE > bytes.len() {
+                return Err("Truncated module header".to_string());
+            }
+            let name = read_name(&bytes[offset..offset + NAME_LENGTH]);
+            let length = read_u32(bytes, offset + NAME_LENGTH + 2);
+            if length < MODULE_HEADER_SIZE || offset + length > bytes.len() {
+                return Err(format!("Invalid length of the {} module: {}", name, length));
+            }
+            modules.push(VsfModule {
+                name,
+                data: bytes[offset + MODULE_HEADER_SIZE..offset + length].to_vec()
+            });
+            offset += length;
+        }
+        Ok(VsfSnapshot { machine, modules })
+    }
+
+    /// The name of the emulated machine, C64 or C64SC for the C64.
+    pub fn machine(self: &VsfSnapshot) -> &str {
+        &self.machine
+    }
+
+    pub fn modules(self: &VsfSnapshot) -> &[VsfModule] {
+        &self.modules
+    }
+
+    pub fn module(self: &VsfSnapshot, name: &str) -> Option<&VsfModule> {
+        self.modules.iter().find(|module| module.name == name)
+    }
+}
+
+/// A snapshot file of the given machine with the given modules, as VICE 3.x
+/// writes it.
+#[cfg(test)]
+pub fn file(machine: &str, modules: &[(&str, &[u8])]) -> Vec<u8> {
+    fn name(bytes: &mut Vec<u8>, name: &str) {
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.resize(bytes.len() + NAME_LENGTH - name.len(), 0);
+    }
+    let mut bytes = SIGNATURE.to_vec();
+    bytes.extend_from_slice(&[2, 0]);
+    name(&mut bytes, machine);
+    bytes.extend_from_slice(VERSION_SIGNATURE);
+    bytes.extend_from_slice(&[3, 4, 0, 0, 0, 0, 0, 0]);
+    for &(module, data) in modules.iter() {
+        name(&mut bytes, module);
+        let length = (MODULE_HEADER_SIZE + data.len()) as u32;
+        bytes.extend_from_slice(&[1, 0, length as u8, (length >> 8) as u8, (length >> 16) as u8, (length >> 24) as u8]);
+        bytes.extend_from_slice(data);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_machine_and_the_modules() {
+        let vsf = VsfSnapshot::parse(&file("C64SC", &[("MAINCPU", &[1, 2, 3]), ("SID", &[])])).unwrap();
+        assert_eq!(vsf.machine(), "C64SC");
+        let names: Vec<&str> = vsf.modules().iter().map(|module| module.name()).collect();
+        assert_eq!(names, ["MAINCPU", "SID"]);
+        let mut cpu = vsf.module("MAINCPU").unwrap().reader();
+        assert_eq!(cpu.read_u16(), Ok(0x0201));
+        assert_eq!(cpu.read_u8(), Ok(3));
+        assert!(cpu.read_u8().is_err());
+        assert!(vsf.module("C64MEM").is_none());
+    }
+
+    #[test]
+    fn reads_files_without_the_vice_version() {
+        let bytes = file("C64", &[("CIA1", &[0x55])]);
+        let version_start = SIGNATURE.len() + 2 + NAME_LENGTH;
+        let mut old = bytes[..version_start].to_vec();
+        old.extend_from_slice(&bytes[version_start + VERSION_SIGNATURE.len() + VERSION_SIZE..]);
+        let vsf = VsfSnapshot::parse(&old).unwrap();
+        assert_eq!(vsf.machine(), "C64");
+        assert_eq!(vsf.module("CIA1").unwrap().reader().read_u8(), Ok(0x55));
+    }
+
+    #[test]
+    fn refuses_broken_files() {
+        let bytes = file("C64", &[("C64MEM", &[0; 10])]);
+        assert!(VsfSnapshot::parse(b"C64EMU SNAPSHOT\x1A").is_err());
+        assert!(VsfSnapshot::parse(&bytes[..bytes.len() - 1]).err().unwrap().contains("Invalid length of the C64MEM module"));
+        assert_eq!(VsfSnapshot::parse(&bytes[..bytes.len() - 20]).err(), Some("Truncated module header".to_string()));
+    }
+}